@@ -0,0 +1,104 @@
+//! Benchmarks of attack tables, move generation, evaluation and search.
+
+use std::hint::black_box;
+use std::sync::{Arc, atomic, mpsc};
+
+use criterion::{criterion_group, Criterion};
+
+use vatu::analysis;
+use vatu::attacks;
+use vatu::capabilities;
+use vatu::movement;
+use vatu::node::Node;
+use vatu::notation;
+use vatu::rules;
+
+/// Positions to benchmark: opening, middlegame with many captures and
+/// castling options ("Kiwipete"), and endgame.
+const POSITIONS: [(&str, &str); 3] = [
+    ("start", notation::FEN_START),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"),
+];
+
+fn get_node(fen: &str) -> Node {
+    let fen = notation::parse_fen(fen).unwrap();
+    let (board, game_state) = notation::fen_to_position(&fen).unwrap();
+    let mut node = Node::new();
+    node.board = board;
+    node.game_state = game_state;
+    node
+}
+
+/// Compare magic bitboard lookups to ray-walking for the queen attacks
+/// of all squares.
+fn bench_attacks(c: &mut Criterion) {
+    let node = get_node(POSITIONS[1].1);
+    let occupancy = attacks::occupancy(&node.board);
+    attacks::init();
+    c.bench_function("attacks magic", |b| b.iter(|| {
+        (0..64).fold(0, |bb, sq| bb ^ attacks::queen_attacks(sq, black_box(occupancy)))
+    }));
+    c.bench_function("attacks rays", |b| b.iter(|| {
+        (0..64).fold(0, |bb, sq| {
+            bb ^ attacks::sliding_attacks(sq, black_box(occupancy), &attacks::ROOK_DIRS)
+            ^ attacks::sliding_attacks(sq, black_box(occupancy), &attacks::BISHOP_DIRS)
+        })
+    }));
+}
+
+fn bench_movegen(c: &mut Criterion) {
+    for (name, fen) in POSITIONS.iter() {
+        let node = get_node(fen);
+        c.bench_function(&format!("movegen {}", name), |b| b.iter(|| {
+            rules::generate_legal(black_box(&node.board), black_box(&node.game_state))
+        }));
+        // Legal moves found by playing each pseudo-legal move, as done
+        // before check and pin detection, for comparison.
+        c.bench_function(&format!("movegen {} make-unmake", name), |b| b.iter(|| {
+            let (board, game_state) = (black_box(&node.board), black_box(&node.game_state));
+            rules::generate_pseudo_legal(board, game_state).into_iter().filter(|m| {
+                let mut board = *board;
+                movement::apply_move_to_board(&mut board, m);
+                !rules::is_in_check(&board, game_state)
+            }).count()
+        }));
+    }
+}
+
+fn bench_eval(c: &mut Criterion) {
+    for (name, fen) in POSITIONS.iter() {
+        let node = get_node(fen);
+        c.bench_function(&format!("eval {}", name), |b| b.iter(|| {
+            analysis::evaluate_node(black_box(&node), &analysis::SHANNON_WEIGHTS)
+        }));
+    }
+}
+
+/// Search to `analysis::MAX_DEPTH` without time limits or
+/// transposition table, so each run does the same work.
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for (name, fen) in POSITIONS.iter() {
+        let node = get_node(fen);
+        let (tx, rx) = mpsc::channel();
+        group.bench_function(*name, |b| b.iter(|| {
+            let mut analyzer = analysis::Analyzer::new(node.clone(), tx.clone());
+            let args = analysis::AnalysisParams::default();
+            analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+            rx.try_iter().count()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_attacks, bench_movegen, bench_eval, bench_search);
+
+/// Like `criterion_main!`, with the capabilities of the build first so
+/// that results can be compared knowingly.
+fn main() {
+    println!("{}\n", capabilities::detect());
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
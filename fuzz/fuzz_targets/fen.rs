@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use vatu::notation;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Some(fen) = notation::parse_fen(s) {
+            notation::fen_to_position(&fen);
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use vatu::pgn;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = pgn::load_game(data);
+});
@@ -1,24 +1,85 @@
 //! Analysis functions.
 
+use std::fmt;
 use std::sync::{Arc, atomic, mpsc};
+use std::sync::atomic::AtomicU64;
 use std::time::Instant;
 
 use crate::board;
+use crate::endgame;
 use crate::engine;
+use crate::eval::activity::Activity;
+use crate::eval::imbalance::{self, Imbalance, ImbalanceTable, Material};
+use crate::eval::pawns::{self, PawnStructure, PawnTable};
+use crate::eval::phase;
+use crate::eval::pst::{self, PstScore};
+use crate::move_ordering::{self, MoveOrderer};
 use crate::movement::Move;
 use crate::node::Node;
 use crate::notation;
+use crate::reductions::{self, ReductionTable};
 use crate::rules;
-use crate::stats;
+use crate::score::{self, RelativeScore};
+use crate::time;
+use crate::tt::{self, TranspositionTable};
+use crate::tune;
+use crate::zobrist;
 
-const MIN_F32: f32 = std::f32::NEG_INFINITY;
-const MAX_F32: f32 = std::f32::INFINITY;
+const MIN_F32: f32 = f32::NEG_INFINITY;
+const MAX_F32: f32 = f32::INFINITY;
+
+/// Score of a checkmate; mates found closer to the root score higher.
+const MATE_SCORE: f32 = 1000.0;
+
+/// Maximum depth that can be requested, keeping mate scores apart from
+/// other scores.
+pub const MAX_SEARCH_DEPTH: u32 = 64;
+
+/// Depth always searched before honoring the soft time limit.
+const MIN_DEPTH: u32 = 2;
+
+/// Default interval between node stats reports, in milliseconds.
+pub const DEFAULT_INFO_INTERVAL: u32 = 1000;
+
+/// Maximum absolute contempt in centipawns.
+pub const MAX_CONTEMPT: i32 = 100;
+
+/// Width of the null windows used to test moves after the first one.
+///
+/// Raw scores are multiples of 0.01 at most (contempt has a centipawn
+/// resolution), so a smaller width can't let a better move go unnoticed.
+const NULL_WINDOW: f32 = 0.001;
+
+/// Interval in ms between checks of the pondering flag once the search
+/// is over.
+const PONDER_WAIT_INTERVAL: u64 = 10;
+
+/// Maximum depth of capture sequences searched after `max_depth`.
+const MAX_QUIESCENCE_DEPTH: u32 = 4;
+
+/// Non-pawn material of both sides, in pawns, below which delta pruning
+/// is disabled: in late endgames a capture can be worth much more than
+/// the piece taken, e.g. by leaving a passed pawn unstoppable.
+const DELTA_PRUNING_MIN_MATERIAL: u32 = 10;
+
+/// Scores beyond this value are mates, stored relative to the node in
+/// the transposition table.
+const MATE_THRESHOLD: f32 = MATE_SCORE - 100.0;
 
 /// Analysis worker.
 ///
-/// Parameters specifying when to stop an analysis (e.g. `max_depth`
-/// and `time_limit`) can be used together without issues and the
-/// worker will try to stop as soon as the first limit is reached.
+/// The search uses iterative deepening up to the depth requested, else
+/// up to `MAX_SEARCH_DEPTH`, see `search_depth`. It always completes `MIN_DEPTH`
+/// unless the hard time limit is reached, then stops at the soft time
+/// limit; results of an interrupted iteration are discarded for those
+/// of the previous one.
+///
+/// All search data belongs to the analyzer instance and there is no
+/// global state, so several analyzers can run at the same time, e.g.
+/// to compare different positions or settings in one process. Keep it
+/// that way when adding search tables or parameters, see
+/// `set_search_params`. The transposition table is the exception as it
+/// is shared on purpose, but it is safe to use from several threads.
 pub struct Analyzer {
     /// Enable some debug logs.
     pub debug: bool,
@@ -28,21 +89,107 @@ pub struct Analyzer {
     engine_tx: mpsc::Sender<engine::Cmd>,
     /// Stop working if flag is unset.
     working: Option<Arc<atomic::AtomicBool>>,
-    /// Max depth to reach in the next analysis.
+    /// Ignore time limits while flag is set, see `set_pondering`.
+    pondering: Option<Arc<atomic::AtomicBool>>,
+    /// Max depth to reach in the current iteration.
     max_depth: u32,
-    /// Time limit for the next analysis.
-    time_limit: i32,
+    /// Depth of the last iteration.
+    last_depth: u32,
+    /// Maximum depth set by the user, 0 for none.
+    depth_limit: u32,
+    /// Time limit after which the search stops, once MIN_DEPTH is searched.
+    soft_time_limit: i32,
+    /// Time limit after which the search stops in any case.
+    hard_time_limit: i32,
     /// Instant when the analysis began.
     start_time: Option<Instant>,
-    /// Instant of the last "per second" stats calculation.
+    /// Instant of the last node stats report.
     current_per_second_timer: Option<Instant>,
-    /// Nodes analyzed in this analysis.
-    num_nodes: u64,
-    /// Node analyzed since the last NPS stat.
+    /// Interval between node stats reports, in milliseconds.
+    info_interval: u32,
+    /// Maximum nodes per second, 0 for no limit.
+    max_nps: u32,
+    /// Nodes analyzed in this analysis, by all analyzers sharing the
+    /// counter in a parallel search.
+    num_nodes: Arc<AtomicU64>,
+    /// Value of `num_nodes` at the last node stats report.
+    num_nodes_at_report: u64,
+    /// Node analyzed by this analyzer since the last node stats report.
     num_nodes_in_second: u64,
+    /// Index of this analyzer in a parallel search, 0 for the main one.
+    helper_index: usize,
+    /// True if the current iteration stopped before reaching max depth everywhere.
+    interrupted: bool,
+    /// Deepest ply reached in the current iteration, including quiescence.
+    sel_depth: u32,
+    /// Transposition table probes in this analysis.
+    tt_probes: u64,
+    /// Transposition table probes that found an entry.
+    tt_hits: u64,
+    /// Transposition table to use, if any.
+    tt: Option<Arc<TranspositionTable>>,
+    /// Principal variation found from each depth of the current branch.
+    pv: Vec<Vec<Move>>,
+    /// Killer moves and history gathered during this analysis.
+    ordering: MoveOrderer,
+    /// Move lists reused at each depth of the current branch.
+    move_buffers: Vec<Vec<Move>>,
+    /// Late move reductions, if enabled.
+    reductions: Option<ReductionTable>,
+    /// Plies cut from the current branch by late move reductions.
+    reduction: u32,
+    /// Depth of the current node in the quiescence search.
+    q_depth: u32,
+    /// True to prune late quiet moves with a poor history.
+    history_pruning: bool,
+    /// Values of the tunable search parameters.
+    params: tune::SearchParams,
+    /// Moves played from the root to the current node.
+    line: Vec<Move>,
+    /// Static evaluations of the nodes of the current branch by ply,
+    /// where they are used by history pruning.
+    static_evals: Vec<Option<f32>>,
+    /// Caches of evaluation terms, allocated on first use unless set.
+    eval_caches: Option<EvalCaches>,
+    /// Weights of the evaluation terms.
+    weights: EvalWeights,
+    /// Number of best root moves to report with their lines.
+    multi_pv: usize,
+    /// Root moves skipped by the search, the best ones already found at
+    /// this depth when searching secondary lines.
+    excluded_root_moves: Vec<Move>,
+    /// Root moves to search, all of them if empty.
+    search_moves: Vec<Move>,
+    /// Raw score of a draw for the player to move at the root, negated.
+    contempt: f32,
+}
+
+/// Caches of evaluation terms of a search thread.
+///
+/// Unlike the transposition table they belong to a single thread, so an
+/// engine keeps one per search thread and passes it to the analyzer of
+/// each search, see `Analyzer::set_eval_caches`.
+pub struct EvalCaches {
+    /// Pawn structures of evaluated positions.
+    pub pawns: PawnTable,
+    /// Material imbalances of evaluated positions.
+    pub imbalances: ImbalanceTable,
+}
+
+impl Default for EvalCaches {
+    fn default() -> Self {
+        EvalCaches {
+            pawns: PawnTable::new(pawns::DEFAULT_TABLE_ENTRIES),
+            imbalances: ImbalanceTable::new(imbalance::DEFAULT_TABLE_ENTRIES),
+        }
+    }
 }
 
 /// Analysis parameters.
+///
+/// Values are in milliseconds (or number of moves for `moves_to_go`),
+/// and -1 means the value is not provided. An empty `search_moves`
+/// searches all legal moves.
 #[derive(Clone)]
 pub struct AnalysisParams {
     pub move_time: i32,
@@ -50,14 +197,95 @@ pub struct AnalysisParams {
     pub black_time: i32,
     pub white_inc: i32,
     pub black_inc: i32,
+    pub moves_to_go: i32,
+    pub depth: i32,
+    /// Only root moves to search.
+    pub search_moves: Vec<Move>,
+    /// Keep the result until stopped, even once the last depth is
+    /// searched.
+    pub infinite: bool,
+}
+
+impl Default for AnalysisParams {
+    fn default() -> Self {
+        AnalysisParams {
+            move_time: -1,
+            white_time: -1,
+            black_time: -1,
+            white_inc: -1,
+            black_inc: -1,
+            moves_to_go: -1,
+            depth: -1,
+            search_moves: vec!(),
+            infinite: false,
+        }
+    }
 }
 
 /// Analysis info to report.
 #[derive(Debug, Clone)]
 pub enum AnalysisInfo {
+    /// Depth fully searched.
+    Depth(u32),
+    /// Deepest ply reached, including quiescence.
+    SelDepth(u32),
     Nodes(u64),
     Nps(u64),
     CurrentMove(Move),
+    /// Time searched in ms.
+    Time(u64),
+    /// Index of the line the other infos are about, from 1, when
+    /// several lines are searched.
+    MultiPv(u32),
+    /// Normalized score in centipawns, for the player to move.
+    Score(RelativeScore),
+    /// Mate in this number of moves, negative if the player to move
+    /// gets mated; replaces the score.
+    Mate(i32),
+    /// Transposition table usage in permill.
+    HashFull(u32),
+    /// Principal variation, the expected line of best moves.
+    Pv(Vec<Move>),
+    /// Free-form message for the user.
+    String(String),
+}
+
+/// Statistics of a finished analysis.
+#[derive(Debug, Clone, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub tt_probes: u64,
+    /// Transposition table probes that found an entry.
+    pub tt_hits: u64,
+    /// True if the search took longer than the remaining clock time.
+    pub out_of_time: bool,
+}
+
+/// Result of a finished analysis.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// Move to play, None if there are no legal moves.
+    pub best_move: Option<Move>,
+    /// Expected reply to the best move, to ponder on.
+    pub ponder_move: Option<Move>,
+    /// Normalized score in centipawns for the player to move, None if
+    /// no best move has been found by the search.
+    pub score: Option<RelativeScore>,
+    /// Mate in this number of moves, negative if the player to move
+    /// gets mated, None if the score is not a mate score.
+    pub mate: Option<i32>,
+    /// Exact if an iteration completed, else a lower bound from the
+    /// root moves searched.
+    pub bound: tt::Bound,
+    /// Principal variation, starting with the best move.
+    pub pv: Vec<Move>,
+    /// Depth fully searched.
+    pub depth: u32,
+    /// Deepest ply reached at that depth, including quiescence.
+    pub sel_depth: u32,
+    /// Statistics of the search, None if the result comes from the
+    /// analysis cache.
+    pub stats: Option<SearchStats>,
 }
 
 impl Analyzer {
@@ -68,178 +296,1617 @@ impl Analyzer {
             node,
             engine_tx,
             working: None,
+            pondering: None,
             max_depth: 1,
-            time_limit: 0,
+            last_depth: MAX_SEARCH_DEPTH,
+            depth_limit: 0,
+            soft_time_limit: 0,
+            hard_time_limit: 0,
             start_time: None,
             current_per_second_timer: None,
-            num_nodes: 0,
+            info_interval: DEFAULT_INFO_INTERVAL,
+            max_nps: 0,
+            num_nodes: Arc::new(AtomicU64::new(0)),
+            num_nodes_at_report: 0,
             num_nodes_in_second: 0,
+            helper_index: 0,
+            interrupted: false,
+            sel_depth: 0,
+            tt_probes: 0,
+            tt_hits: 0,
+            tt: None,
+            pv: vec!(),
+            ordering: MoveOrderer::new(),
+            move_buffers: vec!(),
+            reductions: Some(ReductionTable::default()),
+            reduction: 0,
+            q_depth: 0,
+            history_pruning: true,
+            params: tune::SearchParams::default(),
+            line: vec!(),
+            static_evals: vec!(),
+            eval_caches: None,
+            weights: SHANNON_WEIGHTS,
+            multi_pv: 1,
+            excluded_root_moves: vec!(),
+            search_moves: vec!(),
+            contempt: 0.0,
+        }
+    }
+
+    /// Set the contempt in centipawns: how much a draw is considered
+    /// as worse than equality for the player to move at the root.
+    pub fn set_contempt(&mut self, cp: i32) {
+        self.contempt = score::cp_to_raw(cp);
+    }
+
+    /// Report node stats every `ms` milliseconds.
+    pub fn set_info_interval(&mut self, ms: u32) {
+        self.info_interval = ms;
+    }
+
+    /// Ponder: search without time limits while `pondering` is set.
+    ///
+    /// When it is unset, the time limits apply from that moment. The
+    /// best move is not reported before, even if the search is over,
+    /// unless the working flag is unset.
+    pub fn set_pondering(&mut self, pondering: Arc<atomic::AtomicBool>) {
+        self.pondering = Some(pondering);
+    }
+
+    /// Limit the search speed to `nps` nodes per second, to leave CPU
+    /// time to other processes; 0 disables the limit.
+    pub fn set_max_nps(&mut self, nps: u32) {
+        self.max_nps = nps;
+    }
+
+    /// Never search deeper than `depth`; 0 disables the limit.
+    ///
+    /// With a depth limit, the soft time limit is ignored and only the
+    /// hard one can stop the search before the limit, so that the
+    /// strength of the engine does not depend on the clock.
+    pub fn set_depth_limit(&mut self, depth: u32) {
+        self.depth_limit = depth;
+    }
+
+    /// Enable or disable late move reductions, enabled by default.
+    pub fn set_late_move_reductions(&mut self, enabled: bool) {
+        self.reductions = if enabled { Some(ReductionTable::with_params(&self.params)) } else { None };
+    }
+
+    /// Search with the tunable parameters `params` instead of their
+    /// defaults.
+    pub fn set_search_params(&mut self, params: tune::SearchParams) {
+        self.params = params;
+        if self.reductions.is_some() {
+            self.reductions = Some(ReductionTable::with_params(&params));
         }
     }
 
+    /// Enable or disable history pruning, enabled by default.
+    pub fn set_history_pruning(&mut self, enabled: bool) {
+        self.history_pruning = enabled;
+    }
+
+    /// Evaluate positions with `weights` instead of `SHANNON_WEIGHTS`.
+    pub fn set_eval_weights(&mut self, weights: EvalWeights) {
+        self.weights = weights;
+    }
+
+    /// Report the `lines` best root moves at each depth, with their
+    /// scores and principal variations, instead of the best one only.
+    ///
+    /// Each line is a search of the root without the moves of the
+    /// lines before it; the result is still the best line.
+    pub fn set_multi_pv(&mut self, lines: usize) {
+        self.multi_pv = lines.max(1);
+    }
+
+    /// Use this transposition table for the search.
+    pub fn set_transposition_table(&mut self, tt: Arc<TranspositionTable>) {
+        self.tt = Some(tt);
+    }
+
+    /// Cache evaluation terms in `caches`, e.g. those of a previous
+    /// search, instead of new ones.
+    pub fn set_eval_caches(&mut self, caches: EvalCaches) {
+        self.eval_caches = Some(caches);
+    }
+
+    /// Take back the evaluation caches used by the search, if any, to
+    /// pass them to a later one.
+    pub fn take_eval_caches(&mut self) -> Option<EvalCaches> {
+        self.eval_caches.take()
+    }
+
+    /// Make this analyzer the helper `index` of a parallel search, see
+    /// `engine::Engine`; `index` must be strictly positive.
+    ///
+    /// Helpers only fill the shared transposition table: they report
+    /// nothing and their result is to be discarded. Odd helpers search
+    /// from depth 2, so that helpers do not all search the same tree.
+    pub fn set_helper(&mut self, index: usize) {
+        self.helper_index = index;
+    }
+
+    /// Count nodes with `counter`, shared with the other analyzers of a
+    /// parallel search so that stats cover all of them.
+    pub fn set_node_counter(&mut self, counter: Arc<AtomicU64>) {
+        self.num_nodes = counter;
+    }
+
     fn log(&self, message: String) {
-        self.engine_tx.send(engine::Cmd::Log(message)).unwrap();
+        if self.helper_index == 0 {
+            self.engine_tx.send(engine::Cmd::Log(message)).unwrap();
+        }
     }
 
     fn report_info(&self, infos: Vec<AnalysisInfo>) {
-        self.engine_tx.send(engine::Cmd::WorkerInfo(infos)).unwrap();
+        if self.helper_index == 0 {
+            self.engine_tx.send(engine::Cmd::WorkerInfo(infos)).unwrap();
+        }
     }
 
-    fn report_best_move(&self, m: Option<Move>) {
-        self.engine_tx.send(engine::Cmd::WorkerBestMove(m)).unwrap();
+    /// Return node count, speed `nps`, time and transposition table
+    /// infos of the search.
+    fn progress_infos(&self, nps: u64) -> Vec<AnalysisInfo> {
+        let mut infos = vec![
+            AnalysisInfo::Nodes(self.num_nodes.load(atomic::Ordering::Relaxed)),
+            AnalysisInfo::Nps(nps),
+            AnalysisInfo::Time(self.start_time.unwrap().elapsed().as_millis() as u64),
+        ];
+        if let Some(tt) = &self.tt {
+            infos.push(AnalysisInfo::HashFull(tt.hashfull()));
+        }
+        infos
+    }
+
+    fn count_node(&mut self) {
+        self.num_nodes.fetch_add(1, atomic::Ordering::Relaxed);
+        self.num_nodes_in_second += 1;
     }
 
     /// Analyse best moves for the node.
     ///
     /// - `args`: parameters provided for this analysis.
-    /// - `score_map`: a NodeEvalMap to read and update.
     /// - `working`: flag telling whether to keep working or to stop.
+    ///
+    /// Infos are reported along the search, the result is returned once
+    /// it is over.
     pub fn analyze(
         &mut self,
         args: &AnalysisParams,
         working: Arc<atomic::AtomicBool>,
-    ) {
+    ) -> SearchResult {
         self.working = Some(working);
         self.set_limits(args);
+        self.set_search_moves(&args.search_moves);
 
-        if self.debug {
+        if self.debug && self.helper_index == 0 {
             self.log(format!("Analyzing node:\n{}", &self.node));
+            self.log(format!("FEN: {}", notation::to_fen(&self.node.board, &self.node.game_state)));
             let moves = self.node.get_player_moves(true);
             self.log(format!("Legal moves: {}", notation::move_list_to_string(&moves)));
-            self.log(format!("Move time: {}", self.time_limit));
+            self.log(format!("Evaluation: {}", evaluation_terms(&self.node, &self.weights)));
+            self.log(format!("Move time: {} (max {})", self.soft_time_limit, self.hard_time_limit));
         }
 
         self.start_time = Some(Instant::now());
         self.current_per_second_timer = Some(Instant::now());
-        let (max_score, best_move) = self.negamax(&self.node.clone(), MIN_F32, MAX_F32, 0);
+        let root = self.node.clone();
+        let root_key = zobrist::hash(&root.board, &root.game_state);
+        let root_pst = pst::score(&root.board);
+        let mut result = None;
+        let mut completed_depth = 0;
+        let first_depth = (1 + self.helper_index as u32 % 2).min(self.last_depth);
+        for depth in first_depth..=self.last_depth {
+            self.max_depth = depth;
+            self.interrupted = false;
+            self.sel_depth = 0;
+            self.excluded_root_moves.clear();
+            for line in 0..self.multi_pv {
+                let (score, best_move) = self.negamax(&root, root_key, root_pst, MIN_F32, MAX_F32, 0);
+                // Transposition table cutoffs return before reaching the
+                // horizon, but the depth has been searched nonetheless.
+                let sel_depth = self.sel_depth.max(depth);
+                // Keep the previous iteration results if this one has
+                // been interrupted, and drop interrupted secondary lines.
+                if self.interrupted && (result.is_some() || line > 0) {
+                    break
+                }
+                // No move left for another line.
+                let m = match best_move {
+                    Some(m) => m,
+                    None => break,
+                };
+                // An interrupted iteration has no complete principal variation.
+                let pv = if self.interrupted { vec![m] } else { self.pv[0].clone() };
+                if line == 0 {
+                    result = Some((score, pv, self.interrupted, sel_depth));
+                }
+                let mut infos = vec!();
+                if !self.interrupted {
+                    completed_depth = depth;
+                    infos.push(AnalysisInfo::Depth(depth));
+                    infos.push(AnalysisInfo::SelDepth(sel_depth));
+                }
+                if self.multi_pv > 1 {
+                    infos.push(AnalysisInfo::MultiPv(line as u32 + 1));
+                }
+                infos.push(score_info(score));
+                let elapsed = self.start_time.unwrap().elapsed().as_millis().max(1);
+                let nps = self.num_nodes.load(atomic::Ordering::Relaxed) as u128 * 1000 / elapsed;
+                infos.append(&mut self.progress_infos(nps as u64));
+                if !self.interrupted {
+                    infos.push(AnalysisInfo::Pv(self.pv[0].clone()));
+                }
+                self.report_info(infos);
+                if self.interrupted {
+                    break
+                }
+                self.excluded_root_moves.push(m);
+            }
+            self.excluded_root_moves.clear();
+            if self.interrupted {
+                break
+            }
+        }
 
-        if best_move.is_some() {
-            let log_str = format!(
-                "Best move {} evaluated {}",
-                notation::move_to_string(&best_move.unwrap()), max_score
-            );
-            self.log(log_str);
-            self.report_best_move(best_move);
+        // UCI forbids sending the result of pondering or infinite
+        // searches before being told to stop.
+        while
+            (self.is_pondering() || args.infinite)
+            && self.working.as_ref().unwrap().load(atomic::Ordering::Relaxed)
+        {
+            std::thread::sleep(std::time::Duration::from_millis(PONDER_WAIT_INTERVAL));
+        }
+
+        let remaining_time = if board::is_white(self.node.game_state.color) {
+            args.white_time
         } else {
-            // If no best move could be found, checkmate is unavoidable; send the first legal move.
-            self.log("Checkmate is unavoidable.".to_string());
-            let moves = rules::get_player_moves(&self.node.board, &self.node.game_state, true);
-            let m = if moves.len() > 0 { Some(moves[0]) } else { None };
-            self.report_best_move(m);
+            args.black_time
+        };
+        let elapsed = self.start_time.unwrap().elapsed().as_millis();
+        let stats = SearchStats {
+            nodes: self.num_nodes.load(atomic::Ordering::Relaxed),
+            tt_probes: self.tt_probes,
+            tt_hits: self.tt_hits,
+            out_of_time: remaining_time != -1 && elapsed > remaining_time.max(0) as u128,
+        };
+
+        match result {
+            Some((max_score, pv, interrupted, sel_depth)) => {
+                self.log(format!("Best move {} evaluated {}", notation::move_to_string(&pv[0]), max_score));
+                SearchResult {
+                    best_move: Some(pv[0]),
+                    ponder_move: pv.get(1).copied(),
+                    score: Some(RelativeScore::from_raw(max_score)),
+                    mate: mate_distance(max_score),
+                    bound: if interrupted { tt::Bound::Lower } else { tt::Bound::Exact },
+                    pv,
+                    depth: completed_depth,
+                    sel_depth,
+                    stats: Some(stats),
+                }
+            }
+            None => {
+                // If no best move could be found, checkmate is unavoidable; play the first legal move.
+                self.log("Checkmate is unavoidable.".to_string());
+                let moves = rules::get_player_moves(&self.node.board, &self.node.game_state, true);
+                SearchResult {
+                    best_move: moves.first().copied(),
+                    ponder_move: None,
+                    score: None,
+                    mate: None,
+                    bound: tt::Bound::Exact,
+                    pv: vec!(),
+                    depth: completed_depth,
+                    sel_depth: 0,
+                    stats: Some(stats),
+                }
+            }
         }
     }
 
     /// Set search limits.
+    ///
+    /// Inconsistent clock parameters are reported to the user.
     fn set_limits(&mut self, args: &AnalysisParams) {
-        self.max_depth = 4;
-        self.time_limit = if args.move_time != -1 {
-            args.move_time
-        } else {
-            let (time, inc) = if board::is_white(self.node.game_state.color) {
-                (args.white_time, args.white_inc)
-            } else {
-                (args.black_time, args.black_inc)
-            };
-            // If more than 2 minutes is left, use a 1m time limit.
-            if time > 2*60*1000 {
-                60*1000
-            }
-            // Else use 1/4 of the remaining time (plus the increment).
-            else if time > 0 {
-                (time / 4) + inc
-            }
-            // Or if there is not remaining time, do not use a time limit.
-            else {
-                i32::MAX
-            }
-        };
+        let (soft_time_limit, hard_time_limit, warnings) =
+            time::get_time_limits(args, self.node.game_state.color);
+        self.soft_time_limit = soft_time_limit;
+        self.hard_time_limit = hard_time_limit;
+        self.last_depth = search_depth(args, self.depth_limit);
+        for warning in warnings {
+            self.report_info(vec![AnalysisInfo::String(warning)]);
+        }
+    }
+
+    /// Only search the root moves of `moves`, or all if it is empty.
+    ///
+    /// Illegal moves are reported to the user and ignored.
+    fn set_search_moves(&mut self, moves: &[Move]) {
+        let legal_moves = self.node.get_player_moves(true);
+        let (legal, illegal): (Vec<Move>, Vec<Move>) = moves.iter().partition(|m| legal_moves.contains(m));
+        if !illegal.is_empty() {
+            let warning = format!("ignoring illegal searchmoves {}", notation::move_list_to_string(&illegal));
+            self.report_info(vec![AnalysisInfo::String(warning)]);
+        }
+        self.search_moves = legal;
     }
 
     /// Return best score and associated move for this node.
     ///
-    /// `depth` is the current search depth. `alpha` and `beta` are
-    /// used for alpha-beta search tree pruning, where `alpha` is the
-    /// lower score bound and `beta` the upper bound.
+    /// `key` is the Zobrist hash of the node, `pst` its piece-square
+    /// score and `depth` is the current search depth. `alpha` and `beta` are used for alpha-beta search
+    /// tree pruning, where `alpha` is the lower score bound and `beta`
+    /// the upper bound.
+    ///
+    /// Results deep enough in the transposition table are used instead
+    /// of searching again, except at the root; else the stored best move
+    /// is searched first.
+    ///
+    /// The search is a fail-soft principal variation search: the first
+    /// move is searched with the full window, others with a null window
+    /// to prove they are not better, and searched again if they are. If
+    /// a move improves `alpha`, the principal variation of this depth
+    /// is updated with it. Late quiet moves are first searched with
+    /// a reduced depth, see the `reductions` module.
+    ///
+    /// Close to the horizon, late quiet moves that rarely caused cutoffs
+    /// are not searched at all, unless they give check or are killers or
+    /// countermoves: this history pruning is stricter when the static
+    /// evaluation did not improve since the last move of the player.
     fn negamax(
         &mut self,
         node: &Node,
+        key: u64,
+        pst: PstScore,
         alpha: f32,
         beta: f32,
         depth: u32,
     ) -> (f32, Option<Move>) {
         // Increment number of nodes for stats.
-        self.num_nodes += 1;
-        self.num_nodes_in_second += 1;
+        self.count_node();
+        self.sel_depth = self.sel_depth.max(depth);
 
-        // If we should stop searching, evaluate the node and stop.
-        if self.should_stop_search(depth) {
-            let stats = node.compute_stats();
-            let ev = evaluate(&stats);
-            return (ev, None)
+        let ply = depth as usize;
+        if self.pv.len() <= ply + 1 {
+            self.pv.resize(ply + 2, vec!());
+            self.static_evals.resize(ply + 2, None);
+        }
+        self.pv[ply].clear();
+        self.static_evals[ply] = None;
+
+        // If we should stop searching, evaluate the node and stop. When
+        // max depth is reached, captures are searched before evaluating
+        // to not stop in the middle of an exchange.
+        let remaining_depth = self.max_depth - depth - self.reduction;
+        if self.should_stop_search(remaining_depth) {
+            if self.interrupted {
+                return (self.evaluate(node, pst), None)
+            }
+            return (self.quiesce(node, key, pst, alpha, beta, depth), None)
         }
 
         // Here's a good time to get some stats!
-        if self.current_per_second_timer.unwrap().elapsed().as_millis() >= 1000 {
-            self.report_info(vec![
-                AnalysisInfo::Nodes(self.num_nodes),
-                AnalysisInfo::Nps(self.num_nodes_in_second),
-            ]);
+        let elapsed = self.current_per_second_timer.unwrap().elapsed().as_millis();
+        if elapsed >= self.info_interval as u128 {
+            let num_nodes = self.num_nodes.load(atomic::Ordering::Relaxed);
+            let nodes_in_second = num_nodes - self.num_nodes_at_report;
+            let nps = nodes_in_second as u128 * 1000 / elapsed.max(1);
+            self.report_info(self.progress_infos(nps as u64));
+            self.num_nodes_at_report = num_nodes;
             self.num_nodes_in_second = 0;
             self.current_per_second_timer = Some(Instant::now());
         }
+        if self.max_nps > 0 {
+            let expected = self.num_nodes_in_second * 1000 / self.max_nps as u64;
+            let elapsed = self.current_per_second_timer.unwrap().elapsed().as_millis() as u64;
+            if expected > elapsed {
+                std::thread::sleep(std::time::Duration::from_millis(expected - elapsed));
+            }
+        }
+
+        let entry = self.tt.as_ref().and_then(|tt| tt.probe(key));
+        if self.tt.is_some() {
+            self.tt_probes += 1;
+            self.tt_hits += entry.is_some() as u64;
+        }
+        if let Some(entry) = entry.filter(|e| depth > 0 && e.depth >= remaining_depth) {
+            let score = score_from_tt(entry.score, depth);
+            let usable = match entry.bound {
+                tt::Bound::Exact => true,
+                tt::Bound::Lower => score >= beta,
+                tt::Bound::Upper => score <= alpha,
+            };
+            if usable {
+                return (score, entry.best_move)
+            }
+        }
 
         // Get negamax for playable moves.
-        let moves = node.get_player_moves(true);
+        let mut moves = self.take_move_buffer(ply);
+        node.get_player_moves_into(&mut moves);
+        let excluding = depth == 0 && !(self.excluded_root_moves.is_empty() && self.search_moves.is_empty());
+        if excluding {
+            let (excluded, search_moves) = (&self.excluded_root_moves, &self.search_moves);
+            moves.retain(|m| !excluded.contains(m) && (search_moves.is_empty() || search_moves.contains(m)));
+            if moves.is_empty() {
+                self.move_buffers[ply] = moves;
+                return (MIN_F32, None)
+            }
+        }
+        if moves.is_empty() {
+            let score = if let rules::Status::Checkmate(_) = rules::no_move_status(&node.board, &node.game_state) {
+                -MATE_SCORE + depth as f32
+            } else if node.game_state.color == self.node.game_state.color {
+                -self.contempt
+            } else {
+                self.contempt
+            };
+            self.move_buffers[ply] = moves;
+            return (score, None)
+        }
+        self.ordering.order(&node.board, &mut moves, ply, entry.and_then(|e| e.best_move));
+        let original_alpha = alpha;
         let mut alpha = alpha;
         let mut best_score = MIN_F32;
         let mut best_move = None;
-        for m in moves {
+        let in_check = rules::is_in_check(&node.board, &node.game_state);
+        let reduce = remaining_depth >= reductions::MIN_DEPTH && self.reductions.is_some() && !in_check;
+        // Evaluations two plies up tell if the player is improving.
+        let prune = self.history_pruning && depth > 0 && !in_check;
+        let max_pruning_depth = self.params.history_pruning_max_depth as u32;
+        if prune && remaining_depth <= max_pruning_depth + 2 {
+            self.static_evals[ply] = Some(self.evaluate(node, pst));
+        }
+        let prune = prune && remaining_depth <= max_pruning_depth;
+        let improving = match (self.static_evals[ply], ply.checked_sub(2).and_then(|p| self.static_evals[p])) {
+            (Some(eval), Some(previous_eval)) => eval > previous_eval,
+            _ => true,
+        };
+        let history_threshold = self.params.history_pruning_threshold as u32 * remaining_depth * remaining_depth
+            / (1 + improving as u32);
+        let min_pruning_index = self.params.history_pruning_min_move_index as usize;
+        let previous = self.line.last().copied();
+        let static_eval = self.static_evals[ply].unwrap_or(MAX_F32);
+        for (i, &m) in moves.iter().enumerate() {
+            let quiet = m.2.is_none() && !move_ordering::is_capture(&node.board, &m);
+            // Moves without history are not known to be bad, so keep
+            // searching until a move holds the static evaluation.
+            let prunable = prune
+                && quiet
+                && i >= min_pruning_index
+                && best_score >= static_eval
+                && self.ordering.history(&m) < history_threshold
+                && !self.ordering.is_refutation(&m, ply, previous);
+            let sub_key = zobrist::hash_after(key, &node.board, &node.game_state, &m);
+            if let Some(tt) = self.tt.as_ref() {
+                tt.prefetch(sub_key);
+            }
             let mut sub_node = node.clone();
-            sub_node.apply_move(&m);
-            let result = self.negamax(&sub_node, -beta, -alpha, depth + 1);
-            let score = -result.0;
+            let dirty = sub_node.apply_move(&m);
+            if prunable && !rules::is_in_check(&sub_node.board, &sub_node.game_state) {
+                continue
+            }
+            let sub_pst = pst::update_score(pst, &dirty);
+            self.line.push(m);
+            let score = if i == 0 {
+                -self.negamax(&sub_node, sub_key, sub_pst, -beta, -alpha, depth + 1).0
+            } else {
+                // Late quiet moves are searched at a reduced depth, then
+                // at full depth if they may be better.
+                let late_reduction = match &self.reductions {
+                    Some(table) if reduce && quiet => {
+                        let r = table.get(remaining_depth, i);
+                        if r > 0 && rules::is_in_check(&sub_node.board, &sub_node.game_state) { 0 } else { r }
+                    }
+                    _ => 0,
+                };
+                let null_alpha = -alpha - NULL_WINDOW;
+                self.reduction += late_reduction;
+                let mut score = -self.negamax(&sub_node, sub_key, sub_pst, null_alpha, -alpha, depth + 1).0;
+                self.reduction -= late_reduction;
+                if late_reduction > 0 && score > alpha {
+                    score = -self.negamax(&sub_node, sub_key, sub_pst, null_alpha, -alpha, depth + 1).0;
+                }
+                if score > alpha && score < beta {
+                    -self.negamax(&sub_node, sub_key, sub_pst, -beta, -alpha, depth + 1).0
+                } else {
+                    score
+                }
+            };
+            self.line.pop();
             if score > best_score {
                 best_score = score;
                 best_move = Some(m);
             }
             if best_score > alpha {
                 alpha = best_score;
+                let (pv, sub_pv) = self.pv.split_at_mut(ply + 1);
+                pv[ply].clear();
+                pv[ply].push(m);
+                pv[ply].extend_from_slice(&sub_pv[0]);
             }
             if alpha >= beta {
+                self.ordering.record_cutoff(&node.board, &m, ply, remaining_depth, previous);
                 break
             }
         }
+        self.move_buffers[ply] = moves;
+        // Results of an interrupted iteration are not reliable, and the
+        // root without some moves is not the actual root.
+        if let (Some(tt), false) = (self.tt.as_ref(), self.interrupted || excluding) {
+            let entry = tt::TtEntry {
+                depth: remaining_depth,
+                score: score_to_tt(best_score, depth),
+                bound: bound(best_score, original_alpha, beta),
+                best_move,
+            };
+            tt.store(key, &entry);
+        }
         (best_score, best_move)
     }
 
+    /// Return the score of `node` after searching captures only.
+    ///
+    /// The player to move can stand pat, i.e. keep the static
+    /// evaluation instead of capturing. `depth` is the depth of the
+    /// node in the whole search; past `MAX_QUIESCENCE_DEPTH` plies of
+    /// quiescence search, the static evaluation is returned. `key` is
+    /// the Zobrist hash of the node and `pst` its piece-square score.
+    ///
+    /// Results at the horizon are stored in the transposition table at
+    /// depth 0, so they are used by other quiescence searches but never
+    /// instead of a search of the main tree, and they do not replace its
+    /// entries. Results within the quiescence search are not stored as
+    /// they are cut short by `MAX_QUIESCENCE_DEPTH`.
+    fn quiesce(&mut self, node: &Node, key: u64, pst: PstScore, alpha: f32, beta: f32, depth: u32) -> f32 {
+        if self.q_depth > 0 {
+            self.count_node();
+            self.sel_depth = self.sel_depth.max(depth);
+        }
+        let entry = self.tt.as_ref().and_then(|tt| tt.probe(key));
+        if self.tt.is_some() {
+            self.tt_probes += 1;
+            self.tt_hits += entry.is_some() as u64;
+        }
+        if let Some(entry) = entry {
+            let score = score_from_tt(entry.score, depth);
+            let usable = match entry.bound {
+                tt::Bound::Exact => true,
+                tt::Bound::Lower => score >= beta,
+                tt::Bound::Upper => score <= alpha,
+            };
+            if usable {
+                return score
+            }
+        }
+        let ply = depth as usize;
+        let mut moves = self.take_move_buffer(ply);
+        node.get_captures_into(&mut moves);
+        if moves.is_empty() {
+            // Without captures, look for any move to tell mates and stalemates.
+            node.get_player_moves_into(&mut moves);
+            let no_move = moves.is_empty();
+            moves.clear();
+            if no_move {
+                self.move_buffers[ply] = moves;
+                let score = if let rules::Status::Checkmate(_) = rules::no_move_status(&node.board, &node.game_state) {
+                    -MATE_SCORE + depth as f32
+                } else if node.game_state.color == self.node.game_state.color {
+                    -self.contempt
+                } else {
+                    self.contempt
+                };
+                self.store_quiescence(key, score, alpha, beta, depth, None);
+                return score
+            }
+        }
+        let stand_pat = self.evaluate(node, pst);
+        if stand_pat >= beta || self.q_depth >= MAX_QUIESCENCE_DEPTH {
+            self.store_quiescence(key, stand_pat, alpha, beta, depth, None);
+            self.move_buffers[ply] = moves;
+            return stand_pat
+        }
+        // Captures come in MVV-LVA order, only the stored move goes first.
+        if let Some(i) = moves.iter().position(|m| Some(*m) == entry.and_then(|e| e.best_move)) {
+            moves[..=i].rotate_right(1);
+        }
+        let original_alpha = alpha;
+        let mut alpha = alpha.max(stand_pat);
+        let delta_pruning = non_pawn_material(&node.board) >= DELTA_PRUNING_MIN_MATERIAL;
+        let mut best_score = stand_pat;
+        let mut best_move = None;
+        let mut pruned = false;
+        for &m in moves.iter() {
+            // Promotions gain more than the captured piece, never prune them.
+            if delta_pruning && m.2.is_none() {
+                let gain = move_ordering::piece_value(board::get_square(&node.board, &m.1)).max(1);
+                if stand_pat + score::cp_to_raw(gain as i32 * 100 + self.params.delta_margin_cp) < alpha {
+                    pruned = true;
+                    continue
+                }
+            }
+            let mut sub_node = node.clone();
+            let dirty = sub_node.apply_move(&m);
+            let sub_key = zobrist::update_hash(
+                key, &node.board, &node.game_state, &sub_node.board, &sub_node.game_state, &m
+            );
+            let sub_pst = pst::update_score(pst, &dirty);
+            self.q_depth += 1;
+            let score = -self.quiesce(&sub_node, sub_key, sub_pst, -beta, -alpha, depth + 1);
+            self.q_depth -= 1;
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+            }
+            if best_score > alpha {
+                alpha = best_score;
+            }
+            if alpha >= beta {
+                break
+            }
+        }
+        self.move_buffers[ply] = moves;
+        // Pruned captures may score up to alpha, so do not store less.
+        let stored_score = if pruned { best_score.max(alpha) } else { best_score };
+        self.store_quiescence(key, stored_score, original_alpha, beta, depth, best_move);
+        best_score
+    }
+
+    /// Store the quiescence `score` of the node with hash `key` found at
+    /// `depth` in window (`alpha`, `beta`), if at the horizon, see
+    /// `quiesce`.
+    fn store_quiescence(&self, key: u64, score: f32, alpha: f32, beta: f32, depth: u32, best_move: Option<Move>) {
+        if let (Some(tt), 0) = (self.tt.as_ref(), self.q_depth) {
+            let bound = bound(score, alpha, beta);
+            tt.store_shallow(key, &tt::TtEntry { depth: 0, score: score_to_tt(score, depth), bound, best_move });
+        }
+    }
+
+    /// Return the static evaluation of `node` for the player to move,
+    /// `pst` being its piece-square score.
+    fn evaluate(&mut self, node: &Node, pst: PstScore) -> f32 {
+        let caches = self.eval_caches.get_or_insert_with(EvalCaches::default);
+        let pawns = caches.pawns.get(&node.board);
+        let imbalance = caches.imbalances.get(&node.board);
+        compute_features(node, pst, &pawns, &imbalance).weigh(&self.weights).total()
+    }
+
+    /// Take the move buffer of `ply`, to put back once done with it.
+    fn take_move_buffer(&mut self, ply: usize) -> Vec<Move> {
+        if self.move_buffers.len() <= ply {
+            self.move_buffers.resize_with(ply + 1, Vec::new);
+        }
+        std::mem::take(&mut self.move_buffers[ply])
+    }
+
     /// Return true if some parameter requires to stop searching.
     ///
-    /// Check for max node depth, time limit and engine stop flag.
-    fn should_stop_search(&self, depth: u32) -> bool {
-        !self.working.as_ref().unwrap().load(atomic::Ordering::Relaxed)
-        || depth == self.max_depth
-        || self.start_time.unwrap().elapsed().as_millis() >= self.time_limit as u128
+    /// Check for max node depth, time limits and engine stop flag. If
+    /// the search has to stop before max depth, the iteration is marked
+    /// as interrupted.
+    fn should_stop_search(&mut self, remaining_depth: u32) -> bool {
+        if remaining_depth == 0 {
+            return true
+        }
+        let time_limit = if self.max_depth <= MIN_DEPTH || self.depth_limit > 0 {
+            self.hard_time_limit
+        } else {
+            self.soft_time_limit
+        };
+        if
+            !self.working.as_ref().unwrap().load(atomic::Ordering::Relaxed)
+            || (!self.is_pondering() && self.start_time.unwrap().elapsed().as_millis() >= time_limit as u128)
+        {
+            self.interrupted = true;
+            return true
+        }
+        false
+    }
+
+    /// Return true if pondering, else start the clock when pondering
+    /// just stopped.
+    fn is_pondering(&mut self) -> bool {
+        match &self.pondering {
+            Some(flag) if flag.load(atomic::Ordering::Relaxed) => true,
+            Some(_) => {
+                self.pondering = None;
+                self.start_time = Some(Instant::now());
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Return the depth of the last iteration of a search with `args`,
+/// never deeper than `depth_limit` unless it is 0.
+///
+/// Without a requested depth, searches deepen up to `MAX_SEARCH_DEPTH`
+/// until their time limits stop them.
+pub fn search_depth(args: &AnalysisParams, depth_limit: u32) -> u32 {
+    let depth = match Some(args.depth).filter(|d| *d > 0) {
+        Some(depth) => depth as u32,
+        None if depth_limit > 0 => depth_limit,
+        None => MAX_SEARCH_DEPTH,
+    };
+    let depth = if depth_limit > 0 { depth.min(depth_limit) } else { depth };
+    depth.min(MAX_SEARCH_DEPTH)
+}
+
+/// Return the material of both sides on `board` in pawns, without
+/// pawns and kings.
+fn non_pawn_material(board: &board::Board) -> u32 {
+    board::get_piece_iterator(board)
+        .filter(|(s, _)| !board::is_type(*s, board::SQ_P) && !board::is_type(*s, board::SQ_K))
+        .map(|(s, _)| move_ordering::piece_value(s))
+        .sum()
+}
+
+/// Return the number of moves to mate of a search `score`, negative if
+/// the player to move gets mated, or None if it is not a mate score.
+fn mate_distance(score: f32) -> Option<i32> {
+    if score >= MATE_THRESHOLD {
+        let plies = (MATE_SCORE - score).round() as i32;
+        Some((plies + 1) / 2)
+    } else if score <= -MATE_THRESHOLD {
+        let plies = (MATE_SCORE + score).round() as i32;
+        Some(-((plies + 1) / 2))
+    } else {
+        None
+    }
+}
+
+/// Return the number of moves to mate of a normalized `score`, e.g. from
+/// the analysis cache, as in `SearchResult::mate`.
+pub fn mate_in(score: RelativeScore) -> Option<i32> {
+    mate_distance(score::cp_to_raw(score.0))
+}
+
+/// Return the info reporting a search `score`, in moves to mate if it
+/// is a mate score, else in centipawns.
+fn score_info(score: f32) -> AnalysisInfo {
+    match mate_distance(score) {
+        Some(moves) => AnalysisInfo::Mate(moves),
+        None => AnalysisInfo::Score(RelativeScore::from_raw(score)),
+    }
+}
+
+/// Return the bound of a fail-soft search `score` in window
+/// (`alpha`, `beta`), for the transposition table.
+fn bound(score: f32, alpha: f32, beta: f32) -> tt::Bound {
+    if score <= alpha {
+        tt::Bound::Upper
+    } else if score >= beta {
+        tt::Bound::Lower
+    } else {
+        tt::Bound::Exact
+    }
+}
+
+/// Return `score` found at `depth` as stored in the transposition table.
+///
+/// Mate scores depend on the distance to the root, so they are stored
+/// as distance to the node instead.
+fn score_to_tt(score: f32, depth: u32) -> f32 {
+    if score >= MATE_THRESHOLD {
+        score + depth as f32
+    } else if score <= -MATE_THRESHOLD {
+        score - depth as f32
+    } else {
+        score
+    }
+}
+
+/// Return the score of a transposition table entry found at `depth`,
+/// see `score_to_tt`.
+fn score_from_tt(score: f32, depth: u32) -> f32 {
+    if score >= MATE_THRESHOLD {
+        score - depth as f32
+    } else if score <= -MATE_THRESHOLD {
+        score + depth as f32
+    } else {
+        score
+    }
+}
+
+/// Weights of the evaluation terms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalWeights {
+    pub king: f32,
+    pub queen: f32,
+    pub rook: f32,
+    pub bishop: f32,
+    pub knight: f32,
+    pub pawn: f32,
+    /// Penalty for each doubled, isolated or backward pawn.
+    pub weak_pawn: f32,
+    /// Bonus for each passed pawn.
+    pub passed_pawn: f32,
+    /// Bonus for each candidate passed pawn.
+    pub candidate_pawn: f32,
+    /// Bonus for each pawn protected by another pawn.
+    pub pawn_chain: f32,
+    /// Bonus for each safe square attacked by a piece, see `eval::activity`.
+    pub mobility: f32,
+    /// Bonus for each rook on a file without pawns.
+    pub rook_open_file: f32,
+    /// Bonus for each rook on a file with only enemy pawns.
+    pub rook_semi_open_file: f32,
+    /// Bonus for each knight on an outpost.
+    pub knight_outpost: f32,
+    /// Factor of piece-square tables bonuses, see `eval::pst`.
+    pub pst: f32,
+    /// Factor of material imbalance bonuses, see `eval::imbalance`.
+    pub imbalance: f32,
+}
+
+/// Weights proposed by Shannon in his 1949 paper called "Programming a
+/// Computer for Playing Chess", as they are quite simple yet provide
+/// good enough results, though mobility counts safe squares attacked
+/// instead of legal moves. Piece-square tables, pawn structure terms
+/// other than weak pawns, piece activity and material imbalance terms
+/// come on top of them.
+pub const SHANNON_WEIGHTS: EvalWeights = EvalWeights {
+    king: 200.0,
+    queen: 9.0,
+    rook: 5.0,
+    bishop: 3.0,
+    knight: 3.0,
+    pawn: 1.0,
+    weak_pawn: 0.5,
+    passed_pawn: 0.3,
+    candidate_pawn: 0.1,
+    pawn_chain: 0.05,
+    mobility: 0.1,
+    rook_open_file: 0.2,
+    rook_semi_open_file: 0.1,
+    knight_outpost: 0.2,
+    pst: 1.0,
+    imbalance: 1.0,
+};
+
+impl EvalWeights {
+    /// Parse weights, one "name value" pair per line.
+    ///
+    /// Names are those of the struct fields; missing weights keep the
+    /// value of `SHANNON_WEIGHTS`. Empty lines and lines starting with
+    /// "#" are ignored.
+    pub fn parse(s: &str) -> Result<EvalWeights, String> {
+        let mut weights = SHANNON_WEIGHTS;
+        for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, value) = match fields.as_slice() {
+                [name, value] => match value.parse::<f32>() {
+                    Ok(value) => (*name, value),
+                    Err(_) => return Err(format!("Bad weight value: {}", line)),
+                },
+                _ => return Err(format!("Bad weight line: {}", line)),
+            };
+            let weight = match name {
+                "king" => &mut weights.king,
+                "queen" => &mut weights.queen,
+                "rook" => &mut weights.rook,
+                "bishop" => &mut weights.bishop,
+                "knight" => &mut weights.knight,
+                "pawn" => &mut weights.pawn,
+                "weak_pawn" => &mut weights.weak_pawn,
+                "passed_pawn" => &mut weights.passed_pawn,
+                "candidate_pawn" => &mut weights.candidate_pawn,
+                "pawn_chain" => &mut weights.pawn_chain,
+                "mobility" => &mut weights.mobility,
+                "rook_open_file" => &mut weights.rook_open_file,
+                "rook_semi_open_file" => &mut weights.rook_semi_open_file,
+                "knight_outpost" => &mut weights.knight_outpost,
+                "pst" => &mut weights.pst,
+                "imbalance" => &mut weights.imbalance,
+                _ => return Err(format!("Unknown weight: {}", name)),
+            };
+            *weight = value;
+        }
+        Ok(weights)
+    }
+}
+
+/// Weighted terms of a static evaluation, for the player to move.
+///
+/// The evaluation is their sum; see `evaluation_terms` to check what
+/// drives it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalTerms {
+    pub material: f32,
+    pub weak_pawns: f32,
+    pub passed_pawns: f32,
+    pub candidate_pawns: f32,
+    pub pawn_chains: f32,
+    pub mobility: f32,
+    pub rook_files: f32,
+    pub outposts: f32,
+    pub pst: f32,
+    pub imbalance: f32,
+    pub mate_bonus: f32,
+}
+
+impl EvalTerms {
+    /// Return the evaluation, sum of all terms.
+    pub fn total(&self) -> f32 {
+        self.material + self.weak_pawns + self.passed_pawns + self.candidate_pawns
+        + self.pawn_chains + self.mobility + self.rook_files + self.outposts + self.pst
+        + self.imbalance + self.mate_bonus
+    }
+}
+
+impl fmt::Display for EvalTerms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "material {:.2}, weak pawns {:.2}, passed pawns {:.2}, candidate pawns {:.2}, \
+             pawn chains {:.2}, mobility {:.2}, rook files {:.2}, outposts {:.2}, pst {:.2}, \
+             imbalance {:.2}, mate bonus {:.2}, total {:.2}",
+            self.material, self.weak_pawns, self.passed_pawns, self.candidate_pawns,
+            self.pawn_chains, self.mobility, self.rook_files, self.outposts, self.pst,
+            self.imbalance, self.mate_bonus, self.total()
+        )
     }
 }
 
-/// Compute a score for white/black board stats.
+/// Names of the evaluation features, in the order of
+/// `EvalFeatures::values`: those of the weights applied to them, then
+/// the mate bonus, which is not weighted.
+pub const FEATURE_NAMES: [&str; 17] = [
+    "king", "queen", "rook", "bishop", "knight", "pawn", "weak_pawn", "passed_pawn",
+    "candidate_pawn", "pawn_chain", "mobility", "rook_open_file", "rook_semi_open_file",
+    "knight_outpost", "pst", "imbalance", "mate_bonus",
+];
+
+/// Evaluation terms before weighting, for the player to move: counts of
+/// pieces and features of the player minus those of the opponent, and
+/// piece-square and imbalance bonuses as raw scores.
 ///
-/// This uses the formula proposed by Shannon in his 1949 paper called
-/// "Programming a Computer for Playing Chess", as it is quite simple
-/// yet provide good enough results.
-fn evaluate(stats: &(stats::BoardStats, stats::BoardStats)) -> f32 {
-    let (player_stats, opponent_stats) = stats;
-
-    200.0 * (player_stats.num_kings - opponent_stats.num_kings) as f32
-    + 9.0 * (player_stats.num_queens - opponent_stats.num_queens) as f32
-    + 5.0 * (player_stats.num_rooks - opponent_stats.num_rooks) as f32
-    + 3.0 * (player_stats.num_bishops - opponent_stats.num_bishops) as f32
-    + 3.0 * (player_stats.num_knights - opponent_stats.num_knights) as f32
-    + (player_stats.num_pawns - opponent_stats.num_pawns) as f32
-    - 0.5 * (
-        player_stats.num_doubled_pawns - opponent_stats.num_doubled_pawns +
-        player_stats.num_isolated_pawns - opponent_stats.num_isolated_pawns +
-        player_stats.num_backward_pawns - opponent_stats.num_backward_pawns
-    ) as f32
-    + 0.1 * (player_stats.mobility - opponent_stats.mobility) as f32
+/// The evaluation is the sum of features multiplied by their weight,
+/// see `weigh`, which makes them usable to fit weights.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalFeatures {
+    pub kings: i32,
+    pub queens: i32,
+    pub rooks: i32,
+    pub bishops: i32,
+    pub knights: i32,
+    pub pawns: i32,
+    pub weak_pawns: i32,
+    pub passed_pawns: i32,
+    pub candidate_pawns: i32,
+    pub pawn_chains: i32,
+    pub mobility: i32,
+    pub rooks_on_open_files: i32,
+    pub rooks_on_semi_open_files: i32,
+    pub outposts: i32,
+    pub pst: f32,
+    pub imbalance: f32,
+    pub mate_bonus: f32,
+}
+
+impl EvalFeatures {
+    /// Return the features in the order of `FEATURE_NAMES`.
+    pub fn values(&self) -> [f32; 17] {
+        [
+            self.kings as f32, self.queens as f32, self.rooks as f32, self.bishops as f32,
+            self.knights as f32, self.pawns as f32, self.weak_pawns as f32, self.passed_pawns as f32,
+            self.candidate_pawns as f32, self.pawn_chains as f32, self.mobility as f32,
+            self.rooks_on_open_files as f32, self.rooks_on_semi_open_files as f32,
+            self.outposts as f32, self.pst, self.imbalance, self.mate_bonus,
+        ]
+    }
+
+    /// Return the evaluation terms with `weights`.
+    pub fn weigh(&self, weights: &EvalWeights) -> EvalTerms {
+        EvalTerms {
+            material: weights.king * self.kings as f32 + weights.queen * self.queens as f32
+                + weights.rook * self.rooks as f32 + weights.bishop * self.bishops as f32
+                + weights.knight * self.knights as f32 + weights.pawn * self.pawns as f32,
+            weak_pawns: weights.weak_pawn * self.weak_pawns as f32,
+            passed_pawns: weights.passed_pawn * self.passed_pawns as f32,
+            candidate_pawns: weights.candidate_pawn * self.candidate_pawns as f32,
+            pawn_chains: weights.pawn_chain * self.pawn_chains as f32,
+            mobility: weights.mobility * self.mobility as f32,
+            rook_files: weights.rook_open_file * self.rooks_on_open_files as f32
+                + weights.rook_semi_open_file * self.rooks_on_semi_open_files as f32,
+            outposts: weights.knight_outpost * self.outposts as f32,
+            pst: weights.pst * self.pst,
+            imbalance: weights.imbalance * self.imbalance,
+            mate_bonus: self.mate_bonus,
+        }
+    }
+}
+
+/// Return the static evaluation of `node` for the player to move,
+/// as used at the leaves of the search.
+pub fn evaluate_node(node: &Node, weights: &EvalWeights) -> f32 {
+    evaluation_terms(node, weights).total()
+}
+
+/// Return the terms of the static evaluation of `node`.
+pub fn evaluation_terms(node: &Node, weights: &EvalWeights) -> EvalTerms {
+    evaluation_features(node).weigh(weights)
+}
+
+/// Return the features of the static evaluation of `node`.
+pub fn evaluation_features(node: &Node) -> EvalFeatures {
+    let (white_pawns, black_pawns) = pawns::pawn_bitboards(&node.board);
+    let pawns = PawnStructure::new(white_pawns, black_pawns);
+    let imbalance = Imbalance::new(&Material::new(&node.board));
+    compute_features(node, pst::score(&node.board), &pawns, &imbalance)
+}
+
+/// Compute evaluation features of `node` with `pst`, its piece-square
+/// score, `pawns`, its pawn structure, and `imbalance`, its material
+/// imbalance, already known, e.g. updated along the search or from
+/// tables.
+fn compute_features(node: &Node, pst: PstScore, pawns: &PawnStructure, imbalance: &Imbalance) -> EvalFeatures {
+    let color = node.game_state.color;
+    let (player_pawns, opponent_pawns) = (pawns.of(color), pawns.of(board::opposite(color)));
+    let activity = Activity::new(&node.board);
+    let (player_activity, opponent_activity) = (activity.of(color), activity.of(board::opposite(color)));
+    let pst_cp = pst.tapered(phase::phase(&node.board)).relative_to(color);
+
+    let mut features = EvalFeatures {
+        weak_pawns: (opponent_pawns.weak() - player_pawns.weak()) as i32,
+        passed_pawns: (player_pawns.passed - opponent_pawns.passed) as i32,
+        candidate_pawns: (player_pawns.candidates - opponent_pawns.candidates) as i32,
+        pawn_chains: (player_pawns.protected - opponent_pawns.protected) as i32,
+        mobility: player_activity.mobility - opponent_activity.mobility,
+        rooks_on_open_files: (player_activity.rooks_on_open_files - opponent_activity.rooks_on_open_files) as i32,
+        rooks_on_semi_open_files: (player_activity.rooks_on_semi_open_files
+            - opponent_activity.rooks_on_semi_open_files) as i32,
+        outposts: (player_activity.knight_outposts - opponent_activity.knight_outposts) as i32,
+        pst: score::cp_to_raw(pst_cp.0),
+        imbalance: score::cp_to_raw(imbalance.of(color) - imbalance.of(board::opposite(color))),
+        mate_bonus: endgame::get_mate_bonus(&node.board, color).unwrap_or(0.0),
+        ..Default::default()
+    };
+    for (piece, _) in board::get_piece_iterator(&node.board) {
+        let count = match board::get_type(piece) {
+            board::SQ_P => &mut features.pawns,
+            board::SQ_B => &mut features.bishops,
+            board::SQ_N => &mut features.knights,
+            board::SQ_R => &mut features.rooks,
+            board::SQ_Q => &mut features.queens,
+            _ => &mut features.kings,
+        };
+        *count += if board::is_color(piece, node.game_state.color) { 1 } else { -1 };
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_analyzers() {
+        let fens = [
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "4k3/8/8/8/8/8/3q4/R3K3 w - - 0 1",
+        ];
+        let handles: Vec<_> = fens.iter().map(|fen| {
+            let (board, game_state) = notation::position(fen);
+            let node = Node { board, game_state };
+            thread::spawn(move || {
+                let (tx, _rx) = mpsc::channel();
+                let mut analyzer = Analyzer::new(node, tx);
+                let args = AnalysisParams { move_time: 100, ..Default::default() };
+                analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true))).best_move
+            })
+        }).collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_min_depth() {
+        // With a tiny soft limit, MIN_DEPTH is searched nonetheless, then
+        // the next iteration is interrupted. The speed limit makes each
+        // iteration last longer than the soft limit, whatever the build.
+        let (tx, rx) = mpsc::channel();
+        let (board, game_state) = notation::position("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+        analyzer.set_max_nps(10_000);
+        let args = AnalysisParams { white_time: 60_000, moves_to_go: 100_000, ..Default::default() };
+        let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+        assert_eq!(analyzer.soft_time_limit, time::MIN_TIME_LIMIT);
+        let depths: Vec<u32> = rx.try_iter().filter_map(|cmd| match cmd {
+            engine::Cmd::WorkerInfo(infos) => infos.iter().find_map(|i| match i {
+                AnalysisInfo::Depth(d) => Some(*d),
+                _ => None,
+            }),
+            _ => None,
+        }).collect();
+        assert_eq!(depths, (1..=MIN_DEPTH).collect::<Vec<_>>());
+        assert_eq!(analyzer.max_depth, MIN_DEPTH + 1);
+        assert!(analyzer.interrupted);
+        assert_eq!((result.depth, result.bound), (MIN_DEPTH, tt::Bound::Exact));
+    }
+
+    #[test]
+    fn test_eval_weights() {
+        assert_eq!(EvalWeights::parse(""), Ok(SHANNON_WEIGHTS));
+        let weights = EvalWeights::parse("# Stronger knights.\nknight 3.5\n\nmobility 0").unwrap();
+        assert_eq!(weights.knight, 3.5);
+        assert_eq!(weights.mobility, 0.0);
+        assert_eq!(weights.queen, SHANNON_WEIGHTS.queen);
+        assert!(EvalWeights::parse("knight").is_err());
+        assert!(EvalWeights::parse("knight heavy").is_err());
+        assert!(EvalWeights::parse("archbishop 7").is_err());
+        // Without mobility, the starting position is balanced.
+        let mut node = Node::new();
+        node.board = board::new();
+        assert_eq!(evaluate_node(&node, &weights), 0.0);
+    }
+
+    #[test]
+    fn test_evaluation_terms() {
+        // White has a passed e-pawn protecting a candidate d-pawn.
+        let (board, game_state) = notation::position("4k3/8/2p5/3P4/4P3/8/8/4K3 b - - 0 1");
+        let node = Node { board, game_state };
+        let terms = evaluation_terms(&node, &SHANNON_WEIGHTS);
+        assert_eq!(terms.material, -1.0);
+        assert_eq!(terms.passed_pawns, -SHANNON_WEIGHTS.passed_pawn);
+        assert_eq!(terms.candidate_pawns, -SHANNON_WEIGHTS.candidate_pawn);
+        assert_eq!(terms.pawn_chains, -SHANNON_WEIGHTS.pawn_chain);
+        assert_eq!(terms.total(), evaluate_node(&node, &SHANNON_WEIGHTS));
+        // The search gets pawn structures from its table.
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        let pst = pst::score(&node.board);
+        assert_eq!(analyzer.evaluate(&node, pst), terms.total());
+        assert_eq!(analyzer.evaluate(&node, pst), terms.total());
+    }
+
+    #[test]
+    fn test_negamax_terminal_nodes() {
+        let analyze_with_contempt = |fen: &str, contempt: i32| {
+            let mut node = Node::new();
+            let (board, game_state) = notation::position(fen);
+            node.board = board;
+            node.game_state = game_state;
+            let (tx, _rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(node.clone(), tx);
+            analyzer.working = Some(Arc::new(atomic::AtomicBool::new(true)));
+            analyzer.max_depth = 2;
+            analyzer.set_contempt(contempt);
+            analyzer.soft_time_limit = i32::MAX;
+            analyzer.hard_time_limit = i32::MAX;
+            analyzer.start_time = Some(Instant::now());
+            analyzer.current_per_second_timer = Some(Instant::now());
+            let key = zobrist::hash(&node.board, &node.game_state);
+            analyzer.negamax(&node, key, pst::score(&node.board), MIN_F32, MAX_F32, 0)
+        };
+        let analyze = |fen: &str| analyze_with_contempt(fen, 0);
+        // Checkmated and stalemated.
+        assert_eq!(analyze("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1"), (-MATE_SCORE, None));
+        assert_eq!(analyze("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), (0.0, None));
+        // With contempt, a draw is worse than equality for the root player.
+        assert_eq!(analyze_with_contempt("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 20), (-0.2, None));
+        // Mate in one is found and scored by its distance.
+        let (score, m) = analyze("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert_eq!(score, MATE_SCORE - 1.0);
+        assert_eq!(m, Some((board::pos("a1"), board::pos("a8"), None)));
+    }
+
+    #[test]
+    fn test_mate_distance() {
+        assert_eq!(mate_distance(0.0), None);
+        assert_eq!(mate_distance(MATE_THRESHOLD - 1.0), None);
+        // Mating on the first or third ply is a mate in 1 or 2 moves,
+        // getting mated on the second ply a mate in -1.
+        assert_eq!(mate_distance(MATE_SCORE - 1.0), Some(1));
+        assert_eq!(mate_distance(MATE_SCORE - 3.0), Some(2));
+        assert_eq!(mate_distance(-MATE_SCORE + 2.0), Some(-1));
+        assert_eq!(mate_distance(-MATE_SCORE), Some(0));
+        assert!(matches!(score_info(score::cp_to_raw(100)), AnalysisInfo::Score(RelativeScore(100))));
+        // Normalized mate scores keep their distance.
+        assert_eq!(mate_in(RelativeScore::from_raw(MATE_SCORE - 3.0)), Some(2));
+        assert_eq!(mate_in(RelativeScore::from_raw(-MATE_SCORE + 4.0)), Some(-2));
+        assert_eq!(mate_in(RelativeScore(500)), None);
+    }
+
+    #[test]
+    fn test_principal_variation() {
+        let mut node = Node::new();
+        let (board, game_state) = notation::position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        node.board = board;
+        node.game_state = game_state;
+        let (tx, rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        analyzer.set_transposition_table(Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB)));
+        let result = analyzer.analyze(&AnalysisParams { depth: 4, ..Default::default() }, Arc::new(atomic::AtomicBool::new(true)));
+        let infos: Vec<Vec<AnalysisInfo>> = rx.try_iter().filter_map(|cmd| match cmd {
+            engine::Cmd::WorkerInfo(infos) => Some(infos),
+            _ => None,
+        }).collect();
+        let pvs: Vec<Vec<Move>> = infos.iter().filter_map(|infos| infos.iter().find_map(|i| match i {
+            AnalysisInfo::Pv(pv) => Some(pv.clone()),
+            _ => None,
+        })).collect();
+        // Iterations report the mate and search progress.
+        let last_infos = infos.last().unwrap();
+        assert!(last_infos.iter().any(|i| matches!(i, AnalysisInfo::Mate(1))));
+        assert!(!last_infos.iter().any(|i| matches!(i, AnalysisInfo::Score(_))));
+        assert!(last_infos.iter().any(|i| matches!(i, AnalysisInfo::HashFull(_))));
+        assert!(last_infos.iter().any(|i| matches!(i, AnalysisInfo::Time(_))));
+        assert!(matches!(last_infos.last(), Some(AnalysisInfo::Pv(_))));
+        assert_eq!(result.mate, Some(1));
+        assert_eq!(pvs.len(), 4);
+        // Each line is playable from the root, and the last one mates.
+        for pv in &pvs {
+            let mut sub_node = node.clone();
+            for m in pv {
+                assert!(sub_node.get_player_moves(true).contains(m));
+                sub_node.apply_move(m);
+            }
+        }
+        assert_eq!(pvs.last().unwrap(), &vec!((board::pos("a1"), board::pos("a8"), None)));
+        assert_eq!(&result.pv, pvs.last().unwrap());
+        assert_eq!(result.ponder_move, None);
+    }
+
+    #[test]
+    fn test_search_result() {
+        let (board, game_state) = notation::position("4k3/8/8/8/8/8/3q4/R3K3 w - - 0 1");
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+        let result = analyzer.analyze(&AnalysisParams { depth: 4, ..Default::default() }, Arc::new(atomic::AtomicBool::new(true)));
+        // White has to take the queen, which black can't take back.
+        assert_eq!(result.best_move, Some((board::pos("e1"), board::pos("d2"), None)));
+        assert_eq!(result.best_move, result.pv.first().copied());
+        assert!(result.ponder_move.is_some());
+        assert_eq!(result.ponder_move, result.pv.get(1).copied());
+        assert_eq!(result.bound, tt::Bound::Exact);
+        assert!(result.score.unwrap() > RelativeScore(0));
+        assert_eq!(result.depth, 4);
+        assert!(result.sel_depth >= result.depth);
+        assert!(result.stats.unwrap().nodes > 0);
+
+        // With a transposition table filled by a deeper search, cutoffs
+        // do not make the reported seldepth lower than the depth.
+        let (tx, rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(Node { board: board::new(), game_state: rules::GameState::new() }, tx);
+        analyzer.set_transposition_table(Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB)));
+        let args = |depth| AnalysisParams { depth, ..Default::default() };
+        analyzer.analyze(&args(4), Arc::new(atomic::AtomicBool::new(true)));
+        let result = analyzer.analyze(&args(2), Arc::new(atomic::AtomicBool::new(true)));
+        assert!(result.sel_depth >= 2);
+        for cmd in rx.try_iter() {
+            if let engine::Cmd::WorkerInfo(infos) = cmd {
+                if let [AnalysisInfo::Depth(depth), AnalysisInfo::SelDepth(sel_depth), ..] = infos[..] {
+                    assert!(sel_depth >= depth);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_depth() {
+        let args = |depth| AnalysisParams { depth, ..Default::default() };
+        assert_eq!(search_depth(&args(-1), 0), MAX_SEARCH_DEPTH);
+        assert_eq!(search_depth(&args(0), 0), MAX_SEARCH_DEPTH);
+        assert_eq!(search_depth(&args(2), 0), 2);
+        assert_eq!(search_depth(&args(7), 0), 7);
+        assert_eq!(search_depth(&args(1000), 0), MAX_SEARCH_DEPTH);
+        // The user limit applies to requested depths as well.
+        assert_eq!(search_depth(&args(-1), 6), 6);
+        assert_eq!(search_depth(&args(2), 6), 2);
+        assert_eq!(search_depth(&args(7), 6), 6);
+
+        // Requested depths are searched to the end.
+        let (board, game_state) = notation::position("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+        let result = analyzer.analyze(&args(5), Arc::new(atomic::AtomicBool::new(true)));
+        assert_eq!(result.depth, 5);
+        // The soft time limit is ignored with a depth limit, unlike in
+        // test_min_depth.
+        let (tx, _rx) = mpsc::channel();
+        let mut node = Node::new();
+        node.board = board::new();
+        let mut analyzer = Analyzer::new(node, tx);
+        analyzer.set_depth_limit(MIN_DEPTH + 1);
+        let args = AnalysisParams { white_time: 60_000, moves_to_go: 100_000, ..Default::default() };
+        let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+        assert_eq!(result.depth, MIN_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_multi_pv() {
+        // Search up to `depth` with `lines` lines, returning the result
+        // and the line index, score and first move of the lines of the
+        // last depth.
+        let analyze = |fen: &str, depth: i32, lines: usize| {
+            let (board, game_state) = notation::position(fen);
+            let (tx, rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+            analyzer.set_transposition_table(Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB)));
+            analyzer.set_multi_pv(lines);
+            let args = AnalysisParams { depth, ..Default::default() };
+            let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+            let reports: Vec<(u32, AnalysisInfo, Move)> = rx.try_iter().filter_map(|cmd| match cmd {
+                engine::Cmd::WorkerInfo(infos) => {
+                    let line = infos.iter().find_map(|i| match i { AnalysisInfo::MultiPv(n) => Some(*n), _ => None });
+                    let score = infos.iter().find(|i| matches!(i, AnalysisInfo::Score(_) | AnalysisInfo::Mate(_)));
+                    let pv = infos.iter().find_map(|i| match i { AnalysisInfo::Pv(pv) => Some(pv[0]), _ => None });
+                    Some((line?, score?.clone(), pv?))
+                }
+                _ => None,
+            }).collect();
+            let last_depth = reports.iter().rposition(|r| r.0 == 1).unwrap();
+            (result, reports[last_depth..].to_vec())
+        };
+
+        // Each legal move gets its own line, up to the number of lines.
+        let (_, lines) = analyze(notation::FEN_START, 1, 30);
+        let mut first_moves: Vec<Move> = lines.iter().map(|l| l.2).collect();
+        assert_eq!(lines.iter().map(|l| l.0).collect::<Vec<_>>(), (1..=20).collect::<Vec<_>>());
+        first_moves.sort();
+        first_moves.dedup();
+        assert_eq!(first_moves.len(), 20);
+
+        // Lines are sorted from the mate, which is the result.
+        let (result, lines) = analyze("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 2, 3);
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(lines[0].1, AnalysisInfo::Mate(1)));
+        assert_eq!(lines[0].2, (board::pos("a1"), board::pos("a8"), None));
+        let scores: Vec<i32> = lines[1..].iter().map(|l| match l.1 {
+            AnalysisInfo::Score(cp) => cp.0,
+            _ => panic!(),
+        }).collect();
+        assert!(scores[0] >= scores[1]);
+        assert_eq!(result.mate, Some(1));
+        assert_eq!(result.best_move, Some(lines[0].2));
+    }
+
+    #[test]
+    fn test_search_moves() {
+        let (board, game_state) = notation::position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let (tx, rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+        analyzer.set_transposition_table(Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB)));
+        // The mate is not searched, and illegal moves are ignored.
+        let moves = ["a1a2", "g1g2", "a1h8"].iter().map(|m| notation::parse_move(m).unwrap()).collect();
+        let args = AnalysisParams { search_moves: moves, depth: 4, ..Default::default() };
+        let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+        assert!(args.search_moves[..2].contains(&result.best_move.unwrap()));
+        assert_eq!(result.mate, None);
+        assert!(rx.try_iter().any(|cmd| matches!(
+            cmd,
+            engine::Cmd::WorkerInfo(infos) if matches!(&infos[..], [AnalysisInfo::String(s)] if s.contains("a1h8"))
+        )));
+        // Without restriction, the mate is found again.
+        let result = analyzer.analyze(&AnalysisParams { depth: 4, ..Default::default() }, Arc::new(atomic::AtomicBool::new(true)));
+        assert_eq!(result.mate, Some(1));
+    }
+
+    #[test]
+    fn test_helper() {
+        // Helpers report nothing but count nodes with the main analyzer.
+        let num_nodes = Arc::new(AtomicU64::new(0));
+        let working = Arc::new(atomic::AtomicBool::new(true));
+        let args = AnalysisParams { depth: 3, ..Default::default() };
+        let (tx, rx) = mpsc::channel();
+        let mut node = Node::new();
+        node.board = board::new();
+        let mut helper = Analyzer::new(node.clone(), tx);
+        helper.set_helper(1);
+        helper.set_node_counter(num_nodes.clone());
+        let helper_result = helper.analyze(&args, working.clone());
+        assert!(rx.try_recv().is_err());
+        let helper_nodes = num_nodes.load(atomic::Ordering::Relaxed);
+        assert_eq!(helper_result.stats.unwrap().nodes, helper_nodes);
+        let (tx, rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node, tx);
+        analyzer.set_node_counter(num_nodes);
+        let result = analyzer.analyze(&args, working);
+        assert!(rx.try_recv().is_ok());
+        assert!(result.stats.unwrap().nodes > helper_nodes);
+    }
+
+    #[test]
+    fn test_late_move_reductions() {
+        let (board, game_state) = notation::position("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+        let search = |enabled| {
+            let (tx, _rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(Node { board, game_state: game_state.clone() }, tx);
+            analyzer.set_late_move_reductions(enabled);
+            let args = AnalysisParams { depth: 4, ..Default::default() };
+            analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)))
+        };
+        // Reduced searches still reach the requested depth, with fewer nodes.
+        let (reduced, full) = (search(true), search(false));
+        assert_eq!(reduced.depth, 4);
+        assert_eq!(full.depth, 4);
+        assert!(reduced.stats.unwrap().nodes < full.stats.unwrap().nodes);
+    }
+
+    #[test]
+    fn test_history_pruning() {
+        let (board, game_state) = notation::position("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+        let search = |enabled| {
+            let (tx, _rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(Node { board, game_state: game_state.clone() }, tx);
+            analyzer.set_history_pruning(enabled);
+            let args = AnalysisParams { depth: 5, ..Default::default() };
+            let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+            assert!(analyzer.line.is_empty());
+            result
+        };
+        // Pruned searches still reach the requested depth, with fewer nodes.
+        let (pruned, full) = (search(true), search(false));
+        assert_eq!(pruned.depth, 5);
+        assert!(pruned.best_move.is_some());
+        assert!(pruned.stats.unwrap().nodes < full.stats.unwrap().nodes);
+
+        // After 1. Rxd5 Ke7, the first rook moves generated hang the
+        // rook; other moves must not be pruned before one keeps it, even
+        // with a transposition table filled by previous searches.
+        let tt = Arc::new(TranspositionTable::new(1));
+        let search = |fen: &str, depth| {
+            let (board, game_state) = notation::position(fen);
+            let (tx, _rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(Node { board, game_state }, tx);
+            analyzer.set_transposition_table(tt.clone());
+            let args = AnalysisParams { depth, ..Default::default() };
+            analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)))
+        };
+        search("4k3/8/8/q7/8/8/3R4/4K3 b - - 0 1", 3);
+        search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 1 2", 3);
+        search("4k3/8/8/3R4/8/8/8/4K3 b - - 0 2", 3);
+        let result = search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 1 2", 5);
+        assert!(result.score.unwrap().0 > 400);
+    }
+
+    #[test]
+    fn test_quiescence() {
+        let mut node = Node::new();
+        let (board, game_state) = notation::position("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1");
+        node.board = board;
+        node.game_state = game_state;
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        analyzer.working = Some(Arc::new(atomic::AtomicBool::new(true)));
+        analyzer.max_depth = 1;
+        analyzer.soft_time_limit = i32::MAX;
+        analyzer.hard_time_limit = i32::MAX;
+        analyzer.start_time = Some(Instant::now());
+        analyzer.current_per_second_timer = Some(Instant::now());
+        // Taking the d5 pawn looks good at depth 1, but it is defended.
+        let key = zobrist::hash(&node.board, &node.game_state);
+        let (_, m) = analyzer.negamax(&node, key, pst::score(&node.board), MIN_F32, MAX_F32, 0);
+        assert_ne!(m, Some((board::pos("d1"), board::pos("d5"), None)));
+        assert!(analyzer.sel_depth > analyzer.max_depth);
+        // The player to move can stand pat rather than capture.
+        let pst = pst::score(&node.board);
+        assert_eq!(analyzer.quiesce(&node, 0, pst, MIN_F32, MAX_F32, 0), evaluate_node(&node, &SHANNON_WEIGHTS));
+    }
+
+    #[test]
+    fn test_pondering() {
+        let (tx, _rx) = mpsc::channel();
+        let mut node = Node::new();
+        node.board = board::new();
+        let mut analyzer = Analyzer::new(node, tx);
+        let pondering = Arc::new(atomic::AtomicBool::new(true));
+        analyzer.set_pondering(pondering.clone());
+        let handle = std::thread::spawn(move || {
+            let args = AnalysisParams { move_time: 10, ..Default::default() };
+            analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)))
+        });
+        // The time limit does not apply until the ponder hit.
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!handle.is_finished());
+        pondering.store(false, atomic::Ordering::Relaxed);
+        assert!(handle.join().unwrap().best_move.is_some());
+    }
+
+    #[test]
+    fn test_infinite() {
+        // The result of an infinite search waits for the stop flag.
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(Node { board: board::new(), game_state: rules::GameState::new() }, tx);
+        let working = Arc::new(atomic::AtomicBool::new(true));
+        let stop = working.clone();
+        let handle = std::thread::spawn(move || {
+            let args = AnalysisParams { depth: 1, infinite: true, ..Default::default() };
+            analyzer.analyze(&args, working)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!handle.is_finished());
+        stop.store(false, atomic::Ordering::Relaxed);
+        assert_eq!(handle.join().unwrap().depth, 1);
+    }
+
+    #[test]
+    fn test_max_nps() {
+        let mut node = Node::new();
+        let (board, game_state) = notation::position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        node.board = board;
+        node.game_state = game_state;
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node, tx);
+        analyzer.set_max_nps(200);
+        let args = AnalysisParams { move_time: 500, ..Default::default() };
+        let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+        assert!(result.stats.unwrap().nodes <= 300);
+    }
+
+    #[test]
+    fn test_delta_pruning() {
+        let quiesce_nodes = |fen: &str, alpha_margin: f32| {
+            let mut node = Node::new();
+            let (board, game_state) = notation::position(fen);
+            node.board = board;
+            node.game_state = game_state;
+            let (tx, _rx) = mpsc::channel();
+            let mut analyzer = Analyzer::new(node.clone(), tx);
+            let alpha = evaluate_node(&node, &SHANNON_WEIGHTS) + alpha_margin;
+            analyzer.quiesce(&node, 0, pst::score(&node.board), alpha, MAX_F32, 0);
+            analyzer.num_nodes.load(atomic::Ordering::Relaxed)
+        };
+        // Taking the a7 pawn can't make up for the missing queen.
+        let fen = "r2qk2r/p4ppp/8/8/8/8/5PPP/R3K2R w - - 0 1";
+        assert_eq!(quiesce_nodes(fen, 5.0), 0);
+        assert!(quiesce_nodes(fen, 1.0) > 0);
+        // There is not enough material left to prune safely.
+        assert!(quiesce_nodes("4k3/p7/8/8/8/8/8/R3K3 w - - 0 1", 5.0) > 0);
+    }
+
+    #[test]
+    fn test_quiescence_transposition_table() {
+        // White can take the queen, then black the rook.
+        let mut node = Node::new();
+        let (board, game_state) = notation::position("4k3/8/2n5/3q4/8/8/8/3RK3 w - - 0 1");
+        node.board = board;
+        node.game_state = game_state;
+        let key = zobrist::hash(&node.board, &node.game_state);
+        let pst = pst::score(&node.board);
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        let score = analyzer.quiesce(&node, key, pst, MIN_F32, MAX_F32, 0);
+        let tt = Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB));
+        analyzer.set_transposition_table(tt.clone());
+        assert_eq!(analyzer.quiesce(&node, key, pst, MIN_F32, MAX_F32, 0), score);
+        let entry = tt.probe(key).unwrap();
+        assert_eq!((entry.depth, entry.bound), (0, tt::Bound::Exact));
+        assert_eq!(entry.best_move, Some((board::pos("d1"), board::pos("d5"), None)));
+        // The stored score is used without searching again.
+        let num_nodes = analyzer.num_nodes.load(atomic::Ordering::Relaxed);
+        assert_eq!(analyzer.quiesce(&node, key, pst, MIN_F32, MAX_F32, 0), score);
+        assert_eq!(analyzer.num_nodes.load(atomic::Ordering::Relaxed), num_nodes);
+    }
+
+    #[test]
+    fn test_transposition_table() {
+        let mut node = Node::new();
+        let (board, game_state) = notation::position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        node.board = board;
+        node.game_state = game_state;
+        let key = zobrist::hash(&node.board, &node.game_state);
+        let (tx, _rx) = mpsc::channel();
+        let tt = Arc::new(TranspositionTable::new(tt::MIN_SIZE_MB));
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        analyzer.set_transposition_table(tt.clone());
+        analyzer.analyze(&AnalysisParams { depth: 4, ..Default::default() }, Arc::new(atomic::AtomicBool::new(true)));
+        // The root result is stored, with the mate score relative to it.
+        let entry = tt.probe(key).unwrap();
+        assert_eq!(entry.depth, 4);
+        assert_eq!(entry.score, MATE_SCORE - 1.0);
+        assert_eq!(entry.best_move, Some((board::pos("a1"), board::pos("a8"), None)));
+        // Mate scores are adjusted to the distance from the root.
+        assert_eq!(score_from_tt(score_to_tt(MATE_SCORE - 3.0, 2), 1), MATE_SCORE - 2.0);
+        assert_eq!(score_from_tt(score_to_tt(-MATE_SCORE + 3.0, 2), 1), -MATE_SCORE + 2.0);
+        assert_eq!(score_from_tt(score_to_tt(4.5, 2), 1), 4.5);
+    }
 }
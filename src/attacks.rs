@@ -0,0 +1,400 @@
+//! Sliding piece attacks with magic bitboards.
+//!
+//! A bitboard is a u64 where bit n is set for square n, numbered as in
+//! `board::pos_square` (a1 = 0, h8 = 63). The squares attacked by a
+//! rook or a bishop only depend on the pieces on its lines, so they are
+//! precomputed for every occupancy of these lines. A "magic" multiplier
+//! maps the occupancy bits of a square lines to an index in its attack
+//! table, without collisions between different attack sets.
+//!
+//! Magics have been found by trying random sparse numbers until one
+//! had no collisions. Tables are built when first used, see `init`.
+//! Knight and king attacks do not depend on occupancy and are simply
+//! computed at compile time.
+//!
+//! `AttackMaps` gathers the squares attacked by each side, so that
+//! evaluation terms can share them instead of computing attacks again.
+
+use std::sync::OnceLock;
+
+use crate::board::*;
+
+pub type Bitboard = u64;
+
+pub const ROOK_DIRS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+pub const BISHOP_DIRS: [(i8, i8); 4] = [(1, -1), (1, 1), (-1, 1), (-1, -1)];
+pub const KNIGHT_OFFSETS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+pub const KING_OFFSETS: [(i8, i8); 8] = [(-1, 1), (0, 1), (1, 1), (-1, 0), (1, 0), (-1, -1), (0, -1), (1, -1)];
+
+pub const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+const FILE_H: Bitboard = FILE_A << 7;
+
+const KNIGHT_ATTACKS: [Bitboard; 64] = leaper_attacks(&KNIGHT_OFFSETS);
+const KING_ATTACKS: [Bitboard; 64] = leaper_attacks(&KING_OFFSETS);
+
+/// Rook magics by square.
+const ROOK_MAGICS: [u64; 64] = [
+    0x4080_0040_0050_2688, 0x04c0_2000_1001_4005, 0x4080_1000_8008_2000, 0x0280_0801_5000_8004,
+    0x0600_2024_0810_2200, 0x4200_0200_0190_0844, 0x0300_1200_0420_c100, 0x1100_0201_8040_7300,
+    0x0481_0020_8000_4100, 0x0401_0040_0081_0028, 0xe081_0020_0011_0840, 0x0009_0010_0120_0901,
+    0x6305_0050_0801_0044, 0x2000_8080_0400_0200, 0x0001_0002_0004_0100, 0x2802_0004_0040_8122,
+    0x0080_0140_2000_4002, 0x4840_0040_2000_5002, 0x02c0_8280_1000_2008, 0x0080_1200_2008_4200,
+    0x2149_0100_0408_0110, 0x0802_0080_8002_0400, 0x4000_0400_0201_5028, 0x4404_0200_0081_0044,
+    0x0090_8030_8000_4004, 0x0040_2105_0040_0480, 0x2200_2001_0010_4100, 0x1001_0025_0008_1000,
+    0x0060_0400_8080_0800, 0x0002_0002_0008_0410, 0x0110_1004_0002_0108, 0x1004_0122_0000_9044,
+    0x0000_8040_0480_0020, 0x8080_8040_0080_2000, 0x1028_4100_1100_2001, 0x0040_8010_0080_0802,
+    0x0242_0104_0a00_1020, 0xa60a_0004_0200_1008, 0x0100_0801_0400_c210, 0x8000_1080_4600_2411,
+    0x0a20_9040_0028_8000, 0x0080_2010_0040_4000, 0x4cc0_1100_2001_0040, 0x0020_2010_4202_0008,
+    0x0016_0005_0812_0020, 0x0000_0440_2008_0110, 0x0200_0200_0401_0100, 0x0040_0040_9102_0004,
+    0x0200_4201_0080_2200, 0x0000_4000_2010_0140, 0x9008_8120_0810_0180, 0x0024_2040_5200_ca00,
+    0x00c1_0010_0408_0100, 0x0007_0042_2400_1900, 0x0001_0014_0a00_0500, 0x0204_0400_4081_0200,
+    0x2450_1100_2040_8003, 0x0100_4000_1080_2101, 0x00a0_0022_1100_0a41, 0x2061_7884_1000_2101,
+    0x0002_0084_0820_9002, 0x010a_0004_0190_0802, 0x0002_0001_0448_0082, 0x0104_0418_2142_8102,
+];
+
+/// Bishop magics by square.
+const BISHOP_MAGICS: [u64; 64] = [
+    0x1020_0404_2040_4600, 0x0010_a102_0404_4090, 0x1188_00a4_0880_8000, 0x8044_0420_8262_0080,
+    0x0242_0210_0888_0000, 0x8002_0804_0400_0800, 0x8504_0082_8820_9000, 0x4100_1048_0210_1000,
+    0x28c8_1020_0800_9081, 0x0110_0801_0904_0b00, 0x00c0_1040_c200_4003, 0x250a_088a_0200_0024,
+    0x0004_4202_100a_0005, 0x080c_0101_0840_0841, 0x0004_1c01_4848_0420, 0x0000_1044_1084_1083,
+    0x4a50_0140_0302_4400, 0x1002_2a04_1002_0220, 0x0848_0004_2444_0088, 0x2308_0044_2040_1128,
+    0xc512_8004_00e0_0320, 0x0008_0802_8209_2000, 0x0220_8489_0801_1000, 0x0010_2000_8401_0802,
+    0x2c31_0441_0820_0411, 0x0081_1008_2084_2118, 0x0044_0848_1000_8020, 0xc204_0020_0412_0080,
+    0x8010_1010_8100_4001, 0x2000_8081_1300_6001, 0x001c_0860_0401_1400, 0x8004_b220_8202_020a,
+    0x0090_0210_0263_1404, 0xa084_5024_0002_0400, 0x0011_8020_8014_0801, 0xa000_4808_0002_0a00,
+    0x0240_0302_0001_0084, 0x3000_8822_0000_4100, 0x8001_c404_0440_8200, 0x0241_2204_8808_2c00,
+    0x0102_0210_0401_c200, 0x0085_0088_2100_0400, 0x4202_8400_4808_2100, 0x0004_2102_8080_2800,
+    0x8000_020a_0c00_0200, 0x0004_1042_4840_1600, 0x0008_4801_1404_0040, 0x1232_0410_420a_0080,
+    0x0041_080a_2220_1020, 0x0002_2084_04e0_0400, 0x0000_0244_0411_0001, 0x2026_0001_2088_4000,
+    0x0001_20d0_0202_2420, 0x828a_0d18_5059_0500, 0x1048_0881_8404_0001, 0x0034_1094_0300_2808,
+    0x220f_0900_80a0_0808, 0x2318_0080_8410_0200, 0x0404_1029_0045_5010, 0x2000_4000_0022_8804,
+    0x0690_4008_4082_8201, 0x0820_0288_9004_0828, 0x0814_1020_0101_1200, 0x0083_3208_0104_0080,
+];
+
+/// Magic lookup data of a square.
+#[derive(Clone, Copy, Default)]
+struct Magic {
+    /// Squares that can block the piece: its lines without the edges.
+    mask: Bitboard,
+    magic: u64,
+    /// Shift keeping the index bits of the product.
+    shift: u32,
+    /// Start of the square attack table in `Tables::attacks`.
+    offset: usize,
+}
+
+impl Magic {
+    #[inline]
+    fn index(&self, occupancy: Bitboard) -> usize {
+        self.offset + ((occupancy & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct Tables {
+    rook: [Magic; 64],
+    bishop: [Magic; 64],
+    /// Attack tables of all squares, for rooks and bishops.
+    attacks: Vec<Bitboard>,
+}
+
+static TABLES: OnceLock<Tables> = OnceLock::new();
+
+/// Build attack tables if they are not built yet.
+///
+/// Tables are built on first use anyway; call this beforehand to not
+/// slow down the first search.
+pub fn init() {
+    tables();
+}
+
+/// Return the memory used by attack tables in bytes, building them if
+/// needed.
+pub fn tables_size() -> usize {
+    let tables = tables();
+    std::mem::size_of::<Tables>() + tables.attacks.len() * std::mem::size_of::<Bitboard>()
+}
+
+#[inline]
+fn tables() -> &'static Tables {
+    TABLES.get_or_init(build_tables)
+}
+
+/// Return squares attacked by a rook on `sq` with pieces on `occupancy`.
+///
+/// Occupied squares are attacked, whatever the color of their piece.
+#[inline]
+pub fn rook_attacks(sq: usize, occupancy: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.attacks[tables.rook[sq].index(occupancy)]
+}
+
+/// Return squares attacked by a bishop on `sq`, see `rook_attacks`.
+#[inline]
+pub fn bishop_attacks(sq: usize, occupancy: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.attacks[tables.bishop[sq].index(occupancy)]
+}
+
+/// Return squares attacked by a queen on `sq`, see `rook_attacks`.
+#[inline]
+pub fn queen_attacks(sq: usize, occupancy: Bitboard) -> Bitboard {
+    rook_attacks(sq, occupancy) | bishop_attacks(sq, occupancy)
+}
+
+/// Return squares attacked by a knight on `sq`.
+#[inline]
+pub const fn knight_attacks(sq: usize) -> Bitboard { KNIGHT_ATTACKS[sq] }
+
+/// Return squares attacked by a king on `sq`.
+#[inline]
+pub const fn king_attacks(sq: usize) -> Bitboard { KING_ATTACKS[sq] }
+
+/// Return squares attacked by a pawn of `color` on `sq`.
+#[inline]
+pub const fn pawn_attacks(sq: usize, color: u8) -> Bitboard {
+    let bb: Bitboard = 1 << sq;
+    // Remove squares wrapping around to the other side of the board.
+    if is_white(color) {
+        (bb << 7 & !FILE_H) | (bb << 9 & !FILE_A)
+    } else {
+        (bb >> 9 & !FILE_H) | (bb >> 7 & !FILE_A)
+    }
+}
+
+/// Return attacks of a piece jumping by `offsets`, by square.
+const fn leaper_attacks(offsets: &[(i8, i8); 8]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (f, r) = square_pos(sq);
+        let mut i = 0;
+        while i < 8 {
+            let p = (f + offsets[i].0, r + offsets[i].1);
+            if p.0 >= POS_MIN && p.0 <= POS_MAX && p.1 >= POS_MIN && p.1 <= POS_MAX {
+                table[sq] |= 1 << pos_square(&p);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+/// Squares attacked by each color, by piece type.
+///
+/// A square is attacked even if occupied by a piece of the same color,
+/// i.e. defended. Pawns only attack diagonally.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttackMaps {
+    /// Attacks by color (white then black) and piece type index, see
+    /// `type_index`.
+    maps: [[Bitboard; 6]; 2],
+}
+
+impl AttackMaps {
+    /// Compute attack maps of both colors on `board`.
+    pub fn new(board: &Board) -> AttackMaps {
+        Self::from_occupancy(board, occupancy(board))
+    }
+
+    /// Compute attack maps of both colors on `board`, with sliding
+    /// pieces blocked by `occupancy` instead of the board pieces.
+    pub fn from_occupancy(board: &Board, occupancy: Bitboard) -> AttackMaps {
+        let mut maps = [[0; 6]; 2];
+        for (piece, p) in get_piece_iterator(board) {
+            let sq = pos_square(&p);
+            let attacked = match get_type(piece) {
+                SQ_P => pawn_attacks(sq, get_color(piece)),
+                SQ_B => bishop_attacks(sq, occupancy),
+                SQ_N => knight_attacks(sq),
+                SQ_R => rook_attacks(sq, occupancy),
+                SQ_Q => queen_attacks(sq, occupancy),
+                SQ_K => king_attacks(sq),
+                _ => continue,
+            };
+            maps[color_index(piece)][type_index(piece)] |= attacked;
+        }
+        AttackMaps { maps }
+    }
+
+    /// Return squares attacked by pieces of `color` and `piece_type`.
+    pub const fn by_type(&self, color: u8, piece_type: u8) -> Bitboard {
+        self.maps[color_index(color)][type_index(piece_type)]
+    }
+
+    /// Return squares attacked by any piece of `color`.
+    pub fn by_color(&self, color: u8) -> Bitboard {
+        self.maps[color_index(color)].iter().fold(0, |bb, map| bb | map)
+    }
+
+    /// Return true if `sq` is attacked by a piece of `color`.
+    pub fn is_attacked(&self, sq: usize, color: u8) -> bool {
+        self.by_color(color) & 1 << sq != 0
+    }
+}
+
+/// Return 0 for white and 1 for black pieces or color flags.
+const fn color_index(square: u8) -> usize { if is_white(square) { 0 } else { 1 } }
+
+/// Return the index of the piece type, from 0 for pawns to 5 for kings.
+const fn type_index(square: u8) -> usize { get_type(square).trailing_zeros() as usize }
+
+/// Return the bitboard of occupied squares of `board`.
+pub fn occupancy(board: &Board) -> Bitboard {
+    let mut bb: Bitboard = 0;
+    for (i, square) in board.iter().enumerate() {
+        if *square != SQ_E {
+            bb |= 1 << pos_square(&index_pos(i));
+        }
+    }
+    bb
+}
+
+/// Iterator over the squares of a bitboard, see `squares`.
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None
+        }
+        let sq = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(sq)
+    }
+}
+
+/// Return an iterator over the squares set in `bb`, from a1 to h8.
+pub const fn squares(bb: Bitboard) -> Squares { Squares(bb) }
+
+/// Return squares attacked by a piece on `sq` sliding in `dirs`, by
+/// walking each ray until an occupied square or the edge.
+///
+/// This is how attack tables are built; use `rook_attacks` and
+/// `bishop_attacks` instead.
+pub fn sliding_attacks(sq: usize, occupancy: Bitboard, dirs: &[(i8, i8)]) -> Bitboard {
+    let mut attacks: Bitboard = 0;
+    for dir in dirs {
+        for p in ray(&square_pos(sq), *dir) {
+            let bit: Bitboard = 1 << pos_square(&p);
+            attacks |= bit;
+            if occupancy & bit != 0 {
+                break
+            }
+        }
+    }
+    attacks
+}
+
+/// Return squares that can block a piece on `sq` sliding in `dirs`.
+///
+/// The last square of a ray is attacked whether it is occupied or not,
+/// so it is not part of the mask.
+fn blockers_mask(sq: usize, dirs: &[(i8, i8)]) -> Bitboard {
+    let mut mask: Bitboard = 0;
+    for dir in dirs {
+        let mut ray = ray(&square_pos(sq), *dir).peekable();
+        while let Some(p) = ray.next() {
+            if ray.peek().is_some() {
+                mask |= 1 << pos_square(&p);
+            }
+        }
+    }
+    mask
+}
+
+fn build_tables() -> Tables {
+    let mut attacks = vec!();
+    let mut rook = [Magic::default(); 64];
+    let mut bishop = [Magic::default(); 64];
+    for sq in 0..64 {
+        rook[sq] = fill_table(sq, &ROOK_DIRS, ROOK_MAGICS[sq], &mut attacks);
+        bishop[sq] = fill_table(sq, &BISHOP_DIRS, BISHOP_MAGICS[sq], &mut attacks);
+    }
+    Tables { rook, bishop, attacks }
+}
+
+/// Append to `attacks` the attack table of a piece on `sq` sliding in
+/// `dirs`, indexed with `magic`.
+fn fill_table(sq: usize, dirs: &[(i8, i8)], magic: u64, attacks: &mut Vec<Bitboard>) -> Magic {
+    let mask = blockers_mask(sq, dirs);
+    let bits = mask.count_ones();
+    let magic = Magic { mask, magic, shift: 64 - bits, offset: attacks.len() };
+    attacks.resize(attacks.len() + (1 << bits), 0);
+    // Enumerate all subsets of the mask with the Carry-Rippler trick.
+    let mut subset: Bitboard = 0;
+    loop {
+        let subset_attacks = sliding_attacks(sq, subset, dirs);
+        let slot = &mut attacks[magic.index(subset)];
+        // A piece always attacks at least a square, so 0 is a free slot.
+        assert!(*slot == 0 || *slot == subset_attacks, "Bad magic for square {}.", sq);
+        *slot = subset_attacks;
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            break
+        }
+    }
+    magic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+    use crate::zobrist::splitmix64;
+
+    #[test]
+    fn test_attacks() {
+        let (board, _) = notation::position("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let occupancy = occupancy(&board);
+        assert_eq!(occupancy.count_ones(), 32);
+        // Rook on a1 sees b1, c1, d1 and e1 (its own king), and a2.
+        let expected = ["b1", "c1", "d1", "e1", "a2"].iter()
+            .fold(0, |bb, s| bb | 1 << pos_square(&pos(s)));
+        assert_eq!(rook_attacks(pos_square(&pos("a1")), occupancy), expected);
+        // Compare to ray-walking on all squares with a few occupancies.
+        let mut state = 0;
+        for _ in 0..16 {
+            let occupancy = splitmix64(&mut state) & splitmix64(&mut state);
+            for sq in 0..64 {
+                assert_eq!(rook_attacks(sq, occupancy), sliding_attacks(sq, occupancy, &ROOK_DIRS));
+                assert_eq!(bishop_attacks(sq, occupancy), sliding_attacks(sq, occupancy, &BISHOP_DIRS));
+            }
+        }
+        assert_eq!(queen_attacks(0, 0).count_ones(), 21);
+    }
+
+    #[test]
+    fn test_leaper_attacks() {
+        let bb = |squares: &[&str]| squares.iter().fold(0, |bb, s| bb | 1 << pos_square(&pos(s)));
+        assert_eq!(knight_attacks(pos_square(&pos("a1"))), bb(&["b3", "c2"]));
+        assert_eq!(knight_attacks(pos_square(&pos("e4"))).count_ones(), 8);
+        assert_eq!(king_attacks(pos_square(&pos("h8"))), bb(&["g8", "g7", "h7"]));
+        assert_eq!(pawn_attacks(pos_square(&pos("a2")), SQ_WH), bb(&["b3"]));
+        assert_eq!(pawn_attacks(pos_square(&pos("h2")), SQ_WH), bb(&["g3"]));
+        assert_eq!(pawn_attacks(pos_square(&pos("e7")), SQ_BL), bb(&["d6", "f6"]));
+    }
+
+    #[test]
+    fn test_attack_maps() {
+        let bb = |squares: &[&str]| squares.iter().fold(0, |bb, s| bb | 1 << pos_square(&pos(s)));
+        let maps = AttackMaps::new(&new());
+        assert_eq!(maps.by_type(SQ_WH, SQ_N), bb(&["a3", "c3", "d2", "e2", "f3", "h3"]));
+        assert_eq!(maps.by_type(SQ_BL, SQ_P).count_ones(), 8);
+        assert_eq!(maps.by_type(SQ_WH, SQ_Q), bb(&["c1", "e1", "c2", "d2", "e2"]));
+        assert_eq!(maps.by_color(SQ_WH).count_ones(), 22);
+        assert!(maps.is_attacked(pos_square(&pos("f3")), SQ_WH));
+        assert!(!maps.is_attacked(pos_square(&pos("f3")), SQ_BL));
+        assert!(!maps.is_attacked(pos_square(&pos("a1")), SQ_WH));
+    }
+
+    #[test]
+    fn test_squares() {
+        let bb = 1 << 3 | 1 << 17 | 1 << 63;
+        assert_eq!(squares(bb).collect::<Vec<_>>(), vec!(3, 17, 63));
+        assert_eq!(squares(0).count(), 0);
+    }
+}
@@ -77,12 +77,58 @@ pub type Pos = (i8, i8);
 
 /// Check if a Pos component is in the [0, 7] range.
 #[inline]
-pub fn is_valid_pos_c(component: i8) -> bool { component >= 0 && component <= 7 }
+pub fn is_valid_pos_c(component: i8) -> bool { (POS_MIN..=POS_MAX).contains(&component) }
 
 /// Check if both `pos` components are valid.
 #[inline]
 pub fn is_valid_pos(pos: Pos) -> bool { is_valid_pos_c(pos.0) && is_valid_pos_c(pos.1) }
 
+/// Return the index in a Board of the square at `pos`.
+///
+/// Boards are stored file by file: a1, a2, ..., a8, b1, etc. Use these
+/// helpers instead of computing indexes by hand.
+#[inline]
+pub const fn pos_index(pos: &Pos) -> usize { (pos.0 * 8 + pos.1) as usize }
+
+/// Return the Pos of the square at index `i` in a Board.
+#[inline]
+pub const fn index_pos(i: usize) -> Pos { ((i / 8) as i8, (i % 8) as i8) }
+
+/// Return the conventional square number of `pos`.
+///
+/// Most external formats (Polyglot keys, NNUE features, bitboard
+/// constants) number squares rank by rank: a1 = 0, b1 = 1, ..., h8 = 63.
+/// This is not the Board layout, see `pos_index`.
+#[inline]
+pub const fn pos_square(pos: &Pos) -> usize { (pos.1 * 8 + pos.0) as usize }
+
+/// Return the Pos of the conventional square number `sq`, see `pos_square`.
+#[inline]
+pub const fn square_pos(sq: usize) -> Pos { ((sq % 8) as i8, (sq / 8) as i8) }
+
+/// Iterator over the positions in a direction, see `ray`.
+pub struct Ray {
+    pos: Pos,
+    dir: (i8, i8),
+}
+
+impl Iterator for Ray {
+    type Item = Pos;
+
+    fn next(&mut self) -> Option<Pos> {
+        let p = (self.pos.0 + self.dir.0, self.pos.1 + self.dir.1);
+        if !is_valid_pos(p) {
+            return None
+        }
+        self.pos = p;
+        Some(p)
+    }
+}
+
+/// Return an iterator over positions from `from` (excluded) going in
+/// direction `dir` (file and rank steps) until the edge of the board.
+pub const fn ray(from: &Pos, dir: (i8, i8)) -> Ray { Ray { pos: *from, dir } }
+
 /// Convert string coordinates to Pos.
 ///
 /// `s` has to be valid UTF8, or the very least ASCII because chars
@@ -93,6 +139,16 @@ pub const fn pos(s: &str) -> Pos {
     ((chars[0] - 0x61) as i8, (chars[1] - 0x31) as i8)
 }
 
+/// Parse string coordinates to Pos, or return None if they are invalid.
+pub fn parse_pos(s: &str) -> Option<Pos> {
+    let chars = s.as_bytes();
+    if chars.len() != 2 {
+        return None
+    }
+    let p = (chars[0].wrapping_sub(0x61) as i8, chars[1].wrapping_sub(0x31) as i8);
+    if is_valid_pos(p) { Some(p) } else { None }
+}
+
 /// Return string coordinates from Pos.
 pub fn pos_string(p: &Pos) -> String {
     let mut bytes = [0u8; 2];
@@ -128,30 +184,56 @@ pub const fn new_empty() -> Board {
 }
 
 /// Generate a board from a FEN placement string.
-pub fn new_from_fen(fen: &str) -> Board {
+///
+/// Parsing stops at the first whitespace, so a full FEN string can be
+/// used. Return None if the placement does not describe 8 ranks of
+/// 8 squares.
+pub fn new_from_fen(fen: &str) -> Option<Board> {
     let mut board = [SQ_E; 64];
     let mut f = 0;
     let mut r = 7;
     for c in fen.chars() {
-        match c {
-            'r' => { set_square(&mut board, &(f, r), SQ_BL_R); f += 1 }
-            'n' => { set_square(&mut board, &(f, r), SQ_BL_N); f += 1 }
-            'b' => { set_square(&mut board, &(f, r), SQ_BL_B); f += 1 }
-            'q' => { set_square(&mut board, &(f, r), SQ_BL_Q); f += 1 }
-            'k' => { set_square(&mut board, &(f, r), SQ_BL_K); f += 1 }
-            'p' => { set_square(&mut board, &(f, r), SQ_BL_P); f += 1 }
-            'R' => { set_square(&mut board, &(f, r), SQ_WH_R); f += 1 }
-            'N' => { set_square(&mut board, &(f, r), SQ_WH_N); f += 1 }
-            'B' => { set_square(&mut board, &(f, r), SQ_WH_B); f += 1 }
-            'Q' => { set_square(&mut board, &(f, r), SQ_WH_Q); f += 1 }
-            'K' => { set_square(&mut board, &(f, r), SQ_WH_K); f += 1 }
-            'P' => { set_square(&mut board, &(f, r), SQ_WH_P); f += 1 }
-            '/' => { f = 0; r -= 1; }
-            d if d.is_digit(10) => { f += d.to_digit(10).unwrap() as i8 }
-            _ => break,
+        let piece = match c {
+            'r' => SQ_BL_R,
+            'n' => SQ_BL_N,
+            'b' => SQ_BL_B,
+            'q' => SQ_BL_Q,
+            'k' => SQ_BL_K,
+            'p' => SQ_BL_P,
+            'R' => SQ_WH_R,
+            'N' => SQ_WH_N,
+            'B' => SQ_WH_B,
+            'Q' => SQ_WH_Q,
+            'K' => SQ_WH_K,
+            'P' => SQ_WH_P,
+            '/' => {
+                if f != 8 || r == 0 {
+                    return None
+                }
+                f = 0;
+                r -= 1;
+                continue
+            }
+            d if ('1'..='8').contains(&d) => {
+                f += d.to_digit(10).unwrap() as i8;
+                if f > 8 {
+                    return None
+                }
+                continue
+            }
+            c if c.is_whitespace() => break,
+            _ => return None,
+        };
+        if f > POS_MAX {
+            return None
         }
+        set_square(&mut board, &(f, r), piece);
+        f += 1;
     }
-    board
+    if f != 8 || r != 0 {
+        return None
+    }
+    Some(board)
 }
 
 /// Return true of both boards are equal.
@@ -162,13 +244,13 @@ pub fn eq(b1: &Board, b2: &Board) -> bool {
 /// Get value of the square at this position.
 #[inline]
 pub const fn get_square(board: &Board, coords: &Pos) -> u8 {
-    board[(coords.0 * 8 + coords.1) as usize]
+    board[pos_index(coords)]
 }
 
 /// Set a new value for the square at this position.
 #[inline]
 pub fn set_square(board: &mut Board, coords: &Pos, piece: u8) {
-    board[(coords.0 * 8 + coords.1) as usize] = piece;
+    board[pos_index(coords)] = piece;
 }
 
 /// Set the square empty at this position.
@@ -180,8 +262,8 @@ pub fn clear_square(board: &mut Board, coords: &Pos) {
 /// Move a piece from a position to another, clearing initial square.
 #[inline]
 pub fn move_piece(board: &mut Board, from: &Pos, to: &Pos) {
-    set_square(board, &to, get_square(board, &from));
-    clear_square(board, &from);
+    set_square(board, to, get_square(board, from));
+    clear_square(board, from);
 }
 
 /// Return true of the square at this position is empty.
@@ -195,7 +277,7 @@ pub fn get_piece_iterator<'a>(board: &'a Board) -> Box<dyn Iterator<Item = (u8,
     Box::new(
         board.iter().enumerate()
             .filter(|(_, s)| **s != SQ_E)
-            .map(|(i, s)| (*s, ((i / 8) as i8, (i % 8) as i8)))
+            .map(|(i, s)| (*s, index_pos(i)))
     )
 }
 
@@ -223,26 +305,100 @@ pub fn num_pieces(board: &Board) -> u8 {
     count
 }
 
-/// Write a text view of the board. Used for debugging.
-pub fn draw(board: &Board, f: &mut dyn std::io::Write) {
+/// Return the FEN char for this square content, or None if it is empty.
+pub fn piece_to_fen_char(square: u8) -> Option<char> {
+    let piece = match get_type(square) {
+        SQ_P => 'p',
+        SQ_B => 'b',
+        SQ_N => 'n',
+        SQ_R => 'r',
+        SQ_Q => 'q',
+        SQ_K => 'k',
+        _ => return None,
+    };
+    Some(if is_white(square) { piece.to_ascii_uppercase() } else { piece })
+}
+
+/// Return the Unicode chess symbol for this square content, or None if
+/// it is empty.
+pub fn piece_to_unicode_char(square: u8) -> Option<char> {
+    let c = match square {
+        SQ_WH_K => '♔',
+        SQ_WH_Q => '♕',
+        SQ_WH_R => '♖',
+        SQ_WH_B => '♗',
+        SQ_WH_N => '♘',
+        SQ_WH_P => '♙',
+        SQ_BL_K => '♚',
+        SQ_BL_Q => '♛',
+        SQ_BL_R => '♜',
+        SQ_BL_B => '♝',
+        SQ_BL_N => '♞',
+        SQ_BL_P => '♟',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Create the FEN placement string of this board.
+pub fn to_fen_placement(board: &Board) -> String {
+    let mut placement = String::with_capacity(64 + 7);
     for r in (0..8).rev() {
-        let mut rank = String::with_capacity(8);
+        let mut num_empty = 0;
         for f in 0..8 {
-            let s = get_square(board, &(f, r));
-            let piece =
-                if is_piece(s, SQ_P) { 'p' }
-                else if is_piece(s, SQ_B) { 'b' }
-                else if is_piece(s, SQ_N) { 'n' }
-                else if is_piece(s, SQ_R) { 'r' }
-                else if is_piece(s, SQ_Q) { 'q' }
-                else if is_piece(s, SQ_K) { 'k' }
-                else { '.' };
-            let piece = if is_color(s, SQ_WH) { piece.to_ascii_uppercase() } else { piece };
-            rank.push(piece);
+            match piece_to_fen_char(get_square(board, &(f, r))) {
+                Some(c) => {
+                    if num_empty > 0 {
+                        placement.push_str(&num_empty.to_string());
+                        num_empty = 0;
+                    }
+                    placement.push(c);
+                }
+                None => num_empty += 1,
+            }
+        }
+        if num_empty > 0 {
+            placement.push_str(&num_empty.to_string());
+        }
+        if r > 0 {
+            placement.push('/');
         }
-        writeln!(f, "{} {}", r + 1, rank).unwrap();
     }
-    write!(f, "  abcdefgh").unwrap();
+    placement
+}
+
+/// Return the board mirrored vertically with piece colors swapped.
+///
+/// The position is the same for the other player, e.g. a white king on
+/// e1 becomes a black king on e8.
+pub fn color_flip(board: &Board) -> Board {
+    let mut flipped = new_empty();
+    for (piece, (f, r)) in get_piece_iterator(board) {
+        set_square(&mut flipped, &(f, POS_MAX - r), opposite(get_color(piece))|get_type(piece));
+    }
+    flipped
+}
+
+/// Write a text view of the board seen from White. Used for debugging.
+pub fn draw(board: &Board, f: &mut dyn std::io::Write) {
+    draw_from(board, SQ_WH, f)
+}
+
+/// Write a text view of the board with Unicode pieces and coordinates,
+/// seen from the side of `color`.
+pub fn draw_from(board: &Board, color: u8, f: &mut dyn std::io::Write) {
+    // Files and ranks in drawing order, from the top left corner.
+    let flipped = color == SQ_BL;
+    let file = |i: i8| if flipped { POS_MAX - i } else { i };
+    let rank = |i: i8| if flipped { i } else { POS_MAX - i };
+    for r in (0..8).map(rank) {
+        let squares: Vec<String> = (0..8).map(|i| {
+            piece_to_unicode_char(get_square(board, &(file(i), r))).unwrap_or('·').to_string()
+        }).collect();
+        writeln!(f, "{} {} {}", r + 1, squares.join(" "), r + 1).unwrap();
+    }
+    let files: Vec<String> = (0..8).map(|i| ((b'a' + file(i) as u8) as char).to_string()).collect();
+    write!(f, "  {}", files.join(" ")).unwrap();
 }
 
 #[cfg(test)]
@@ -265,6 +421,48 @@ mod tests {
         assert_eq!(pos("h8"), (7, 7));
     }
 
+    #[test]
+    fn test_pos_index() {
+        assert_eq!(pos_index(&pos("a1")), 0);
+        assert_eq!(pos_index(&pos("a2")), 1);
+        assert_eq!(pos_index(&pos("b1")), 8);
+        assert_eq!(pos_index(&pos("h8")), 63);
+        for i in 0..64 {
+            assert_eq!(pos_index(&index_pos(i)), i);
+        }
+    }
+
+    #[test]
+    fn test_pos_square() {
+        assert_eq!(pos_square(&pos("a1")), 0);
+        assert_eq!(pos_square(&pos("b1")), 1);
+        assert_eq!(pos_square(&pos("a2")), 8);
+        assert_eq!(pos_square(&pos("e4")), 28);
+        assert_eq!(pos_square(&pos("h8")), 63);
+        for sq in 0..64 {
+            assert_eq!(pos_square(&square_pos(sq)), sq);
+        }
+    }
+
+    #[test]
+    fn test_ray() {
+        assert_eq!(ray(&pos("c3"), (1, 1)).collect::<Vec<_>>(), vec!(pos("d4"), pos("e5"), pos("f6"), pos("g7"), pos("h8")));
+        assert_eq!(ray(&pos("c3"), (-1, 0)).collect::<Vec<_>>(), vec!(pos("b3"), pos("a3")));
+        assert_eq!(ray(&pos("h1"), (1, -1)).count(), 0);
+        assert_eq!(ray(&pos("a1"), (1, 2)).collect::<Vec<_>>(), vec!(pos("b3"), pos("c5"), pos("d7")));
+    }
+
+    #[test]
+    fn test_parse_pos() {
+        assert_eq!(parse_pos("a1"), Some((0, 0)));
+        assert_eq!(parse_pos("h8"), Some((7, 7)));
+        assert_eq!(parse_pos("i1"), None);
+        assert_eq!(parse_pos("a9"), None);
+        assert_eq!(parse_pos("A1"), None);
+        assert_eq!(parse_pos("a"), None);
+        assert_eq!(parse_pos("a1a"), None);
+    }
+
     #[test]
     fn test_pos_string() {
         assert_eq!(pos_string(&(0, 0)), "a1");
@@ -276,8 +474,32 @@ mod tests {
     #[test]
     fn test_new_from_fen() {
         let b1 = new();
-        let b2 = new_from_fen(notation::FEN_START);
+        let b2 = new_from_fen(notation::FEN_START).unwrap();
         assert!(eq(&b1, &b2));
+
+        // Bad ranks or files.
+        assert!(new_from_fen("8/8/8/8/8/8/8").is_none());
+        assert!(new_from_fen("8/8/8/8/8/8/8/8/8").is_none());
+        assert!(new_from_fen("8/8/8/8/8/8/8/7").is_none());
+        assert!(new_from_fen("8/8/8/8/8/8/8/9").is_none());
+        assert!(new_from_fen("8/8/8/8/8/8/8/7KK").is_none());
+        assert!(new_from_fen("8/8/8/8/8/8/8/7X").is_none());
+    }
+
+    #[test]
+    fn test_to_fen_placement() {
+        assert_eq!(to_fen_placement(&new()), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR");
+        assert_eq!(to_fen_placement(&new_empty()), "8/8/8/8/8/8/8/8");
+        let placement = "r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR";
+        assert_eq!(to_fen_placement(&new_from_fen(placement).unwrap()), placement);
+    }
+
+    #[test]
+    fn test_color_flip() {
+        assert!(eq(&color_flip(&new()), &new()));
+        let b = new_from_fen("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR").unwrap();
+        assert_eq!(to_fen_placement(&color_flip(&b)), "r3k1nr/8/8/8/2Pp4/8/1P4P1/R3K2R");
+        assert!(eq(&color_flip(&color_flip(&b)), &b));
     }
 
     #[test]
@@ -310,9 +532,9 @@ mod tests {
     #[test]
     fn test_is_empty() {
         let b = new();
-        assert_eq!(is_empty(&b, &pos("a1")), false);
-        assert_eq!(is_empty(&b, &pos("a2")), false);
-        assert_eq!(is_empty(&b, &pos("a3")), true);
+        assert!(!is_empty(&b, &pos("a1")));
+        assert!(!is_empty(&b, &pos("a2")));
+        assert!(is_empty(&b, &pos("a3")));
     }
 
     #[test]
@@ -329,4 +551,23 @@ mod tests {
         assert_eq!(num_pieces(&new_empty()), 0);
         assert_eq!(num_pieces(&new()), 32);
     }
+
+    #[test]
+    fn test_draw() {
+        let mut b = new_empty();
+        set_square(&mut b, &pos("a1"), SQ_WH_R);
+        set_square(&mut b, &pos("e8"), SQ_BL_K);
+        let mut s = vec!();
+        draw(&b, &mut s);
+        let lines: Vec<String> = String::from_utf8(s).unwrap().lines().map(String::from).collect();
+        assert_eq!(lines[0], "8 · · · · ♚ · · · 8");
+        assert_eq!(lines[7], "1 ♖ · · · · · · · 1");
+        assert_eq!(lines[8], "  a b c d e f g h");
+        let mut s = vec!();
+        draw_from(&b, SQ_BL, &mut s);
+        let lines: Vec<String> = String::from_utf8(s).unwrap().lines().map(String::from).collect();
+        assert_eq!(lines[0], "1 · · · · · · · ♖ 1");
+        assert_eq!(lines[7], "8 · · · ♚ · · · · 8");
+        assert_eq!(lines[8], "  h g f e d c b a");
+    }
 }
@@ -0,0 +1,230 @@
+//! Polyglot opening books.
+//!
+//! A Polyglot book is a list of 16-byte entries sorted by position key,
+//! with big-endian fields: the key (u64), a move (u16), its weight (u16)
+//! and learning data (u32), ignored here. Keys are Polyglot hashes of
+//! positions, see `polyglot_key`, unrelated to the `zobrist` hashes.
+//!
+//! Books are read in memory at once: they rarely weigh more than a few
+//! MB, and probes are then binary searches without any file access.
+
+use std::cmp::Reverse;
+use std::convert::TryInto;
+use std::fs;
+
+use crate::board::*;
+use crate::castling::*;
+use crate::movement::Move;
+use crate::node::Node;
+use crate::rules::GameState;
+
+mod keys;
+
+/// Size of a book entry in bytes.
+const ENTRY_SIZE: usize = 16;
+
+// Offsets of castling, en passant and turn keys in `keys::RANDOM64`.
+const CASTLING_KEYS: usize = 768;
+const EN_PASSANT_KEYS: usize = 772;
+const TURN_KEY: usize = 780;
+
+/// Return the Polyglot hash of a position.
+///
+/// As in Polyglot, the en passant file only counts if a pawn of the
+/// player to move stands next to the pawn that just moved, whether it
+/// can legally take it or not.
+pub fn polyglot_key(board: &Board, game_state: &GameState) -> u64 {
+    let mut key = 0;
+    for (piece, p) in get_piece_iterator(board) {
+        key ^= keys::RANDOM64[64 * piece_kind(piece) + pos_square(&p)];
+    }
+    let castles = [CASTLING_WH_K, CASTLING_WH_Q, CASTLING_BL_K, CASTLING_BL_Q];
+    for (i, castle) in castles.iter().enumerate() {
+        if game_state.castling & castle != 0 {
+            key ^= keys::RANDOM64[CASTLING_KEYS + i];
+        }
+    }
+    if let Some((file, _)) = game_state.en_passant {
+        let rank = if is_white(game_state.color) { 4 } else { 3 };
+        let pawn = game_state.color | SQ_P;
+        let next_pawn = [file - 1, file + 1].iter()
+            .any(|f| (0..8).contains(f) && get_square(board, &(*f, rank)) == pawn);
+        if next_pawn {
+            key ^= keys::RANDOM64[EN_PASSANT_KEYS + file as usize];
+        }
+    }
+    if is_white(game_state.color) {
+        key ^= keys::RANDOM64[TURN_KEY];
+    }
+    key
+}
+
+/// Return the Polyglot kind of `piece`: from pawns to kings, black
+/// then white for each type.
+fn piece_kind(piece: u8) -> usize {
+    let type_index = match get_type(piece) {
+        SQ_P => 0,
+        SQ_N => 1,
+        SQ_B => 2,
+        SQ_R => 3,
+        SQ_Q => 4,
+        _ => 5,
+    };
+    2 * type_index + is_white(piece) as usize
+}
+
+/// Decode a Polyglot move.
+///
+/// Promotions are numbered 1 to 4 from knight to queen, and castles are
+/// written as the king taking its own rook, which `Node::play_move`
+/// understands.
+fn decode_move(raw: u16) -> Move {
+    let pos = |bits: u16| ((bits & 7) as i8, (bits >> 3 & 7) as i8);
+    let promotion = match raw >> 12 & 7 {
+        1 => Some(SQ_N),
+        2 => Some(SQ_B),
+        3 => Some(SQ_R),
+        4 => Some(SQ_Q),
+        _ => None,
+    };
+    (pos(raw >> 6), pos(raw), promotion)
+}
+
+/// A move found in a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookMove {
+    pub m: Move,
+    /// Weight of the move, higher for better ones; 0 if it should not
+    /// be played.
+    pub weight: u16,
+}
+
+/// A Polyglot book, see the module documentation.
+pub struct Book {
+    data: Vec<u8>,
+}
+
+impl Book {
+    /// Read the book at `path`.
+    pub fn open(path: &str) -> Result<Book, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        Book::from_bytes(data)
+    }
+
+    /// Use `data`, the content of a book file.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Book, String> {
+        if !data.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!("size {} is not a multiple of {} bytes", data.len(), ENTRY_SIZE))
+        }
+        Ok(Book { data })
+    }
+
+    fn num_entries(&self) -> usize {
+        self.data.len() / ENTRY_SIZE
+    }
+
+    fn entry(&self, index: usize) -> &[u8] {
+        &self.data[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE]
+    }
+
+    fn key(&self, index: usize) -> u64 {
+        u64::from_be_bytes(self.entry(index)[..8].try_into().unwrap())
+    }
+
+    /// Return the moves of the book for a position, by decreasing weight.
+    ///
+    /// Entries are checked against the legal moves, so that bad entries
+    /// or key collisions are skipped.
+    pub fn moves(&self, board: &Board, game_state: &GameState) -> Vec<BookMove> {
+        let key = polyglot_key(board, game_state);
+        // Binary search of the first entry with this key.
+        let (mut low, mut high) = (0, self.num_entries());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.key(middle) < key {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let node = Node { board: *board, game_state: game_state.clone() };
+        let mut moves: Vec<BookMove> = (low..self.num_entries())
+            .take_while(|i| self.key(*i) == key)
+            .filter_map(|i| {
+                let entry = self.entry(i);
+                let m = node.clone().play_move(&decode_move(u16::from_be_bytes([entry[8], entry[9]])))?;
+                Some(BookMove { m, weight: u16::from_be_bytes([entry[10], entry[11]]) })
+            })
+            .collect();
+        moves.sort_by_key(|bm| Reverse(bm.weight));
+        moves
+    }
+
+    /// Return the move of the book with the highest weight, if any.
+    pub fn best_move(&self, board: &Board, game_state: &GameState) -> Option<Move> {
+        self.moves(board, game_state).first().filter(|bm| bm.weight > 0).map(|bm| bm.m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{self, parse_move};
+
+    fn key(fen: &str) -> u64 {
+        let (board, game_state) = notation::position(fen);
+        polyglot_key(&board, &game_state)
+    }
+
+    #[test]
+    fn test_polyglot_key() {
+        // Keys published with the Polyglot format.
+        assert_eq!(key(notation::FEN_START), 0x463b_9618_1691_fc9c);
+        assert_eq!(key("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"), 0x823c_9b50_fd11_4196);
+        assert_eq!(key("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2"), 0x0756_b944_61c5_0fb0);
+        assert_eq!(key("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2"), 0x662f_afb9_65db_29d4);
+        assert_eq!(key("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"), 0x22a4_8b5a_8e47_ff78);
+        assert_eq!(key("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3"), 0x652a_607c_a3f2_42c1);
+        assert_eq!(key("rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4"), 0x00fd_d303_c946_bdd9);
+        assert_eq!(key("rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3"), 0x3c81_23ea_7b06_7637);
+        assert_eq!(key("rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4"), 0x5c3f_9b82_9b27_9560);
+    }
+
+    #[test]
+    fn test_book() {
+        let entry = |key: u64, m: u16, weight: u16| {
+            let mut entry = key.to_be_bytes().to_vec();
+            entry.extend_from_slice(&m.to_be_bytes());
+            entry.extend_from_slice(&weight.to_be_bytes());
+            entry.extend_from_slice(&[0; 4]);
+            entry
+        };
+        // From the start, e2e4 and d2d4 with an illegal e1e2 entry; a
+        // castle, written e1h1, in another position.
+        let start = key(notation::FEN_START);
+        let castle_fen = "rnbqk2r/pppp1ppp/5n2/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let e2e4 = 4 << 6 | 1 << 9 | 4 | 3 << 3;
+        let d2d4 = 3 << 6 | 1 << 9 | 3 | 3 << 3;
+        let e1e2 = 4 << 6 | 4 | 1 << 3;
+        let e1h1 = 4 << 6 | 7;
+        let mut entries = [(start, e1e2, 50), (start, d2d4, 10), (start, e2e4, 20), (key(castle_fen), e1h1, 1)];
+        entries.sort();
+        let data = entries.iter().flat_map(|(k, m, w)| entry(*k, *m, *w)).collect();
+        let book = Book::from_bytes(data).unwrap();
+
+        let (board, game_state) = (new(), GameState::new());
+        let moves = book.moves(&board, &game_state);
+        assert_eq!(moves, vec![
+            BookMove { m: parse_move("e2e4").unwrap(), weight: 20 },
+            BookMove { m: parse_move("d2d4").unwrap(), weight: 10 },
+        ]);
+        assert_eq!(book.best_move(&board, &game_state), parse_move("e2e4"));
+        let (board, game_state) = notation::position(castle_fen);
+        assert_eq!(book.best_move(&board, &game_state), parse_move("e1g1"));
+        // Out of book.
+        let (board, game_state) = notation::position("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(book.best_move(&board, &game_state), None);
+
+        assert!(Book::from_bytes(vec![0; 15]).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Analysis cache persisted across sessions.
+//!
+//! Results of completed searches are stored by position, so repeated
+//! positions across games or runs do not trigger redundant work. The
+//! cache file is a text file with one entry per line, appended as
+//! results come: `FEN;depth;score;bestmove`, where the FEN has no move
+//! counters. When a position appears several times, the deepest entry
+//! (or the last one among equally deep entries) is used.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::board::Board;
+use crate::movement::Move;
+use crate::notation;
+use crate::rules::GameState;
+use crate::score::RelativeScore;
+
+/// Cached result of a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub depth: u32,
+    /// Normalized score in centipawns for the player to move.
+    pub score: RelativeScore,
+    pub best_move: Move,
+}
+
+/// Analysis cache backed by a file.
+pub struct AnalysisCache {
+    path: String,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl AnalysisCache {
+    /// Load the cache from file `path`, which is created on first insert
+    /// if it does not exist. Malformed lines are ignored.
+    pub fn open(path: &str) -> Result<AnalysisCache, String> {
+        let mut cache = AnalysisCache { path: path.to_string(), entries: HashMap::new() };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(format!("Could not read analysis cache {}: {}", path, e)),
+        };
+        for line in content.lines() {
+            if let Some((key, entry)) = parse_line(line) {
+                cache.update(key, entry);
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Number of cached positions.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the cached entry for this position, if any.
+    pub fn get(&self, board: &Board, game_state: &GameState) -> Option<&CacheEntry> {
+        self.entries.get(&get_key(board, game_state))
+    }
+
+    /// Store an entry for this position and append it to the cache file,
+    /// unless there is already a deeper entry for it.
+    pub fn insert(
+        &mut self,
+        board: &Board,
+        game_state: &GameState,
+        entry: CacheEntry,
+    ) -> Result<(), String> {
+        let key = get_key(board, game_state);
+        let line = format!(
+            "{};{};{};{}",
+            key, entry.depth, entry.score, notation::move_to_string(&entry.best_move)
+        );
+        if !self.update(key, entry) {
+            return Ok(())
+        }
+        fs::OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut f| writeln!(f, "{}", line))
+            .map_err(|e| format!("Could not write analysis cache {}: {}", self.path, e))
+    }
+
+    /// Update the in-memory entries; return false if a deeper entry exists.
+    fn update(&mut self, key: String, entry: CacheEntry) -> bool {
+        if let Some(existing) = self.entries.get(&key) {
+            if existing.depth > entry.depth {
+                return false
+            }
+        }
+        self.entries.insert(key, entry);
+        true
+    }
+}
+
+/// Return the cache key of a position: its FEN without move counters.
+fn get_key(board: &Board, game_state: &GameState) -> String {
+    let fen = notation::to_fen(board, game_state);
+    fen.split(' ').take(4).collect::<Vec<_>>().join(" ")
+}
+
+/// Parse a cache file line into a key and an entry.
+fn parse_line(line: &str) -> Option<(String, CacheEntry)> {
+    let fields: Vec<&str> = line.trim().split(';').collect();
+    if fields.len() != 4 {
+        return None
+    }
+    // Normalize the key through the position it describes.
+    let fen = notation::parse_fen(&format!("{} 0 1", fields[0]))?;
+    let (board, game_state) = notation::fen_to_position(&fen)?;
+    let entry = CacheEntry {
+        depth: fields[1].parse().ok()?,
+        score: RelativeScore(fields[2].parse().ok()?),
+        best_move: notation::parse_move(fields[3])?,
+    };
+    Some((get_key(&board, &game_state), entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::pos;
+
+    #[test]
+    fn test_analysis_cache() {
+        let path = std::env::temp_dir().join(format!("vatu-cache-test-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut cache = AnalysisCache::open(path).unwrap();
+        assert!(cache.is_empty());
+        let (board, game_state) = notation::position(notation::FEN_START);
+        let entry = CacheEntry { depth: 4, score: RelativeScore(20), best_move: (pos("e2"), pos("e4"), None) };
+        cache.insert(&board, &game_state, entry.clone()).unwrap();
+        // Move counters are not part of the key.
+        let (board2, game_state2) = notation::position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 9");
+        assert_eq!(cache.get(&board2, &game_state2), Some(&entry));
+        // Shallower results do not replace deeper ones.
+        let shallow = CacheEntry { depth: 2, score: RelativeScore(0), best_move: (pos("d2"), pos("d4"), None) };
+        cache.insert(&board, &game_state, shallow).unwrap();
+        assert_eq!(cache.get(&board, &game_state), Some(&entry));
+        let (board3, game_state3) = notation::position("4k3/8/8/8/8/8/8/R3K3 b - - 0 1");
+        assert_eq!(cache.get(&board3, &game_state3), None);
+        let entry3 = CacheEntry { depth: 3, score: RelativeScore(-500), best_move: (pos("e8"), pos("d7"), None) };
+        cache.insert(&board3, &game_state3, entry3.clone()).unwrap();
+
+        // Reload from disk, ignoring garbage.
+        fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"garbage\n").unwrap();
+        let cache = AnalysisCache::open(path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&board, &game_state), Some(&entry));
+        assert_eq!(cache.get(&board3, &game_state3), Some(&entry3));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let (key, entry) = parse_line("4k3/8/8/8/8/8/8/R3K3 b - -;3;-500;e8d7").unwrap();
+        assert_eq!(key, "4k3/8/8/8/8/8/8/R3K3 b - -");
+        assert_eq!(entry, CacheEntry { depth: 3, score: RelativeScore(-500), best_move: (pos("e8"), pos("d7"), None) });
+        assert!(parse_line("4k3/8/8/8/8/8/8/R3K3 b - -;3;-500").is_none());
+        assert!(parse_line("4k3/8/8/8/8/8/8/R3K3 x - -;3;-500;e8d7").is_none());
+        assert!(parse_line("4k3/8/8/8/8/8/8/R3K3 b - -;-3;-500;e8d7").is_none());
+        assert!(parse_line("4k3/8/8/8/8/8/8/R3K3 b - -;3;-500;e8").is_none());
+    }
+}
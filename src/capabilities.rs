@@ -0,0 +1,120 @@
+//! Build and runtime capabilities.
+//!
+//! The build (version, profile, Cargo features), the CPU features of the
+//! host and the sizes of the engine tables are all described here, so
+//! that the `features` subcommand, benchmarks and session recordings
+//! report the same thing.
+
+use std::fmt;
+
+use crate::attacks;
+use crate::eval::{imbalance, pawns};
+use crate::tt;
+
+/// A CPU feature that matters to the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuFeature {
+    pub name: &'static str,
+    /// True if the host CPU has it.
+    pub detected: bool,
+    /// True if the build is compiled to use it.
+    pub used: bool,
+}
+
+/// Capabilities of this build on this host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub version: &'static str,
+    /// "debug" or "release".
+    pub profile: &'static str,
+    /// Architecture and OS, e.g. "x86_64-linux".
+    pub target: String,
+    /// Enabled Cargo features.
+    pub features: Vec<&'static str>,
+    pub cpu_features: Vec<CpuFeature>,
+    /// Default transposition table size in MB.
+    pub hash_mb: usize,
+    pub pawn_table_entries: usize,
+    pub imbalance_table_entries: usize,
+    /// Memory used by the attack tables, in bytes.
+    pub attack_tables_size: usize,
+    pub allocator: &'static str,
+}
+
+#[cfg(target_arch = "x86_64")]
+macro_rules! cpu_feature {
+    ($name:tt) => {
+        CpuFeature {
+            name: $name,
+            detected: is_x86_feature_detected!($name),
+            used: cfg!(target_feature = $name),
+        }
+    };
+}
+
+/// Return CPU features of the host that bitboard code benefits from.
+fn detect_cpu_features() -> Vec<CpuFeature> {
+    #[cfg(target_arch = "x86_64")]
+    return vec![cpu_feature!("popcnt"), cpu_feature!("bmi2"), cpu_feature!("avx2")];
+    #[cfg(not(target_arch = "x86_64"))]
+    return vec!()
+}
+
+/// Detect capabilities of this build on this host.
+pub fn detect() -> Capabilities {
+    let mut features = vec!();
+    if cfg!(feature = "svg") {
+        features.push("svg");
+    }
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        profile: if cfg!(debug_assertions) { "debug" } else { "release" },
+        target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        features,
+        cpu_features: detect_cpu_features(),
+        hash_mb: tt::DEFAULT_SIZE_MB,
+        pawn_table_entries: pawns::DEFAULT_TABLE_ENTRIES,
+        imbalance_table_entries: imbalance::DEFAULT_TABLE_ENTRIES,
+        attack_tables_size: attacks::tables_size(),
+        allocator: "system",
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Write one "name: value" line per capability.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let features = if self.features.is_empty() { "none".to_string() } else { self.features.join(" ") };
+        let cpu_features: Vec<String> = self.cpu_features.iter().map(|feature| {
+            let state = match (feature.detected, feature.used) {
+                (_, true) => "used",
+                (true, false) => "detected",
+                (false, false) => "missing",
+            };
+            format!("{} ({})", feature.name, state)
+        }).collect();
+        let cpu_features = if cpu_features.is_empty() { "none".to_string() } else { cpu_features.join(", ") };
+        writeln!(f, "version: {} ({}, {})", self.version, self.profile, self.target)?;
+        writeln!(f, "features: {}", features)?;
+        writeln!(f, "cpu: {}", cpu_features)?;
+        writeln!(f, "hash: {} MB", self.hash_mb)?;
+        writeln!(f, "pawn table: {} entries", self.pawn_table_entries)?;
+        writeln!(f, "imbalance table: {} entries", self.imbalance_table_entries)?;
+        writeln!(f, "attack tables: {} KB", self.attack_tables_size / 1024)?;
+        write!(f, "allocator: {}", self.allocator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let capabilities = detect();
+        assert!(capabilities.cpu_features.iter().all(|f| f.detected || !f.used));
+        let report = capabilities.to_string();
+        assert!(report.starts_with(&format!("version: {} ({}, ", env!("CARGO_PKG_VERSION"), capabilities.profile)));
+        assert_eq!(report.lines().count(), 8);
+        assert!(capabilities.attack_tables_size > 0);
+    }
+}
@@ -10,6 +10,11 @@ pub const CASTLING_K_MASK: u8  = 0b00000101;
 pub const CASTLING_Q_MASK: u8  = 0b00001010;
 pub const CASTLING_MASK: u8    = 0b00001111;
 
+/// Swap white and black castling options.
+pub const fn color_flip_castling(castling: u8) -> u8 {
+    (castling & CASTLING_WH_MASK) << 2 | (castling & CASTLING_BL_MASK) >> 2
+}
+
 /// Castling sides parameters.
 ///
 /// For both sides, the 3-uple contains files that should be empty
@@ -0,0 +1,86 @@
+//! Thread priority and CPU affinity.
+//!
+//! Pinning each search thread to its own core avoids migrations that
+//! make benchmark speeds less consistent, and a lower priority leaves
+//! the CPU to other programs, e.g. when analyzing in the background or
+//! running the bot on a shared machine. Both are only supported on
+//! Linux, other platforms return errors.
+
+/// Highest niceness, i.e. the lowest priority.
+pub const MAX_NICENESS: i32 = 19;
+
+#[cfg(target_os = "linux")]
+fn last_os_error() -> String {
+    std::io::Error::last_os_error().to_string()
+}
+
+/// Set the niceness of the calling thread, from 0 (normal priority) to
+/// `MAX_NICENESS`; threads started afterwards inherit it.
+///
+/// Unprivileged users can only increase it.
+#[cfg(target_os = "linux")]
+pub fn set_niceness(niceness: i32) -> Result<(), String> {
+    // On Linux, the process ID 0 is the calling thread only.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } != 0 {
+        return Err(last_os_error())
+    }
+    Ok(())
+}
+
+/// Return the cores the calling thread can run on.
+#[cfg(target_os = "linux")]
+pub fn allowed_cores() -> Result<Vec<usize>, String> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return Err(last_os_error())
+    }
+    Ok((0..libc::CPU_SETSIZE as usize).filter(|core| unsafe { libc::CPU_ISSET(*core, &set) }).collect())
+}
+
+/// Restrict the calling thread to run on `core` only.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> Result<(), String> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut set) };
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(last_os_error())
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+const UNSUPPORTED: &str = "not supported on this platform";
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_niceness(_niceness: i32) -> Result<(), String> {
+    Err(UNSUPPORTED.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cores() -> Result<Vec<usize>, String> {
+    Err(UNSUPPORTED.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_core: usize) -> Result<(), String> {
+    Err(UNSUPPORTED.to_string())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_settings() {
+        // Change a new thread, not the test thread.
+        let cores = allowed_cores().unwrap();
+        assert!(!cores.is_empty());
+        let core = *cores.last().unwrap();
+        let pinned = std::thread::spawn(move || {
+            pin_current_thread(core).unwrap();
+            set_niceness(MAX_NICENESS).unwrap();
+            allowed_cores().unwrap()
+        }).join().unwrap();
+        assert_eq!(pinned, vec![core]);
+    }
+}
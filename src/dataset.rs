@@ -0,0 +1,89 @@
+//! Export of evaluation features, for experiments on evaluation weights
+//! with external tools.
+
+use crate::analysis::{self, FEATURE_NAMES};
+use crate::node::Node;
+use crate::notation;
+
+/// Output format of exported features.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A header line then a line per position, starting with the FEN.
+    Csv,
+    /// JSON Lines: an object per position, with the FEN and features.
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Format> {
+        match s {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Return the evaluation features of each position of `positions`, see
+/// `analysis::EvalFeatures`.
+///
+/// Positions are a FEN per line, optionally followed by ";" and
+/// anything else, like for evaluation comparisons. Empty lines and
+/// lines starting with "#" are ignored. Return an error on invalid FENs.
+pub fn export_features(positions: &str, format: Format) -> Result<String, String> {
+    let mut output = vec!();
+    if format == Format::Csv {
+        output.push(format!("fen,{}", FEATURE_NAMES.join(",")));
+    }
+    for (i, line) in positions.lines().enumerate() {
+        let fen_str = line.split(';').next().unwrap_or("").trim();
+        if fen_str.is_empty() || fen_str.starts_with('#') {
+            continue
+        }
+        let (board, game_state) = notation::parse_fen(fen_str)
+            .and_then(|fen| notation::fen_to_position(&fen))
+            .ok_or_else(|| format!("Invalid FEN line {}: \"{}\"", i + 1, fen_str))?;
+        let values = analysis::evaluation_features(&Node { board, game_state }).values();
+        output.push(match format {
+            Format::Csv => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                format!("{},{}", fen_str, values.join(","))
+            }
+            Format::Json => {
+                let fields: Vec<String> = FEATURE_NAMES.iter().zip(values.iter())
+                    .map(|(name, v)| format!("\"{}\": {}", name, v))
+                    .collect();
+                format!("{{\"fen\": \"{}\", \"features\": {{{}}}}}", fen_str, fields.join(", "))
+            }
+        });
+    }
+    Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_features() {
+        // Black to move, a pawn and a passed pawn down.
+        let positions = "# Comment.\n4k3/8/8/8/4P3/8/8/4K3 b - - 0 1 ; ongoing\n";
+        let csv = export_features(positions, Format::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("fen,king,queen,"));
+        let values: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(values[0], "4k3/8/8/8/4P3/8/8/4K3 b - - 0 1");
+        assert_eq!(values.len(), FEATURE_NAMES.len() + 1);
+        assert_eq!(values[6], "-1");
+        assert_eq!(values[8], "-1");
+
+        let json = export_features(positions, Format::Json).unwrap();
+        assert!(json.starts_with("{\"fen\": \"4k3/8/8/8/4P3/8/8/4K3 b - - 0 1\", \"features\": {\"king\": 0,"));
+        assert!(json.contains("\"pawn\": -1,"));
+
+        assert!(export_features("8/8 w", Format::Csv).is_err());
+        assert_eq!(Format::parse("json"), Some(Format::Json));
+        assert_eq!(Format::parse("xml"), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! Basic mates knowledge.
+//!
+//! With a lone enemy king against a queen, a rook, or a bishop and a
+//! knight, the regular evaluation has no idea how to make progress at
+//! low depth. When the material signature matches one of these mates,
+//! the evaluation gets a bonus for driving the lost king to the edge
+//! (or to the right corner) and for bringing the kings closer.
+
+use crate::board::*;
+
+/// Basic mates the engine knows how to convert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mate {
+    /// King and queen versus king.
+    KQK,
+    /// King and rook versus king.
+    KRK,
+    /// King, bishop and knight versus king; the value is the color of
+    /// the bishop squares, true for light squares.
+    KBNK(bool),
+}
+
+/// Return the basic mate matching the board material, if any, along
+/// with the color of the stronger side.
+pub fn get_mate_signature(board: &Board) -> Option<(Mate, u8)> {
+    let mut strong_color = None;
+    let mut strong_pieces = vec!();
+    for (piece, pos) in get_piece_iterator(board) {
+        if is_type(piece, SQ_K) {
+            continue
+        }
+        let color = get_color(piece);
+        if *strong_color.get_or_insert(color) != color {
+            return None
+        }
+        strong_pieces.push((get_type(piece), pos));
+    }
+    strong_pieces.sort_unstable();
+    let mate = match strong_pieces.as_slice() {
+        [(SQ_Q, _)] => Mate::KQK,
+        [(SQ_R, _)] => Mate::KRK,
+        [(SQ_B, bishop_pos), (SQ_N, _)] => Mate::KBNK(is_light_square(bishop_pos)),
+        _ => return None,
+    };
+    Some((mate, strong_color.unwrap()))
+}
+
+/// Return the basic mate bonus from the point of view of `color`, or
+/// None if the board is not a basic mate.
+pub fn get_mate_bonus(board: &Board, color: u8) -> Option<f32> {
+    let (mate, strong_color) = get_mate_signature(board)?;
+    let strong_king = find_king(board, strong_color)?;
+    let weak_king = find_king(board, opposite(strong_color))?;
+    let kings_closeness = 7 - distance(&strong_king, &weak_king);
+    let bonus = match mate {
+        Mate::KQK | Mate::KRK => {
+            1.0 * center_distance(&weak_king) as f32 + 0.25 * kings_closeness as f32
+        }
+        Mate::KBNK(light) => {
+            // Mate can only be forced in a corner of the bishop color.
+            let corners = if light { [(0, 7), (7, 0)] } else { [(0, 0), (7, 7)] };
+            let corner_distance = corners.iter().map(|c| distance(c, &weak_king)).min().unwrap();
+            0.5 * center_distance(&weak_king) as f32
+            + 0.5 * (7 - corner_distance) as f32
+            + 0.25 * kings_closeness as f32
+        }
+    };
+    Some(if color == strong_color { bonus } else { -bonus })
+}
+
+/// Return true if `pos` is a light square.
+fn is_light_square(pos: &Pos) -> bool {
+    (pos.0 + pos.1) % 2 == 1
+}
+
+/// Chebyshev distance between two squares, i.e. king moves.
+fn distance(a: &Pos, b: &Pos) -> i8 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs())
+}
+
+/// Distance from the center, from 0 on the 4 central squares to 3 on edges.
+fn center_distance(pos: &Pos) -> i8 {
+    let f = if pos.0 < 4 { 3 - pos.0 } else { pos.0 - 4 };
+    let r = if pos.1 < 4 { 3 - pos.1 } else { pos.1 - 4 };
+    f.max(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn board(placement: &str) -> Board {
+        new_from_fen(placement).unwrap()
+    }
+
+    #[test]
+    fn test_get_mate_signature() {
+        assert_eq!(get_mate_signature(&board("8/8/8/4k3/8/8/8/3QK3")), Some((Mate::KQK, SQ_WH)));
+        assert_eq!(get_mate_signature(&board("3rk3/8/8/8/8/8/8/4K3")), Some((Mate::KRK, SQ_BL)));
+        assert_eq!(
+            get_mate_signature(&board("8/8/8/4k3/8/8/8/2B1KN2")),
+            Some((Mate::KBNK(false), SQ_WH))
+        );
+        assert_eq!(
+            get_mate_signature(&board("8/8/8/4k3/8/8/8/1N2KB2")),
+            Some((Mate::KBNK(true), SQ_WH))
+        );
+        // Not basic mates.
+        assert_eq!(get_mate_signature(&board("8/8/8/4k3/8/8/8/3QKQ2")), None);
+        assert_eq!(get_mate_signature(&board("8/8/8/4k3/8/8/3p4/3QK3")), None);
+        assert_eq!(get_mate_signature(&board("8/8/8/4k3/8/8/8/2B1K1B1")), None);
+        assert_eq!(get_mate_signature(&board("8/8/8/4k3/8/8/8/4K3")), None);
+        let start = notation::parse_fen(notation::FEN_START).unwrap();
+        assert_eq!(get_mate_signature(&board(&start.placement)), None);
+    }
+
+    #[test]
+    fn test_get_mate_bonus() {
+        // Lost king on the edge is better than in the center.
+        let centered = get_mate_bonus(&board("8/8/8/4k3/8/8/8/R3K3"), SQ_WH).unwrap();
+        let edged = get_mate_bonus(&board("4k3/8/8/8/8/8/8/R3K3"), SQ_WH).unwrap();
+        assert!(edged > centered);
+        assert_eq!(get_mate_bonus(&board("4k3/8/8/8/8/8/8/R3K3"), SQ_BL), Some(-edged));
+        // Closer kings are better.
+        let far = get_mate_bonus(&board("4k3/8/8/8/8/8/8/Q3K3"), SQ_WH).unwrap();
+        let close = get_mate_bonus(&board("4k3/8/4K3/8/8/8/8/Q7"), SQ_WH).unwrap();
+        assert!(close > far);
+        // With a dark-squared bishop, a1 and h8 are the right corners.
+        let right_corner = get_mate_bonus(&board("7k/8/8/8/8/8/8/2B1KN2"), SQ_WH).unwrap();
+        let wrong_corner = get_mate_bonus(&board("k7/8/8/8/8/8/8/2B1KN2"), SQ_WH).unwrap();
+        assert!(right_corner > wrong_corner);
+        assert_eq!(get_mate_bonus(&board("8/8/8/4k3/8/8/8/3QKQ2"), SQ_WH), None);
+    }
+
+    #[test]
+    fn test_center_distance() {
+        assert_eq!(center_distance(&pos("d4")), 0);
+        assert_eq!(center_distance(&pos("e5")), 0);
+        assert_eq!(center_distance(&pos("b6")), 2);
+        assert_eq!(center_distance(&pos("h1")), 3);
+        assert_eq!(center_distance(&pos("a5")), 3);
+    }
+}
@@ -3,19 +3,91 @@
 //! Hold the various data needed to perform a game analysis,
 //! but actual analysis code is in the `analysis` module.
 
+use std::fs;
 use std::sync::Arc;
 use std::sync::mpsc;
-use std::sync::atomic::{self, AtomicBool};
+use std::sync::atomic::{self, AtomicBool, AtomicU64};
 use std::thread;
 
 use crate::analysis;
-use crate::board;
-use crate::castling;
-use crate::movement::{self, Move};
+use crate::book;
+use crate::cache;
+use crate::cpu;
+use crate::movement::Move;
 use crate::node::Node;
 use crate::notation;
+use crate::time;
+use crate::tt;
+use crate::tune;
 use crate::uci;
 
+/// Named set of engine settings for a use case.
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    pub name: &'static str,
+    /// Transposition table size in MB.
+    pub hash_mb: usize,
+    /// Interval between node stats reports in ms.
+    pub info_interval: u32,
+}
+
+/// Available profiles, the first one being the default.
+pub const PROFILES: [Profile; 3] = [
+    Profile { name: "tournament", hash_mb: tt::DEFAULT_SIZE_MB, info_interval: analysis::DEFAULT_INFO_INTERVAL },
+    // Short games: keep the table small and do not flood the interface.
+    Profile { name: "bullet", hash_mb: tt::DEFAULT_SIZE_MB, info_interval: 5000 },
+    // Long analyses: use more memory and report often.
+    Profile { name: "analysis", hash_mb: 256, info_interval: 250 },
+];
+
+/// Return the profile with this name, ignoring case.
+pub fn get_profile(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+/// Maximum absolute rating advantage accepted with UCI_RatingAdv.
+pub const MAX_RATING_ADVANTAGE: i32 = 10000;
+
+/// Maximum value of the MaxNps option.
+pub const MAX_NPS: i32 = 100_000_000;
+
+/// Maximum value of the Threads option.
+pub const MAX_THREADS: i32 = 64;
+
+/// Maximum value of the MultiPV option.
+pub const MAX_MULTI_PV: i32 = 64;
+
+/// Maximum value of the Move Overhead option, in ms.
+pub const MAX_MOVE_OVERHEAD: i32 = 5000;
+
+/// Maximum value of the BookDepth option, in moves.
+pub const MAX_BOOK_DEPTH: i32 = 200;
+
+/// Rating advantage adding 1cp of contempt.
+const RATING_PER_CONTEMPT_CP: i32 = 10;
+
+/// Maximum absolute contempt added from the rating advantage, in centipawns.
+const MAX_AUTO_CONTEMPT: i32 = 50;
+
+/// A search running in a worker thread.
+struct Search {
+    /// Identifier of the search, so that notifications from workers of
+    /// aborted searches can be ignored.
+    id: u64,
+    /// Node being analyzed.
+    node: Node,
+    /// Worker thread, returning the search result and its evaluation
+    /// caches.
+    handle: thread::JoinHandle<(analysis::SearchResult, Option<analysis::EvalCaches>)>,
+    /// Helper threads of a parallel search, stopped once the main
+    /// worker is done.
+    /// They return their evaluation caches.
+    helpers: Vec<thread::JoinHandle<Option<analysis::EvalCaches>>>,
+    /// False if the search is restricted to some root moves, so that
+    /// its result may not be the best move of the node.
+    cacheable: bool,
+}
+
 /// Analysis engine.
 pub struct Engine {
     /// Debug mode, log some data.
@@ -28,6 +100,53 @@ pub struct Engine {
     listening: bool,
     /// flag to notify workers if they should keep working.
     working: Arc<AtomicBool>,
+    /// Flag telling workers they are pondering, unset on ponder hit.
+    pondering: Arc<AtomicBool>,
+    /// Cache of previous analyses, if enabled.
+    analysis_cache: Option<cache::AnalysisCache>,
+    /// Search running in a worker thread, if any.
+    search: Option<Search>,
+    /// Number of searches started, to identify them.
+    num_searches: u64,
+    /// Transposition table shared with workers, kept between searches.
+    tt: Arc<tt::TranspositionTable>,
+    /// Evaluation caches of search threads, kept between searches: each
+    /// worker takes one, if any, and gives it back once done.
+    eval_caches: Vec<analysis::EvalCaches>,
+    /// Contempt in centipawns set by the user.
+    contempt: i32,
+    /// Rating of the engine minus the opponent's, 0 if unknown.
+    rating_advantage: i32,
+    /// Interval between node stats reports of workers in ms.
+    info_interval: u32,
+    /// Maximum nodes per second of searches, 0 for no limit.
+    max_nps: u32,
+    /// Maximum depth of searches, 0 for no limit.
+    max_depth: u32,
+    /// Enable late move reductions in searches.
+    late_move_reductions: bool,
+    /// Enable history pruning in searches.
+    history_pruning: bool,
+    /// Values of the tunable search parameters, see `tune`.
+    search_params: tune::SearchParams,
+    /// Number of threads of searches.
+    threads: usize,
+    /// Number of best lines reported by searches.
+    multi_pv: usize,
+    /// Time taken out of time limits for communication delays, in ms.
+    move_overhead: i32,
+    /// Weights of the evaluation terms.
+    eval_weights: analysis::EvalWeights,
+    /// Positions before the moves played with "makemove", to undo them.
+    played_nodes: Vec<Node>,
+    /// Play moves from the opening book if there is one.
+    own_book: bool,
+    /// Opening book, if loaded.
+    book: Option<book::Book>,
+    /// Last move number at which book moves are played, 0 for no limit.
+    book_depth: i32,
+    /// Cores to pin search threads to in turn, if enabled.
+    affinity_cores: Option<Vec<usize>>,
 }
 
 /// Engine communication mode.
@@ -52,16 +171,26 @@ pub enum Cmd {
     UciChannel(mpsc::Sender<Cmd>),
     /// UCI "debug" command.
     UciDebug(bool),
+    /// UCI "setoption" command, with option name and value.
+    UciSetOption(&'static str, uci::OptionValue),
     /// UCI "position" command.
     UciPosition(Vec<uci::PositionArgs>),
     /// UCI "go" command.
     UciGo(Vec<uci::GoArgs>),
+    /// UCI "ponderhit" command: the expected move has been played.
+    UciPonderHit,
+    /// Debug "makemove" command: play a move on the current position.
+    MakeMove(String),
+    /// Debug "undomove" command: take back the last "makemove".
+    UndoMove,
+    /// Console "tune" command: report the tunables, see `tune`.
+    Tune,
     /// Stop working ASAP.
     Stop,
     /// Informations from a worker.
     WorkerInfo(Vec<analysis::AnalysisInfo>),
-    /// Send best move found by analysis worker.
-    WorkerBestMove(Option<Move>),
+    /// Notify that the worker of this search is done, see `Search`.
+    WorkerDone(u64),
 
     // Commands that can be sent by the engine.
 
@@ -72,8 +201,19 @@ pub enum Cmd {
     Log(String),
     /// Report ongoing analysis information.
     Info(Vec<analysis::AnalysisInfo>),
-    /// Report found best move.
-    BestMove(Option<Move>),
+    /// Report the result of a finished search, with its best move.
+    SearchResult(analysis::SearchResult),
+    /// Report the tunables with their values in the SPSA input format.
+    SpsaInput(String),
+    /// Report the actual size of the transposition table in MB, on
+    /// setup and after each resize.
+    HashSize(usize),
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// General engine implementation.
@@ -85,6 +225,29 @@ impl Engine {
             mode: Mode::No,
             listening: false,
             working: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
+            analysis_cache: None,
+            search: None,
+            num_searches: 0,
+            tt: Arc::new(tt::TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+            eval_caches: vec!(),
+            contempt: 0,
+            rating_advantage: 0,
+            info_interval: PROFILES[0].info_interval,
+            max_nps: 0,
+            max_depth: 0,
+            late_move_reductions: true,
+            history_pruning: true,
+            search_params: tune::SearchParams::default(),
+            threads: 1,
+            multi_pv: 1,
+            move_overhead: 0,
+            eval_weights: analysis::SHANNON_WEIGHTS,
+            played_nodes: vec!(),
+            own_book: false,
+            book: None,
+            book_depth: 0,
+            affinity_cores: None,
         }
     }
 
@@ -112,93 +275,274 @@ impl Engine {
         self.debug = true;
     }
 
+    /// Apply the settings of this profile.
+    ///
+    /// Options set afterwards override them.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        self.resize_transposition_table(profile.hash_mb);
+        self.info_interval = profile.info_interval;
+    }
+
+    /// Replace the transposition table with a new one of `size_mb` MB.
+    ///
+    /// If there is not enough memory, a smaller table is used and the
+    /// interface is warned with its actual size.
+    fn resize_transposition_table(&mut self, size_mb: usize) {
+        // Free the current table first, unless a worker still uses it.
+        self.tt = Arc::new(tt::TranspositionTable::new(tt::MIN_SIZE_MB));
+        self.tt = Arc::new(tt::TranspositionTable::new(size_mb));
+        if self.tt.size_mb() < size_mb {
+            let message = format!(
+                "could not allocate {} MB for Hash, using {} MB", size_mb, self.tt.size_mb()
+            );
+            self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+        }
+        self.reply(Cmd::HashSize(self.tt.size_mb()));
+    }
+
+    /// Use this analysis cache for further analyses.
+    pub fn set_analysis_cache(&mut self, cache: cache::AnalysisCache) {
+        self.analysis_cache = Some(cache);
+    }
+
     /// Handle UCI commands passed as engine Cmds.
     fn handle_command(&mut self, cmd: &Cmd) {
         match cmd {
             // UCI commands.
             Cmd::UciDebug(on) => self.debug = *on,
+            Cmd::UciSetOption(name, value) => self.uci_set_option(name, value),
             Cmd::UciPosition(args) => self.uci_position(args),
             Cmd::UciGo(args) => self.uci_go(args),
+            Cmd::UciPonderHit => self.pondering.store(false, atomic::Ordering::Relaxed),
+            Cmd::MakeMove(m) => self.make_move(m),
+            Cmd::UndoMove => self.undo_move(),
+            Cmd::Tune => self.reply(Cmd::SpsaInput(self.search_params.spsa_input())),
             Cmd::Stop => self.stop(),
             // Workers commands.
             Cmd::Log(s) => self.reply(Cmd::Log(s.to_string())),
             Cmd::WorkerInfo(infos) => self.reply(Cmd::Info(infos.to_vec())),
-            Cmd::WorkerBestMove(m) => self.reply(Cmd::BestMove(*m)),
+            // Searches aborted by a new command are already over.
+            Cmd::WorkerDone(id) => if self.search.as_ref().is_some_and(|s| s.id == *id) {
+                self.finish_search();
+            },
             _ => eprintln!("Not an engine input command: {:?}", cmd),
         }
     }
 
     /// Send a command back to the controlling interface.
     fn reply(&mut self, cmd: Cmd) {
-        match &self.mode {
-            Mode::Uci(tx, _, _) => {
-                tx.send(uci::Cmd::Engine(cmd)).unwrap();
-            }
-            _ => {}
+        if let Mode::Uci(tx, _, _) = &self.mode {
+            tx.send(uci::Cmd::Engine(cmd)).unwrap();
         }
     }
 
     /// Apply a FEN string to the engine state, replacing it.
-    ///
-    /// For speed purposes, it assumes values are always valid.
     fn apply_fen(&mut self, fen: &notation::Fen) {
-        // Placement.
-        self.node.board = board::new_from_fen(&fen.placement);
-        // Color.
-        match fen.color.chars().next().unwrap() {
-            'w' => self.node.game_state.color = board::SQ_WH,
-            'b' => self.node.game_state.color = board::SQ_BL,
-            _ => {}
-        };
-        // Castling.
-        for c in fen.castling.chars() {
-            match c {
-                'K' => self.node.game_state.castling |= castling::CASTLING_WH_K,
-                'Q' => self.node.game_state.castling |= castling::CASTLING_WH_Q,
-                'k' => self.node.game_state.castling |= castling::CASTLING_BL_K,
-                'q' => self.node.game_state.castling |= castling::CASTLING_BL_Q,
-                _ => {}
+        match notation::fen_to_position(fen) {
+            Some((board, game_state)) => {
+                self.node.board = board;
+                self.node.game_state = game_state;
             }
+            None => self.reply(Cmd::Log(format!("Invalid FEN: {:?}", fen))),
         }
-        // En passant.
-        self.node.game_state.en_passant = match fen.en_passant.as_ref() {
-            "-" => None,
-            p => Some(board::pos(p)),
-        };
-        // Half moves.
-        self.node.game_state.halfmove = fen.halfmove.parse::<i32>().ok().unwrap();
-        // Full moves.
-        self.node.game_state.fullmove = fen.fullmove.parse::<i32>().ok().unwrap();
     }
 
     /// Apply a series of moves to the current node.
-    fn apply_moves(&mut self, moves: &Vec<Move>) {
-        moves.iter().for_each(|m| self.apply_move(m));
-    }
-
-    /// Apply a move to the current node.
-    fn apply_move(&mut self, m: &Move) {
-        movement::apply_move_to(&mut self.node.board, &mut self.node.game_state, m);
+    ///
+    /// Moves are applied up to the first illegal one, which is logged
+    /// with the position it was played in.
+    fn apply_moves(&mut self, moves: &[Move]) {
+        for (i, m) in moves.iter().enumerate() {
+            if self.node.play_move(m).is_none() {
+                let message = notation::illegal_move_message(
+                    &self.node.board, &self.node.game_state, i + 1, &notation::move_to_string(m)
+                );
+                self.reply(Cmd::Log(message));
+                return
+            }
+        }
     }
 
     /// Start working on board, returning the best move found.
     ///
     /// Stop working after `movetime` ms, or go on forever if it's -1.
     fn work(&mut self, args: &analysis::AnalysisParams) {
+        // Book moves and cached results of searches as deep as ours are
+        // played at once, so never while pondering as the best move has
+        // to wait, nor for restricted searches.
+        let pondering = self.pondering.load(atomic::Ordering::Relaxed);
+        let in_book = self.book_depth == 0 || self.node.game_state.fullmove <= self.book_depth;
+        let book_move = self.book.as_ref()
+            .filter(|_| self.own_book && in_book && !pondering && self.multi_pv == 1 && args.search_moves.is_empty())
+            .and_then(|b| b.best_move(&self.node.board, &self.node.game_state));
+        if let Some(m) = book_move {
+            let result = analysis::SearchResult {
+                best_move: Some(m),
+                ponder_move: None,
+                score: None,
+                mate: None,
+                bound: tt::Bound::Exact,
+                pv: vec![m],
+                depth: 0,
+                sel_depth: 0,
+                stats: None,
+            };
+            self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String("book move".to_string())]));
+            self.reply(Cmd::SearchResult(result));
+            return
+        }
+        let depth = analysis::search_depth(args, self.max_depth);
+        let cached = self.analysis_cache.as_ref()
+            .filter(|_| !pondering && self.multi_pv == 1 && args.search_moves.is_empty())
+            .and_then(|c| c.get(&self.node.board, &self.node.game_state))
+            .filter(|entry| entry.depth == depth);
+        if let Some(entry) = cached {
+            let mate = analysis::mate_in(entry.score);
+            let infos = vec![
+                analysis::AnalysisInfo::Depth(entry.depth),
+                match mate {
+                    Some(moves) => analysis::AnalysisInfo::Mate(moves),
+                    None => analysis::AnalysisInfo::Score(entry.score),
+                },
+                analysis::AnalysisInfo::String("from analysis cache".to_string()),
+            ];
+            let result = analysis::SearchResult {
+                best_move: Some(entry.best_move),
+                ponder_move: None,
+                score: Some(entry.score),
+                mate,
+                bound: tt::Bound::Exact,
+                pv: vec![entry.best_move],
+                depth: entry.depth,
+                sel_depth: entry.depth,
+                stats: None,
+            };
+            self.reply(Cmd::Info(infos));
+            self.reply(Cmd::SearchResult(result));
+            return
+        }
+        self.num_searches += 1;
+        let id = self.num_searches;
         self.working.store(true, atomic::Ordering::Relaxed);
+        let cacheable = args.search_moves.is_empty();
         let args = args.clone();
         let working = self.working.clone();
         let tx = match &self.mode { Mode::Uci(_, _, tx) => tx.clone(), _ => return };
+        // Helpers of a parallel search share the transposition table
+        // and the node counter with the main worker.
+        let num_nodes = Arc::new(AtomicU64::new(0));
+        let helpers = (1..self.threads).map(|index| {
+            let mut helper = self.new_worker(pondering, tx.clone());
+            helper.set_helper(index);
+            helper.set_node_counter(num_nodes.clone());
+            let (args, working, tx, core) = (args.clone(), working.clone(), tx.clone(), self.thread_core(index));
+            thread::spawn(move || {
+                pin_search_thread(core, &tx);
+                helper.analyze(&args, working);
+                helper.take_eval_caches()
+            })
+        }).collect();
+        let mut worker = self.new_worker(pondering, tx.clone());
+        worker.set_node_counter(num_nodes);
+        // Only the main worker reports, so it alone searches several lines.
+        worker.set_multi_pv(self.multi_pv);
+        let core = self.thread_core(0);
+        let handle = thread::spawn(move || {
+            pin_search_thread(core, &tx);
+            let result = worker.analyze(&args, working);
+            tx.send(Cmd::WorkerDone(id)).unwrap();
+            (result, worker.take_eval_caches())
+        });
+        self.search = Some(Search { id, node: self.node.clone(), handle, helpers, cacheable });
+    }
+
+    /// Return the core of search thread `index` if threads are pinned.
+    fn thread_core(&self, index: usize) -> Option<usize> {
+        self.affinity_cores.as_ref().map(|cores| cores[index % cores.len()])
+    }
+
+    /// Return an analyzer of the current node with the engine settings,
+    /// and evaluation caches of a previous search if there are some left.
+    fn new_worker(&mut self, pondering: bool, tx: mpsc::Sender<Cmd>) -> analysis::Analyzer {
         let mut worker = analysis::Analyzer::new(self.node.clone(), tx);
         worker.debug = self.debug;
-        thread::spawn(move || {
-            worker.analyze(&args, working);
-        });
+        worker.set_transposition_table(self.tt.clone());
+        if let Some(caches) = self.eval_caches.pop() {
+            worker.set_eval_caches(caches);
+        }
+        worker.set_contempt(self.get_contempt());
+        worker.set_info_interval(self.info_interval);
+        // The speed limit applies to the whole search.
+        if self.max_nps > 0 {
+            worker.set_max_nps((self.max_nps / self.threads as u32).max(1));
+        }
+        worker.set_depth_limit(self.max_depth);
+        worker.set_late_move_reductions(self.late_move_reductions);
+        worker.set_history_pruning(self.history_pruning);
+        worker.set_search_params(self.search_params);
+        worker.set_eval_weights(self.eval_weights.clone());
+        if pondering {
+            worker.set_pondering(self.pondering.clone());
+        }
+        worker
+    }
+
+    /// Wait for the running search to end and report its result.
+    fn finish_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            let (result, caches) = search.handle.join().unwrap();
+            self.working.store(false, atomic::Ordering::SeqCst);
+            self.eval_caches.extend(caches);
+            for helper in search.helpers {
+                self.eval_caches.extend(helper.join().unwrap());
+            }
+            if search.cacheable {
+                self.record_search_result(&search.node, &result);
+            }
+            self.reply(Cmd::SearchResult(result));
+        }
+    }
+
+    /// Stop the running search, if any, and wait for its result to be
+    /// reported before going on.
+    ///
+    /// Workers only use their own copy of the position, but the engine
+    /// state (position, pondering flag, analysis cache) has to be left
+    /// untouched until they are done.
+    fn abort_search(&mut self) {
+        if self.search.is_some() {
+            self.stop();
+            self.finish_search();
+        }
+    }
+
+    /// Return the contempt to use in centipawns.
+    ///
+    /// The contempt set by the user is adjusted by the rating advantage
+    /// against the opponent, if known: the engine avoids draws against
+    /// weaker opponents and welcomes them against stronger ones.
+    fn get_contempt(&self) -> i32 {
+        let auto_contempt = (self.rating_advantage / RATING_PER_CONTEMPT_CP)
+            .clamp(-MAX_AUTO_CONTEMPT, MAX_AUTO_CONTEMPT);
+        (self.contempt + auto_contempt).clamp(-analysis::MAX_CONTEMPT, analysis::MAX_CONTEMPT)
+    }
+
+    /// Store the result of a completed search of `node` in the analysis cache.
+    fn record_search_result(&mut self, node: &Node, result: &analysis::SearchResult) {
+        let (m, score, cache) = match (result.best_move, result.score, self.analysis_cache.as_mut()) {
+            (Some(m), Some(score), Some(cache)) if result.depth > 0 => (m, score, cache),
+            _ => return,
+        };
+        let entry = cache::CacheEntry { depth: result.depth, score, best_move: m };
+        if let Err(e) = cache.insert(&node.board, &node.game_state, entry) {
+            self.reply(Cmd::Log(e));
+        }
     }
 
     /// Unset the work flag, stopping workers.
     fn stop(&mut self) {
         self.working.store(false, atomic::Ordering::SeqCst);
+        self.pondering.store(false, atomic::Ordering::SeqCst);
     }
 }
 
@@ -208,49 +552,424 @@ impl Engine {
     pub fn setup_uci(&mut self, uci_s: mpsc::Sender<uci::Cmd>) {
         // Create a channel to receive commands from Uci.
         let (engine_s, engine_r) = mpsc::channel();
+        uci_s.send(uci::Cmd::Engine(Cmd::HashSize(self.tt.size_mb()))).unwrap();
         uci_s.send(uci::Cmd::Engine(Cmd::UciChannel(engine_s.clone()))).unwrap();
         self.mode = Mode::Uci(uci_s, engine_r, engine_s);
         self.listen();
     }
 
     /// Update board state from a "position" command's args.
-    fn uci_position(&mut self, p_args: &Vec<uci::PositionArgs>) {
+    ///
+    /// A running search is aborted first.
+    fn uci_position(&mut self, p_args: &[uci::PositionArgs]) {
+        self.abort_search();
+        self.played_nodes.clear();
         for arg in p_args {
             match arg {
                 uci::PositionArgs::Fen(fen) => {
-                    self.apply_fen(&fen);
+                    self.apply_fen(fen);
                 },
                 uci::PositionArgs::Startpos => {
                     let fen = notation::parse_fen(notation::FEN_START).unwrap();
                     self.apply_fen(&fen);
                 },
                 uci::PositionArgs::Moves(moves) => {
-                    self.apply_moves(&moves);
+                    self.apply_moves(moves);
                 }
             }
         }
     }
 
-    /// Start working using parameters passed with a "go" command.
-    fn uci_go(&mut self, g_args: &Vec<uci::GoArgs>) {
-        let mut args = analysis::AnalysisParams {
-            move_time: -1,
-            white_time: -1,
-            black_time: -1,
-            white_inc: -1,
-            black_inc: -1,
+    /// Play the move `m_str`, in UCI notation, on the current position.
+    fn make_move(&mut self, m_str: &str) {
+        self.abort_search();
+        let previous = self.node.clone();
+        match self.node.play_uci_str(m_str) {
+            Ok(_) => {
+                self.played_nodes.push(previous);
+                self.report_position();
+            }
+            Err(e) => self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(e)])),
+        }
+    }
+
+    /// Go back to the position before the last move played with
+    /// `make_move`, if any since the last "position" command.
+    fn undo_move(&mut self) {
+        self.abort_search();
+        match self.played_nodes.pop() {
+            Some(node) => {
+                self.node = node;
+                self.report_position();
+            }
+            None => {
+                let message = "no move to undo".to_string();
+                self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+            }
+        }
+    }
+
+    /// Report the FEN of the current position.
+    fn report_position(&mut self) {
+        let message = format!("position {}", notation::to_fen(&self.node.board, &self.node.game_state));
+        self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+    }
+
+    /// Set an engine option from a "setoption" command.
+    ///
+    /// Values have been checked against the declaration of the option,
+    /// see `uci::engine_options`.
+    fn uci_set_option(&mut self, name: &str, value: &uci::OptionValue) {
+        use uci::OptionValue::*;
+        match (name, value) {
+            ("Hash", Spin(v)) => self.resize_transposition_table(*v as usize),
+            ("Clear Hash", Button) => self.tt.clear(),
+            ("Profile", Combo(v)) => if let Some(profile) = get_profile(v) {
+                self.apply_profile(profile)
+            },
+            // The interface decides when to ponder, nothing to set.
+            ("Ponder", Check(_)) => {}
+            ("Contempt", Spin(v)) => self.contempt = *v,
+            ("UCI_RatingAdv", Spin(v)) => self.rating_advantage = *v,
+            ("MaxNps", Spin(v)) => self.max_nps = *v as u32,
+            ("MaxDepth", Spin(v)) => self.max_depth = *v as u32,
+            ("LateMoveReductions", Check(v)) => self.late_move_reductions = *v,
+            ("HistoryPruning", Check(v)) => self.history_pruning = *v,
+            ("Threads", Spin(v)) => self.threads = *v as usize,
+            ("MultiPV", Spin(v)) => self.multi_pv = *v as usize,
+            ("Move Overhead", Spin(v)) => self.move_overhead = *v,
+            ("EvalWeights", String(path)) => self.load_eval_weights(path),
+            ("OwnBook", Check(v)) => self.own_book = *v,
+            ("ThreadAffinity", Check(v)) => self.set_thread_affinity(*v),
+            ("Niceness", Spin(v)) => if let Err(e) = cpu::set_niceness(*v) {
+                let message = format!("could not set Niceness to {}: {}", v, e);
+                self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+            },
+            ("BookFile", String(path)) => self.load_book(path),
+            ("BookDepth", Spin(v)) => self.book_depth = *v,
+            // Searches started afterwards use the new value.
+            (name, Spin(v)) if tune::find(name).is_some() => { self.search_params.set(name, *v); }
+            _ => self.reply(Cmd::Log(format!("Unexpected option: {} {:?}", name, value))),
+        }
+    }
+
+    /// Use evaluation weights from the file at `path`, or the built-in
+    /// ones if `path` is empty.
+    ///
+    /// Weights are kept if the file can't be read or parsed. The
+    /// transposition table is cleared as its scores are outdated.
+    fn load_eval_weights(&mut self, path: &str) {
+        let weights = if path.is_empty() {
+            Ok(analysis::SHANNON_WEIGHTS)
+        } else {
+            fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| analysis::EvalWeights::parse(&s))
         };
+        match weights {
+            Ok(weights) => {
+                self.eval_weights = weights;
+                self.tt.clear();
+            }
+            Err(e) => {
+                let message = format!("invalid EvalWeights {}: {}", path, e);
+                self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+            }
+        }
+    }
+
+    /// Pin search threads to the cores the engine can run on, one per
+    /// core in turn, or let the system schedule them.
+    fn set_thread_affinity(&mut self, enabled: bool) {
+        self.affinity_cores = None;
+        if !enabled {
+            return
+        }
+        match cpu::allowed_cores() {
+            Ok(cores) if !cores.is_empty() => self.affinity_cores = Some(cores),
+            Ok(_) => {}
+            Err(e) => {
+                let message = format!("could not set ThreadAffinity: {}", e);
+                self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+            }
+        }
+    }
+
+    /// Use the Polyglot book at `path`, or none if `path` is empty.
+    ///
+    /// The current book is kept if the file can't be read.
+    fn load_book(&mut self, path: &str) {
+        if path.is_empty() {
+            self.book = None;
+            return
+        }
+        match book::Book::open(path) {
+            Ok(book) => self.book = Some(book),
+            Err(e) => {
+                let message = format!("invalid BookFile {}: {}", path, e);
+                self.reply(Cmd::Info(vec![analysis::AnalysisInfo::String(message)]));
+            }
+        }
+    }
+
+    /// Start working using parameters passed with a "go" command.
+    ///
+    /// A running search is aborted first.
+    fn uci_go(&mut self, g_args: &[uci::GoArgs]) {
+        self.abort_search();
+        let mut args = analysis::AnalysisParams::default();
+        let mut pondering = false;
         for arg in g_args {
             match arg {
+                uci::GoArgs::Ponder => pondering = true,
                 uci::GoArgs::MoveTime(ms) => args.move_time = *ms,
-                uci::GoArgs::Infinite => {}
+                uci::GoArgs::Infinite => args.infinite = true,
                 uci::GoArgs::WTime(ms) => args.white_time = *ms,
                 uci::GoArgs::BTime(ms) => args.black_time = *ms,
                 uci::GoArgs::WInc(ms) => args.white_inc = *ms,
                 uci::GoArgs::BInc(ms) => args.black_inc = *ms,
+                uci::GoArgs::MovesToGo(n) => args.moves_to_go = *n,
+                uci::GoArgs::Depth(n) => args.depth = *n,
+                uci::GoArgs::SearchMoves(moves) => args.search_moves = moves.clone(),
                 _ => {}
             }
         }
+        self.remove_move_overhead(&mut args);
+        self.pondering.store(pondering, atomic::Ordering::Relaxed);
         self.work(&args);
     }
+
+    /// Take the move overhead out of the time limits of `args`.
+    fn remove_move_overhead(&self, args: &mut analysis::AnalysisParams) {
+        if args.move_time != -1 {
+            args.move_time = args.move_time.saturating_sub(self.move_overhead).max(time::MIN_TIME_LIMIT);
+        }
+        for clock in [&mut args.white_time, &mut args.black_time] {
+            if *clock != -1 {
+                *clock = clock.saturating_sub(self.move_overhead).max(0);
+            }
+        }
+    }
+}
+
+/// Pin the calling search thread to `core` if any, logging failures.
+fn pin_search_thread(core: Option<usize>, tx: &mpsc::Sender<Cmd>) {
+    if let Some(core) = core {
+        if let Err(e) = cpu::pin_current_thread(core) {
+            tx.send(Cmd::Log(format!("could not pin search thread to core {}: {}", core, e))).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::*;
+
+    /// Return an engine in UCI mode that is not listening, with the
+    /// receiver of its replies.
+    fn uci_engine() -> (Engine, mpsc::Receiver<uci::Cmd>) {
+        let (uci_tx, uci_rx) = mpsc::channel();
+        let (engine_tx, engine_rx) = mpsc::channel();
+        let mut engine = Engine::new();
+        engine.mode = Mode::Uci(uci_tx, engine_rx, engine_tx);
+        (engine, uci_rx)
+    }
+
+    fn best_moves(uci_rx: &mpsc::Receiver<uci::Cmd>) -> Vec<Option<Move>> {
+        uci_rx.try_iter().filter_map(|cmd| match cmd {
+            uci::Cmd::Engine(Cmd::SearchResult(result)) => Some(result.best_move),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_restart_search() {
+        let (mut engine, uci_rx) = uci_engine();
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Infinite]));
+        assert!(engine.search.is_some());
+
+        // A new position aborts the search, which reports its best move
+        // for the previous position before the position changes.
+        let e4 = notation::parse_move("e2e4").unwrap();
+        engine.handle_command(&Cmd::UciPosition(vec![
+            uci::PositionArgs::Startpos,
+            uci::PositionArgs::Moves(vec![e4]),
+        ]));
+        assert!(engine.search.is_none());
+        let moves = best_moves(&uci_rx);
+        assert_eq!(moves.len(), 1);
+        assert!(moves[0].is_some_and(|m| m.0.1 < 4));
+        assert_eq!(engine.node.board[pos_index(&(4, 3))], SQ_WH_P);
+
+        // The end notification of the aborted worker does not finish the
+        // next search, which a new go aborts as well.
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Infinite]));
+        engine.handle_command(&Cmd::WorkerDone(1));
+        assert!(engine.search.as_ref().is_some_and(|s| s.id == 2));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Infinite]));
+        assert!(engine.search.as_ref().is_some_and(|s| s.id == 3));
+        let moves = best_moves(&uci_rx);
+        assert_eq!(moves.len(), 1);
+        assert!(moves[0].is_some_and(|m| m.0.1 > 4));
+        engine.abort_search();
+        assert_eq!(best_moves(&uci_rx).len(), 1);
+    }
+
+    #[test]
+    fn test_make_move() {
+        let (mut engine, uci_rx) = uci_engine();
+        let info_strings = || -> Vec<String> {
+            uci_rx.try_iter().filter_map(|cmd| match cmd {
+                uci::Cmd::Engine(Cmd::Info(infos)) => match &infos[..] {
+                    [analysis::AnalysisInfo::String(s)] => Some(s.to_string()),
+                    _ => None,
+                },
+                _ => None,
+            }).collect()
+        };
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::MakeMove("e2e4".to_string()));
+        engine.handle_command(&Cmd::MakeMove("e7e5".to_string()));
+        assert_eq!(engine.node.board[pos_index(&(4, 4))], SQ_BL_P);
+        engine.handle_command(&Cmd::MakeMove("e2e4".to_string()));
+        let messages = info_strings();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[1].starts_with("position rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 "));
+        assert!(messages[2].starts_with("Illegal move e2e4"));
+
+        engine.handle_command(&Cmd::UndoMove);
+        assert_eq!(engine.node.board[pos_index(&(4, 4))], SQ_E);
+        assert_eq!(engine.node.board[pos_index(&(4, 3))], SQ_WH_P);
+        engine.handle_command(&Cmd::UndoMove);
+        assert_eq!(engine.node.board, new());
+        engine.handle_command(&Cmd::UndoMove);
+        assert_eq!(info_strings().last().unwrap(), "no move to undo");
+        // A new position forgets moves played before.
+        engine.handle_command(&Cmd::MakeMove("d2d4".to_string()));
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::UndoMove);
+        assert_eq!(info_strings().last().unwrap(), "no move to undo");
+    }
+
+    #[test]
+    fn test_set_option() {
+        let (mut engine, uci_rx) = uci_engine();
+        engine.handle_command(&Cmd::UciSetOption("Move Overhead", uci::OptionValue::Spin(100)));
+        let mut args = analysis::AnalysisParams { move_time: 50, white_time: 1000, ..Default::default() };
+        engine.remove_move_overhead(&mut args);
+        assert_eq!((args.move_time, args.white_time, args.black_time), (time::MIN_TIME_LIMIT, 900, -1));
+        let mut args = analysis::AnalysisParams { move_time: i32::MIN, white_time: i32::MIN, black_time: i32::MAX, ..Default::default() };
+        engine.remove_move_overhead(&mut args);
+        assert_eq!((args.move_time, args.white_time, args.black_time), (time::MIN_TIME_LIMIT, 0, i32::MAX - 100));
+
+        let weights = analysis::EvalWeights { knight: 4.0, ..analysis::SHANNON_WEIGHTS };
+        engine.eval_weights = weights.clone();
+        engine.handle_command(&Cmd::UciSetOption("EvalWeights", uci::OptionValue::String("/nonexistent".to_string())));
+        assert_eq!(engine.eval_weights, weights);
+        assert!(uci_rx.try_recv().is_ok());
+        engine.handle_command(&Cmd::UciSetOption("EvalWeights", uci::OptionValue::String(String::new())));
+        assert_eq!(engine.eval_weights, analysis::SHANNON_WEIGHTS);
+
+        // Tunables belong to each engine.
+        let (other, _other_rx) = uci_engine();
+        engine.handle_command(&Cmd::UciSetOption("LMR_BASE", uci::OptionValue::Spin(120)));
+        assert_eq!(engine.search_params.lmr_base, 120);
+        assert_eq!(other.search_params, tune::SearchParams::default());
+        uci_rx.try_iter().for_each(drop);
+        engine.handle_command(&Cmd::Tune);
+        match uci_rx.try_recv() {
+            Ok(uci::Cmd::Engine(Cmd::SpsaInput(input))) => assert!(input.contains("LMR_BASE, int, 120,")),
+            _ => panic!("no SPSA input"),
+        }
+    }
+
+    #[test]
+    fn test_own_book() {
+        let (mut engine, uci_rx) = uci_engine();
+        // A book playing d2d4 from the start.
+        let key = book::polyglot_key(&new(), &crate::rules::GameState::new());
+        let mut data = key.to_be_bytes().to_vec();
+        data.extend_from_slice(&[0x02, 0xdb, 0x00, 0x01, 0, 0, 0, 0]);
+        engine.book = Some(book::Book::from_bytes(data).unwrap());
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.search.is_some());
+        engine.finish_search();
+        uci_rx.try_iter().for_each(drop);
+        // Book moves are played at once, and searches resume out of book.
+        engine.handle_command(&Cmd::UciSetOption("OwnBook", uci::OptionValue::Check(true)));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.search.is_none());
+        assert_eq!(best_moves(&uci_rx), vec![notation::parse_move("d2d4")]);
+        engine.handle_command(&Cmd::MakeMove("d2d4".to_string()));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.search.is_some());
+        engine.finish_search();
+        // The book is left after BookDepth moves.
+        let fen = notation::parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 5").unwrap();
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Fen(fen)]));
+        engine.handle_command(&Cmd::UciSetOption("BookDepth", uci::OptionValue::Spin(4)));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.search.is_some());
+        engine.finish_search();
+        engine.handle_command(&Cmd::UciSetOption("BookDepth", uci::OptionValue::Spin(5)));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.search.is_none());
+
+        engine.handle_command(&Cmd::UciSetOption("BookFile", uci::OptionValue::String("/nonexistent".to_string())));
+        assert!(engine.book.is_some());
+        engine.handle_command(&Cmd::UciSetOption("BookFile", uci::OptionValue::String(String::new())));
+        assert!(engine.book.is_none());
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        let (mut engine, uci_rx) = uci_engine();
+        engine.handle_command(&Cmd::UciSetOption("Threads", uci::OptionValue::Spin(3)));
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(3)]));
+        assert_eq!(engine.search.as_ref().unwrap().helpers.len(), 2);
+        // Helpers are stopped once the main worker is done.
+        loop {
+            let cmd = match &engine.mode {
+                Mode::Uci(_, rx, _) => rx.recv().unwrap(),
+                Mode::No => unreachable!(),
+            };
+            let done = matches!(cmd, Cmd::WorkerDone(_));
+            engine.handle_command(&cmd);
+            if done {
+                break
+            }
+        }
+        assert!(engine.search.is_none());
+        let results: Vec<analysis::SearchResult> = uci_rx.try_iter().filter_map(|cmd| match cmd {
+            uci::Cmd::Engine(Cmd::SearchResult(result)) => Some(result),
+            _ => None,
+        }).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].depth, 3);
+        assert!(results[0].best_move.is_some());
+        // Each thread gives back its evaluation caches for the next search.
+        assert_eq!(engine.eval_caches.len(), 3);
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(1)]));
+        assert!(engine.eval_caches.is_empty());
+        engine.finish_search();
+        assert_eq!(engine.eval_caches.len(), 3);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_thread_affinity() {
+        let (mut engine, uci_rx) = uci_engine();
+        engine.handle_command(&Cmd::UciSetOption("ThreadAffinity", uci::OptionValue::Check(true)));
+        let cores = engine.affinity_cores.clone().unwrap();
+        assert_eq!(engine.thread_core(cores.len()), Some(cores[0]));
+        engine.handle_command(&Cmd::UciSetOption("Threads", uci::OptionValue::Spin(2)));
+        engine.handle_command(&Cmd::UciPosition(vec![uci::PositionArgs::Startpos]));
+        engine.handle_command(&Cmd::UciGo(vec![uci::GoArgs::Depth(2)]));
+        engine.finish_search();
+        assert_eq!(best_moves(&uci_rx).len(), 1);
+        engine.handle_command(&Cmd::UciSetOption("ThreadAffinity", uci::OptionValue::Check(false)));
+        assert_eq!(engine.thread_core(0), None);
+    }
 }
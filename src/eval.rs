@@ -0,0 +1,9 @@
+//! Evaluation terms.
+//!
+//! Terms are combined with their weights in `analysis::evaluate_node`.
+
+pub mod activity;
+pub mod imbalance;
+pub mod pawns;
+pub mod phase;
+pub mod pst;
@@ -0,0 +1,138 @@
+//! Piece activity.
+//!
+//! Pieces are rewarded for their mobility, the squares they attack that
+//! are neither occupied by a friend piece nor attacked by an enemy pawn,
+//! and for a few good squares: rooks on files without friend pawns and
+//! knights on outposts, squares protected by a friend pawn on the enemy
+//! side that no enemy pawn can attack anymore.
+
+use crate::attacks::{self, Bitboard, FILE_A};
+use crate::board::*;
+use crate::eval::pawns;
+
+/// Piece activity features of one side.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ActivityCounts {
+    /// Safe squares attacked by knights, bishops, rooks and queens.
+    pub mobility: i32,
+    /// Rooks on files without pawns.
+    pub rooks_on_open_files: i8,
+    /// Rooks on files with only enemy pawns.
+    pub rooks_on_semi_open_files: i8,
+    pub knight_outposts: i8,
+}
+
+/// Piece activity features of both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Activity {
+    pub white: ActivityCounts,
+    pub black: ActivityCounts,
+}
+
+impl Activity {
+    /// Count activity features of pieces on `board`.
+    pub fn new(board: &Board) -> Activity {
+        let occupancy = attacks::occupancy(board);
+        let (white_pawns, black_pawns) = pawns::pawn_bitboards(board);
+        let mut pieces: [Bitboard; 2] = [0, 0];
+        for (piece, p) in get_piece_iterator(board) {
+            pieces[is_black(piece) as usize] |= 1 << pos_square(&p);
+        }
+        let pawn_attacks = [all_pawn_attacks(white_pawns, SQ_WH), all_pawn_attacks(black_pawns, SQ_BL)];
+
+        let mut activity = Activity::default();
+        for (piece, p) in get_piece_iterator(board) {
+            let sq = pos_square(&p);
+            let color = get_color(piece);
+            // Index of the side in `pieces` and `pawn_attacks`.
+            let side = is_black(color) as usize;
+            let (own_pawns, enemy_pawns) = if is_white(color) {
+                (white_pawns, black_pawns)
+            } else {
+                (black_pawns, white_pawns)
+            };
+            let counts = if is_white(color) { &mut activity.white } else { &mut activity.black };
+            let attacked = match get_type(piece) {
+                SQ_B => attacks::bishop_attacks(sq, occupancy),
+                SQ_N => attacks::knight_attacks(sq),
+                SQ_R => attacks::rook_attacks(sq, occupancy),
+                SQ_Q => attacks::queen_attacks(sq, occupancy),
+                _ => continue,
+            };
+            counts.mobility += (attacked & !pieces[side] & !pawn_attacks[1 - side]).count_ones() as i32;
+
+            let file = FILE_A << (sq % 8);
+            match get_type(piece) {
+                SQ_R if own_pawns & file == 0 => {
+                    if enemy_pawns & file == 0 {
+                        counts.rooks_on_open_files += 1;
+                    } else {
+                        counts.rooks_on_semi_open_files += 1;
+                    }
+                }
+                SQ_N if is_outpost(sq, color, own_pawns, enemy_pawns) => counts.knight_outposts += 1,
+                _ => {}
+            }
+        }
+        activity
+    }
+
+    /// Return the features of the pieces of `color`.
+    pub fn of(&self, color: u8) -> &ActivityCounts {
+        if is_white(color) { &self.white } else { &self.black }
+    }
+}
+
+/// Return the squares attacked by `pawns` of `color`.
+fn all_pawn_attacks(pawns: Bitboard, color: u8) -> Bitboard {
+    attacks::squares(pawns).fold(0, |bb, sq| bb | attacks::pawn_attacks(sq, color))
+}
+
+/// Return true if `sq` is an outpost for a piece of `color`: on the
+/// 4th to 6th rank from its side, protected by one of `own_pawns` and
+/// out of reach of `enemy_pawns`.
+fn is_outpost(sq: usize, color: u8, own_pawns: Bitboard, enemy_pawns: Bitboard) -> bool {
+    let (file, rank) = (sq % 8, sq / 8);
+    let relative_rank = if is_white(color) { rank } else { 7 - rank };
+    (3..=5).contains(&relative_rank)
+        && attacks::pawn_attacks(sq, opposite(color)) & own_pawns != 0
+        && enemy_pawns & pawns::adjacent_files(file) & pawns::forward_ranks(rank, color) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn activity(fen: &str) -> Activity {
+        let (board, _) = notation::position(fen);
+        Activity::new(&board)
+    }
+
+    #[test]
+    fn test_activity() {
+        let start = activity(notation::FEN_START);
+        assert_eq!(start.white, ActivityCounts { mobility: 4, ..Default::default() });
+        assert_eq!(start.white, start.black);
+
+        // The knight has 8 squares and an outpost protected by the d4
+        // pawn; the a1 rook has 9 squares, taking a7 but not the king,
+        // and the h1 rook 9 as well. Kings do not count.
+        let expected = ActivityCounts {
+            mobility: 8 + 9 + 9,
+            rooks_on_open_files: 1,
+            rooks_on_semi_open_files: 1,
+            knight_outposts: 1,
+        };
+        let a = activity("4k3/p7/8/4N3/3P4/8/8/R3K2R w - - 0 1");
+        assert_eq!(a.white, expected);
+        assert_eq!(a.of(SQ_BL), &ActivityCounts::default());
+        // Black pieces are counted the same way.
+        let a = activity("r3k2r/8/8/3p4/4n3/8/P7/4K3 b - - 0 1");
+        assert_eq!(a.black, expected);
+        // Squares attacked by enemy pawns are not safe, here c6, and a
+        // knight that can be chased by a pawn is not on an outpost.
+        let a = activity("4k3/1p6/3p4/4N3/3P4/8/8/4K3 w - - 0 1");
+        assert_eq!(a.white, ActivityCounts { mobility: 7, ..Default::default() });
+    }
+}
@@ -0,0 +1,177 @@
+//! Material imbalance.
+//!
+//! Piece values depend on the rest of the material, after Larry
+//! Kaufman's "The Evaluation of Material Imbalances": knights gain value
+//! with pawns on the board while rooks lose some, a second rook or a
+//! rook beside a queen is partly redundant, and the bishop pair is worth
+//! more as pawns disappear. Bonuses only depend on piece counts, so they
+//! are cached by material key in an `ImbalanceTable`.
+
+use crate::board::*;
+use crate::hash_cache::HashCache;
+
+/// Default number of entries in an imbalance table.
+pub const DEFAULT_TABLE_ENTRIES: usize = 1 << 12;
+
+/// Bonus of each knight per own pawn above 5, in centipawns.
+const KNIGHT_PAWN_BONUS: i32 = 6;
+/// Penalty of each rook per own pawn above 5, in centipawns.
+const ROOK_PAWN_PENALTY: i32 = 12;
+/// Bonus of the bishop pair with all 16 pawns on the board.
+const BISHOP_PAIR_BONUS: i32 = 30;
+/// Bishop pair bonus added per missing pawn, of both sides.
+const BISHOP_PAIR_PAWN_BONUS: i32 = 2;
+/// Penalty of having more than one rook.
+const ROOK_PAIR_PENALTY: i32 = 25;
+/// Penalty of having a queen and at least one rook.
+const QUEEN_ROOK_PENALTY: i32 = 12;
+
+/// Piece counts of one side, kings excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MaterialCounts {
+    pub pawns: u8,
+    pub knights: u8,
+    pub bishops: u8,
+    pub rooks: u8,
+    pub queens: u8,
+}
+
+impl MaterialCounts {
+    fn add(&mut self, piece_type: u8) {
+        match piece_type {
+            SQ_P => self.pawns += 1,
+            SQ_N => self.knights += 1,
+            SQ_B => self.bishops += 1,
+            SQ_R => self.rooks += 1,
+            SQ_Q => self.queens += 1,
+            _ => {}
+        }
+    }
+
+    /// Pack counts in 20 bits, 4 per piece type; counts above 15 can
+    /// only come from many promotions and are saturated.
+    fn key(&self) -> u64 {
+        [self.pawns, self.knights, self.bishops, self.rooks, self.queens].iter()
+            .fold(0, |key, count| key << 4 | (*count).min(15) as u64)
+    }
+}
+
+/// Piece counts of both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Material {
+    pub white: MaterialCounts,
+    pub black: MaterialCounts,
+}
+
+impl Material {
+    /// Count pieces on `board`.
+    pub fn new(board: &Board) -> Material {
+        let mut material = Material::default();
+        for (piece, _) in get_piece_iterator(board) {
+            let counts = if is_white(piece) { &mut material.white } else { &mut material.black };
+            counts.add(get_type(piece));
+        }
+        material
+    }
+
+    /// Return a key identifying the piece counts of both sides.
+    pub fn key(&self) -> u64 {
+        self.white.key() << 20 | self.black.key()
+    }
+}
+
+/// Imbalance bonuses of both sides, in centipawns.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Imbalance {
+    pub white: i32,
+    pub black: i32,
+}
+
+impl Imbalance {
+    /// Compute bonuses of `material`.
+    pub fn new(material: &Material) -> Imbalance {
+        let all_pawns = (material.white.pawns + material.black.pawns) as i32;
+        Imbalance {
+            white: side_imbalance(&material.white, all_pawns),
+            black: side_imbalance(&material.black, all_pawns),
+        }
+    }
+
+    /// Return the bonus of `color`.
+    pub fn of(&self, color: u8) -> i32 {
+        if is_white(color) { self.white } else { self.black }
+    }
+}
+
+/// Return the imbalance bonus of a side with pieces `own`, with
+/// `all_pawns` pawns on the board.
+fn side_imbalance(own: &MaterialCounts, all_pawns: i32) -> i32 {
+    let extra_pawns = own.pawns as i32 - 5;
+    let mut bonus = own.knights as i32 * extra_pawns * KNIGHT_PAWN_BONUS
+        - own.rooks as i32 * extra_pawns * ROOK_PAWN_PENALTY;
+    if own.bishops >= 2 {
+        bonus += BISHOP_PAIR_BONUS + (16 - all_pawns).max(0) * BISHOP_PAIR_PAWN_BONUS;
+    }
+    if own.rooks >= 2 {
+        bonus -= ROOK_PAIR_PENALTY;
+    }
+    if own.queens > 0 && own.rooks > 0 {
+        bonus -= QUEEN_ROOK_PENALTY;
+    }
+    bonus
+}
+
+/// Cache of imbalances by material key.
+pub struct ImbalanceTable {
+    cache: HashCache<u64, Imbalance>,
+}
+
+impl ImbalanceTable {
+    /// Create a table with `num_entries` entries, see `HashCache::new`.
+    pub fn new(num_entries: usize) -> ImbalanceTable {
+        ImbalanceTable { cache: HashCache::new(num_entries) }
+    }
+
+    /// Return the imbalance of `board`, from the table if possible.
+    pub fn get(&mut self, board: &Board) -> Imbalance {
+        let material = Material::new(board);
+        let key = material.key();
+        let hash = key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+        self.cache.get_or_insert_with(key, hash, || Imbalance::new(&material))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    #[test]
+    fn test_imbalance() {
+        // The starting position is balanced: knights get 18 each, rooks
+        // lose 36 each plus the pair and queen redundancies, and bishops
+        // get the pair bonus.
+        let start = Imbalance::new(&Material::new(&new()));
+        assert_eq!(start.white, 36 - 72 - 25 - 12 + 30);
+        assert_eq!(start.white, start.black);
+
+        // With few pawns, the bishop pair is worth more and rooks gain
+        // value over knights.
+        let material = Material::new(&notation::position("4k3/pp3n2/8/8/8/8/PP6/2B1KB2 w - - 0 1").0);
+        assert_eq!(material.white, MaterialCounts { pawns: 2, bishops: 2, ..MaterialCounts::default() });
+        let imbalance = Imbalance::new(&material);
+        assert_eq!(imbalance.of(SQ_WH), 30 + 12 * 2);
+        assert_eq!(imbalance.of(SQ_BL), -3 * 6);
+    }
+
+    #[test]
+    fn test_imbalance_table() {
+        let b = notation::position("4k3/pp3n2/8/8/8/8/PP6/2B1KB2 w - - 0 1").0;
+        let expected = Imbalance::new(&Material::new(&b));
+        assert_ne!(Material::new(&b).key(), Material::new(&new()).key());
+        let mut table = ImbalanceTable::new(1);
+        assert_eq!(table.get(&b), expected);
+        assert_eq!(table.get(&new()), Imbalance::new(&Material::new(&new())));
+        assert_eq!(table.get(&b), expected);
+    }
+}
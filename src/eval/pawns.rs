@@ -0,0 +1,203 @@
+//! Pawn structure.
+//!
+//! Pawns are counted by structure feature for each side, from the
+//! pawn bitboards only: weak pawns (doubled, isolated, backward),
+//! passed pawns, candidate passers and pawns protected by another pawn,
+//! i.e. members of a pawn chain. Pawns move rarely, so the structure
+//! of a position is usually one seen before; `PawnTable` caches them.
+
+use crate::attacks::{self, Bitboard, FILE_A};
+use crate::board::*;
+use crate::hash_cache::HashCache;
+
+/// Default number of entries in a pawn hash table.
+pub const DEFAULT_TABLE_ENTRIES: usize = 1 << 14;
+
+/// Pawn structure features of one side, in number of pawns.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PawnCounts {
+    /// Pawns with a friend pawn in front of them on their file.
+    pub doubled: i8,
+    /// Pawns without friend pawns on adjacent files.
+    pub isolated: i8,
+    /// Pawns that no friend pawn can protect, with their stop square
+    /// controlled by an enemy pawn.
+    pub backward: i8,
+    /// Pawns without enemy pawns in front of them, on their file or
+    /// adjacent files.
+    pub passed: i8,
+    /// Pawns on a half-open file with at least as many friend pawns
+    /// beside or behind them as enemy pawns in front on adjacent files.
+    pub candidates: i8,
+    /// Pawns protected by a friend pawn.
+    pub protected: i8,
+}
+
+impl PawnCounts {
+    /// Return the number of doubled, isolated and backward pawns.
+    pub fn weak(&self) -> i8 {
+        self.doubled + self.isolated + self.backward
+    }
+}
+
+/// Pawn structure features of both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PawnStructure {
+    pub white: PawnCounts,
+    pub black: PawnCounts,
+}
+
+impl PawnStructure {
+    /// Analyse the structure of `white_pawns` and `black_pawns`.
+    pub fn new(white_pawns: Bitboard, black_pawns: Bitboard) -> PawnStructure {
+        PawnStructure {
+            white: count_features(white_pawns, black_pawns, SQ_WH),
+            black: count_features(black_pawns, white_pawns, SQ_BL),
+        }
+    }
+
+    /// Return the features of the pawns of `color`.
+    pub fn of(&self, color: u8) -> &PawnCounts {
+        if is_white(color) { &self.white } else { &self.black }
+    }
+}
+
+/// Return the white and black pawn bitboards of `board`.
+pub fn pawn_bitboards(board: &Board) -> (Bitboard, Bitboard) {
+    let (mut white, mut black) = (0, 0);
+    for (piece, p) in get_piece_iterator(board) {
+        if is_type(piece, SQ_P) {
+            let bb = if is_white(piece) { &mut white } else { &mut black };
+            *bb |= 1 << pos_square(&p);
+        }
+    }
+    (white, black)
+}
+
+/// Return the squares of ranks in front of `rank` for `color`.
+pub(crate) const fn forward_ranks(rank: usize, color: u8) -> Bitboard {
+    if is_white(color) {
+        if rank == 7 { 0 } else { !0 << ((rank + 1) * 8) }
+    } else {
+        (1 << (rank * 8)) - 1
+    }
+}
+
+/// Return the squares of files adjacent to `file`.
+pub(crate) const fn adjacent_files(file: usize) -> Bitboard {
+    let left = if file > 0 { FILE_A << (file - 1) } else { 0 };
+    let right = if file < 7 { FILE_A << (file + 1) } else { 0 };
+    left | right
+}
+
+/// Count structure features of pawns `own` of `color` facing `enemy` pawns.
+fn count_features(own: Bitboard, enemy: Bitboard, color: u8) -> PawnCounts {
+    let mut counts = PawnCounts::default();
+    for sq in attacks::squares(own) {
+        let (file, rank) = (sq % 8, sq / 8);
+        let forward = forward_ranks(rank, color);
+        let front_span = FILE_A << file & forward;
+        let adjacent = adjacent_files(file);
+        let supporters = own & adjacent & !forward;
+        let sentries = enemy & adjacent & forward;
+        let doubled = own & front_span != 0;
+        let isolated = own & adjacent == 0;
+        let passed = !doubled && enemy & front_span == 0 && sentries == 0;
+
+        counts.doubled += doubled as i8;
+        counts.isolated += isolated as i8;
+        counts.passed += passed as i8;
+        if !passed && !doubled && enemy & front_span == 0
+            && supporters.count_ones() >= sentries.count_ones() {
+            counts.candidates += 1;
+        }
+        // The stop square is controlled by enemy pawns that a pawn of
+        // ours there would attack.
+        let stop = if is_white(color) { sq + 8 } else { sq - 8 };
+        if !isolated && supporters == 0 && attacks::pawn_attacks(stop, color) & enemy != 0 {
+            counts.backward += 1;
+        }
+        if attacks::pawn_attacks(sq, opposite(color)) & own != 0 {
+            counts.protected += 1;
+        }
+    }
+    counts
+}
+
+/// Cache of pawn structures by pawn bitboards.
+pub struct PawnTable {
+    cache: HashCache<(Bitboard, Bitboard), PawnStructure>,
+}
+
+impl PawnTable {
+    /// Create a table with `num_entries` entries, see `HashCache::new`.
+    pub fn new(num_entries: usize) -> PawnTable {
+        PawnTable { cache: HashCache::new(num_entries) }
+    }
+
+    /// Return the pawn structure of `board`, from the table if possible.
+    pub fn get(&mut self, board: &Board) -> PawnStructure {
+        let (white_pawns, black_pawns) = pawn_bitboards(board);
+        let hash = (white_pawns ^ black_pawns.rotate_left(32)).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 16;
+        self.cache.get_or_insert_with((white_pawns, black_pawns), hash, || {
+            PawnStructure::new(white_pawns, black_pawns)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn structure(fen: &str) -> PawnStructure {
+        let (board, _) = notation::position(fen);
+        let (white_pawns, black_pawns) = pawn_bitboards(&board);
+        PawnStructure::new(white_pawns, black_pawns)
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        // The starting position has no weak nor passed pawns, and no
+        // pawn is protected yet.
+        let start = structure(notation::FEN_START);
+        assert_eq!(start.white, PawnCounts::default());
+        assert_eq!(start.white, start.black);
+
+        // White has doubled and isolated a-pawns, the front one being
+        // passed like the c-pawn, and a candidate d-pawn supported by
+        // the c- and e-pawns against the e6 pawn. Pawns on d5 and f5
+        // are protected.
+        let s = structure("4k3/5p1p/4p3/3PpP2/2P1P3/P7/P5P1/4K3 w - - 0 1");
+        assert_eq!(s.white, PawnCounts {
+            doubled: 1,
+            isolated: 2,
+            backward: 0,
+            passed: 2,
+            candidates: 1,
+            protected: 2,
+        });
+        assert_eq!(s.of(SQ_BL), &PawnCounts { doubled: 1, isolated: 1, protected: 1, ..PawnCounts::default() });
+
+        // The d3 pawn can't be protected and c5 controls d4.
+        let s = structure("4k3/8/8/2p5/2P1P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(s.white.backward, 1);
+        assert_eq!(s.white.protected, 2);
+        assert_eq!(s.black.backward, 0);
+    }
+
+    #[test]
+    fn test_pawn_table() {
+        let (board, _) = notation::position("4k3/pp6/8/8/8/8/7P/4K3 w - - 0 1");
+        let (white_pawns, black_pawns) = pawn_bitboards(&board);
+        let expected = PawnStructure::new(white_pawns, black_pawns);
+        let mut table = PawnTable::new(DEFAULT_TABLE_ENTRIES);
+        assert_eq!(table.get(&board), expected);
+        assert_eq!(table.get(&board), expected);
+        // Collisions replace entries without corrupting results.
+        let mut table = PawnTable::new(1);
+        assert_eq!(table.get(&board), expected);
+        assert_eq!(table.get(&new()), structure(notation::FEN_START));
+        assert_eq!(table.get(&board), expected);
+    }
+}
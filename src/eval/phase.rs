@@ -0,0 +1,71 @@
+//! Game phase.
+//!
+//! The phase of a position measures how far it is from the endgame,
+//! from the pieces left on the board: minor pieces count for 1, rooks
+//! for 2 and queens for 4, pawns and kings for nothing. The scale is
+//! stable: 0 is a pawn endgame and `MAX_PHASE` the material of the
+//! starting position, extra pieces from promotions not counting beyond
+//! it. The evaluation uses it to blend its midgame and endgame scores,
+//! and front-ends can use it e.g. to decide when to leave the book.
+
+use crate::board::*;
+
+/// Phase of a position with all pieces.
+pub const MAX_PHASE: i32 = 24;
+
+/// Lowest phase of the opening stage: at most a minor piece pair traded.
+pub const OPENING_MIN_PHASE: i32 = 22;
+/// Highest phase of the endgame stage, e.g. a rook and a minor piece
+/// each, or queens only.
+pub const ENDGAME_MAX_PHASE: i32 = 8;
+
+/// Stage of a game, a coarse view of its phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+/// Return the phase of `board`, from 0 to `MAX_PHASE`.
+pub fn phase(board: &Board) -> i32 {
+    let phase: i32 = get_piece_iterator(board).map(|(square, _)| match get_type(square) {
+        SQ_B | SQ_N => 1,
+        SQ_R => 2,
+        SQ_Q => 4,
+        _ => 0,
+    }).sum();
+    phase.min(MAX_PHASE)
+}
+
+/// Return the stage of a position of `phase`.
+pub fn stage(phase: i32) -> Stage {
+    if phase >= OPENING_MIN_PHASE {
+        Stage::Opening
+    } else if phase > ENDGAME_MAX_PHASE {
+        Stage::Middlegame
+    } else {
+        Stage::Endgame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    #[test]
+    fn test_phase() {
+        assert_eq!(phase(&new()), MAX_PHASE);
+        assert_eq!(stage(phase(&new())), Stage::Opening);
+        assert_eq!(phase(&notation::position("k7/8/8/8/3N4/3K4/8/n7 w - - 0 1").0), 2);
+        assert_eq!(phase(&notation::position("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1").0), 0);
+        // Queens traded.
+        let middlegame = notation::position("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1").0;
+        assert_eq!(phase(&middlegame), MAX_PHASE - 8);
+        assert_eq!(stage(phase(&middlegame)), Stage::Middlegame);
+        assert_eq!(stage(phase(&notation::position("3rk3/8/8/8/8/8/8/2BRK3 w - - 0 1").0)), Stage::Endgame);
+        // Promotions do not go beyond the scale.
+        assert_eq!(phase(&notation::position("qqqqkqqq/8/8/8/8/8/8/QQQQKQQQ w - - 0 1").0), MAX_PHASE);
+    }
+}
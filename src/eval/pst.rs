@@ -0,0 +1,220 @@
+//! Piece-square tables.
+//!
+//! Each piece gets a bonus or a malus in centipawns depending on its
+//! square, from a midgame and an endgame table that are blended by game
+//! phase, see the `phase` module. Values are those of the "Simplified Evaluation Function" of
+//! Tomasz Michniewski, with endgame tables for pawns and kings.
+//!
+//! The score of a position is the sum of its pieces bonuses, white
+//! minus black. As a move only changes a few squares, the score of the
+//! resulting position can be updated from the pieces it changed, see
+//! `update_score`.
+
+use std::ops::{AddAssign, SubAssign};
+
+use crate::board::*;
+use crate::eval::phase::MAX_PHASE;
+use crate::movement::DirtyPieces;
+use crate::score::WhiteScore;
+
+// Tables are from white's point of view, rank 8 first so that they
+// read like a board.
+
+const PAWN_MG: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const PAWN_EG: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    80,  80,  80,  80,  80,  80,  80,  80,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    30,  30,  30,  30,  30,  30,  30,  30,
+    15,  15,  15,  15,  15,  15,  15,  15,
+     5,   5,   5,   5,   5,   5,   5,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const KNIGHT: [i32; 64] = [
+   -50, -40, -30, -30, -30, -30, -40, -50,
+   -40, -20,   0,   0,   0,   0, -20, -40,
+   -30,   0,  10,  15,  15,  10,   0, -30,
+   -30,   5,  15,  20,  20,  15,   5, -30,
+   -30,   0,  15,  20,  20,  15,   0, -30,
+   -30,   5,  10,  15,  15,  10,   5, -30,
+   -40, -20,   0,   5,   5,   0, -20, -40,
+   -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+const BISHOP: [i32; 64] = [
+   -20, -10, -10, -10, -10, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,  10,  10,   5,   0, -10,
+   -10,   5,   5,  10,  10,   5,   5, -10,
+   -10,   0,  10,  10,  10,  10,   0, -10,
+   -10,  10,  10,  10,  10,  10,  10, -10,
+   -10,   5,   0,   0,   0,   0,   5, -10,
+   -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+const ROOK: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+const QUEEN: [i32; 64] = [
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,   5,   5,   5,   0, -10,
+    -5,   0,   5,   5,   5,   5,   0,  -5,
+     0,   0,   5,   5,   5,   5,   0,  -5,
+   -10,   5,   5,   5,   5,   5,   0, -10,
+   -10,   0,   5,   0,   0,   0,   0, -10,
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+const KING_MG: [i32; 64] = [
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -20, -30, -30, -40, -40, -30, -30, -20,
+   -10, -20, -20, -20, -20, -20, -20, -10,
+    20,  20,   0,   0,   0,   0,  20,  20,
+    20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+const KING_EG: [i32; 64] = [
+   -50, -40, -30, -20, -20, -30, -40, -50,
+   -30, -20, -10,   0,   0, -10, -20, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -30,   0,   0,   0,   0, -30, -30,
+   -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+/// Midgame and endgame scores in centipawns, white minus black.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PstScore {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl PstScore {
+    /// Blend midgame and endgame scores for a position of `phase`.
+    pub fn tapered(&self, phase: i32) -> WhiteScore {
+        let phase = phase.clamp(0, MAX_PHASE);
+        WhiteScore((self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE)
+    }
+}
+
+impl AddAssign for PstScore {
+    fn add_assign(&mut self, other: PstScore) {
+        self.mg += other.mg;
+        self.eg += other.eg;
+    }
+}
+
+impl SubAssign for PstScore {
+    fn sub_assign(&mut self, other: PstScore) {
+        self.mg -= other.mg;
+        self.eg -= other.eg;
+    }
+}
+
+/// Return the score of `piece` at `pos`, or 0 for an empty square.
+fn piece_score(piece: u8, pos: &Pos) -> PstScore {
+    let (mg_table, eg_table) = match get_type(piece) {
+        SQ_P => (&PAWN_MG, &PAWN_EG),
+        SQ_B => (&BISHOP, &BISHOP),
+        SQ_N => (&KNIGHT, &KNIGHT),
+        SQ_R => (&ROOK, &ROOK),
+        SQ_Q => (&QUEEN, &QUEEN),
+        SQ_K => (&KING_MG, &KING_EG),
+        _ => return PstScore::default(),
+    };
+    // Black pieces use the table mirrored vertically.
+    let (f, r) = (pos.0 as usize, pos.1 as usize);
+    let (index, sign) = if is_white(piece) { ((7 - r) * 8 + f, 1) } else { (r * 8 + f, -1) };
+    PstScore { mg: sign * mg_table[index], eg: sign * eg_table[index] }
+}
+
+/// Compute the score of a position.
+pub fn score(board: &Board) -> PstScore {
+    let mut score = PstScore::default();
+    for (piece, pos) in get_piece_iterator(board) {
+        score += piece_score(piece, &pos);
+    }
+    score
+}
+
+/// Return the score of a position after a move, from `score`, the
+/// score before it, and the `dirty` pieces it changed.
+pub fn update_score(score: PstScore, dirty: &DirtyPieces) -> PstScore {
+    let mut score = score;
+    for dirty_piece in dirty.as_slice() {
+        if let Some(from) = &dirty_piece.from {
+            score -= piece_score(dirty_piece.piece, from);
+        }
+        if let Some(to) = &dirty_piece.to {
+            score += piece_score(dirty_piece.piece, to);
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+    use crate::random;
+
+    #[test]
+    fn test_score() {
+        // The starting position is symmetrical.
+        let board = new();
+        assert_eq!(score(&board), PstScore::default());
+        // A centralized knight is better than one on the rim, a king in
+        // the center is worse in the midgame but better in the endgame.
+        let (board, _) = notation::position("k7/8/8/8/3N4/3K4/8/n7 w - - 0 1");
+        let score = score(&board);
+        assert_eq!(score, PstScore { mg: 20 + 50 - 20 - 20, eg: 20 + 50 + 30 + 50 });
+        assert_eq!(score.tapered(0), WhiteScore(score.eg));
+        assert_eq!(score.tapered(MAX_PHASE), WhiteScore(score.mg));
+    }
+
+    #[test]
+    fn test_update_score() {
+        // Updated scores match full computations along random games,
+        // with castles, en passant captures and promotions.
+        let mut rng = random::Rng::new(42);
+        for _ in 0..20 {
+            let mut node = random::random_node_from_moves(&mut rng, 0);
+            let mut current = score(&node.board);
+            for _ in 0..100 {
+                let moves = node.get_player_moves(true);
+                if moves.is_empty() {
+                    break
+                }
+                let m = moves[rng.below(moves.len())];
+                current = update_score(current, &node.apply_move(&m));
+                assert_eq!(current, score(&node.board));
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! External UCI engines.
+//!
+//! Vatu can delegate the analysis of a position to another UCI engine,
+//! e.g. when used as a front-end rather than as the sole analyst.
+//! Results are kept apart from Vatu's own so callers can label them.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::board::Board;
+use crate::movement::Move;
+use crate::notation;
+use crate::rules::GameState;
+use crate::score::RelativeScore;
+
+/// An external UCI engine process.
+pub struct ExternalEngine {
+    /// Name reported by the engine, or its path if it did not report any.
+    pub name: String,
+    process: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+/// Results of an external engine analysis.
+#[derive(Debug, Default, PartialEq)]
+pub struct ExternalAnalysis {
+    pub best_move: Option<Move>,
+    /// Score in centipawns for the playing side, as reported by the engine.
+    pub score: Option<RelativeScore>,
+    /// Mate in this number of moves, negative if the playing side gets mated.
+    pub mate: Option<i32>,
+    /// Last depth reported.
+    pub depth: Option<u32>,
+}
+
+impl ExternalEngine {
+    /// Start the engine at `path` and wait for it to be ready.
+    pub fn start(path: &str) -> Result<ExternalEngine, String> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not start {}: {}", path, e))?;
+        let stdin = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        let mut engine = ExternalEngine { name: path.to_string(), process, stdin, stdout };
+        engine.send("uci")?;
+        loop {
+            let line = engine.read_line()?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.to_string();
+            } else if line == "uciok" {
+                break
+            }
+        }
+        engine.wait_ready()?;
+        Ok(engine)
+    }
+
+    /// Analyze a position for `move_time` ms.
+    pub fn analyze(
+        &mut self,
+        board: &Board,
+        game_state: &GameState,
+        move_time: i32,
+    ) -> Result<ExternalAnalysis, String> {
+        self.send("ucinewgame")?;
+        self.wait_ready()?;
+        self.send(&format!("position fen {}", notation::to_fen(board, game_state)))?;
+        self.send(&format!("go movetime {}", move_time))?;
+        let mut analysis = ExternalAnalysis::default();
+        loop {
+            let line = self.read_line()?;
+            if let Some(info) = line.strip_prefix("info ") {
+                update_from_info(&mut analysis, info);
+            } else if let Some(best_move) = line.strip_prefix("bestmove") {
+                analysis.best_move = best_move.split_whitespace().next().and_then(notation::parse_move);
+                return Ok(analysis)
+            }
+        }
+    }
+
+    fn wait_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        while self.read_line()? != "readyok" {}
+        Ok(())
+    }
+
+    fn send(&mut self, s: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", s)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("Could not write to {}: {}", self.name, e))
+    }
+
+    /// Read a line from the engine, without surrounding whitespaces.
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.stdout.read_line(&mut line) {
+            Ok(0) => Err(format!("{} closed its output", self.name)),
+            Ok(_) => Ok(line.trim().to_string()),
+            Err(e) => Err(format!("Could not read from {}: {}", self.name, e)),
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Update `analysis` with the content of an "info" line (without "info").
+fn update_from_info(analysis: &mut ExternalAnalysis, info: &str) {
+    let fields: Vec<&str> = info.split_whitespace().collect();
+    let value = |i: usize| fields.get(i + 1).and_then(|v| v.parse::<i32>().ok());
+    for (i, field) in fields.iter().enumerate() {
+        match *field {
+            "depth" => if let Some(depth) = value(i) { analysis.depth = Some(depth.max(0) as u32) },
+            "cp" if i > 0 && fields[i - 1] == "score" => if let Some(cp) = value(i) {
+                analysis.score = Some(RelativeScore(cp));
+                analysis.mate = None;
+            },
+            "mate" if i > 0 && fields[i - 1] == "score" => if let Some(mate) = value(i) {
+                analysis.mate = Some(mate);
+                analysis.score = None;
+            },
+            // The rest of the line is a free-form message.
+            "string" => break,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_from_info() {
+        let mut analysis = ExternalAnalysis::default();
+        update_from_info(&mut analysis, "depth 12 seldepth 15 score cp 34 nodes 1000 pv e2e4");
+        assert_eq!(analysis.depth, Some(12));
+        assert_eq!(analysis.score, Some(RelativeScore(34)));
+        update_from_info(&mut analysis, "depth 13 score cp -12 lowerbound");
+        assert_eq!((analysis.depth, analysis.score), (Some(13), Some(RelativeScore(-12))));
+        update_from_info(&mut analysis, "depth 20 score mate -3");
+        assert_eq!((analysis.score, analysis.mate), (None, Some(-3)));
+        // Strings and unrelated info are ignored.
+        update_from_info(&mut analysis, "string depth 1 score cp 1");
+        update_from_info(&mut analysis, "nodes 10 nps 100 currmove e2e4");
+        assert_eq!(
+            analysis,
+            ExternalAnalysis { best_move: None, score: None, mate: Some(-3), depth: Some(20) }
+        );
+    }
+}
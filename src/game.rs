@@ -1,2 +1,166 @@
-//! Function to actually play games.
+//! Games: moves played from a starting position, with annotations and a
+//! cursor to navigate them, e.g. for studies.
+//!
+//! Plies are numbered from 0, the starting position, to the number of
+//! moves played; the annotation of a ply is about the move leading to
+//! it, or about the game for ply 0. There are no variations: playing a
+//! move before the last ply drops the moves after it.
 
+use crate::board::Board;
+use crate::movement::Move;
+use crate::node::Node;
+use crate::rules::GameState;
+
+/// Comment and NAGs (Numeric Annotation Glyphs, e.g. 1 for "!") of a
+/// ply.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotation {
+    pub comment: Option<String>,
+    pub nags: Vec<u8>,
+}
+
+/// A game, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Game {
+    /// PGN tags, in order, e.g. ("Event", "Casual").
+    pub tags: Vec<(String, String)>,
+    /// Result: "1-0", "0-1", "1/2-1/2" or "*" if unknown.
+    pub result: String,
+    moves: Vec<Move>,
+    /// Positions of each ply, from the starting position.
+    nodes: Vec<Node>,
+    annotations: Vec<Annotation>,
+    ply: usize,
+}
+
+impl Game {
+    /// Create a game without moves from this position.
+    pub fn new(board: &Board, game_state: &GameState) -> Game {
+        Game {
+            tags: vec!(),
+            result: "*".to_string(),
+            moves: vec!(),
+            nodes: vec!(Node { board: *board, game_state: game_state.clone() }),
+            annotations: vec!(Annotation::default()),
+            ply: 0,
+        }
+    }
+
+    /// Return the moves of the game.
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Return the number of moves of the game, i.e. its last ply.
+    pub fn num_plies(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Return the ply of the cursor.
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// Return the position at ply `ply`, if the game is that long.
+    pub fn node_at(&self, ply: usize) -> Option<&Node> {
+        self.nodes.get(ply)
+    }
+
+    /// Return the position at the cursor.
+    pub fn node(&self) -> &Node {
+        &self.nodes[self.ply]
+    }
+
+    /// Return the move leading to the cursor, None at the start.
+    pub fn last_move(&self) -> Option<Move> {
+        self.ply.checked_sub(1).map(|i| self.moves[i])
+    }
+
+    /// Move the cursor to `ply`; return false if the game is shorter.
+    pub fn goto_ply(&mut self, ply: usize) -> bool {
+        if ply > self.num_plies() {
+            return false
+        }
+        self.ply = ply;
+        true
+    }
+
+    /// Move the cursor one ply forward; return false at the end.
+    pub fn next_ply(&mut self) -> bool {
+        self.goto_ply(self.ply + 1)
+    }
+
+    /// Move the cursor one ply back; return false at the start.
+    pub fn prev_ply(&mut self) -> bool {
+        self.ply > 0 && self.goto_ply(self.ply - 1)
+    }
+
+    /// Play `m` at the cursor and move the cursor after it.
+    ///
+    /// Moves after the cursor and their annotations are dropped. Return
+    /// the move actually played, see `Node::play_move`, or an error if
+    /// it is illegal.
+    pub fn play_move(&mut self, m: &Move) -> Result<Move, String> {
+        let mut node = self.node().clone();
+        let m = node.play_move(m).ok_or_else(|| format!("Illegal move at ply {}", self.ply + 1))?;
+        self.moves.truncate(self.ply);
+        self.nodes.truncate(self.ply + 1);
+        self.annotations.truncate(self.ply + 1);
+        self.moves.push(m);
+        self.nodes.push(node);
+        self.annotations.push(Annotation::default());
+        self.ply += 1;
+        Ok(m)
+    }
+
+    /// Return the annotation of ply `ply`, if the game is that long.
+    pub fn annotation(&self, ply: usize) -> Option<&Annotation> {
+        self.annotations.get(ply)
+    }
+
+    /// Return the annotation of ply `ply` to change it, if the game is
+    /// that long.
+    pub fn annotation_mut(&mut self, ply: usize) -> Option<&mut Annotation> {
+        self.annotations.get_mut(ply)
+    }
+
+    /// Return the value of tag `name`, if any.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board;
+    use crate::notation::parse_move;
+
+    #[test]
+    fn test_game_navigation() {
+        let mut game = Game::new(&board::new(), &GameState::new());
+        for m in &["e2e4", "e7e5", "g1f3"] {
+            game.play_move(&parse_move(m).unwrap()).unwrap();
+        }
+        assert!(game.play_move(&parse_move("e1e3").unwrap()).is_err());
+        assert_eq!((game.ply(), game.num_plies()), (3, 3));
+        assert!(!game.next_ply());
+        assert!(game.prev_ply());
+        assert_eq!(game.last_move(), parse_move("e7e5"));
+        assert!(game.goto_ply(0));
+        assert!(!game.prev_ply() && game.last_move().is_none());
+        assert!(!game.goto_ply(4));
+        assert!(game.next_ply());
+        assert_eq!(game.node().game_state.color, board::SQ_BL);
+
+        // Annotate, then play another move at ply 2.
+        game.annotation_mut(2).unwrap().comment = Some("Symmetrical".to_string());
+        game.annotation_mut(3).unwrap().nags.push(1);
+        assert!(game.goto_ply(2));
+        game.play_move(&parse_move("b1c3").unwrap()).unwrap();
+        assert_eq!(game.annotation(2).unwrap().comment.as_deref(), Some("Symmetrical"));
+        assert_eq!(game.annotation(3), Some(&Annotation::default()));
+        assert_eq!(game.moves()[2], parse_move("b1c3").unwrap());
+        assert!(game.annotation(4).is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Fixed-size caches of computed values, for the perft, pawn structure
+//! and imbalance tables.
+//!
+//! Unlike the transposition table, caches belong to a single thread and
+//! entries are always replaced on collision.
+
+/// Fixed-size cache of values of type `V`, indexed by a hash of their
+/// key of type `K`.
+pub struct HashCache<K, V> {
+    entries: Vec<Option<(K, V)>>,
+}
+
+impl<K: Copy + PartialEq, V: Copy> HashCache<K, V> {
+    /// Create a cache with `num_entries` entries, which must be strictly
+    /// positive.
+    pub fn new(num_entries: usize) -> HashCache<K, V> {
+        HashCache { entries: vec![None; num_entries] }
+    }
+
+    /// Return the value of `key`, with hash `hash`, if cached.
+    pub fn get(&self, key: K, hash: u64) -> Option<V> {
+        match self.entries[self.index(hash)] {
+            Some((k, value)) if k == key => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Cache `value` for `key`, with hash `hash`.
+    pub fn insert(&mut self, key: K, hash: u64, value: V) {
+        let index = self.index(hash);
+        self.entries[index] = Some((key, value));
+    }
+
+    /// Return the value of `key`, with hash `hash`, computing it with `f`
+    /// and caching it if it is not cached.
+    pub fn get_or_insert_with(&mut self, key: K, hash: u64, f: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.get(key, hash) {
+            return value
+        }
+        let value = f();
+        self.insert(key, hash, value);
+        value
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        (hash % self.entries.len() as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_cache() {
+        let mut cache = HashCache::new(2);
+        assert_eq!(cache.get(0u64, 0), None);
+        cache.insert(1, 1, 10);
+        assert_eq!(cache.get(1, 1), Some(10));
+        // Colliding keys replace each other.
+        cache.insert(3, 3, 30);
+        assert_eq!(cache.get(1, 1), None);
+        assert_eq!(cache.get_or_insert_with(3, 3, || panic!("cached")), 30);
+        assert_eq!(cache.get_or_insert_with(2, 2, || 20), 20);
+        assert_eq!(cache.get(2, 2), Some(20));
+    }
+}
@@ -0,0 +1,43 @@
+//! Vatu chess engine library.
+//!
+//! The `vatu` binary is only a command-line front-end to these modules.
+
+pub mod analysis;
+pub mod attacks;
+pub mod board;
+pub mod book;
+pub mod cache;
+pub mod capabilities;
+pub mod castling;
+pub mod cpu;
+pub mod dataset;
+pub mod endgame;
+pub mod engine;
+pub mod eval;
+pub mod external;
+pub mod game;
+pub mod hash_cache;
+pub mod move_ordering;
+pub mod movement;
+pub mod node;
+pub mod notation;
+pub mod oracle;
+pub mod perft;
+pub mod play;
+pub mod pgn;
+pub mod puzzles;
+pub mod random;
+pub mod reductions;
+pub mod rules;
+pub mod score;
+pub mod selftest;
+pub mod stats;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod tactics;
+pub mod time;
+pub mod tt;
+pub mod tune;
+pub mod uci;
+pub mod xboard;
+pub mod zobrist;
@@ -1,15 +1,19 @@
-use clap::{App, Arg};
-
-pub mod analysis;
-pub mod board;
-pub mod castling;
-pub mod engine;
-pub mod movement;
-pub mod node;
-pub mod notation;
-pub mod rules;
-pub mod stats;
-pub mod uci;
+use clap::{App, Arg, SubCommand};
+
+use std::fs;
+use std::sync::{Arc, atomic, mpsc};
+use std::thread;
+use std::time::Instant;
+
+use vatu::{analysis, board, cache, capabilities, dataset, engine, external, node, notation, perft, pgn, play, puzzles, selftest, tt, uci, xboard};
+
+/// Exit code when a subcommand ran fine but reported failures.
+const EXIT_FAILURES: i32 = 1;
+/// Exit code for invalid arguments or inputs.
+const EXIT_USAGE: i32 = 2;
+
+/// Minimum depth of a cached analysis to print it instead of searching.
+const MIN_CACHED_DEPTH: u32 = 4;
 
 fn main() {
     let args = App::new("Vatu")
@@ -19,9 +23,450 @@ fn main() {
         .arg(Arg::with_name("log_file")
             .help("Log file path (default is stderr)")
             .long("log-file").takes_value(true).required(false))
+        .arg(Arg::with_name("record_session")
+            .help("Record UCI commands with their time in FILE, to replay them for bug reports")
+            .long("record-session").value_name("FILE").takes_value(true).required(false)
+            .global(true))
+        .arg(Arg::with_name("xboard")
+            .help("Use the XBoard protocol, also used if the first command is \"xboard\"")
+            .long("xboard").takes_value(false).required(false))
+        .arg(Arg::with_name("session_summary")
+            .help("Log statistics of the UCI session on quit")
+            .long("session-summary").takes_value(false).required(false))
+        .arg(Arg::with_name("quiet")
+            .help("Only print final results, in a machine-parseable format")
+            .short("q").long("quiet").takes_value(false).required(false).global(true))
+        .arg(Arg::with_name("analysis_cache")
+            .help("Reuse and store results of previous analyses in FILE")
+            .long("analysis-cache").value_name("FILE").takes_value(true).required(false)
+            .global(true))
+        .arg(Arg::with_name("profile")
+            .help("Engine settings for a use case: tournament (default), bullet or analysis")
+            .long("profile").value_name("NAME").takes_value(true).required(false)
+            .global(true))
+        .subcommand(SubCommand::with_name("selftest")
+            .about("Run internal test suites")
+            .arg(Arg::with_name("rules")
+                .help("Run the rules suite, from FILE if provided")
+                .long("rules").value_name("FILE").takes_value(true).min_values(0))
+            .arg(Arg::with_name("movegen")
+                .help("Compare legal moves with a reference generator on N random positions")
+                .long("movegen").value_name("N").takes_value(true))
+            .arg(Arg::with_name("seed")
+                .help("Seed for random positions (default is 1)")
+                .long("seed").takes_value(true))
+            .arg(Arg::with_name("eval_diff")
+                .help("Compare evaluations with two sets of weights on the positions of FILE")
+                .long("eval-diff").value_name("FILE").takes_value(true))
+            .arg(Arg::with_name("weights_a")
+                .help("First evaluation weights (default is the built-in ones)")
+                .long("weights-a").value_name("FILE").takes_value(true))
+            .arg(Arg::with_name("weights_b")
+                .help("Second evaluation weights (default is the built-in ones)")
+                .long("weights-b").value_name("FILE").takes_value(true))
+            .arg(Arg::with_name("threshold")
+                .help("Evaluation difference in cp reported by --eval-diff (default is 50)")
+                .long("threshold").value_name("CP").takes_value(true)))
+        .subcommand(SubCommand::with_name("perft")
+            .about("Count leaf nodes of the legal moves tree")
+            .arg(Arg::with_name("depth")
+                .help("Depth of the tree")
+                .long("depth").takes_value(true).required(true))
+            .arg(Arg::with_name("fen")
+                .help("Root position (default is the starting position)")
+                .long("fen").takes_value(true).required(false))
+            .arg(Arg::with_name("no_hash")
+                .help("Do not use a hash table, for strict validation")
+                .long("no-hash").takes_value(false).required(false)))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Replay a UCI session recorded with --record-session")
+            .arg(Arg::with_name("file")
+                .help("Recording file")
+                .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("features")
+            .about("Print build features, detected CPU features and table sizes, for bug reports"))
+        .subcommand(SubCommand::with_name("eval-features")
+            .about("Print unweighted evaluation features of positions, for fitting weights")
+            .arg(Arg::with_name("file")
+                .help("File of positions, a FEN per line")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("format")
+                .help("Output format: csv (default) or json, an object per line")
+                .long("format").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("puzzles")
+            .about("Find puzzles after blunders in the games of a PGN file")
+            .arg(Arg::with_name("file")
+                .help("PGN file")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("scan_depth")
+                .help("Search depth of every position (default is 3)")
+                .long("scan-depth").value_name("N").takes_value(true))
+            .arg(Arg::with_name("verify_depth")
+                .help("Search depth checking puzzles (default is 5)")
+                .long("verify-depth").value_name("N").takes_value(true))
+            .arg(Arg::with_name("min_score")
+                .help("Minimum score of solutions in cp (default is 300)")
+                .long("min-score").value_name("CP").takes_value(true))
+            .arg(Arg::with_name("format")
+                .help("Output format: epd (default) or pgn")
+                .long("format").takes_value(true)))
+        .subcommand(SubCommand::with_name("play")
+            .about("Play against Vatu in the terminal")
+            .arg(Arg::with_name("color")
+                .help("Color to play: white (default) or black")
+                .long("color").takes_value(true))
+            .arg(Arg::with_name("fen")
+                .help("Starting position (default is the starting position)")
+                .long("fen").takes_value(true))
+            .arg(Arg::with_name("movetime")
+                .help("Search time of Vatu per move in ms (default is 1000, unlimited with --depth)")
+                .long("movetime").takes_value(true))
+            .arg(Arg::with_name("depth")
+                .help("Maximum search depth of Vatu, to play weaker")
+                .long("depth").takes_value(true)))
+        .subcommand(SubCommand::with_name("analyze")
+            .about("Analyze a position and print the best move")
+            .arg(Arg::with_name("fen")
+                .help("Position to analyze (default is the starting position)")
+                .long("fen").takes_value(true).required(false))
+            .arg(Arg::with_name("movetime")
+                .help("Analysis time in ms (default is 1000)")
+                .long("movetime").takes_value(true).required(false))
+            .arg(Arg::with_name("external_engine")
+                .help("Also analyze with the UCI engine at PATH")
+                .long("external-engine").value_name("PATH").takes_value(true).required(false)))
         .get_matches();
 
-    let debug = args.is_present("debug");
-    let output = args.value_of("log_file");
-    uci::Uci::start(debug, output);
+    let quiet = args.is_present("quiet");
+    let analysis_cache = args.value_of("analysis_cache");
+    let profile = args.value_of("profile").map(|name| match engine::get_profile(name) {
+        Some(profile) => profile,
+        None => { eprintln!("Unknown profile {}.", name); std::process::exit(EXIT_USAGE) }
+    });
+    match args.subcommand() {
+        ("selftest", Some(sub_args)) => {
+            let mut num_failures = 0;
+            if sub_args.is_present("rules") {
+                match selftest::run_rules_suite(sub_args.value_of("rules"), quiet) {
+                    Ok(results) => {
+                        let num_passed = results.num_tests - results.num_failures;
+                        if quiet {
+                            println!("rules {} {}", num_passed, results.num_tests);
+                        } else {
+                            println!("Rules: {}/{} passed.", num_passed, results.num_tests);
+                        }
+                        num_failures += results.num_failures;
+                    }
+                    Err(e) => { eprintln!("{}", e); std::process::exit(EXIT_USAGE) }
+                }
+            }
+            if let Some(num_positions) = sub_args.value_of("movegen") {
+                let num_positions = match num_positions.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => { eprintln!("Invalid number of positions."); std::process::exit(EXIT_USAGE) }
+                };
+                let seed = match sub_args.value_of("seed").unwrap_or("1").parse::<u64>() {
+                    Ok(seed) => seed,
+                    Err(_) => { eprintln!("Invalid seed."); std::process::exit(EXIT_USAGE) }
+                };
+                let results = selftest::run_movegen_suite(num_positions, seed, quiet);
+                let num_passed = results.num_tests - results.num_failures;
+                if quiet {
+                    println!("movegen {} {}", num_passed, results.num_tests);
+                } else {
+                    println!("Movegen: {}/{} passed.", num_passed, results.num_tests);
+                }
+                num_failures += results.num_failures;
+            }
+            if let Some(path) = sub_args.value_of("eval_diff") {
+                let weights_a = get_eval_weights(sub_args.value_of("weights_a"));
+                let weights_b = get_eval_weights(sub_args.value_of("weights_b"));
+                let threshold = match sub_args.value_of("threshold").unwrap_or("50").parse::<i32>() {
+                    Ok(cp) if cp >= 0 => cp,
+                    _ => { eprintln!("Invalid threshold."); std::process::exit(EXIT_USAGE) }
+                };
+                match selftest::run_eval_comparison(path, &weights_a, &weights_b, threshold, quiet) {
+                    Ok(results) => {
+                        let num_passed = results.num_tests - results.num_failures;
+                        if quiet {
+                            println!("eval {} {}", num_passed, results.num_tests);
+                        } else {
+                            println!("Eval: {}/{} within {} cp.", num_passed, results.num_tests, threshold);
+                        }
+                        num_failures += results.num_failures;
+                    }
+                    Err(e) => { eprintln!("{}", e); std::process::exit(EXIT_USAGE) }
+                }
+            }
+            std::process::exit(if num_failures == 0 { 0 } else { EXIT_FAILURES });
+        }
+        ("perft", Some(sub_args)) => {
+            let depth = match sub_args.value_of("depth").unwrap().parse::<u32>() {
+                Ok(depth) => depth,
+                Err(_) => { eprintln!("Invalid depth."); std::process::exit(EXIT_USAGE) }
+            };
+            let root = get_root_node(sub_args.value_of("fen"));
+            let start = Instant::now();
+            let count = if sub_args.is_present("no_hash") {
+                perft::perft(&root, depth)
+            } else {
+                let mut table = perft::PerftTable::new(perft::DEFAULT_TABLE_ENTRIES);
+                perft::perft_hashed(&root, depth, &mut table)
+            };
+            if quiet {
+                println!("{}", count);
+            } else {
+                println!("Perft({}) = {} ({} ms)", depth, count, start.elapsed().as_millis());
+            }
+        }
+        ("features", Some(_)) => println!("{}", capabilities::detect()),
+        ("eval-features", Some(sub_args)) => {
+            let format = match dataset::Format::parse(sub_args.value_of("format").unwrap_or("csv")) {
+                Some(format) => format,
+                None => { eprintln!("Invalid format."); std::process::exit(EXIT_USAGE) }
+            };
+            let path = sub_args.value_of("file").unwrap();
+            match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| dataset::export_features(&s, format)) {
+                Ok(output) => println!("{}", output),
+                Err(e) => { eprintln!("Could not export features of {}: {}", path, e); std::process::exit(EXIT_USAGE) }
+            }
+        }
+        ("puzzles", Some(sub_args)) => {
+            let parse_arg = |name: &str, default: i32| match sub_args.value_of(name).map(|v| v.parse::<i32>()) {
+                None => default,
+                Some(Ok(value)) if value > 0 => value,
+                _ => { eprintln!("Invalid {}.", name.replace('_', " ")); std::process::exit(EXIT_USAGE) }
+            };
+            let params = puzzles::PuzzleParams {
+                scan_depth: parse_arg("scan_depth", puzzles::DEFAULT_PARAMS.scan_depth as i32) as u32,
+                verify_depth: parse_arg("verify_depth", puzzles::DEFAULT_PARAMS.verify_depth as i32) as u32,
+                min_score: parse_arg("min_score", puzzles::DEFAULT_PARAMS.min_score),
+                ..puzzles::DEFAULT_PARAMS
+            };
+            let pgn_format = match sub_args.value_of("format").unwrap_or("epd") {
+                "epd" => false,
+                "pgn" => true,
+                _ => { eprintln!("Invalid format."); std::process::exit(EXIT_USAGE) }
+            };
+            let path = sub_args.value_of("file").unwrap();
+            let games = match fs::File::open(path).map_err(|e| e.to_string()).and_then(pgn::read_games) {
+                Ok(games) => games,
+                Err(e) => { eprintln!("Could not read games of {}: {}", path, e); std::process::exit(EXIT_USAGE) }
+            };
+            for (i, game) in games.iter().enumerate() {
+                for puzzle in puzzles::find_puzzles(game, &params) {
+                    let id = format!("{} game {} ply {}", path, i + 1, puzzle.ply);
+                    if pgn_format {
+                        println!("{}", pgn::write_game(&puzzle.to_game(&id)));
+                    } else {
+                        println!("{}", puzzle.to_epd(&id));
+                    }
+                }
+            }
+        }
+        ("play", Some(sub_args)) => {
+            let human_color = match sub_args.value_of("color").unwrap_or("white") {
+                "white" => board::SQ_WH,
+                "black" => board::SQ_BL,
+                _ => { eprintln!("Invalid color."); std::process::exit(EXIT_USAGE) }
+            };
+            let depth = match sub_args.value_of("depth").map(|v| v.parse::<i32>()) {
+                None => -1,
+                Some(Ok(depth)) if depth > 0 => depth,
+                _ => { eprintln!("Invalid depth."); std::process::exit(EXIT_USAGE) }
+            };
+            let default_move_time = if depth > 0 { -1 } else { play::DEFAULT_PARAMS.move_time };
+            let move_time = match sub_args.value_of("movetime").map(|v| v.parse::<i32>()) {
+                None => default_move_time,
+                Some(Ok(ms)) if ms > 0 => ms,
+                _ => { eprintln!("Invalid move time."); std::process::exit(EXIT_USAGE) }
+            };
+            let params = play::PlayParams {
+                human_color,
+                move_time,
+                depth,
+                hash_mb: profile.unwrap_or(&engine::PROFILES[0]).hash_mb,
+            };
+            let root = get_root_node(sub_args.value_of("fen"));
+            let stdin = std::io::stdin();
+            play::Play::new(&root, params, std::io::stdout()).run(&mut stdin.lock());
+        }
+        ("analyze", Some(sub_args)) => {
+            let move_time = match sub_args.value_of("movetime").unwrap_or("1000").parse::<i32>() {
+                Ok(ms) if ms > 0 => ms,
+                _ => { eprintln!("Invalid move time."); std::process::exit(EXIT_USAGE) }
+            };
+            let root = get_root_node(sub_args.value_of("fen"));
+            // Run the external engine in parallel, if any.
+            let external_handle = sub_args.value_of("external_engine").map(|path| {
+                let path = path.to_string();
+                let root = root.clone();
+                thread::spawn(move || {
+                    let mut engine = external::ExternalEngine::start(&path)?;
+                    let analysis = engine.analyze(&root.board, &root.game_state, move_time)?;
+                    Ok::<_, String>((engine.name.clone(), analysis))
+                })
+            });
+            let mut cache = analysis_cache.map(|path| match cache::AnalysisCache::open(path) {
+                Ok(cache) => cache,
+                Err(e) => { eprintln!("{}", e); std::process::exit(EXIT_USAGE) }
+            });
+            analyze(&root, move_time, profile.unwrap_or(&engine::PROFILES[0]), cache.as_mut(), quiet);
+            if let Some(handle) = external_handle {
+                match handle.join().unwrap() {
+                    Ok((name, analysis)) => print_external_analysis(&name, &analysis, quiet),
+                    Err(e) => { eprintln!("External engine failed: {}", e); std::process::exit(EXIT_FAILURES) }
+                }
+            }
+        }
+        (subcommand, sub_args) => {
+            let replay = match (subcommand, sub_args) {
+                ("replay", Some(sub_args)) => match uci::load_recording(sub_args.value_of("file").unwrap()) {
+                    Ok(recording) => Some(recording),
+                    Err(e) => { eprintln!("{}", e); std::process::exit(EXIT_USAGE) }
+                },
+                _ => None,
+            };
+            let debug = args.is_present("debug");
+            let output = args.value_of("log_file");
+            let session_summary = args.is_present("session_summary");
+            let record = args.value_of("record_session");
+            if args.is_present("xboard") {
+                // Recording, replaying and summaries only know UCI sessions.
+                if session_summary || record.is_some() || replay.is_some() {
+                    eprintln!("--record-session, --session-summary and replay are not supported with --xboard.");
+                    std::process::exit(EXIT_USAGE)
+                }
+                xboard::Xboard::start(debug, output, analysis_cache, profile);
+                return
+            }
+            uci::Uci::start(debug, output, analysis_cache, profile, session_summary, record, replay);
+        }
+    }
+}
+
+/// Return a node for this FEN, or the starting position if None.
+///
+/// Exit if the FEN is invalid.
+fn get_root_node(fen: Option<&str>) -> node::Node {
+    let mut root = node::Node::new();
+    match fen {
+        Some(fen) => {
+            match notation::parse_fen(fen).and_then(|fen| notation::fen_to_position(&fen)) {
+                Some((board, game_state)) => {
+                    root.board = board;
+                    root.game_state = game_state;
+                }
+                None => { eprintln!("Invalid FEN."); std::process::exit(EXIT_USAGE) }
+            }
+        }
+        None => root.board = board::new(),
+    }
+    root
+}
+
+/// Return evaluation weights read from the file at `path`, or the
+/// built-in ones if None.
+///
+/// Exit if the file can't be read or parsed.
+fn get_eval_weights(path: Option<&str>) -> analysis::EvalWeights {
+    let path = match path {
+        Some(path) => path,
+        None => return analysis::SHANNON_WEIGHTS,
+    };
+    match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| analysis::EvalWeights::parse(&s)) {
+        Ok(weights) => weights,
+        Err(e) => { eprintln!("Invalid weights {}: {}", path, e); std::process::exit(EXIT_USAGE) }
+    }
+}
+
+/// Analyze `root` with Vatu for `move_time` ms, with the settings of
+/// `profile`, and print results.
+///
+/// If an analysis cache is provided, use its result if there is one
+/// of at least `MIN_CACHED_DEPTH`, else store the result of the search in it.
+fn analyze(
+    root: &node::Node,
+    move_time: i32,
+    profile: &engine::Profile,
+    cache: Option<&mut cache::AnalysisCache>,
+    quiet: bool,
+) {
+    let cached = cache.as_ref()
+        .and_then(|c| c.get(&root.board, &root.game_state))
+        .filter(|entry| entry.depth >= MIN_CACHED_DEPTH)
+        .cloned();
+    let (best_move, score, depth) = match &cached {
+        Some(entry) => (Some(entry.best_move), Some(entry.score), Some(entry.depth)),
+        None => {
+            let result = search(root, move_time, profile, quiet);
+            (result.best_move, result.score, Some(result.depth).filter(|d| *d > 0))
+        }
+    };
+    if cached.is_none() {
+        if let (Some(cache), Some(m), Some(score), Some(depth)) = (cache, best_move, score, depth) {
+            let entry = cache::CacheEntry { depth, score, best_move: m };
+            if let Err(e) = cache.insert(&root.board, &root.game_state, entry) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    let move_str = best_move.map(|m| notation::move_to_string(&m))
+        .unwrap_or_else(|| notation::NULL_MOVE.to_string());
+    let score_str = match score {
+        Some(cp) => match analysis::mate_in(cp) {
+            Some(moves) => format!(" score mate {}", moves),
+            None => format!(" score cp {}", cp),
+        },
+        None => String::new(),
+    };
+    if quiet {
+        println!("vatu {}{}", move_str, score_str);
+    } else if cached.is_some() {
+        println!("Vatu: bestmove {}{} (cached, depth {})", move_str, score_str, depth.unwrap());
+    } else {
+        println!("Vatu: bestmove {}{}", move_str, score_str);
+    }
+}
+
+/// Search `root` for `move_time` ms, printing logs unless `quiet`.
+fn search(
+    root: &node::Node,
+    move_time: i32,
+    profile: &engine::Profile,
+    quiet: bool,
+) -> analysis::SearchResult {
+    let (tx, rx) = mpsc::channel();
+    let mut analyzer = analysis::Analyzer::new(root.clone(), tx);
+    analyzer.set_transposition_table(Arc::new(tt::TranspositionTable::new(profile.hash_mb)));
+    let args = analysis::AnalysisParams { move_time, ..Default::default() };
+    let result = analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)));
+    if !quiet {
+        for cmd in rx.try_iter() {
+            if let engine::Cmd::Log(s) = cmd {
+                eprintln!("{}", s);
+            }
+        }
+    }
+    result
+}
+
+/// Print the analysis of an external engine, labeled with its name.
+fn print_external_analysis(name: &str, analysis: &external::ExternalAnalysis, quiet: bool) {
+    let move_str = analysis.best_move.map(|m| notation::move_to_string(&m))
+        .unwrap_or_else(|| notation::NULL_MOVE.to_string());
+    let mut score_str = match (analysis.score, analysis.mate) {
+        (Some(cp), _) => format!(" score cp {}", cp),
+        (None, Some(mate)) => format!(" score mate {}", mate),
+        (None, None) => String::new(),
+    };
+    if let Some(depth) = analysis.depth {
+        score_str.push_str(&format!(" depth {}", depth));
+    }
+    if quiet {
+        println!("external {}{}", move_str, score_str);
+    } else {
+        println!("External ({}): bestmove {}{}", name, move_str, score_str);
+    }
 }
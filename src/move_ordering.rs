@@ -0,0 +1,207 @@
+//! Move ordering for the search.
+//!
+//! Alpha-beta prunes more when good moves are searched first. Moves are
+//! sorted in place by a score: the transposition table move first, then
+//! captures by most valuable victim and least valuable attacker
+//! (MVV-LVA), killer moves of the current ply, and other quiet moves by
+//! history heuristic. Quiet moves refuting the previous move are also
+//! kept as countermoves, which the search never prunes.
+
+use std::cmp::Reverse;
+
+use crate::board::*;
+use crate::movement::{Move, MoveCode};
+
+/// Number of killer moves kept per ply.
+const NUM_KILLERS: usize = 2;
+
+// Score bases of move categories, so that categories never overlap.
+const TT_MOVE_SCORE: u32 = 1 << 30;
+const CAPTURE_SCORE: u32 = 1 << 29;
+const KILLER_SCORE: u32 = 1 << 28;
+/// History counters are kept below this value.
+const MAX_HISTORY: u32 = KILLER_SCORE - 1;
+
+/// Data gathered during a search to order moves.
+pub struct MoveOrderer {
+    /// Quiet moves that caused a beta cutoff, by ply, most recent first.
+    killers: Vec<[MoveCode; NUM_KILLERS]>,
+    /// Cutoff counters of quiet moves by origin and destination index.
+    history: Vec<[u32; 64]>,
+    /// Last quiet move that caused a cutoff after each move, by origin
+    /// and destination index of that previous move.
+    countermoves: Vec<[MoveCode; 64]>,
+    /// Moves being sorted with their score and index, reused so that
+    /// sorting does not allocate.
+    sort_buffer: Vec<(Reverse<u32>, usize, Move)>,
+}
+
+impl Default for MoveOrderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveOrderer {
+    pub fn new() -> MoveOrderer {
+        MoveOrderer {
+            killers: vec!(),
+            history: vec![[0; 64]; 64],
+            countermoves: vec![[MoveCode::NONE; 64]; 64],
+            sort_buffer: vec!(),
+        }
+    }
+
+    /// Sort `moves` to play on `board` at `ply`, best first; moves with
+    /// the same score keep their order.
+    pub fn order(&mut self, board: &Board, moves: &mut [Move], ply: usize, tt_move: Option<Move>) {
+        let mut buffer = std::mem::take(&mut self.sort_buffer);
+        buffer.clear();
+        buffer.extend(moves.iter().enumerate().map(|(i, m)| (Reverse(self.score(board, m, ply, tt_move)), i, *m)));
+        // Indexes are unique so the unstable sort, which does not
+        // allocate, never compares moves and is stable.
+        buffer.sort_unstable();
+        for (m, (_, _, sorted)) in moves.iter_mut().zip(&buffer) {
+            *m = *sorted;
+        }
+        self.sort_buffer = buffer;
+    }
+
+    /// Record that quiet move `m` on `board` at `ply` caused a beta
+    /// cutoff, with `depth` plies left to search, in reply to move
+    /// `previous` if any.
+    ///
+    /// Captures are already ordered by MVV-LVA so they are ignored.
+    pub fn record_cutoff(&mut self, board: &Board, m: &Move, ply: usize, depth: u32, previous: Option<Move>) {
+        if is_capture(board, m) {
+            return
+        }
+        let code = MoveCode::new(m);
+        if let Some(previous) = previous {
+            self.countermoves[pos_index(&previous.0)][pos_index(&previous.1)] = code;
+        }
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [MoveCode::NONE; NUM_KILLERS]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != code {
+            killers[1] = killers[0];
+            killers[0] = code;
+        }
+        let counter = &mut self.history[pos_index(&m.0)][pos_index(&m.1)];
+        *counter = (*counter + depth * depth).min(MAX_HISTORY);
+    }
+
+    /// Return the history counter of quiet move `m`.
+    pub fn history(&self, m: &Move) -> u32 {
+        self.history[pos_index(&m.0)][pos_index(&m.1)]
+    }
+
+    /// Return true if `m` is a killer move of `ply` or the countermove
+    /// of `previous`.
+    pub fn is_refutation(&self, m: &Move, ply: usize, previous: Option<Move>) -> bool {
+        let code = MoveCode::new(m);
+        self.is_killer(code, ply)
+            || previous.is_some_and(|p| self.countermoves[pos_index(&p.0)][pos_index(&p.1)] == code)
+    }
+
+    fn is_killer(&self, code: MoveCode, ply: usize) -> bool {
+        self.killers.get(ply).is_some_and(|k| k.contains(&code))
+    }
+
+    fn score(&self, board: &Board, m: &Move, ply: usize, tt_move: Option<Move>) -> u32 {
+        if tt_move == Some(*m) {
+            TT_MOVE_SCORE
+        } else if is_capture(board, m) {
+            CAPTURE_SCORE + mvv_lva(board, m)
+        } else if self.is_killer(MoveCode::new(m), ply) {
+            KILLER_SCORE
+        } else {
+            self.history[pos_index(&m.0)][pos_index(&m.1)]
+        }
+    }
+}
+
+/// Return true if `m` takes a piece on `board`, including en passant.
+pub fn is_capture(board: &Board, m: &Move) -> bool {
+    !is_empty(board, &m.1) || (is_type(get_square(board, &m.0), SQ_P) && m.0.0 != m.1.0)
+}
+
+/// Return the MVV-LVA score of capture `m` on `board`: higher for more
+/// valuable victims, then for less valuable attackers.
+pub fn mvv_lva(board: &Board, m: &Move) -> u32 {
+    // En passant captures a pawn on an empty square.
+    let victim = match get_square(board, &m.1) {
+        SQ_E => piece_value(SQ_P),
+        square => piece_value(square),
+    };
+    victim * 16 + 15 - piece_value(get_square(board, &m.0))
+}
+
+/// Return the material value of the piece on `square`, 0 if empty.
+///
+/// The king scores more than any piece so it attacks last.
+pub fn piece_value(square: u8) -> u32 {
+    match get_type(square) {
+        SQ_P => 1,
+        SQ_B | SQ_N => 3,
+        SQ_R => 5,
+        SQ_Q => 9,
+        SQ_K => 10,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{self, parse_move};
+    use crate::rules;
+
+    #[test]
+    fn test_order() {
+        // White can take the queen with the pawn or the rook, or the
+        // knight with the rook.
+        let (board, game_state) = notation::position("4k3/8/2n5/3q4/4P3/8/8/2R1K3 w - - 0 1");
+        let mut moves = rules::get_player_moves(&board, &game_state, true);
+        let mut orderer = MoveOrderer::new();
+        orderer.order(&board, &mut moves, 0, None);
+        assert_eq!(moves[0], parse_move("e4d5").unwrap());
+        assert_eq!(moves[1], parse_move("c1c6").unwrap());
+        // The transposition table move comes first.
+        let tt_move = parse_move("e1f1").unwrap();
+        orderer.order(&board, &mut moves, 0, Some(tt_move));
+        assert_eq!(moves[0], tt_move);
+        assert_eq!(moves[1], parse_move("e4d5").unwrap());
+        // Killers come after captures, and only for their ply; other
+        // quiet moves are sorted by history.
+        let killer = parse_move("c1a1").unwrap();
+        let other = parse_move("c1b1").unwrap();
+        orderer.record_cutoff(&board, &killer, 1, 1, None);
+        orderer.record_cutoff(&board, &other, 2, 3, None);
+        orderer.order(&board, &mut moves, 1, None);
+        assert_eq!(moves[1..4], [parse_move("c1c6").unwrap(), killer, other]);
+        orderer.order(&board, &mut moves, 0, None);
+        assert_eq!(moves[2..4], [other, killer]);
+        // Captures are not recorded as killers.
+        orderer.record_cutoff(&board, &parse_move("c1c6").unwrap(), 2, 2, None);
+        assert_eq!(orderer.killers[2], [MoveCode::new(&other), MoveCode::NONE]);
+        assert_eq!(orderer.history(&other), 9);
+        // Countermoves are refutations of their previous move only.
+        let previous = parse_move("e8d8").unwrap();
+        let counter = parse_move("e1d2").unwrap();
+        assert!(!orderer.is_refutation(&counter, 3, Some(previous)));
+        orderer.record_cutoff(&board, &counter, 5, 1, Some(previous));
+        assert!(orderer.is_refutation(&counter, 3, Some(previous)));
+        assert!(!orderer.is_refutation(&counter, 3, Some(parse_move("e8f8").unwrap())));
+        assert!(orderer.is_refutation(&killer, 1, None));
+    }
+
+    #[test]
+    fn test_mvv_lva() {
+        let (board, _) = notation::position("4k3/8/8/3q4/4P3/8/8/3RK3 w - - 0 1");
+        assert!(mvv_lva(&board, &parse_move("e4d5").unwrap()) > mvv_lva(&board, &parse_move("d1d5").unwrap()));
+        assert!(is_capture(&board, &parse_move("d1d5").unwrap()));
+        assert!(!is_capture(&board, &parse_move("d1d4").unwrap()));
+    }
+}
@@ -10,6 +10,76 @@ const START_BL_K_POS: Pos = pos("e8");
 /// A movement, with before/after positions and optional promotion.
 pub type Move = (Pos, Pos, Option<u8>);
 
+/// A piece changed by a move: moved `from` a square `to` another,
+/// removed (no `to`) or added (no `from`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DirtyPiece {
+    pub piece: u8,
+    pub from: Option<Pos>,
+    pub to: Option<Pos>,
+}
+
+/// Pieces changed by a move, so that incremental evaluators can update
+/// only what changed, see `apply_move_to_board`.
+///
+/// A move changes at most 3 pieces: a pawn taking and promoting is
+/// removed as well as the piece it takes, and the new piece is added.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DirtyPieces {
+    pieces: [DirtyPiece; 3],
+    len: usize,
+}
+
+impl DirtyPieces {
+    fn push(&mut self, piece: u8, from: Option<Pos>, to: Option<Pos>) {
+        self.pieces[self.len] = DirtyPiece { piece, from, to };
+        self.len += 1;
+    }
+
+    /// Return the changed pieces, the moving piece first.
+    pub fn as_slice(&self) -> &[DirtyPiece] {
+        &self.pieces[..self.len]
+    }
+}
+
+/// Promotion types by their index in move codes, from 1.
+const PROMOTION_TYPES: [u8; 4] = [SQ_B, SQ_N, SQ_R, SQ_Q];
+
+/// A move packed in 16 bits, for search tables: origin and destination
+/// indexes (6 bits each) then promotion (3 bits, 0 for none).
+///
+/// Castles and en passant need no flag as the board tells them apart.
+/// Code 0 (a1a1) is no move, see `MoveCode::NONE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MoveCode(pub u16);
+
+impl MoveCode {
+    pub const NONE: MoveCode = MoveCode(0);
+
+    pub fn new(m: &Move) -> MoveCode {
+        let promotion = m.2.and_then(|p| PROMOTION_TYPES.iter().position(|t| *t == p)).map_or(0, |i| i + 1);
+        MoveCode((pos_index(&m.0) | pos_index(&m.1) << 6 | promotion << 12) as u16)
+    }
+
+    /// Return the code of `m`, NONE if there is no move.
+    pub fn from_option(m: Option<Move>) -> MoveCode {
+        m.map_or(MoveCode::NONE, |m| MoveCode::new(&m))
+    }
+
+    /// Return the move, None for NONE.
+    pub fn to_move(self) -> Option<Move> {
+        if self == MoveCode::NONE {
+            return None
+        }
+        let code = self.0 as usize;
+        let promotion = match code >> 12 & 0x7 {
+            0 => None,
+            i => Some(PROMOTION_TYPES[i - 1]),
+        };
+        Some((index_pos(code & 0x3F), index_pos(code >> 6 & 0x3F), promotion))
+    }
+}
+
 /// Apply a move `m` to copies to `board` and `game_state`.
 ///
 /// Can be used for conveniance but it's better to write in existing
@@ -19,7 +89,7 @@ pub fn apply_move(
     game_state: &rules::GameState,
     m: &Move
 ) -> (Board, rules::GameState) {
-    let mut new_board = board.clone();
+    let mut new_board = *board;
     let mut new_state = game_state.clone();
     apply_move_to(&mut new_board, &mut new_state, m);
     (new_board, new_state)
@@ -29,14 +99,40 @@ pub fn apply_move(
 ///
 /// The board is updated with correct piece placement.
 ///
-/// The game state is updated with the new player turn and the new
-/// castling options.
+/// The game state is updated with the new player turn, the new
+/// castling options, the en passant square and the move counters.
+///
+/// Return the pieces changed on the board.
 pub fn apply_move_to(
     board: &mut Board,
     game_state: &mut rules::GameState,
     m: &Move
-) {
-    // If a rook is taken, remove its castling option. Needs to be checked before we update board.
+) -> DirtyPieces {
+    update_game_state(board, game_state, m);
+    apply_move_to_board(board, m)
+}
+
+/// Update `game_state` to reflect the move `m` on `board`, the board
+/// being as before the move, see `apply_move_to`.
+pub fn update_game_state(board: &Board, game_state: &mut rules::GameState, m: &Move) {
+    // Update move counters.
+    if is_type(get_square(board, &m.0), SQ_P) || !is_empty(board, &m.1) {
+        game_state.halfmove = 0;
+    } else {
+        game_state.halfmove += 1;
+    }
+    if game_state.color == SQ_BL {
+        game_state.fullmove += 1;
+    }
+
+    // Set the en passant square behind a pawn moving two squares.
+    game_state.en_passant = if is_type(get_square(board, &m.0), SQ_P) && (m.1.1 - m.0.1).abs() == 2 {
+        Some((m.0.0, (m.0.1 + m.1.1) / 2))
+    } else {
+        None
+    };
+
+    // If a rook is taken, remove its castling option.
     if m.1 == pos("a1") && get_square(board, &pos("a1")) == SQ_WH_R {
         game_state.castling &= !CASTLING_WH_Q;
     } else if m.1 == pos("h1") && get_square(board, &pos("h1")) == SQ_WH_R {
@@ -47,12 +143,10 @@ pub fn apply_move_to(
         game_state.castling &= !CASTLING_BL_K;
     }
 
-    // Update board and game state.
-    apply_move_to_board(board, m);
     game_state.color = opposite(game_state.color);
 
     // If the move is a castle, remove it from castling options.
-    if let Some(castle) = get_castle(m) {
+    if let Some(castle) = get_castle(board, m) {
         match castle {
             CASTLING_WH_K | CASTLING_WH_Q => game_state.castling &= !CASTLING_WH_MASK,
             CASTLING_BL_K | CASTLING_BL_Q => game_state.castling &= !CASTLING_BL_MASK,
@@ -61,13 +155,11 @@ pub fn apply_move_to(
     }
     // Else, check if the king or a rook moved to update castling options.
     else {
-        let piece = get_square(board, &m.1);
+        let piece = get_square(board, &m.0);
         if is_white(piece) && game_state.castling & CASTLING_WH_MASK != 0 {
             match get_type(piece) {
-                SQ_K => {
-                    if m.0 == pos("e1") {
-                        game_state.castling &= !CASTLING_WH_MASK;
-                    }
+                SQ_K if m.0 == pos("e1") => {
+                    game_state.castling &= !CASTLING_WH_MASK;
                 }
                 SQ_R => {
                     if m.0 == pos("a1") {
@@ -80,10 +172,8 @@ pub fn apply_move_to(
             }
         } else if is_black(piece) && game_state.castling & CASTLING_BL_MASK != 0 {
             match get_type(piece) {
-                SQ_K => {
-                    if m.0 == pos("e8") {
-                        game_state.castling &= !CASTLING_BL_MASK;
-                    }
+                SQ_K if m.0 == pos("e8") => {
+                    game_state.castling &= !CASTLING_BL_MASK;
                 }
                 SQ_R => {
                     if m.0 == pos("a8") {
@@ -98,39 +188,117 @@ pub fn apply_move_to(
     }
 }
 
-/// Apply a move `m` into `board`.
-pub fn apply_move_to_board(board: &mut Board, m: &Move) {
-    if let Some(castle) = get_castle(m) {
-        match castle {
-            CASTLING_WH_K => {
-                move_piece(board, &START_WH_K_POS, &pos("g1"));
-                move_piece(board, &pos("h1"), &pos("f1"));
-            }
-            CASTLING_WH_Q => {
-                move_piece(board, &START_WH_K_POS, &pos("c1"));
-                move_piece(board, &pos("a1"), &pos("d1"));
-            }
-            CASTLING_BL_K => {
-                move_piece(board, &START_BL_K_POS, &pos("g8"));
-                move_piece(board, &pos("h8"), &pos("f8"));
-            }
-            CASTLING_BL_Q => {
-                move_piece(board, &START_BL_K_POS, &pos("c8"));
-                move_piece(board, &pos("a8"), &pos("d8"));
-            }
-            _ => {}
+/// What a move changed, to take it back with `unmake_move`: the pieces
+/// it changed and the game state before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnmakeInfo {
+    pub dirty: DirtyPieces,
+    pub castling: u8,
+    pub en_passant: Option<Pos>,
+    pub halfmove: i32,
+    pub fullmove: i32,
+}
+
+impl UnmakeInfo {
+    /// Return the pieces changed on the board.
+    pub fn dirty_pieces(&self) -> &[DirtyPiece] {
+        self.dirty.as_slice()
+    }
+}
+
+/// Play `m` like `apply_move_to`, returning what is needed to take it
+/// back with `unmake_move`.
+pub fn make_move(board: &mut Board, game_state: &mut rules::GameState, m: &Move) -> UnmakeInfo {
+    let (castling, en_passant) = (game_state.castling, game_state.en_passant);
+    let (halfmove, fullmove) = (game_state.halfmove, game_state.fullmove);
+    let dirty = apply_move_to(board, game_state, m);
+    UnmakeInfo { dirty, castling, en_passant, halfmove, fullmove }
+}
+
+/// Take back the move that returned `info` in `make_move`, restoring
+/// `board` and `game_state` as they were before it.
+pub fn unmake_move(board: &mut Board, game_state: &mut rules::GameState, info: &UnmakeInfo) {
+    // Empty all destinations first, as a taken piece goes back where
+    // the moving piece stands.
+    for piece in info.dirty_pieces() {
+        if let Some(to) = piece.to {
+            clear_square(board, &to);
         }
-    } else {
-        move_piece(board, &m.0, &m.1);
-        if let Some(prom_type) = m.2 {
-            let color = get_color(get_square(board, &m.1));
-            set_square(board, &m.1, color|prom_type);
+    }
+    for piece in info.dirty_pieces() {
+        if let Some(from) = piece.from {
+            set_square(board, &from, piece.piece);
         }
     }
+    game_state.color = opposite(game_state.color);
+    game_state.castling = info.castling;
+    game_state.en_passant = info.en_passant;
+    game_state.halfmove = info.halfmove;
+    game_state.fullmove = info.fullmove;
 }
 
-/// Get the corresponding castling flag for this move.
-pub fn get_castle(m: &Move) -> Option<u8> {
+/// Apply a move `m` into `board`, returning the pieces changed.
+pub fn apply_move_to_board(board: &mut Board, m: &Move) -> DirtyPieces {
+    let dirty = get_dirty_pieces(board, m);
+    // Empty all origins first, as a destination can be the square of a
+    // taken piece.
+    for piece in dirty.as_slice() {
+        if let Some(from) = piece.from {
+            clear_square(board, &from);
+        }
+    }
+    for piece in dirty.as_slice() {
+        if let Some(to) = piece.to {
+            set_square(board, &to, piece.piece);
+        }
+    }
+    dirty
+}
+
+/// Return the pieces changed by move `m` on `board`, without applying
+/// it.
+///
+/// For a castle, the king and the rook move; the pawn taken en passant
+/// is removed from its square, behind the destination.
+pub fn get_dirty_pieces(board: &Board, m: &Move) -> DirtyPieces {
+    let mut dirty = DirtyPieces::default();
+    if let Some(castle) = get_castle(board, m) {
+        let (king_from, king_to, rook_from, rook_to) = match castle {
+            CASTLING_WH_K => (START_WH_K_POS, pos("g1"), pos("h1"), pos("f1")),
+            CASTLING_WH_Q => (START_WH_K_POS, pos("c1"), pos("a1"), pos("d1")),
+            CASTLING_BL_K => (START_BL_K_POS, pos("g8"), pos("h8"), pos("f8")),
+            CASTLING_BL_Q => (START_BL_K_POS, pos("c8"), pos("a8"), pos("d8")),
+            _ => panic!("Illegal castling: {:08b}", castle),
+        };
+        dirty.push(get_square(board, &king_from), Some(king_from), Some(king_to));
+        dirty.push(get_square(board, &rook_from), Some(rook_from), Some(rook_to));
+        return dirty
+    }
+
+    let piece = get_square(board, &m.0);
+    // A promoting pawn is removed, then the new piece is added.
+    dirty.push(piece, Some(m.0), if m.2.is_some() { None } else { Some(m.1) });
+    // A pawn moving diagonally to an empty square takes en passant.
+    if is_type(piece, SQ_P) && m.0.0 != m.1.0 && is_empty(board, &m.1) {
+        let taken = (m.1.0, m.0.1);
+        dirty.push(get_square(board, &taken), Some(taken), None);
+    } else if !is_empty(board, &m.1) {
+        dirty.push(get_square(board, &m.1), Some(m.1), None);
+    }
+    if let Some(prom_type) = m.2 {
+        dirty.push(get_color(piece) | prom_type, None, Some(m.1));
+    }
+    dirty
+}
+
+/// Get the corresponding castling flag for this move, played on `board`.
+///
+/// Only king moves can be castles: e.g. a rook moving from e1 to c1 is
+/// not a castle.
+pub fn get_castle(board: &Board, m: &Move) -> Option<u8> {
+    if !is_type(get_square(board, &m.0), SQ_K) {
+        return None
+    }
     if m.0 == pos("e1") {
         if m.1 == pos("c1") {
             Some(CASTLING_WH_Q)
@@ -152,6 +320,23 @@ pub fn get_castle(m: &Move) -> Option<u8> {
     }
 }
 
+/// Call `f` with each square changed by move `m` played on `board`:
+/// its origin and destination, and the rook squares of a castle or the
+/// square of a pawn taken en passant.
+pub fn for_each_changed_square<F: FnMut(&Pos)>(board: &Board, m: &Move, mut f: F) {
+    f(&m.0);
+    f(&m.1);
+    if let Some(castle) = get_castle(board, m) {
+        let rank = m.0.1;
+        let (rook_from, rook_to) = if castle & CASTLING_K_MASK != 0 { (7, 5) } else { (0, 3) };
+        f(&(rook_from, rank));
+        f(&(rook_to, rank));
+    } else if is_type(get_square(board, &m.0), SQ_P) && m.0.0 != m.1.0 && is_empty(board, &m.1) {
+        // En passant capture.
+        f(&(m.1.0, m.0.1));
+    }
+}
+
 /// Get the move for this castle.
 pub fn get_castle_move(castle: u8) -> Move {
     match castle {
@@ -166,7 +351,8 @@ pub fn get_castle_move(castle: u8) -> Move {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::notation::parse_move;
+    use crate::notation::{self, parse_move};
+    use crate::random;
 
     #[test]
     fn test_apply_move_to_board() {
@@ -204,14 +390,14 @@ mod tests {
         clear_square(&mut b, &pos("f8"));
         clear_square(&mut b, &pos("g8"));
         // White queen-side castling.
-        apply_move_to(&mut b, &mut gs, &parse_move("e1c1"));
+        apply_move_to(&mut b, &mut gs, &parse_move("e1c1").unwrap());
         assert!(is_piece(get_square(&b, &pos("c1")), SQ_WH_K));
         assert!(is_piece(get_square(&b, &pos("d1")), SQ_WH_R));
         assert!(is_empty(&b, &pos("a1")));
         assert!(is_empty(&b, &pos("e1")));
         assert_eq!(gs.castling, CASTLING_BL_MASK);
         // Black king-side castling.
-        apply_move_to(&mut b, &mut gs, &parse_move("e8g8"));
+        apply_move_to(&mut b, &mut gs, &parse_move("e8g8").unwrap());
         assert!(is_piece(get_square(&b, &pos("g8")), SQ_BL_K));
         assert!(is_piece(get_square(&b, &pos("f8")), SQ_BL_R));
         assert!(is_empty(&b, &pos("h8")));
@@ -219,12 +405,167 @@ mod tests {
         assert_eq!(gs.castling, 0);
     }
 
+    #[test]
+    fn test_apply_move_to_capture_promotion() {
+        // Taking a rook in its corner with a promotion removes its castling option.
+        let mut b = new_empty();
+        set_square(&mut b, &pos("e8"), SQ_BL_K);
+        set_square(&mut b, &pos("a8"), SQ_BL_R);
+        set_square(&mut b, &pos("h8"), SQ_BL_R);
+        set_square(&mut b, &pos("b7"), SQ_WH_P);
+        let mut gs = rules::GameState::new();
+        gs.castling = CASTLING_BL_MASK;
+        apply_move_to(&mut b, &mut gs, &parse_move("b7a8n").unwrap());
+        assert_eq!(get_square(&b, &pos("a8")), SQ_WH_N);
+        assert!(is_empty(&b, &pos("b7")));
+        assert_eq!(gs.castling, CASTLING_BL_K);
+        assert_eq!(gs.halfmove, 0);
+    }
+
+    #[test]
+    fn test_apply_move_to_counters() {
+        let mut b = new();
+        let mut gs = rules::GameState::new();
+
+        // Pawn moves reset the halfmove counter, together with captures.
+        apply_move_to(&mut b, &mut gs, &parse_move("e2e4").unwrap());
+        assert_eq!((gs.halfmove, gs.fullmove), (0, 1));
+        apply_move_to(&mut b, &mut gs, &parse_move("g8f6").unwrap());
+        assert_eq!((gs.halfmove, gs.fullmove), (1, 2));
+        apply_move_to(&mut b, &mut gs, &parse_move("b1c3").unwrap());
+        assert_eq!((gs.halfmove, gs.fullmove), (2, 2));
+        apply_move_to(&mut b, &mut gs, &parse_move("f6e4").unwrap());
+        assert_eq!((gs.halfmove, gs.fullmove), (0, 3));
+    }
+
+    #[test]
+    fn test_apply_move_to_en_passant() {
+        let mut b = new();
+        let mut gs = rules::GameState::new();
+        // Double pushes set the en passant square, other moves clear it.
+        apply_move_to(&mut b, &mut gs, &parse_move("e2e4").unwrap());
+        assert_eq!(gs.en_passant, Some(pos("e3")));
+        apply_move_to(&mut b, &mut gs, &parse_move("a7a6").unwrap());
+        assert_eq!(gs.en_passant, None);
+        apply_move_to(&mut b, &mut gs, &parse_move("e4e5").unwrap());
+        apply_move_to(&mut b, &mut gs, &parse_move("d7d5").unwrap());
+        assert_eq!(gs.en_passant, Some(pos("d6")));
+        // Taking en passant removes the pawn that moved.
+        apply_move_to(&mut b, &mut gs, &parse_move("e5d6").unwrap());
+        assert_eq!(gs.en_passant, None);
+        assert_eq!(get_square(&b, &pos("d6")), SQ_WH_P);
+        assert!(is_empty(&b, &pos("d5")));
+        assert!(is_empty(&b, &pos("e5")));
+        assert_eq!(gs.halfmove, 0);
+    }
+
     #[test]
     fn test_get_castle() {
-        assert_eq!(get_castle(&parse_move("e1c1")), Some(CASTLING_WH_Q));
-        assert_eq!(get_castle(&parse_move("e1g1")), Some(CASTLING_WH_K));
-        assert_eq!(get_castle(&parse_move("e8c8")), Some(CASTLING_BL_Q));
-        assert_eq!(get_castle(&parse_move("e8g8")), Some(CASTLING_BL_K));
-        assert_eq!(get_castle(&parse_move("d2d4")), None);
+        let b = new();
+        assert_eq!(get_castle(&b, &parse_move("e1c1").unwrap()), Some(CASTLING_WH_Q));
+        assert_eq!(get_castle(&b, &parse_move("e1g1").unwrap()), Some(CASTLING_WH_K));
+        assert_eq!(get_castle(&b, &parse_move("e8c8").unwrap()), Some(CASTLING_BL_Q));
+        assert_eq!(get_castle(&b, &parse_move("e8g8").unwrap()), Some(CASTLING_BL_K));
+        assert_eq!(get_castle(&b, &parse_move("d2d4").unwrap()), None);
+        // Other pieces on the king square do not castle.
+        let b = new_from_fen("4k3/8/8/8/8/8/8/R3R1K1").unwrap();
+        assert_eq!(get_castle(&b, &parse_move("e1c1").unwrap()), None);
+        let mut b2 = b;
+        apply_move_to_board(&mut b2, &parse_move("e1c1").unwrap());
+        assert_eq!(get_square(&b2, &pos("a1")), SQ_WH_R);
+        assert_eq!(get_square(&b2, &pos("c1")), SQ_WH_R);
+    }
+
+    #[test]
+    fn test_dirty_pieces() {
+        let dirty = |piece, from: Option<&str>, to: Option<&str>| {
+            DirtyPiece { piece, from: from.map(pos), to: to.map(pos) }
+        };
+        // A quiet move only changes the moving piece.
+        let mut b = new();
+        let pieces = apply_move_to_board(&mut b, &parse_move("g1f3").unwrap());
+        assert_eq!(pieces.as_slice(), [dirty(SQ_WH_N, Some("g1"), Some("f3"))]);
+        // Castles move the rook as well.
+        let mut b = new_from_fen("r3k3/8/8/8/8/8/8/4K2R").unwrap();
+        let pieces = apply_move_to_board(&mut b, &parse_move("e1g1").unwrap());
+        assert_eq!(pieces.as_slice(), [
+            dirty(SQ_WH_K, Some("e1"), Some("g1")),
+            dirty(SQ_WH_R, Some("h1"), Some("f1")),
+        ]);
+        let pieces = apply_move_to_board(&mut b, &parse_move("e8c8").unwrap());
+        assert_eq!(pieces.as_slice(), [
+            dirty(SQ_BL_K, Some("e8"), Some("c8")),
+            dirty(SQ_BL_R, Some("a8"), Some("d8")),
+        ]);
+        // The pawn taken en passant is not on the destination square.
+        let mut b = new_from_fen("4k3/8/8/3pP3/8/8/8/4K3").unwrap();
+        let pieces = apply_move_to_board(&mut b, &parse_move("e5d6").unwrap());
+        assert_eq!(pieces.as_slice(), [
+            dirty(SQ_WH_P, Some("e5"), Some("d6")),
+            dirty(SQ_BL_P, Some("d5"), None),
+        ]);
+        // A pawn taking and promoting is replaced by the new piece.
+        let mut b = new_from_fen("1r2k3/P7/8/8/8/8/8/4K3").unwrap();
+        let pieces = apply_move_to_board(&mut b, &parse_move("a7b8n").unwrap());
+        assert_eq!(pieces.as_slice(), [
+            dirty(SQ_WH_P, Some("a7"), None),
+            dirty(SQ_BL_R, Some("b8"), None),
+            dirty(SQ_WH_N, None, Some("b8")),
+        ]);
+    }
+
+    #[test]
+    fn test_move_code() {
+        for m in &["a1h8", "h8a1", "e7e8q", "b2a1n", "g7g8r", "c2c1b"] {
+            let m = parse_move(m).unwrap();
+            let code = MoveCode::new(&m);
+            assert_ne!(code, MoveCode::NONE);
+            assert_eq!(code.to_move(), Some(m));
+        }
+        assert_eq!(MoveCode::from_option(None).to_move(), None);
+        assert_ne!(MoveCode::new(&parse_move("e7e8q").unwrap()), MoveCode::new(&parse_move("e7e8n").unwrap()));
+    }
+
+    #[test]
+    fn test_make_unmake_move() {
+        // Take back every legal move of a position.
+        let check_moves = |b: &mut Board, gs: &mut rules::GameState| {
+            let moves = rules::get_player_moves(b, gs, true);
+            for m in &moves {
+                let (b2, gs2) = (*b, gs.clone());
+                let info = make_move(b, gs, m);
+                let (b3, gs3) = apply_move(&b2, &gs2, m);
+                assert_eq!((*b, &*gs), (b3, &gs3));
+                unmake_move(b, gs, &info);
+                assert_eq!((*b, &*gs), (b2, &gs2));
+            }
+            moves
+        };
+        // Castles, en passant and promotions taking a rook.
+        for fen in &[
+            "r3k2r/1P6/8/3pP3/8/8/6p1/R3K2R w KQkq d6 0 1",
+            "r3k2r/1P6/8/8/3pP3/8/6p1/R3K2R b KQkq e3 0 1",
+        ] {
+            let (mut b, mut gs) = notation::position(fen);
+            check_moves(&mut b, &mut gs);
+        }
+        // Then along random games, taking back the games afterwards.
+        let mut rng = random::Rng::new(3);
+        for _ in 0..20 {
+            let (mut b, mut gs) = (new(), rules::GameState::new());
+            let mut history = vec!();
+            for _ in 0..60 {
+                let moves = check_moves(&mut b, &mut gs);
+                if moves.is_empty() {
+                    break
+                }
+                let m = moves[rng.below(moves.len())];
+                history.push((b, gs.clone(), make_move(&mut b, &mut gs, &m)));
+            }
+            for (b2, gs2, info) in history.iter().rev() {
+                unmake_move(&mut b, &mut gs, info);
+                assert_eq!((b, &gs), (*b2, gs2));
+            }
+        }
     }
 }
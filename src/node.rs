@@ -1,10 +1,14 @@
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
+use crate::attacks;
 use crate::board;
+use crate::castling;
 use crate::movement::{self, Move};
+use crate::notation;
 use crate::rules;
 use crate::stats;
+use crate::zobrist;
 
 /// Analysis node: a board along with the game state.
 #[derive(Clone)]
@@ -24,9 +28,44 @@ impl Node {
         }
     }
 
-    /// Apply a move to this node.
-    pub fn apply_move(&mut self, m: &Move) {
-        movement::apply_move_to(&mut self.board, &mut self.game_state, m);
+    /// Apply a move to this node, returning the pieces it changed.
+    pub fn apply_move(&mut self, m: &Move) -> movement::DirtyPieces {
+        movement::apply_move_to(&mut self.board, &mut self.game_state, m)
+    }
+
+    /// Play `m` if it is legal, returning the move actually played.
+    ///
+    /// Castles written as the king taking its own rook, e.g. "e1h1",
+    /// are played as the usual king move, and pawn moves to the last
+    /// rank without a promotion piece promote to a queen. Return None
+    /// if the move is illegal; the node is then left untouched.
+    pub fn play_move(&mut self, m: &Move) -> Option<Move> {
+        let piece = board::get_square(&self.board, &m.0);
+        let target = board::get_square(&self.board, &m.1);
+        let mut m = *m;
+        if board::is_type(piece, board::SQ_K) && board::is_type(target, board::SQ_R)
+            && board::get_color(piece) == board::get_color(target) && m.0.1 == m.1.1
+        {
+            m.1.0 = if m.1.0 > m.0.0 { 6 } else { 2 };
+        } else if board::is_type(piece, board::SQ_P) && (m.1.1 == 0 || m.1.1 == board::POS_MAX) && m.2.is_none() {
+            m.2 = Some(board::SQ_Q);
+        }
+        if !self.get_player_moves(true).contains(&m) {
+            return None
+        }
+        self.apply_move(&m);
+        Some(m)
+    }
+
+    /// Parse a move in UCI notation and play it, see `play_move`.
+    ///
+    /// Return the move played, or an error if the notation is invalid
+    /// or the move is illegal.
+    pub fn play_uci_str(&mut self, s: &str) -> Result<Move, String> {
+        let m = notation::parse_move(s).ok_or_else(|| format!("Invalid move notation: {}", s))?;
+        self.play_move(&m).ok_or_else(|| {
+            format!("Illegal move {} in position {}", s, notation::to_fen(&self.board, &self.game_state))
+        })
     }
 
     /// Return player moves from this node.
@@ -34,12 +73,66 @@ impl Node {
         rules::get_player_moves(&self.board, &self.game_state, commit)
     }
 
+    /// Write committed player moves from this node in `moves`,
+    /// replacing its content.
+    pub fn get_player_moves_into(&self, moves: &mut Vec<Move>) {
+        rules::get_player_moves_into(
+            &self.board, &self.game_state, true, rules::PromotionPolicy::All, moves
+        );
+    }
+
+    /// Write legal captures from this node in `moves`, replacing its
+    /// content, in MVV-LVA order.
+    pub fn get_captures_into(&self, moves: &mut Vec<Move>) {
+        rules::get_captures_into(&self.board, &self.game_state, rules::PromotionPolicy::All, moves);
+    }
+
+    /// Return the same position for the other player: the board is
+    /// mirrored with colors swapped, and so are game state options.
+    /// Move counters are kept.
+    pub fn color_flipped(&self) -> Node {
+        let mut game_state = self.game_state.clone();
+        game_state.color = board::opposite(game_state.color);
+        game_state.castling = castling::color_flip_castling(game_state.castling);
+        game_state.en_passant = game_state.en_passant.map(|(f, r)| (f, board::POS_MAX - r));
+        Node { board: board::color_flip(&self.board), game_state }
+    }
+
+    /// Return a hash identifying the position regardless of colors.
+    ///
+    /// A position and its color-flipped counterpart have the same
+    /// canonical hash, so it can be used to merge mirrored positions,
+    /// e.g. when deduplicating openings or training positions. Move
+    /// counters are not part of the hash.
+    pub fn canonical_hash(&self) -> u64 {
+        // The canonical form has white to play.
+        let flipped;
+        let node = if board::is_white(self.game_state.color) {
+            self
+        } else {
+            flipped = self.color_flipped();
+            &flipped
+        };
+        zobrist::hash(&node.board, &node.game_state)
+    }
+
+    /// Compute squares attacked by both players for this node.
+    pub fn attack_maps(&self) -> attacks::AttackMaps {
+        attacks::AttackMaps::new(&self.board)
+    }
+
     /// Compute stats for both players for this node.
     pub fn compute_stats(&self) -> (stats::BoardStats, stats::BoardStats) {
         stats::compute_stats(&self.board, &self.game_state)
     }
 }
 
+impl Default for Node {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -79,3 +172,61 @@ impl Hash for Node {
         self.game_state.hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn node(fen: &str) -> Node {
+        let (board, game_state) = notation::position(fen);
+        Node { board, game_state }
+    }
+
+    #[test]
+    fn test_color_flipped() {
+        let n = node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR w Kkq c6 0 2");
+        let flipped = n.color_flipped();
+        assert_eq!(
+            notation::to_fen(&flipped.board, &flipped.game_state),
+            "r3k1nr/8/8/8/2Pp4/8/1P4P1/R3K2R b KQk c3 0 2"
+        );
+        assert_eq!(flipped.color_flipped(), n);
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let n = node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR w Kkq c6 0 2");
+        assert_eq!(n.canonical_hash(), n.color_flipped().canonical_hash());
+        // Counters do not matter.
+        let n2 = node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR w Kkq c6 5 30");
+        assert_eq!(n.canonical_hash(), n2.canonical_hash());
+        // But the player to move and options do.
+        let n3 = node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR b Kkq - 0 2");
+        assert_ne!(n3.canonical_hash(), node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR w Kkq - 0 2").canonical_hash());
+        let n4 = node("r3k2r/1p4p1/8/2pP4/8/8/8/R3K1NR w kq c6 0 2");
+        assert_ne!(n.canonical_hash(), n4.canonical_hash());
+        // The starting position is symmetric, so it does not matter who plays.
+        let start = node(notation::FEN_START);
+        let black_start = node("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(start.canonical_hash(), black_start.canonical_hash());
+    }
+
+    #[test]
+    fn test_play_uci_str() {
+        let mut n = node("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        // King-takes-rook castles are normalized.
+        assert_eq!(n.play_uci_str("e1a1"), Ok(notation::parse_move("e1c1").unwrap()));
+        assert_eq!(n.play_uci_str("e8g8"), Ok(notation::parse_move("e8g8").unwrap()));
+        assert_eq!(notation::to_fen(&n.board, &n.game_state), "r4rk1/1P6/8/8/8/8/8/2KR3R w - - 2 2");
+        // Promotions are to a queen unless specified.
+        let mut promoted = n.clone();
+        assert_eq!(promoted.play_uci_str("b7b8"), Ok(notation::parse_move("b7b8q").unwrap()));
+        assert_eq!(n.play_uci_str("b7b8n"), Ok(notation::parse_move("b7b8n").unwrap()));
+        // Invalid and illegal moves leave the node untouched.
+        let before = n.clone();
+        assert!(n.play_uci_str("e9e1").unwrap_err().starts_with("Invalid"));
+        assert!(n.play_uci_str("a1a8").unwrap_err().starts_with("Illegal move a1a8 in position"));
+        assert_eq!(n, before);
+    }
+}
@@ -1,7 +1,9 @@
 //! Functions using various notations.
 
 use crate::board::*;
-use crate::movement::Move;
+use crate::castling::*;
+use crate::movement::{self, Move};
+use crate::rules;
 
 pub const NULL_MOVE: &str = "0000";
 
@@ -23,24 +25,30 @@ pub fn move_to_string(m: &Move) -> String {
 }
 
 /// Parse an UCI move algebraic notation string to a Move.
-pub fn parse_move(m_str: &str) -> Move {
+///
+/// Return None if the string is not a valid move notation; note that
+/// it does not mean that the move is legal.
+pub fn parse_move(m_str: &str) -> Option<Move> {
+    if !m_str.is_ascii() || m_str.len() < 4 || m_str.len() > 5 {
+        return None
+    }
     let prom = if m_str.len() == 5 {
         Some(match m_str.as_bytes()[4] {
             b'b' => SQ_B,
             b'n' => SQ_N,
             b'r' => SQ_R,
             b'q' => SQ_Q,
-            _ => panic!("What is the opponent doing? This is illegal, I'm out."),
+            _ => return None,
         })
     } else {
         None
     };
-    (pos(&m_str[0..2]), pos(&m_str[2..4]), prom)
+    Some((parse_pos(&m_str[0..2])?, parse_pos(&m_str[2..4])?, prom))
 }
 
 /// Create a space-separated string of moves. Used for debugging.
-pub fn move_list_to_string(moves: &Vec<Move>) -> String {
-    moves.iter().map(|m| move_to_string(m)).collect::<Vec<_>>().join(" ")
+pub fn move_list_to_string(moves: &[Move]) -> String {
+    moves.iter().map(move_to_string).collect::<Vec<_>>().join(" ")
 }
 
 pub const FEN_START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
@@ -75,8 +83,207 @@ pub fn parse_fen_fields(fields: &[&str]) -> Option<Fen> {
     })
 }
 
+/// Create a board and a game state from FEN fields.
+///
+/// Return None if a field has an invalid value.
+pub fn fen_to_position(fen: &Fen) -> Option<(Board, rules::GameState)> {
+    let board = new_from_fen(&fen.placement)?;
+    let mut game_state = rules::GameState::new();
+    // Color.
+    game_state.color = match fen.color.as_ref() {
+        "w" => SQ_WH,
+        "b" => SQ_BL,
+        _ => return None,
+    };
+    // Castling.
+    game_state.castling = 0;
+    if fen.castling != "-" {
+        for c in fen.castling.chars() {
+            match c {
+                'K' => game_state.castling |= CASTLING_WH_K,
+                'Q' => game_state.castling |= CASTLING_WH_Q,
+                'k' => game_state.castling |= CASTLING_BL_K,
+                'q' => game_state.castling |= CASTLING_BL_Q,
+                _ => return None,
+            }
+        }
+    }
+    // En passant.
+    game_state.en_passant = match fen.en_passant.as_ref() {
+        "-" => None,
+        p => Some(parse_pos(p)?),
+    };
+    // Half moves.
+    game_state.halfmove = fen.halfmove.parse::<i32>().ok()?;
+    // Full moves.
+    game_state.fullmove = fen.fullmove.parse::<i32>().ok()?;
+    Some((board, game_state))
+}
+
+/// Create a FEN string from a board and a game state.
+pub fn to_fen(board: &Board, game_state: &rules::GameState) -> String {
+    let color = if game_state.color == SQ_WH { "w" } else { "b" };
+    let mut castling = String::with_capacity(4);
+    for (flag, c) in [
+        (CASTLING_WH_K, 'K'), (CASTLING_WH_Q, 'Q'), (CASTLING_BL_K, 'k'), (CASTLING_BL_Q, 'q')
+    ].iter() {
+        if game_state.castling & flag != 0 {
+            castling.push(*c);
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+    format!(
+        "{} {} {} {} {} {}",
+        to_fen_placement(board), color, castling,
+        en_passant_to_string(game_state.en_passant), game_state.halfmove, game_state.fullmove
+    )
+}
+
 pub fn en_passant_to_string(ep: Option<Pos>) -> String {
-    ep.and_then(|p| Some(pos_string(&p))).unwrap_or("-".to_string())
+    ep.map(|p| pos_string(&p)).unwrap_or_else(|| "-".to_string())
+}
+
+/// Return the SAN letter of a piece type, None for pawns.
+fn san_piece_char(piece_type: u8) -> Option<char> {
+    match piece_type {
+        SQ_N => Some('N'),
+        SQ_B => Some('B'),
+        SQ_R => Some('R'),
+        SQ_Q => Some('Q'),
+        SQ_K => Some('K'),
+        _ => None,
+    }
+}
+
+/// Return the piece type of a SAN letter, if it is one.
+fn san_piece_type(c: char) -> Option<u8> {
+    match c {
+        'N' => Some(SQ_N),
+        'B' => Some(SQ_B),
+        'R' => Some(SQ_R),
+        'Q' => Some(SQ_Q),
+        'K' => Some(SQ_K),
+        _ => None,
+    }
+}
+
+/// Create a string containing the standard algebraic notation (SAN) of
+/// legal move `m` played on `board`, e.g. "Nbd7", "exd6", "e8=Q+" or
+/// "O-O-O".
+pub fn move_to_san(board: &Board, game_state: &rules::GameState, m: &Move) -> String {
+    let mut san = String::new();
+    let piece_type = get_type(get_square(board, &m.0));
+    let is_capture = !is_empty(board, &m.1) || (piece_type == SQ_P && m.0.0 != m.1.0);
+    match movement::get_castle(board, m) {
+        Some(CASTLING_WH_K) | Some(CASTLING_BL_K) => san.push_str("O-O"),
+        Some(_) => san.push_str("O-O-O"),
+        None => {
+            if let Some(c) = san_piece_char(piece_type) {
+                san.push(c);
+                // Disambiguate from other pieces of the same type able
+                // to reach the same square, by file if possible.
+                let others: Vec<Move> = rules::get_player_moves(board, game_state, true).into_iter()
+                    .filter(|o| o.1 == m.1 && o.0 != m.0 && is_type(get_square(board, &o.0), piece_type))
+                    .collect();
+                if !others.is_empty() {
+                    let from = pos_string(&m.0);
+                    if others.iter().all(|o| o.0.0 != m.0.0) {
+                        san.push_str(&from[..1]);
+                    } else if others.iter().all(|o| o.0.1 != m.0.1) {
+                        san.push_str(&from[1..]);
+                    } else {
+                        san.push_str(&from);
+                    }
+                }
+            } else if is_capture {
+                san.push_str(&pos_string(&m.0)[..1]);
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&pos_string(&m.1));
+            if let Some(c) = m.2.and_then(san_piece_char) {
+                san.push('=');
+                san.push(c);
+            }
+        }
+    }
+    let (new_board, new_state) = movement::apply_move(board, game_state, m);
+    if rules::is_in_check(&new_board, &new_state) {
+        let mate = matches!(rules::game_status(&new_board, &new_state), rules::Status::Checkmate(_));
+        san.push(if mate { '#' } else { '+' });
+    }
+    san
+}
+
+/// Parse a SAN move string for the position of `board` to a Move.
+///
+/// Check and annotation suffixes ("+", "#", "!", "?") are ignored,
+/// castling can be written with zeros and the "=" of promotions can be
+/// omitted. Return None if the string does not match exactly one legal
+/// move.
+pub fn san_to_move(board: &Board, game_state: &rules::GameState, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let moves = rules::get_player_moves(board, game_state, true);
+    let castle = match san {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(king_side) = castle {
+        return moves.into_iter().find(|m| match movement::get_castle(board, m) {
+            Some(c) => king_side == (c == CASTLING_WH_K || c == CASTLING_BL_K),
+            None => false,
+        })
+    }
+    if !san.is_ascii() {
+        return None
+    }
+    let (san, promotion) = match san.chars().last().and_then(san_piece_type) {
+        Some(t) if san.len() > 2 => (san[..san.len() - 1].trim_end_matches('='), Some(t)),
+        _ => (san, None),
+    };
+    let (piece_type, san) = match san.chars().next().and_then(san_piece_type) {
+        Some(t) => (t, &san[1..]),
+        None => (SQ_P, san),
+    };
+    if san.len() < 2 {
+        return None
+    }
+    let to = parse_pos(&san[san.len() - 2..])?;
+    let from_hint = san[..san.len() - 2].trim_end_matches('x');
+    if from_hint.len() > 2 {
+        return None
+    }
+    let mut candidates = moves.into_iter().filter(|m| {
+        m.1 == to
+        && m.2 == promotion
+        && is_type(get_square(board, &m.0), piece_type)
+        && pos_string(&m.0).contains(from_hint)
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some(m), None) => Some(m),
+        _ => None,
+    }
+}
+
+/// Describe illegal move `text`, found at `ply` (from 1) of a move
+/// list when playing it on `board`, with the FEN and legal moves of the
+/// position to debug game imports.
+pub fn illegal_move_message(board: &Board, game_state: &rules::GameState, ply: usize, text: &str) -> String {
+    let moves = rules::get_player_moves(board, game_state, true);
+    format!(
+        "Illegal move {} at ply {} in position {} (legal moves: {})",
+        text, ply, to_fen(board, game_state), move_list_to_string(&moves)
+    )
+}
+
+/// Return the position of `fen`, for tests; panic if it is invalid.
+#[cfg(test)]
+pub(crate) fn position(fen: &str) -> (Board, rules::GameState) {
+    fen_to_position(&parse_fen(fen).expect("invalid FEN")).expect("invalid position")
 }
 
 #[cfg(test)]
@@ -93,9 +300,69 @@ mod tests {
 
     #[test]
     fn test_parse_move() {
-        assert_eq!(parse_move("a1d4"), ((0, 0), (3, 3), None));
-        assert_eq!(parse_move("a7a8q"), ((0, 6), (0, 7), Some(SQ_Q)));
-        assert_eq!(parse_move("a7a8r"), ((0, 6), (0, 7), Some(SQ_R)));
+        assert_eq!(parse_move("a1d4"), Some(((0, 0), (3, 3), None)));
+        assert_eq!(parse_move("a7a8q"), Some(((0, 6), (0, 7), Some(SQ_Q))));
+        assert_eq!(parse_move("a7a8r"), Some(((0, 6), (0, 7), Some(SQ_R))));
+        assert_eq!(parse_move("a7a8k"), None);
+        assert_eq!(parse_move("a7a9"), None);
+        assert_eq!(parse_move("a7"), None);
+        assert_eq!(parse_move("a7a8qq"), None);
+        assert_eq!(parse_move("a7é8"), None);
+        // Capture-promotions round-trip, including into the corners.
+        for m in ["b7a8n", "g7h8q", "b2a1r", "g2h1b"].iter() {
+            assert_eq!(move_to_string(&parse_move(m).unwrap()), *m);
+        }
+    }
+
+    #[test]
+    fn test_move_to_san() {
+        let san = |fen: &str, m: &str| {
+            let (board, game_state) = position(fen);
+            move_to_san(&board, &game_state, &parse_move(m).unwrap())
+        };
+        assert_eq!(san(FEN_START, "e2e4"), "e4");
+        assert_eq!(san(FEN_START, "g1f3"), "Nf3");
+        // Disambiguation by file, then by rank, then both.
+        let fen = "4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1";
+        assert_eq!(san(fen, "f4d3"), "Nfd3");
+        assert_eq!(san(fen, "b2d3"), "N2d3");
+        assert_eq!(san(fen, "b4d3"), "Nb4d3");
+        // Captures, en passant, promotions, checks and mates.
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q+");
+        assert_eq!(san("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8n"), "bxa8=N");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+        // Castling.
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"), "O-O-O");
+    }
+
+    #[test]
+    fn test_san_to_move() {
+        let parse = |fen: &str, s: &str| {
+            let (board, game_state) = position(fen);
+            san_to_move(&board, &game_state, s).map(|m| move_to_string(&m))
+        };
+        assert_eq!(parse(FEN_START, "e4").as_deref(), Some("e2e4"));
+        assert_eq!(parse(FEN_START, "Nf3!?").as_deref(), Some("g1f3"));
+        assert_eq!(parse(FEN_START, "e5"), None);
+        assert_eq!(parse(FEN_START, "Qd4"), None);
+        let fen = "4k3/8/8/8/1N3N2/8/1N6/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "Nd3"), None);
+        assert_eq!(parse(fen, "Nfd3").as_deref(), Some("f4d3"));
+        assert_eq!(parse(fen, "N2d3").as_deref(), Some("b2d3"));
+        assert_eq!(parse(fen, "Nb4xd3").as_deref(), Some("b4d3"));
+        assert_eq!(parse("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "bxa8=N").as_deref(), Some("b7a8n"));
+        assert_eq!(parse("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8Q+").as_deref(), Some("b7b8q"));
+        assert_eq!(parse("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8"), None);
+        assert_eq!(parse("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(parse("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "0-0-0").as_deref(), Some("e8c8"));
+        assert_eq!(parse("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "O-O"), None);
+        // Round trip on every legal move.
+        let (board, game_state) = position("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        for m in rules::get_player_moves(&board, &game_state, true) {
+            assert_eq!(san_to_move(&board, &game_state, &move_to_san(&board, &game_state, &m)), Some(m));
+        }
     }
 
     #[test]
@@ -108,4 +375,43 @@ mod tests {
         assert_eq!(&fen_start.halfmove, "0");
         assert_eq!(&fen_start.fullmove, "1");
     }
+
+    #[test]
+    fn test_to_fen() {
+        assert_eq!(to_fen(&new(), &rules::GameState::new()), FEN_START);
+        for fen in [
+            "8/8/4k3/8/4P3/8/8/4K3 b - e3 0 12",
+            "r3k2r/8/8/8/8/8/8/R3K2R w Kq - 7 40",
+        ].iter() {
+            let (b, gs) = position(fen);
+            assert_eq!(&to_fen(&b, &gs), fen);
+        }
+    }
+
+    #[test]
+    fn test_fen_to_position() {
+        let (b, gs) = fen_to_position(&parse_fen(FEN_START).unwrap()).unwrap();
+        assert!(eq(&b, &new()));
+        assert_eq!(gs, rules::GameState::new());
+
+        let fen = parse_fen("8/8/4k3/8/4P3/8/8/4K3 b - e3 0 12").unwrap();
+        let (b, gs) = fen_to_position(&fen).unwrap();
+        assert_eq!(num_pieces(&b), 3);
+        assert_eq!(gs.color, SQ_BL);
+        assert_eq!(gs.castling, 0);
+        assert_eq!(gs.en_passant, Some(pos("e3")));
+        assert_eq!(gs.fullmove, 12);
+
+        // Invalid fields.
+        for bad_fen in [
+            "8/8/4k3/8/4P3/8/8/4K3/8 b - - 0 1",
+            "8/8/4k3/8/4P3/8/8/4K3 x - - 0 1",
+            "8/8/4k3/8/4P3/8/8/4K3 b KX - 0 1",
+            "8/8/4k3/8/4P3/8/8/4K3 b - e9 0 1",
+            "8/8/4k3/8/4P3/8/8/4K3 b - - a 1",
+            "8/8/4k3/8/4P3/8/8/4K3 b - - 0 -",
+        ].iter() {
+            assert!(fen_to_position(&parse_fen(bad_fen).unwrap()).is_none());
+        }
+    }
 }
@@ -0,0 +1,246 @@
+//! Reference move generator.
+//!
+//! This is a second, deliberately simple and slow legal move generator,
+//! sharing no code with the `rules` module besides board accessors. It
+//! tries every pseudo-legal move, plays it on a copy of the board and
+//! looks for attacks on the king by scanning from its square. It is
+//! only meant to cross-check the real move generator.
+
+use crate::board::*;
+use crate::castling::*;
+use crate::movement::Move;
+use crate::rules::GameState;
+
+const KNIGHT_OFFSETS: [(i8, i8); 8] =
+    [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_OFFSETS: [(i8, i8); 8] =
+    [(1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1)];
+const BISHOP_DIRS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const ROOK_DIRS: [(i8, i8); 4] = [(1, 0), (0, -1), (-1, 0), (0, 1)];
+const PROMOTIONS: [u8; 4] = [SQ_Q, SQ_R, SQ_B, SQ_N];
+
+/// Return all legal moves for the playing color.
+pub fn get_legal_moves(board: &Board, game_state: &GameState) -> Vec<Move> {
+    let color = game_state.color;
+    get_pseudo_legal_moves(board, game_state).into_iter()
+        .filter(|m| {
+            let board = play(board, game_state, m);
+            match find_king(&board, color) {
+                Some(king) => !is_attacked_by(&board, &king, opposite(color)),
+                None => true,
+            }
+        })
+        .collect()
+}
+
+/// Return moves respecting piece movement, ignoring checks, except for
+/// castling which is fully checked.
+fn get_pseudo_legal_moves(board: &Board, game_state: &GameState) -> Vec<Move> {
+    let color = game_state.color;
+    let mut moves = vec!();
+    for f in 0..8 {
+        for r in 0..8 {
+            let at = (f, r);
+            let piece = get_square(board, &at);
+            if piece == SQ_E || !is_color(piece, color) {
+                continue
+            }
+            match get_type(piece) {
+                SQ_P => add_pawn_moves(board, game_state, &at, &mut moves),
+                SQ_N => add_steps(board, &at, color, &KNIGHT_OFFSETS, &mut moves),
+                SQ_K => {
+                    add_steps(board, &at, color, &KING_OFFSETS, &mut moves);
+                    add_castles(board, game_state, &at, &mut moves);
+                }
+                SQ_B => add_slides(board, &at, color, &BISHOP_DIRS, &mut moves),
+                SQ_R => add_slides(board, &at, color, &ROOK_DIRS, &mut moves),
+                SQ_Q => {
+                    add_slides(board, &at, color, &BISHOP_DIRS, &mut moves);
+                    add_slides(board, &at, color, &ROOK_DIRS, &mut moves);
+                }
+                _ => {}
+            }
+        }
+    }
+    moves
+}
+
+fn add_pawn_moves(board: &Board, game_state: &GameState, at: &Pos, moves: &mut Vec<Move>) {
+    let color = game_state.color;
+    let (dir, start_rank, last_rank) = if color == SQ_WH { (1, 1, 7) } else { (-1, 6, 0) };
+    let mut add = |to: Pos| {
+        if to.1 == last_rank {
+            for prom in PROMOTIONS.iter() {
+                moves.push((*at, to, Some(*prom)));
+            }
+        } else {
+            moves.push((*at, to, None));
+        }
+    };
+    let one = (at.0, at.1 + dir);
+    if is_valid_pos(one) && is_empty(board, &one) {
+        add(one);
+        let two = (at.0, at.1 + 2 * dir);
+        if at.1 == start_rank && is_empty(board, &two) {
+            add(two);
+        }
+    }
+    for df in [-1, 1].iter() {
+        let to = (at.0 + df, at.1 + dir);
+        if !is_valid_pos(to) {
+            continue
+        }
+        let target = get_square(board, &to);
+        if (target != SQ_E && is_color(target, opposite(color))) || game_state.en_passant == Some(to) {
+            add(to);
+        }
+    }
+}
+
+fn add_steps(board: &Board, at: &Pos, color: u8, offsets: &[(i8, i8)], moves: &mut Vec<Move>) {
+    for (df, dr) in offsets.iter() {
+        let to = (at.0 + df, at.1 + dr);
+        if is_valid_pos(to) && !is_color(get_square(board, &to), color) {
+            moves.push((*at, to, None));
+        }
+    }
+}
+
+fn add_slides(board: &Board, at: &Pos, color: u8, dirs: &[(i8, i8)], moves: &mut Vec<Move>) {
+    for dir in dirs.iter() {
+        for to in ray(at, *dir) {
+            let target = get_square(board, &to);
+            if is_color(target, color) {
+                break
+            }
+            moves.push((*at, to, None));
+            if target != SQ_E {
+                break
+            }
+        }
+    }
+}
+
+/// Add castles: the king and rook are on their initial squares, the
+/// squares between them are empty and the king does not start from,
+/// pass through or end on an attacked square.
+fn add_castles(board: &Board, game_state: &GameState, at: &Pos, moves: &mut Vec<Move>) {
+    let color = game_state.color;
+    let (rank, king_side, queen_side) = if color == SQ_WH {
+        (0, CASTLING_WH_K, CASTLING_WH_Q)
+    } else {
+        (7, CASTLING_BL_K, CASTLING_BL_Q)
+    };
+    if *at != (4, rank) {
+        return
+    }
+    let enemy = opposite(color);
+    let sides = [(king_side, 7, [5, 6], vec!(5, 6)), (queen_side, 0, [3, 2], vec!(3, 2, 1))];
+    for (castle, rook_file, king_path, empty_files) in sides.iter() {
+        if game_state.castling & castle == 0
+            || get_square(board, &(*rook_file, rank)) != color|SQ_R
+            || empty_files.iter().any(|f| !is_empty(board, &(*f, rank)))
+            || is_attacked_by(board, at, enemy)
+            || king_path.iter().any(|f| is_attacked_by(board, &(*f, rank), enemy))
+        {
+            continue
+        }
+        moves.push((*at, (king_path[1], rank), None));
+    }
+}
+
+/// Return the board after playing `m`.
+fn play(board: &Board, game_state: &GameState, m: &Move) -> Board {
+    let mut board = *board;
+    let piece = get_square(&board, &m.0);
+    if is_type(piece, SQ_P) && game_state.en_passant == Some(m.1) && is_empty(&board, &m.1) {
+        clear_square(&mut board, &(m.1.0, m.0.1));
+    }
+    if is_type(piece, SQ_K) && (m.1.0 - m.0.0).abs() == 2 {
+        let (rook_from, rook_to) = if m.1.0 == 6 { (7, 5) } else { (0, 3) };
+        move_piece(&mut board, &(rook_from, m.0.1), &(rook_to, m.0.1));
+    }
+    move_piece(&mut board, &m.0, &m.1);
+    if let Some(prom) = m.2 {
+        set_square(&mut board, &m.1, get_color(piece)|prom);
+    }
+    board
+}
+
+/// Return true if square `at` is attacked by a piece of color `by`.
+fn is_attacked_by(board: &Board, at: &Pos, by: u8) -> bool {
+    let is_enemy = |p: Pos, types: &[u8]| {
+        is_valid_pos(p) && {
+            let s = get_square(board, &p);
+            is_color(s, by) && types.contains(&get_type(s))
+        }
+    };
+    // Pawns attack diagonally towards the other side.
+    let pawn_dir = if by == SQ_WH { -1 } else { 1 };
+    if is_enemy((at.0 - 1, at.1 + pawn_dir), &[SQ_P]) || is_enemy((at.0 + 1, at.1 + pawn_dir), &[SQ_P]) {
+        return true
+    }
+    if KNIGHT_OFFSETS.iter().any(|(df, dr)| is_enemy((at.0 + df, at.1 + dr), &[SQ_N])) {
+        return true
+    }
+    if KING_OFFSETS.iter().any(|(df, dr)| is_enemy((at.0 + df, at.1 + dr), &[SQ_K])) {
+        return true
+    }
+    for (dirs, types) in [(BISHOP_DIRS, [SQ_B, SQ_Q]), (ROOK_DIRS, [SQ_R, SQ_Q])].iter() {
+        for dir in dirs.iter() {
+            if let Some(p) = ray(at, *dir).find(|p| !is_empty(board, p)) {
+                if is_enemy(p, types) {
+                    return true
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn legal_moves(fen: &str) -> Vec<String> {
+        let (board, game_state) = notation::position(fen);
+        let mut moves: Vec<String> = get_legal_moves(&board, &game_state).iter()
+            .map(notation::move_to_string)
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn test_get_legal_moves() {
+        assert_eq!(legal_moves(notation::FEN_START).len(), 20);
+        // Kiwipete, a classic movegen test position.
+        assert_eq!(
+            legal_moves("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").len(),
+            48
+        );
+        // En passant, and castling through an attacked square.
+        assert!(legal_moves("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").contains(&"e5d6".to_string()));
+        let moves = legal_moves("4k3/8/8/8/8/8/6p1/R3K2R w KQ - 0 1");
+        assert!(moves.contains(&"e1c1".to_string()));
+        assert!(!moves.contains(&"e1g1".to_string()));
+        // Promotions.
+        assert_eq!(legal_moves("8/P6k/8/8/8/8/8/K7 w - - 0 1").iter().filter(|m| m.starts_with("a7")).count(), 4);
+        // Pinned pieces can't leave the pin line.
+        assert_eq!(legal_moves("4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1").iter().filter(|m| m.starts_with("e2")).count(), 0);
+    }
+
+    #[test]
+    fn test_is_attacked_by() {
+        let board = new_from_fen("4k3/8/8/3p4/8/5n2/8/R3K3").unwrap();
+        assert!(is_attacked_by(&board, &pos("e4"), SQ_BL));
+        assert!(is_attacked_by(&board, &pos("c4"), SQ_BL));
+        assert!(is_attacked_by(&board, &pos("d4"), SQ_BL));
+        assert!(!is_attacked_by(&board, &pos("d3"), SQ_BL));
+        assert!(is_attacked_by(&board, &pos("g1"), SQ_BL));
+        assert!(is_attacked_by(&board, &pos("a8"), SQ_WH));
+        assert!(!is_attacked_by(&board, &pos("b2"), SQ_WH));
+        assert!(is_attacked_by(&board, &pos("d7"), SQ_BL));
+    }
+}
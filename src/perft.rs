@@ -0,0 +1,136 @@
+//! Perft: count leaf nodes of the legal moves tree to validate move generation.
+
+use crate::hash_cache::HashCache;
+use crate::movement;
+use crate::node::Node;
+use crate::zobrist;
+
+/// Default number of entries in a perft hash table.
+pub const DEFAULT_TABLE_ENTRIES: usize = 1 << 20;
+
+/// Count the leaf nodes of the legal moves tree at `depth` from `node`.
+///
+/// Moves are not applied at depth 1 as the number of legal moves is
+/// the number of leaves (bulk counting).
+pub fn perft(node: &Node, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1
+    }
+    let moves = node.get_player_moves(true);
+    if depth == 1 {
+        return moves.len() as u64
+    }
+    let mut count = 0;
+    for m in moves {
+        let mut sub_node = node.clone();
+        sub_node.apply_move(&m);
+        count += perft(&sub_node, depth - 1);
+    }
+    count
+}
+
+/// Same as `perft` but reuse counts of positions already visited at the same depth.
+///
+/// Positions are identified by a 64-bit hash so collisions can in
+/// theory produce wrong counts; use `perft` for strict validation.
+pub fn perft_hashed(node: &Node, depth: u32, table: &mut PerftTable) -> u64 {
+    let key = zobrist::hash(&node.board, &node.game_state);
+    perft_hashed_rec(node, key, depth, table)
+}
+
+/// Recursive part of `perft_hashed`; `key` is the Zobrist hash of `node`.
+fn perft_hashed_rec(node: &Node, key: u64, depth: u32, table: &mut PerftTable) -> u64 {
+    if depth <= 1 {
+        return perft(node, depth)
+    }
+    if let Some(count) = table.probe(key, depth) {
+        return count
+    }
+    let mut count = 0;
+    for m in node.get_player_moves(true) {
+        // Moves to positions already counted are not even played.
+        let sub_key = zobrist::hash_after(key, &node.board, &node.game_state, &m);
+        if let Some(sub_count) = table.probe(sub_key, depth - 1) {
+            count += sub_count;
+            continue
+        }
+        let (board, game_state) = movement::apply_move(&node.board, &node.game_state, &m);
+        let sub_node = Node { board, game_state };
+        count += perft_hashed_rec(&sub_node, sub_key, depth - 1, table);
+    }
+    table.store(key, depth, count);
+    count
+}
+
+/// Cache of perft counts by position hash and depth.
+pub struct PerftTable {
+    cache: HashCache<(u64, u32), u64>,
+}
+
+impl PerftTable {
+    /// Create a table with `num_entries` entries, see `HashCache::new`.
+    pub fn new(num_entries: usize) -> PerftTable {
+        PerftTable { cache: HashCache::new(num_entries) }
+    }
+
+    fn probe(&self, key: u64, depth: u32) -> Option<u64> {
+        self.cache.get((key, depth), key)
+    }
+
+    fn store(&mut self, key: u64, depth: u32, count: u64) {
+        self.cache.insert((key, depth), key, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board;
+    use crate::notation;
+
+    #[test]
+    fn test_perft() {
+        let mut node = Node::new();
+        node.board = board::new();
+        assert_eq!(perft(&node, 0), 1);
+        assert_eq!(perft(&node, 1), 20);
+        assert_eq!(perft(&node, 2), 400);
+        assert_eq!(perft(&node, 3), 8902);
+        // Position with en passant captures and pins, from the Chess
+        // Programming Wiki perft results.
+        let (board, game_state) = notation::position("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+        let node = Node { board, game_state };
+        assert_eq!(perft(&node, 1), 14);
+        assert_eq!(perft(&node, 2), 191);
+        assert_eq!(perft(&node, 3), 2812);
+        // Promotion-heavy positions, with capture-promotions into the corners.
+        for (fen, counts) in [
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", [6, 264, 9467]),
+            ("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", [24, 496, 9483]),
+        ].iter() {
+            let (board, game_state) = notation::position(fen);
+            let node = Node { board, game_state };
+            for (depth, count) in counts.iter().enumerate() {
+                assert_eq!(perft(&node, depth as u32 + 1), *count);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_hashed() {
+        let mut node = Node::new();
+        node.board = board::new();
+        let mut table = PerftTable::new(1024);
+        assert_eq!(perft_hashed(&node, 3, &mut table), 8902);
+        // Counts are reused from the table.
+        assert_eq!(perft_hashed(&node, 3, &mut table), 8902);
+        // A tiny table produces a lot of collisions but results are still correct.
+        let mut table = PerftTable::new(1);
+        assert_eq!(perft_hashed(&node, 3, &mut table), 8902);
+        // Kiwipete, with castles and en passant captures.
+        let (board, game_state) = notation::position("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let node = Node { board, game_state };
+        let mut table = PerftTable::new(1 << 12);
+        assert_eq!(perft_hashed(&node, 3, &mut table), 97862);
+    }
+}
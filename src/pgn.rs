@@ -0,0 +1,341 @@
+//! PGN import and export.
+//!
+//! Only the mainline of the first game is read, with its comments and
+//! NAGs: variations are skipped. A "FEN" tag sets the starting position.
+
+use std::io;
+
+use crate::board::{self, Board};
+use crate::game::Game;
+use crate::movement::Move;
+use crate::notation;
+use crate::rules::GameState;
+
+/// Tokens ending a game.
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Move suffixes and their NAG.
+const SUFFIX_NAGS: [(&str, u8); 6] = [("!", 1), ("?", 2), ("!!", 3), ("??", 4), ("!?", 5), ("?!", 6)];
+
+/// Maximum length of exported lines, as recommended by the PGN standard.
+const MAX_LINE_LENGTH: usize = 79;
+
+/// Movetext elements of the mainline.
+enum Token {
+    Move(String),
+    Comment(String),
+    Nag(u8),
+}
+
+/// Load the first game of a PGN file from `reader`.
+///
+/// Return the position at the end of the mainline and the moves played
+/// to reach it, or an error if the PGN is invalid or contains an
+/// illegal move.
+pub fn load_game<R: io::Read>(reader: R) -> Result<(Board, GameState, Vec<Move>), String> {
+    let game = read_game(reader)?;
+    let node = game.node_at(game.num_plies()).unwrap();
+    Ok((node.board, node.game_state.clone(), game.moves().to_vec()))
+}
+
+/// Read the first game of a PGN file from `reader`, with its tags and
+/// annotations; the cursor is at the end of the game.
+///
+/// Move suffixes like "!?" are read as their NAG. Return an error if
+/// the PGN is invalid or contains an illegal move.
+pub fn read_game<R: io::Read>(reader: R) -> Result<Game, String> {
+    let text = read_text(reader)?;
+    parse_game(&text).map(|(game, _)| game)
+}
+
+/// Read all games of a PGN file from `reader`, see `read_game`.
+///
+/// Return an error if any game is invalid, with its number from 1.
+pub fn read_games<R: io::Read>(reader: R) -> Result<Vec<Game>, String> {
+    let text = read_text(reader)?;
+    let mut games = vec!();
+    let mut rest = text.as_str();
+    while !rest.trim().is_empty() {
+        let (game, end) = parse_game(rest).map_err(|e| format!("Game {}: {}", games.len() + 1, e))?;
+        games.push(game);
+        rest = &rest[end..];
+    }
+    Ok(games)
+}
+
+fn read_text<R: io::Read>(mut reader: R) -> Result<String, String> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(|e| format!("Could not read PGN: {}", e))?;
+    Ok(text)
+}
+
+/// Parse the first game of `text`, returning it with the length of the
+/// text it was read from.
+fn parse_game(text: &str) -> Result<(Game, usize), String> {
+    let mut tags = vec!();
+    let mut tokens = vec!();
+    let mut result = None;
+    let mut chars = text.char_indices().peekable();
+    let mut end = text.len();
+    let mut variation_depth = 0;
+    let mut found_game = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '[' if variation_depth == 0 => {
+                // Tags after the movetext belong to the next game.
+                if !tokens.is_empty() {
+                    end = i;
+                    break
+                }
+                found_game = true;
+                let tag: String = chars.by_ref().map(|(_, c)| c).take_while(|c| *c != ']').collect();
+                if let Some(tag) = parse_tag(&tag) {
+                    tags.push(tag);
+                }
+            }
+            '{' | ';' => {
+                let closing = if c == '{' { '}' } else { '\n' };
+                let comment: String = chars.by_ref().map(|(_, c)| c).take_while(|c| *c != closing).collect();
+                if variation_depth == 0 {
+                    tokens.push(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+                }
+            }
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            '$' => {
+                let mut nag = String::new();
+                while let Some((_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+                    nag.push(*c);
+                    chars.next();
+                }
+                if let (0, Ok(nag)) = (variation_depth, nag.parse::<u8>()) {
+                    tokens.push(Token::Nag(nag));
+                }
+            }
+            c if c.is_whitespace() || c == ']' || c == '}' => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some((_, c)) = chars.peek().filter(|(_, c)| !c.is_whitespace() && !"{}()[];$".contains(*c)) {
+                    token.push(*c);
+                    chars.next();
+                }
+                if variation_depth > 0 {
+                    continue
+                }
+                found_game = true;
+                if RESULTS.contains(&token.as_str()) {
+                    end = i + token.len();
+                    result = Some(token);
+                    break
+                }
+                tokens.push(Token::Move(token));
+            }
+        }
+    }
+    if !found_game {
+        return Err("No game in PGN".to_string())
+    }
+
+    let fen = tags.iter().find(|(name, _)| name == "FEN")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| notation::FEN_START.to_string());
+    let (board, game_state) = notation::parse_fen(&fen)
+        .and_then(|fen| notation::fen_to_position(&fen))
+        .ok_or_else(|| format!("Invalid FEN tag in PGN: {}", fen))?;
+    let mut game = Game::new(&board, &game_state);
+    game.tags = tags;
+    game.result = result
+        .or_else(|| game.tag("Result").map(|r| r.to_string()))
+        .unwrap_or_else(|| "*".to_string());
+    for token in tokens {
+        let token = match token {
+            Token::Move(token) => token,
+            Token::Comment(comment) => {
+                let annotation = game.annotation_mut(game.ply()).unwrap();
+                annotation.comment = Some(match annotation.comment.take() {
+                    Some(previous) => format!("{} {}", previous, comment),
+                    None => comment,
+                });
+                continue
+            }
+            Token::Nag(nag) => {
+                game.annotation_mut(game.ply()).unwrap().nags.push(nag);
+                continue
+            }
+        };
+        // Skip move numbers, possibly stuck to the move, e.g. "1.e4".
+        let token = match token.trim_start_matches(|c: char| c.is_ascii_digit()) {
+            rest if rest.starts_with('.') => rest.trim_start_matches('.'),
+            _ => token.as_str(),
+        };
+        let san = token.trim_end_matches(['!', '?']);
+        let suffix_nag = SUFFIX_NAGS.iter().find(|(s, _)| *s == &token[san.len()..]).map(|(_, nag)| *nag);
+        if !san.is_empty() {
+            let node = game.node();
+            let m = notation::san_to_move(&node.board, &node.game_state, san)
+                .ok_or_else(|| notation::illegal_move_message(&node.board, &node.game_state, game.ply() + 1, san))?;
+            game.play_move(&m)?;
+        }
+        if let Some(nag) = suffix_nag {
+            game.annotation_mut(game.ply()).unwrap().nags.push(nag);
+        }
+    }
+    Ok((game, end))
+}
+
+/// Write `game` as PGN, with its tags and annotations.
+///
+/// The "Result" tag is set to the result of the game, and "SetUp" and
+/// "FEN" tags are added if it does not start from the usual starting
+/// position.
+pub fn write_game(game: &Game) -> String {
+    let mut tags: Vec<(String, String)> = game.tags.iter()
+        .filter(|(name, _)| name != "Result")
+        .cloned()
+        .collect();
+    tags.push(("Result".to_string(), game.result.clone()));
+    let start = game.node_at(0).unwrap();
+    let start_fen = notation::to_fen(&start.board, &start.game_state);
+    if start_fen != notation::FEN_START && game.tag("FEN").is_none() {
+        tags.push(("SetUp".to_string(), "1".to_string()));
+        tags.push(("FEN".to_string(), start_fen));
+    }
+    let mut pgn: String = tags.iter()
+        .map(|(name, value)| format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    pgn.push('\n');
+
+    let comment_token = |ply: usize| {
+        game.annotation(ply).unwrap().comment.as_ref().map(|c| format!("{{{}}}", c.replace('}', "")))
+    };
+    let mut tokens: Vec<String> = comment_token(0).into_iter().collect();
+    for (i, m) in game.moves().iter().enumerate() {
+        let node = game.node_at(i).unwrap();
+        let fullmove = node.game_state.fullmove;
+        if board::is_white(node.game_state.color) {
+            tokens.push(format!("{}.", fullmove));
+        } else if i == 0 || game.annotation(i).unwrap().comment.is_some() {
+            tokens.push(format!("{}...", fullmove));
+        }
+        tokens.push(notation::move_to_san(&node.board, &node.game_state, m));
+        tokens.extend(game.annotation(i + 1).unwrap().nags.iter().map(|nag| format!("${}", nag)));
+        tokens.extend(comment_token(i + 1));
+    }
+    tokens.push(game.result.clone());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    pgn
+}
+
+/// Parse the content of a tag between brackets, e.g. `Event "Casual"`,
+/// into its name and value.
+fn parse_tag(tag: &str) -> Option<(String, String)> {
+    let (name, value) = tag.trim().split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{move_list_to_string, to_fen};
+
+    #[test]
+    fn test_load_game() {
+        let pgn = r#"[Event "Casual \"blitz\""]
+[Site "?"]
+[Result "1-0"]
+
+1. e4 {Best by test} e5 2.Nf3 $1 (2. f4 exf4 (2... d5) 3. Nf3) 2... Nc6
+3. Bc4 ; Italian
+Nf6?! 4. Ng5 d5 5. exd5 Na5 6. Bb5+ c6 7. dxc6 bxc6 8. Qf3 1-0
+
+[Event "Next game"]
+
+1. d4 *
+"#;
+        let (board, game_state, moves) = load_game(pgn.as_bytes()).unwrap();
+        assert_eq!(moves.len(), 15);
+        let games = read_games(pgn.as_bytes()).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!((games[0].num_plies(), games[0].result.as_str()), (15, "1-0"));
+        assert_eq!(games[1].tag("Event"), Some("Next game"));
+        assert_eq!(move_list_to_string(games[1].moves()), "d2d4");
+        assert_eq!(move_list_to_string(&moves[..4]), "e2e4 e7e5 g1f3 b8c6");
+        assert_eq!(
+            to_fen(&board, &game_state),
+            "r1bqkb1r/p4ppp/2p2n2/nB2p1N1/8/5Q2/PPPP1PPP/RNB1K2R b KQkq - 1 8"
+        );
+    }
+
+    #[test]
+    fn test_load_game_from_fen() {
+        let pgn = "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 w Q - 0 1\"]\n\n1. O-O-O Kf7 *\n";
+        let (board, game_state, moves) = load_game(pgn.as_bytes()).unwrap();
+        assert_eq!(moves.len(), 2);
+        assert_eq!(to_fen(&board, &game_state), "8/5k2/8/8/8/8/8/2KR4 w - - 2 2");
+    }
+
+    #[test]
+    fn test_read_write_game() {
+        let pgn = r#"[Event "Casual \"blitz\""]
+
+{Annotated} 1. e4 {Best by test} e5 2. Nf3 $1 (2. f4) Nc6!? 3. Bb5 a6 4. Ba4 ; Keeping the pin
+Nf6 5. O-O Be7 *
+"#;
+        let mut game = read_game(pgn.as_bytes()).unwrap();
+        assert_eq!((game.ply(), game.num_plies()), (10, 10));
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(game.annotation(0).unwrap().comment.as_deref(), Some("Annotated"));
+        assert_eq!(game.annotation(3).unwrap().nags, vec![1]);
+        assert_eq!(game.annotation(4).unwrap().nags, vec![5]);
+        assert_eq!(game.annotation(7).unwrap().comment.as_deref(), Some("Keeping the pin"));
+        game.annotation_mut(10).unwrap().nags.push(2);
+        game.result = "1/2-1/2".to_string();
+        let written = write_game(&game);
+        assert_eq!(written, "[Event \"Casual \\\"blitz\\\"\"]\n[Result \"1/2-1/2\"]\n\n\
+            {Annotated} 1. e4 {Best by test} 1... e5 2. Nf3 $1 Nc6 $5 3. Bb5 a6 4. Ba4\n\
+            {Keeping the pin} 4... Nf6 5. O-O Be7 $2 1/2-1/2\n");
+        let reread = read_game(written.as_bytes()).unwrap();
+        assert_eq!(reread.moves(), game.moves());
+        assert_eq!(write_game(&reread), written);
+
+        // Other starting positions get a FEN tag.
+        let (board, game_state) = notation::position("4k3/8/8/8/8/8/8/R3K3 b Q - 0 1");
+        let mut game = Game::new(&board, &game_state);
+        game.play_move(&notation::parse_move("e8d7").unwrap()).unwrap();
+        assert_eq!(
+            write_game(&game),
+            "[Result \"*\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 b Q - 0 1\"]\n\n1... Kd7 *\n"
+        );
+    }
+
+    #[test]
+    fn test_load_game_errors() {
+        assert!(load_game("".as_bytes()).is_err());
+        // Illegal moves are reported with their position.
+        let error = load_game("1. e4 e5 2. Nf3 Nc6 3. Ke3 *".as_bytes()).unwrap_err();
+        assert!(error.starts_with(
+            "Illegal move Ke3 at ply 5 in position \
+             r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3 (legal moves: "
+        ));
+        assert!(error.contains(" e1e2 "));
+        assert!(load_game("[FEN \"8/8 w\"]\n*".as_bytes()).is_err());
+        // A game without moves is valid.
+        let (_, _, moves) = load_game("[Event \"?\"]\n\n*".as_bytes()).unwrap();
+        assert!(moves.is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+//! Interactive play in a terminal: a human enters moves in SAN or UCI
+//! notation against the engine, which searches each move with a fixed
+//! time or depth.
+//!
+//! Undoing takes back the last move of the player and the reply of the
+//! engine, so that it is the player's turn again.
+
+use std::io::{BufRead, Write};
+use std::sync::{Arc, atomic, mpsc};
+
+use crate::analysis::{AnalysisParams, Analyzer, SearchResult};
+use crate::board;
+use crate::game::Game;
+use crate::movement::Move;
+use crate::node::Node;
+use crate::notation;
+use crate::rules::{self, DrawRule, Status};
+use crate::tt::{self, TranspositionTable};
+
+const HELP: &str = "Enter moves in SAN (Nf3) or UCI (g1f3) notation, or a command:
+  undo   take back your last move
+  board  draw the board again
+  help   show this help
+  quit   leave the game";
+
+/// Settings of a game against the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayParams {
+    /// Color of the human player.
+    pub human_color: u8,
+    /// Search time per move in ms, -1 to only limit the depth.
+    pub move_time: i32,
+    /// Maximum search depth, -1 for no limit.
+    pub depth: i32,
+    /// Transposition table size in MB.
+    pub hash_mb: usize,
+}
+
+pub const DEFAULT_PARAMS: PlayParams = PlayParams {
+    human_color: board::SQ_WH,
+    move_time: 1000,
+    depth: -1,
+    hash_mb: tt::DEFAULT_SIZE_MB,
+};
+
+/// Input line of the player.
+#[derive(Debug, PartialEq)]
+pub enum PlayCmd {
+    Move(String),
+    Undo,
+    Board,
+    Help,
+    Quit,
+    Empty,
+}
+
+/// A game between a human and the engine, see the module documentation.
+pub struct Play<W: Write> {
+    game: Game,
+    params: PlayParams,
+    tt: Arc<TranspositionTable>,
+    output: W,
+}
+
+impl<W: Write> Play<W> {
+    /// Create a game from `node`, writing to `output`.
+    pub fn new(node: &Node, params: PlayParams, output: W) -> Play<W> {
+        let tt = Arc::new(TranspositionTable::new(params.hash_mb));
+        Play { game: Game::new(&node.board, &node.game_state), params, tt, output }
+    }
+
+    /// Play until the player quits or `input` ends.
+    pub fn run(&mut self, input: &mut dyn BufRead) {
+        writeln!(self.output, "{}", HELP).unwrap();
+        self.draw();
+        loop {
+            if self.status() == Status::Ongoing && !self.is_human_turn() {
+                if !self.play_engine_move() {
+                    break
+                }
+                continue
+            }
+            write!(self.output, "> ").unwrap();
+            self.output.flush().unwrap();
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 {
+                writeln!(self.output).unwrap();
+                break
+            }
+            match parse_command(&line) {
+                PlayCmd::Move(s) => self.play_human_move(&s),
+                PlayCmd::Undo => {
+                    if self.undo() {
+                        self.draw();
+                    } else {
+                        writeln!(self.output, "No move to undo.").unwrap();
+                    }
+                }
+                PlayCmd::Board => self.draw(),
+                PlayCmd::Help => writeln!(self.output, "{}", HELP).unwrap(),
+                PlayCmd::Quit => break,
+                PlayCmd::Empty => {}
+            }
+        }
+    }
+
+    /// Return the status of the current position.
+    fn status(&self) -> Status {
+        let node = self.game.node();
+        rules::game_status(&node.board, &node.game_state)
+    }
+
+    fn is_human_turn(&self) -> bool {
+        self.game.node().game_state.color == self.params.human_color
+    }
+
+    /// Draw the board from the side of the player.
+    fn draw(&mut self) {
+        board::draw_from(&self.game.node().board, self.params.human_color, &mut self.output);
+        writeln!(self.output).unwrap();
+    }
+
+    /// Play the move of the player written `s`, if legal.
+    fn play_human_move(&mut self, s: &str) {
+        if self.status() != Status::Ongoing {
+            writeln!(self.output, "The game is over, undo or quit.").unwrap();
+            return
+        }
+        let played = parse_user_move(self.game.node(), s).and_then(|m| self.game.play_move(&m).ok());
+        match played {
+            Some(_) => self.after_move(),
+            None => writeln!(self.output, "Illegal move: {}", s).unwrap(),
+        }
+    }
+
+    /// Search and play the move of the engine; return false if it found
+    /// none.
+    fn play_engine_move(&mut self) -> bool {
+        let node = self.game.node().clone();
+        let result = search(&node, &self.params, self.tt.clone());
+        let m = match result.best_move {
+            Some(m) => m,
+            None => {
+                writeln!(self.output, "Vatu found no move.").unwrap();
+                return false
+            }
+        };
+        let san = notation::move_to_san(&node.board, &node.game_state, &m);
+        if self.game.play_move(&m).is_err() {
+            writeln!(self.output, "Vatu played an illegal move: {}", notation::move_to_string(&m)).unwrap();
+            return false
+        }
+        writeln!(self.output, "Vatu plays {}{}", san, format_result(&result)).unwrap();
+        self.after_move();
+        true
+    }
+
+    /// Draw the board and announce the end of the game, if it is.
+    fn after_move(&mut self) {
+        self.draw();
+        let result = match self.status() {
+            Status::Ongoing => return,
+            Status::Checkmate(board::SQ_WH) => "Checkmate, White wins.",
+            Status::Checkmate(_) => "Checkmate, Black wins.",
+            Status::Stalemate => "Stalemate, draw.",
+            Status::DrawByRule(DrawRule::FiftyMove) => "Draw by the fifty move rule.",
+            Status::DrawByRule(DrawRule::InsufficientMaterial) => "Draw by insufficient material.",
+        };
+        writeln!(self.output, "{}", result).unwrap();
+    }
+
+    /// Take back moves until the player is to move, before their last
+    /// move; return false if they did not play yet.
+    fn undo(&mut self) -> bool {
+        let human_color = self.params.human_color;
+        let ply = (0..self.game.ply()).rev()
+            .find(|ply| self.game.node_at(*ply).unwrap().game_state.color == human_color);
+        match ply {
+            Some(ply) => self.game.goto_ply(ply),
+            None => false,
+        }
+    }
+}
+
+/// Parse an input line of the player.
+pub fn parse_command(s: &str) -> PlayCmd {
+    match s.trim() {
+        "" => PlayCmd::Empty,
+        "undo" => PlayCmd::Undo,
+        "board" => PlayCmd::Board,
+        "help" | "?" => PlayCmd::Help,
+        "quit" | "exit" => PlayCmd::Quit,
+        s => PlayCmd::Move(s.to_string()),
+    }
+}
+
+/// Return the move written `s` in UCI or SAN notation for `node`; it
+/// may still be illegal if written in UCI notation.
+fn parse_user_move(node: &Node, s: &str) -> Option<Move> {
+    notation::parse_move(s).or_else(|| notation::san_to_move(&node.board, &node.game_state, s))
+}
+
+/// Search the move to play in `node`.
+fn search(node: &Node, params: &PlayParams, tt: Arc<TranspositionTable>) -> SearchResult {
+    // Infos are not shown but the receiver must outlive the search.
+    let (tx, _rx) = mpsc::channel();
+    let mut analyzer = Analyzer::new(node.clone(), tx);
+    analyzer.set_transposition_table(tt);
+    let args = AnalysisParams { move_time: params.move_time, depth: params.depth, ..Default::default() };
+    analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)))
+}
+
+/// Return the depth and score of a search result, for the player.
+fn format_result(result: &SearchResult) -> String {
+    match (result.mate, result.score) {
+        (Some(moves), _) => format!(" (depth {}, mate in {})", result.depth, moves),
+        (None, Some(score)) => format!(" (depth {}, score {} cp)", result.depth, score),
+        (None, None) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("undo\n"), PlayCmd::Undo);
+        assert_eq!(parse_command(" quit "), PlayCmd::Quit);
+        assert_eq!(parse_command("\n"), PlayCmd::Empty);
+        assert_eq!(parse_command("Nf3\n"), PlayCmd::Move("Nf3".to_string()));
+        let node = Node { board: board::new(), game_state: rules::GameState::new() };
+        assert_eq!(parse_user_move(&node, "Nf3"), notation::parse_move("g1f3"));
+        assert_eq!(parse_user_move(&node, "g1f3"), notation::parse_move("g1f3"));
+        assert_eq!(parse_user_move(&node, "Nd2"), None);
+    }
+
+    #[test]
+    fn test_play() {
+        let node = Node { board: board::new(), game_state: rules::GameState::new() };
+        let params = PlayParams { move_time: -1, depth: 1, hash_mb: 1, ..DEFAULT_PARAMS };
+        let mut play = Play::new(&node, params, vec!());
+        let mut input = "e4\ne2e4\nundo\nundo\nd2d4\nquit\n".as_bytes();
+        play.run(&mut input);
+        let output = String::from_utf8(play.output).unwrap();
+        assert!(output.contains("Illegal move: e2e4\n"));
+        assert!(output.contains("No move to undo.\n"));
+        assert_eq!(output.matches("Vatu plays ").count(), 2);
+        assert_eq!(play.game.num_plies(), 2);
+        assert_eq!(play.game.moves()[0], notation::parse_move("d2d4").unwrap());
+    }
+
+    #[test]
+    fn test_game_over() {
+        // Black to move is mated after Ra8, then undo returns to ply 0.
+        let (board, game_state) = notation::position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let params = PlayParams { move_time: -1, depth: 1, hash_mb: 1, ..DEFAULT_PARAMS };
+        let mut play = Play::new(&Node { board, game_state }, params, vec!());
+        let mut input = "Ra8#\nKh1\nundo\n".as_bytes();
+        play.run(&mut input);
+        let output = String::from_utf8(play.output).unwrap();
+        assert!(output.contains("Checkmate, White wins.\n"));
+        assert!(output.contains("The game is over, undo or quit.\n"));
+        assert_eq!(play.game.ply(), 0);
+    }
+}
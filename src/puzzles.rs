@@ -0,0 +1,187 @@
+//! Puzzles found in games: positions after a blunder where a single
+//! move wins.
+//!
+//! Every position of a game is searched at a low depth to find blunders,
+//! moves after which the opponent wins while the player was not lost.
+//! The position after each blunder is then searched deeper to check
+//! that the best move wins and that the second best move does not.
+
+use std::sync::{Arc, atomic, mpsc};
+
+use crate::analysis::{AnalysisParams, Analyzer, SearchResult};
+use crate::game::Game;
+use crate::movement::Move;
+use crate::node::Node;
+use crate::notation;
+use crate::score::RelativeScore;
+use crate::tt::TranspositionTable;
+
+/// Size of the transposition table shared by the searches, in MB.
+const HASH_MB: usize = 16;
+
+/// Settings of the puzzle search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PuzzleParams {
+    /// Depth of the search of every position.
+    pub scan_depth: u32,
+    /// Depth of the searches checking puzzles.
+    pub verify_depth: u32,
+    /// Minimum score in centipawns of the solution; the second best move
+    /// must score less.
+    pub min_score: i32,
+    /// Minimum score loss in centipawns of a blunder, and minimum
+    /// difference between the solution and the second best move.
+    pub min_margin: i32,
+}
+
+pub const DEFAULT_PARAMS: PuzzleParams = PuzzleParams {
+    scan_depth: 3,
+    verify_depth: 5,
+    min_score: 300,
+    min_margin: 200,
+};
+
+/// A puzzle: a position and its solution.
+#[derive(Debug, Clone)]
+pub struct Puzzle {
+    pub node: Node,
+    /// Ply of the position in its game.
+    pub ply: usize,
+    /// Expected line, starting with the winning move.
+    pub solution: Vec<Move>,
+    /// Score of the solution for the player to move.
+    pub score: RelativeScore,
+    /// Score of the second best move, None if there is none.
+    pub second_score: Option<RelativeScore>,
+}
+
+impl Puzzle {
+    /// Return the puzzle as an EPD line, with the solution as "bm", its
+    /// score as "ce", the line as "pv" and `id` as "id".
+    pub fn to_epd(&self, id: &str) -> String {
+        let (board, game_state) = (&self.node.board, &self.node.game_state);
+        let fen = notation::to_fen(board, game_state);
+        let position: Vec<&str> = fen.split_whitespace().take(4).collect();
+        let mut pv_node = self.node.clone();
+        let pv: Vec<String> = self.solution.iter().map(|m| {
+            let san = notation::move_to_san(&pv_node.board, &pv_node.game_state, m);
+            pv_node.apply_move(m);
+            san
+        }).collect();
+        format!(
+            "{} bm {}; ce {}; pv {}; id \"{}\";",
+            position.join(" "), pv[0], self.score.0, pv.join(" "), id.replace('"', "")
+        )
+    }
+
+    /// Return the puzzle as a game from its position, with the solution
+    /// as moves, for PGN export.
+    pub fn to_game(&self, event: &str) -> Game {
+        let mut game = Game::new(&self.node.board, &self.node.game_state);
+        game.tags.push(("Event".to_string(), event.to_string()));
+        for m in &self.solution {
+            if game.play_move(m).is_err() {
+                break
+            }
+        }
+        if let Some(annotation) = game.annotation_mut(1) {
+            annotation.nags.push(1);
+            annotation.comment = Some(format!("{} cp", self.score));
+        }
+        game
+    }
+}
+
+/// Searches sharing a transposition table.
+struct Searcher {
+    tt: Arc<TranspositionTable>,
+}
+
+impl Searcher {
+    /// Search `node` at `depth`, only among `search_moves` if not empty.
+    fn search(&self, node: &Node, depth: u32, search_moves: Vec<Move>) -> SearchResult {
+        // Infos are not used but the receiver must outlive the search.
+        let (tx, _rx) = mpsc::channel();
+        let mut analyzer = Analyzer::new(node.clone(), tx);
+        analyzer.set_transposition_table(self.tt.clone());
+        let args = AnalysisParams { depth: depth as i32, search_moves, ..Default::default() };
+        analyzer.analyze(&args, Arc::new(atomic::AtomicBool::new(true)))
+    }
+}
+
+/// Return the puzzles found in `game`, see the module documentation.
+pub fn find_puzzles(game: &Game, params: &PuzzleParams) -> Vec<Puzzle> {
+    let searcher = Searcher { tt: Arc::new(TranspositionTable::new(HASH_MB)) };
+    // Scores of each position for the player to move, None if over.
+    let scores: Vec<Option<i32>> = (0..=game.num_plies()).map(|ply| {
+        let node = game.node_at(ply).unwrap();
+        if node.get_player_moves(true).is_empty() {
+            return None
+        }
+        searcher.search(node, params.scan_depth, vec!()).score.map(|s| s.0)
+    }).collect();
+
+    let mut puzzles = vec!();
+    for ply in 1..=game.num_plies() {
+        let (before, after) = match (scores[ply - 1], scores[ply]) {
+            (Some(before), Some(after)) => (before, after),
+            _ => continue,
+        };
+        // The player who moved was not lost and now the opponent wins.
+        if before <= -params.min_score || after < params.min_score || before + after < params.min_margin {
+            continue
+        }
+        let node = game.node_at(ply).unwrap();
+        if let Some(puzzle) = verify(&searcher, node, ply, params) {
+            puzzles.push(puzzle);
+        }
+    }
+    puzzles
+}
+
+/// Return a puzzle for `node` if its best move is winning and unique at
+/// the verification depth.
+fn verify(searcher: &Searcher, node: &Node, ply: usize, params: &PuzzleParams) -> Option<Puzzle> {
+    let moves = node.get_player_moves(true);
+    if moves.len() < 2 {
+        return None
+    }
+    let best = searcher.search(node, params.verify_depth, vec!());
+    let (best_move, score) = (best.best_move?, best.score?);
+    if score.0 < params.min_score {
+        return None
+    }
+    let others: Vec<Move> = moves.into_iter().filter(|m| *m != best_move).collect();
+    let second_score = searcher.search(node, params.verify_depth, others).score;
+    if let Some(second) = second_score {
+        if second.0 >= params.min_score || score.0 - second.0 < params.min_margin {
+            return None
+        }
+    }
+    Some(Puzzle { node: node.clone(), ply, solution: best.pv, score, second_score })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn;
+
+    #[test]
+    fn test_find_puzzles() {
+        // Black puts the queen in front of the rook, only Rxd5 wins.
+        let pgn = "[FEN \"4k3/8/8/q7/8/8/3R4/4K3 b - - 0 1\"]\n\n1... Qd5 2. Rxd5 Kf7 *\n";
+        let game = pgn::read_game(pgn.as_bytes()).unwrap();
+        let params = PuzzleParams { scan_depth: 2, verify_depth: 3, ..DEFAULT_PARAMS };
+        let puzzles = find_puzzles(&game, &params);
+        assert_eq!(puzzles.len(), 1);
+        let puzzle = &puzzles[0];
+        assert_eq!(puzzle.ply, 1);
+        assert_eq!(puzzle.solution[0], notation::parse_move("d2d5").unwrap());
+        assert!(puzzle.score.0 >= params.min_score);
+        assert!(puzzle.second_score.unwrap().0 < 0);
+        assert!(puzzle.to_epd("test 1").starts_with("4k3/8/8/3q4/8/8/3R4/4K3 w - - bm Rxd5; ce "));
+        let game = puzzle.to_game("Puzzle");
+        assert_eq!(game.moves(), &puzzle.solution[..]);
+        assert!(pgn::write_game(&game).contains("[FEN \"4k3/8/8/3q4/8/8/3R4/4K3 w - - 1 2\"]"));
+    }
+}
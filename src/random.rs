@@ -0,0 +1,144 @@
+//! Random legal positions generation, for fuzzing and testing.
+
+use crate::board::*;
+use crate::node::Node;
+use crate::rules;
+
+/// Xorshift pseudo-random number generator.
+///
+/// It is fast and deterministic for a given seed, which is what we
+/// want to reproduce failures, but not suitable for anything else.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator; a zero seed is replaced as xorshift gets stuck on it.
+    pub const fn new(seed: u64) -> Rng {
+        Rng { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+
+    /// Return the next random u64.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Return a random number in [0, n[. `n` must be strictly positive.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Generate a position by playing at most `num_moves` random legal moves from the start.
+///
+/// Generation stops earlier if the game is over, so the returned
+/// node may have no legal moves.
+pub fn random_node_from_moves(rng: &mut Rng, num_moves: u32) -> Node {
+    let mut node = Node::new();
+    node.board = new();
+    for _ in 0..num_moves {
+        let moves = node.get_player_moves(true);
+        if moves.is_empty() {
+            break
+        }
+        let m = moves[rng.below(moves.len())];
+        node.apply_move(&m);
+    }
+    node
+}
+
+/// Generate a position by placing kings and `num_pieces` other random pieces.
+///
+/// Placements are drawn until they form a valid position: pawns are
+/// not on the first or last ranks, kings are not next to each other
+/// and the player not to move is not in check. Castling is never
+/// available.
+pub fn random_node_from_placement(rng: &mut Rng, num_pieces: u8) -> Node {
+    const PIECES: [u8; 10] = [
+        SQ_WH_P, SQ_WH_B, SQ_WH_N, SQ_WH_R, SQ_WH_Q,
+        SQ_BL_P, SQ_BL_B, SQ_BL_N, SQ_BL_R, SQ_BL_Q,
+    ];
+    let num_pieces = num_pieces.min(62);
+    loop {
+        let mut node = Node::new();
+        node.game_state.castling = 0;
+        node.game_state.color = if rng.below(2) == 0 { SQ_WH } else { SQ_BL };
+        place_randomly(rng, &mut node.board, SQ_WH_K);
+        place_randomly(rng, &mut node.board, SQ_BL_K);
+        for _ in 0..num_pieces {
+            let piece = PIECES[rng.below(PIECES.len())];
+            place_randomly(rng, &mut node.board, piece);
+        }
+        if is_valid_node(&node) {
+            return node
+        }
+    }
+}
+
+/// Put `piece` on a random empty square of `board`, respecting pawn ranks.
+fn place_randomly(rng: &mut Rng, board: &mut Board, piece: u8) {
+    loop {
+        let p = (rng.below(8) as i8, rng.below(8) as i8);
+        if !is_empty(board, &p) || (is_type(piece, SQ_P) && (p.1 == POS_MIN || p.1 == POS_MAX)) {
+            continue
+        }
+        set_square(board, &p, piece);
+        return
+    }
+}
+
+/// Return true if kings are apart and the player not to move is not in check.
+fn is_valid_node(node: &Node) -> bool {
+    let (wk, bk) = match (find_king(&node.board, SQ_WH), find_king(&node.board, SQ_BL)) {
+        (Some(wk), Some(bk)) => (wk, bk),
+        _ => return false,
+    };
+    if (wk.0 - bk.0).abs() <= 1 && (wk.1 - bk.1).abs() <= 1 {
+        return false
+    }
+    let mut opponent_state = node.game_state.clone();
+    opponent_state.color = opposite(node.game_state.color);
+    !rules::is_in_check(&node.board, &opponent_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut rng1 = Rng::new(42);
+        let mut rng2 = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(rng1.next_u64(), rng2.next_u64());
+            assert!(rng1.below(10) < 10);
+            rng2.below(10);
+        }
+        let mut rng = Rng::new(0);
+        assert_ne!(rng.next_u64(), 0);
+    }
+
+    #[test]
+    fn test_random_node_from_moves() {
+        let mut rng = Rng::new(1);
+        for _ in 0..5 {
+            let node = random_node_from_moves(&mut rng, 20);
+            assert!(is_valid_node(&node));
+        }
+        // No moves, no changes.
+        assert!(eq(&random_node_from_moves(&mut rng, 0).board, &new()));
+    }
+
+    #[test]
+    fn test_random_node_from_placement() {
+        let mut rng = Rng::new(2);
+        for n in 0..20 {
+            let node = random_node_from_placement(&mut rng, n);
+            assert_eq!(num_pieces(&node.board), n + 2);
+            assert!(is_valid_node(&node));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Late move reductions (LMR).
+//!
+//! With good move ordering, moves searched late rarely turn out to be
+//! the best ones, so quiet late moves are searched at a reduced depth
+//! first. The reduction grows with the logarithms of both the remaining
+//! depth and the move index, and a move beating alpha anyway is searched
+//! again at full depth.
+
+use crate::tune;
+
+/// Default reduction added to every reducible move, in plies.
+pub const DEFAULT_BASE: f32 = 0.75;
+/// Default divisor of the product of the depth and index logarithms.
+pub const DEFAULT_DIVISOR: f32 = 2.25;
+
+/// Minimum remaining depth to reduce moves.
+pub const MIN_DEPTH: u32 = 3;
+/// Index of the first move that can be reduced.
+pub const MIN_MOVE_INDEX: usize = 3;
+
+/// Move indices beyond this one use its reductions.
+const MAX_MOVE_INDEX: usize = 63;
+
+/// Table of reductions by remaining depth and move index.
+pub struct ReductionTable {
+    max_depth: u32,
+    reductions: Vec<[u8; MAX_MOVE_INDEX + 1]>,
+}
+
+impl Default for ReductionTable {
+    fn default() -> Self {
+        Self::with_params(&tune::SearchParams::default())
+    }
+}
+
+impl ReductionTable {
+    /// Compute reductions with the tunable base and divisor of `params`,
+    /// for remaining depths up to `MAX_SEARCH_DEPTH`.
+    pub fn with_params(params: &tune::SearchParams) -> ReductionTable {
+        let (base, divisor) = (params.lmr_base as f32 / 100.0, params.lmr_divisor as f32 / 100.0);
+        Self::new(base, divisor, crate::analysis::MAX_SEARCH_DEPTH)
+    }
+
+    /// Compute reductions `base + ln(depth) * ln(index) / divisor` for
+    /// remaining depths up to `max_depth`.
+    pub fn new(base: f32, divisor: f32, max_depth: u32) -> ReductionTable {
+        let reductions = (0..=max_depth).map(|depth| {
+            let mut row = [0; MAX_MOVE_INDEX + 1];
+            for (index, r) in row.iter_mut().enumerate() {
+                if depth >= MIN_DEPTH && index >= MIN_MOVE_INDEX {
+                    let reduction = base + (depth as f32).ln() * (index as f32).ln() / divisor;
+                    *r = reduction.max(0.0) as u8;
+                }
+            }
+            row
+        }).collect();
+        ReductionTable { max_depth, reductions }
+    }
+
+    /// Return the reduction of the move at `index` in the move list,
+    /// with `depth` plies left to search.
+    ///
+    /// At least one ply is always left to search.
+    pub fn get(&self, depth: u32, index: usize) -> u32 {
+        let row = &self.reductions[depth.min(self.max_depth) as usize];
+        (row[index.min(MAX_MOVE_INDEX)] as u32).min(depth.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduction_table() {
+        let table = ReductionTable::default();
+        // Early moves and shallow nodes are never reduced.
+        assert_eq!(table.get(MIN_DEPTH - 1, 40), 0);
+        assert_eq!(table.get(10, MIN_MOVE_INDEX - 1), 0);
+        assert_eq!(table.get(MIN_DEPTH, MIN_MOVE_INDEX), 1);
+        // Reductions grow with depth and index, leaving a ply to search.
+        assert!(table.get(20, 30) > table.get(10, 30));
+        assert!(table.get(20, 30) > table.get(20, 10));
+        assert_eq!(table.get(20, 1000), table.get(20, MAX_MOVE_INDEX));
+        assert_eq!(table.get(1000, 10), table.get(crate::analysis::MAX_SEARCH_DEPTH, 10));
+        let table = ReductionTable::new(10.0, DEFAULT_DIVISOR, 8);
+        assert_eq!(table.get(4, 10), 3);
+        let params = tune::SearchParams { lmr_base: 1000, ..Default::default() };
+        assert_eq!(ReductionTable::with_params(&params).get(4, 10), 3);
+    }
+}
@@ -1,5 +1,6 @@
 //! Functions to determine legal moves.
 
+use crate::attacks::{self, Bitboard};
 use crate::board::*;
 use crate::castling::*;
 use crate::movement::{self, Move};
@@ -13,8 +14,10 @@ use crate::notation;
 /// - `color`: current player's turn
 /// - `castling`: which castling options are available; updated throughout the game.
 /// - `en_passant`: position of a pawn that can be taken using en passant attack.
-/// - `halfmove`: eh not sure
-/// - `fullmove`: same
+/// - `halfmove`: number of half moves since the last capture or pawn move,
+///   for the fifty-move rule.
+/// - `fullmove`: number of the current full move, starting at 1 and
+///   incremented after each black move.
 #[derive(Debug, PartialEq, Clone, Hash)]
 pub struct GameState {
     pub color: u8,
@@ -36,6 +39,12 @@ impl GameState {
     }
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for GameState {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -52,19 +61,54 @@ impl std::fmt::Display for GameState {
     }
 }
 
+/// Promotions to generate for pawns reaching the last rank.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromotionPolicy {
+    /// Promote to queen, rook, bishop and knight.
+    All,
+    /// Only promote to queen and knight, the only pieces that can not
+    /// be replaced by a queen, for searches where speed matters more
+    /// than completeness (e.g. quiescence).
+    QueenKnight,
+}
+
 /// Get a list of moves for all pieces of the playing color.
 ///
-/// If `commit` is false, do not check for illegal moves. This is used
-/// to avoid endless recursion when checking if a P move is illegal,
-/// as it needs to check all possible following enemy moves, e.g. to
-/// see if P's king can be taken. Consider a call with true `commit` as
-/// a collection of attacked squares instead of legal move collection.
+/// If `commit` is false, do not check for illegal moves nor generate
+/// castling, e.g. to get squares where pieces can go regardless of
+/// their own king safety. Prefer `generate_legal` and
+/// `generate_pseudo_legal` when the intent matters.
 pub fn get_player_moves(
     board: &Board,
     game_state: &GameState,
     commit: bool,
+) -> Vec<Move> {
+    get_player_moves_with_promotions(board, game_state, commit, PromotionPolicy::All)
+}
+
+/// Same as `get_player_moves` but generate promotions according to `promotions`.
+pub fn get_player_moves_with_promotions(
+    board: &Board,
+    game_state: &GameState,
+    commit: bool,
+    promotions: PromotionPolicy,
 ) -> Vec<Move> {
     let mut moves = Vec::with_capacity(256);
+    get_player_moves_into(board, game_state, commit, promotions, &mut moves);
+    moves
+}
+
+/// Same as `get_player_moves_with_promotions` but write moves in
+/// `moves`, replacing its content, so its buffer can be reused.
+pub fn get_player_moves_into(
+    board: &Board,
+    game_state: &GameState,
+    commit: bool,
+    promotions: PromotionPolicy,
+    moves: &mut Vec<Move>,
+) {
+    moves.clear();
+    let occupancy = attacks::occupancy(board);
     for r in 0..8 {
         for f in 0..8 {
             let p = (f, r);
@@ -72,13 +116,114 @@ pub fn get_player_moves(
                 continue
             }
             if is_color(get_square(board, &p), game_state.color) {
-                moves.append(&mut get_piece_moves(board, &p, game_state, commit));
+                push_piece_moves(board, &p, game_state, promotions, occupancy, moves);
             }
         }
     }
+    if commit {
+        let legality = Legality::new(board, game_state, occupancy);
+        moves.retain(|m| legality.allows(board, game_state, m));
+        if let Some(king) = legality.king {
+            push_castling_moves(board, game_state, &king, legality.king_danger, moves);
+        }
+    }
+}
+
+/// Return legal moves of the playing color.
+///
+/// Checkers, pinned pieces and squares attacked around the king are
+/// found once beforehand, so that moves are legal by construction
+/// instead of being played to see if they leave the king in check.
+pub fn generate_legal(board: &Board, game_state: &GameState) -> Vec<Move> {
+    get_player_moves(board, game_state, true)
+}
+
+/// Return pseudo-legal moves of the playing color: moves following
+/// pieces rules but that may leave the king in check.
+///
+/// Castling is generated if the player has the right and the squares
+/// between the king and the rook are empty, even if the king is in
+/// check or goes through attacked squares.
+pub fn generate_pseudo_legal(board: &Board, game_state: &GameState) -> Vec<Move> {
+    let mut moves = get_player_moves(board, game_state, false);
+    if let Some(king) = find_king(board, game_state.color) {
+        push_castling_moves(board, game_state, &king, 0, &mut moves);
+    }
     moves
 }
 
+/// Capture victim types, from the most valuable, pieces of the same
+/// value together; kings can't be captured.
+const VICTIMS: [u8; 4] = [SQ_Q, SQ_R, SQ_B | SQ_N, SQ_P];
+/// Capturing pieces, from the least valuable.
+const ATTACKERS: [u8; 6] = [SQ_P, SQ_N, SQ_B, SQ_R, SQ_Q, SQ_K];
+
+/// Write legal captures of the playing color in `moves`, replacing its
+/// content, in MVV-LVA order as scored by `move_ordering::mvv_lva`:
+/// most valuable victims first, then least valuable attackers.
+///
+/// Captures are generated victim type by victim type, from the squares
+/// attacking the victims, so they need no sorting. Capturing pawns
+/// promote according to `promotions`.
+pub fn get_captures_into(
+    board: &Board,
+    game_state: &GameState,
+    promotions: PromotionPolicy,
+    moves: &mut Vec<Move>,
+) {
+    moves.clear();
+    let occupancy = attacks::occupancy(board);
+    let opponent = opposite(game_state.color);
+    // Pieces of both sides by type, indexed by flag bit.
+    let (mut own, mut enemy) = ([0; 6], [0; 6]);
+    for (piece, p) in get_piece_iterator(board) {
+        let pieces = if is_color(piece, game_state.color) { &mut own } else { &mut enemy };
+        pieces[get_type(piece).trailing_zeros() as usize] |= 1 << pos_square(&p);
+    }
+    let pieces = |bitboards: &[Bitboard; 6], types: u8| {
+        (0..6).filter(|i| types >> i & 1 == 1).fold(0, |bb, i| bb | bitboards[i])
+    };
+    for victim in VICTIMS {
+        let victims = pieces(&enemy, victim);
+        for attacker in ATTACKERS {
+            let attackers = pieces(&own, attacker);
+            if attackers == 0 {
+                continue
+            }
+            let mut targets = victims;
+            // Pawns also capture en passant a pawn on an empty square.
+            if victim == SQ_P && attacker == SQ_P {
+                let capture_rank = if is_white(game_state.color) { 5 } else { 2 };
+                if let Some(p) = game_state.en_passant.filter(|p| p.1 == capture_rank) {
+                    targets |= 1 << pos_square(&p);
+                }
+            }
+            for target in attacks::squares(targets) {
+                let from = attackers & match attacker {
+                    // Pawns attacking the target are where an enemy pawn
+                    // on the target would attack.
+                    SQ_P => attacks::pawn_attacks(target, opponent),
+                    SQ_N => attacks::knight_attacks(target),
+                    SQ_B => attacks::bishop_attacks(target, occupancy),
+                    SQ_R => attacks::rook_attacks(target, occupancy),
+                    SQ_Q => attacks::queen_attacks(target, occupancy),
+                    _ => attacks::king_attacks(target),
+                };
+                for sq in attacks::squares(from) {
+                    let m = (square_pos(sq), square_pos(target), None);
+                    if attacker == SQ_P {
+                        push_pawn_move(moves, m, promotions);
+                    } else {
+                        moves.push(m);
+                    }
+                }
+            }
+        }
+    }
+    let legality = Legality::new(board, game_state, occupancy);
+    moves.retain(|m| legality.allows(board, game_state, m));
+}
+
 /// Get a list of moves for the piece at position `at`.
 pub fn get_piece_moves(
     board: &Board,
@@ -86,26 +231,66 @@ pub fn get_piece_moves(
     game_state: &GameState,
     commit: bool,
 ) -> Vec<Move> {
+    let occupancy = attacks::occupancy(board);
+    let mut moves = vec!();
+    push_piece_moves(board, at, game_state, PromotionPolicy::All, occupancy, &mut moves);
+    if commit {
+        let legality = Legality::new(board, game_state, occupancy);
+        moves.retain(|m| legality.allows(board, game_state, m));
+        if legality.king == Some(*at) {
+            push_castling_moves(board, game_state, at, legality.king_danger, &mut moves);
+        }
+    }
+    moves
+}
+
+/// Return the legal destinations of the piece at `at`, e.g. to
+/// highlight them in a user interface.
+///
+/// Promotions to different pieces share their destination, which is
+/// returned once. Pieces of the color not playing have no moves.
+pub fn moves_from(board: &Board, game_state: &GameState, at: &Pos) -> Vec<Pos> {
+    if !is_color(get_square(board, at), game_state.color) {
+        return vec!()
+    }
+    let mut destinations: Vec<Pos> = get_piece_moves(board, at, game_state, true).into_iter()
+        .map(|m| m.1)
+        .collect();
+    destinations.dedup();
+    destinations
+}
+
+/// Push moves of the piece at `at` in `moves`, without checking if
+/// they leave the king in check, and without castling.
+fn push_piece_moves(
+    board: &Board,
+    at: &Pos,
+    game_state: &GameState,
+    promotions: PromotionPolicy,
+    occupancy: Bitboard,
+    moves: &mut Vec<Move>,
+) {
+    let sq = pos_square(at);
     match get_square(board, at) {
-        p if is_piece(p, SQ_P) => get_pawn_moves(board, at, p, game_state, commit),
-        p if is_piece(p, SQ_B) => get_bishop_moves(board, at, p, game_state, commit),
-        p if is_piece(p, SQ_N) => get_knight_moves(board, at, p, game_state, commit),
-        p if is_piece(p, SQ_R) => get_rook_moves(board, at, p, game_state, commit),
-        p if is_piece(p, SQ_Q) => get_queen_moves(board, at, p, game_state, commit),
-        p if is_piece(p, SQ_K) => get_king_moves(board, at, p, game_state, commit),
-        _ => vec!(),
+        p if is_piece(p, SQ_P) => push_pawn_moves(board, at, p, game_state, promotions, moves),
+        p if is_piece(p, SQ_B) => push_moves_to(board, at, p, attacks::bishop_attacks(sq, occupancy), moves),
+        p if is_piece(p, SQ_N) => push_moves_to(board, at, p, attacks::knight_attacks(sq), moves),
+        p if is_piece(p, SQ_R) => push_moves_to(board, at, p, attacks::rook_attacks(sq, occupancy), moves),
+        p if is_piece(p, SQ_Q) => push_moves_to(board, at, p, attacks::queen_attacks(sq, occupancy), moves),
+        p if is_piece(p, SQ_K) => push_moves_to(board, at, p, attacks::king_attacks(sq), moves),
+        _ => {}
     }
 }
 
-fn get_pawn_moves(
+fn push_pawn_moves(
     board: &Board,
     at: &Pos,
     piece: u8,
     game_state: &GameState,
-    commit: bool,
-) -> Vec<Move> {
+    promotions: PromotionPolicy,
+    moves: &mut Vec<Move>,
+) {
     let (f, r) = *at;
-    let mut moves = vec!();
     // Direction: positive for white, negative for black.
     let dir: i8 = if is_white(piece) { 1 } else { -1 };
     // Check 1 or 2 square forward.
@@ -113,202 +298,80 @@ fn get_pawn_moves(
     for i in 1..=move_len {
         let forward_r = r + dir * i;
         if dir > 0 && forward_r > POS_MAX {
-            return moves
+            return
         }
         if dir < 0 && forward_r < POS_MIN {
-            return moves
+            return
         }
         let forward: Pos = (f, forward_r);
         // If forward square is empty (and we are not jumping over an occupied square), add it.
         if is_empty(board, &forward) && (i == 1 || is_empty(board, &(f, forward_r - dir))) {
-            // Pawns that get to the opposite rank automatically promote as queens.
-            let prom = if (dir > 0 && forward_r == POS_MAX) || (dir < 0 && forward_r == POS_MIN) {
-                Some(SQ_Q)
-            } else {
-                None
-            };
-            let m = (*at, forward, prom);
-            if can_register(commit, board, game_state, &m) {
-                moves.push(m);
-            }
+            push_pawn_move(moves, (*at, forward, None), promotions);
         }
         // Check diagonals for pieces to attack.
         if i == 1 {
             // First diagonal.
-            if f - 1 >= POS_MIN {
+            if f > POS_MIN {
                 let diag: Pos = (f - 1, forward_r);
-                if let Some(m) = move_on_enemy(piece, at, get_square(board, &diag), &diag) {
-                    if can_register(commit, board, game_state, &m) {
-                        moves.push(m);
-                    }
+                if let Some(m) = move_on_enemy(piece, at, get_square(board, &diag), &diag)
+                    .or_else(|| move_en_passant(piece, at, game_state, &diag))
+                {
+                    push_pawn_move(moves, m, promotions);
                 }
             }
             // Second diagonal.
-            if f + 1 <= POS_MAX {
+            if f < POS_MAX {
                 let diag: Pos = (f + 1, forward_r);
-                if let Some(m) = move_on_enemy(piece, at, get_square(board, &diag), &diag) {
-                    if can_register(commit, board, game_state, &m) {
-                        moves.push(m);
-                    }
+                if let Some(m) = move_on_enemy(piece, at, get_square(board, &diag), &diag)
+                    .or_else(|| move_en_passant(piece, at, game_state, &diag))
+                {
+                    push_pawn_move(moves, m, promotions);
                 }
             }
         }
-        // TODO en passant
     }
-    moves
 }
 
-fn get_bishop_moves(
-    board: &Board,
-    at: &Pos,
-    piece: u8,
-    game_state: &GameState,
-    commit: bool,
-) -> Vec<Move> {
-    let (f, r) = at;
-    let mut views = [true; 4];  // Store diagonals where a piece blocks commit.
-    let mut moves = Vec::with_capacity(8);
-    for dist in 1..=7 {
-        for (dir, offset) in [(1, -1), (1, 1), (-1, 1), (-1, -1)].iter().enumerate() {
-            if !views[dir] {
-                continue
-            }
-            let p = (f + offset.0 * dist, r + offset.1 * dist);
-            // If this position is out of the board, stop looking in that direction.
-            if !is_valid_pos(p) {
-                views[dir] = false;
-                continue
-            }
-            if is_empty(board, &p) {
-                let m = (*at, p, None);
-                if can_register(commit, board, game_state, &m) {
-                    moves.push(m);
-                }
-            } else {
-                if let Some(m) = move_on_enemy(piece, at, get_square(board, &p), &p) {
-                    if can_register(commit, board, game_state, &m) {
-                        moves.push(m);
-                    }
-                }
-                views[dir] = false;  // Stop looking in that direction.
-            }
-        }
+/// Push a pawn move, or its promotions allowed by `promotions` if it reaches the last rank.
+fn push_pawn_move(moves: &mut Vec<Move>, m: Move, promotions: PromotionPolicy) {
+    if m.1.1 != POS_MIN && m.1.1 != POS_MAX {
+        moves.push(m);
+        return
+    }
+    let prom_types: &[u8] = match promotions {
+        PromotionPolicy::All => &[SQ_Q, SQ_N, SQ_R, SQ_B],
+        PromotionPolicy::QueenKnight => &[SQ_Q, SQ_N],
+    };
+    for prom_type in prom_types {
+        moves.push((m.0, m.1, Some(*prom_type)));
     }
-    moves
 }
 
-fn get_knight_moves(
-    board: &Board,
-    at: &Pos,
-    piece: u8,
-    game_state: &GameState,
-    commit: bool,
-) -> Vec<Move> {
-    let (f, r) = at;
-    let mut moves = Vec::with_capacity(8);
-    for offset in [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)].iter() {
-        let p = (f + offset.0, r + offset.1);
-        if !is_valid_pos(p) {
-            continue
-        }
+/// Push moves of a piece other than a pawn to the `attacked` squares
+/// that are empty or have an enemy piece.
+fn push_moves_to(board: &Board, at: &Pos, piece: u8, attacked: Bitboard, moves: &mut Vec<Move>) {
+    for sq in attacks::squares(attacked) {
+        let p = square_pos(sq);
         if is_empty(board, &p) {
-            let m = (*at, p, None);
-            if can_register(commit, board, game_state, &m) {
-                moves.push(m);
-            }
+            moves.push((*at, p, None));
         } else if let Some(m) = move_on_enemy(piece, at, get_square(board, &p), &p) {
-            if can_register(commit, board, game_state, &m) {
-                moves.push(m);
-            }
+            moves.push(m);
         }
     }
-    moves
 }
 
-fn get_rook_moves(
+/// Push castling moves of the king at `at` in `moves`.
+///
+/// The king can not castle from, through or into a square of
+/// `king_danger`; use 0 to only check castling rights and empty
+/// squares.
+fn push_castling_moves(
     board: &Board,
-    at: &Pos,
-    piece: u8,
     game_state: &GameState,
-    commit: bool,
-) -> Vec<Move> {
-    let (f, r) = at;
-    let mut moves = Vec::with_capacity(8);
-    let mut views = [true; 4];  // Store lines where a piece blocks commit.
-    for dist in 1..=7 {
-        for (dir, offset) in [(0, 1), (1, 0), (0, -1), (-1, 0)].iter().enumerate() {
-            if !views[dir] {
-                continue
-            }
-            let p = (f + offset.0 * dist, r + offset.1 * dist);
-            // If this position is out of the board, stop looking in that direction.
-            if !is_valid_pos(p) {
-                views[dir] = false;
-                continue
-            }
-            if is_empty(board, &p) {
-                let m = (*at, p, None);
-                if can_register(commit, board, game_state, &m) {
-                    moves.push(m);
-                }
-            } else {
-                if let Some(m) = move_on_enemy(piece, at, get_square(board, &p), &p) {
-                    if can_register(commit, board, game_state, &m) {
-                        moves.push(m);
-                    }
-                }
-                views[dir] = false;  // Stop looking in that direction.
-            }
-        }
-    }
-    moves
-}
-
-fn get_queen_moves(
-    board: &Board,
     at: &Pos,
-    piece: u8,
-    game_state: &GameState,
-    commit: bool
-) -> Vec<Move> {
-    let mut moves = vec!();
-    // Easy way to get queen moves, but may be a bit quicker if everything was rewritten here.
-    moves.append(&mut get_bishop_moves(board, at, piece, game_state, commit));
-    moves.append(&mut get_rook_moves(board, at, piece, game_state, commit));
-    moves
-}
-
-fn get_king_moves(
-    board: &Board,
-    at: &Pos,
-    piece: u8,
-    game_state: &GameState,
-    commit: bool
-) -> Vec<Move> {
-    let (f, r) = at;
-    let mut moves = vec!();
-    for offset in [(-1, 1), (0, 1), (1, 1), (-1, 0), (1, 0), (-1, -1), (0, -1), (1, -1)].iter() {
-        let p = (f + offset.0, r + offset.1);
-        if !is_valid_pos(p) {
-            continue
-        }
-        if is_empty(board, &p) {
-            let m = (*at, p, None);
-            if can_register(commit, board, game_state, &m) {
-                moves.push(m);
-            }
-        } else if let Some(m) = move_on_enemy(piece, at, get_square(board, &p), &p) {
-            if can_register(commit, board, game_state, &m) {
-                moves.push(m);
-            }
-        }
-    }
-
-    // Stop here for uncommitted moves.
-    if !commit {
-        return moves
-    }
-
+    king_danger: Bitboard,
+    moves: &mut Vec<Move>,
+) {
     // Castling. Here are the rules that should ALL be respected:
     // 1. The king and the chosen rook are on the player's first rank.
     // 2. Neither the king nor the chosen rook has previously moved.
@@ -316,6 +379,7 @@ fn get_king_moves(
     // 4. The king is not currently in check.
     // 5. The king does not pass through a square that is attacked by an enemy piece.
     // 6. The king does not end up in check.
+    let is_safe = |p: &Pos| king_danger & 1 << pos_square(p) == 0;
 
     // First get the required castling rank and color mask for the player.
     let (castling_rank, castling_color_mask) = if is_white(game_state.color) {
@@ -326,69 +390,155 @@ fn get_king_moves(
 
     // Check for castling if the king is on its castling rank (R1)
     // and is not in check (R4).
-    if
-        *r == castling_rank &&
-        !is_attacked(board, game_state, at)
-    {
-        // Check for both castling sides.
-        for (path_files, opt_empty_file, castling_side_mask) in CASTLING_SIDES.iter() {
-            // Check for castling availability for this color and side.
-            if (game_state.castling & castling_color_mask & castling_side_mask) != 0 {
-                // Check that squares in the king's path are empty and not attacked (R3.1, R5, R6).
-                let mut path_is_clear = true;
-                for path_f in path_files {
-                    let p = (*path_f, castling_rank);
-                    if !is_empty(board, &p) || is_illegal(board, game_state, &(*at, p, None)) {
-                        path_is_clear = false;
-                        break;
-                    }
+    if at.1 != castling_rank || !is_safe(at) {
+        return
+    }
+    // Check for both castling sides.
+    for (path_files, opt_empty_file, castling_side_mask) in CASTLING_SIDES.iter() {
+        // Check for castling availability for this color and side.
+        if (game_state.castling & castling_color_mask & castling_side_mask) == 0 {
+            continue
+        }
+        // Check that squares in the king's path are empty and not attacked (R3.1, R5, R6).
+        let path_is_clear = path_files.iter().all(|path_f| {
+            let p = (*path_f, castling_rank);
+            is_empty(board, &p) && is_safe(&p)
+        });
+        if !path_is_clear {
+            continue
+        }
+        // Check that rook jumps over an empty square on queen-side (R3.2).
+        if let Some(rook_path_f) = opt_empty_file {
+            if !is_empty(board, &(*rook_path_f, castling_rank)) {
+                continue
+            }
+        }
+        moves.push(movement::get_castle_move(castling_side_mask & castling_color_mask));
+    }
+}
+
+/// Constraints for moves of the playing color to be legal.
+struct Legality {
+    /// Position of the king, if any; without king all moves are legal.
+    king: Option<Pos>,
+    /// Number of enemy pieces giving check.
+    num_checkers: u32,
+    /// Squares where pieces can stop a single check: the checking piece
+    /// and squares between it and the king. All squares if not in check.
+    check_mask: Bitboard,
+    /// Squares allowed for pieces pinned to the king, by square number:
+    /// the line between the king and the pinning piece. All squares for
+    /// pieces that are not pinned.
+    pin_masks: [Bitboard; 64],
+    /// Squares attacked by the enemy, as if the king was not on the
+    /// board so it can not step back along the line of a check.
+    king_danger: Bitboard,
+}
+
+impl Legality {
+    fn new(board: &Board, game_state: &GameState, occupancy: Bitboard) -> Legality {
+        let mut legality = Legality {
+            king: find_king(board, game_state.color),
+            num_checkers: 0,
+            check_mask: !0,
+            pin_masks: [!0; 64],
+            king_danger: 0,
+        };
+        let king = match legality.king {
+            Some(king) => king,
+            None => return legality,
+        };
+        let color = game_state.color;
+        let enemy = opposite(color);
+        let king_sq = pos_square(&king);
+
+        // Walk lines from the king: the first enemy slider met gives
+        // check, or pins the piece in between if there is only one.
+        let lines = attacks::ROOK_DIRS.iter().map(|dir| (dir, SQ_R))
+            .chain(attacks::BISHOP_DIRS.iter().map(|dir| (dir, SQ_B)));
+        for (dir, slider_type) in lines {
+            let mut line: Bitboard = 0;
+            let mut pinned = None;
+            for p in ray(&king, *dir) {
+                line |= 1 << pos_square(&p);
+                let square = get_square(board, &p);
+                if square == SQ_E {
+                    continue
                 }
-                if !path_is_clear {
-                    continue;
+                if is_color(square, color) {
+                    if pinned.is_some() {
+                        break
+                    }
+                    pinned = Some(p);
+                    continue
                 }
-                // Check that rook jumps over an empty square on queen-side (R3.2).
-                if let Some(rook_path_f) = opt_empty_file {
-                    let p = (*rook_path_f, castling_rank);
-                    if !is_empty(board, &p) {
-                        continue;
+                if is_type(square, slider_type) || is_type(square, SQ_Q) {
+                    match pinned {
+                        Some(pinned) => legality.pin_masks[pos_square(&pinned)] = line,
+                        None => legality.add_checker(line),
                     }
                 }
-                let castle = castling_side_mask & castling_color_mask;
-                let m = movement::get_castle_move(castle);
-                if can_register(commit, board, game_state, &m) {
-                    moves.push(m);
+                break
+            }
+        }
+        // Knights and pawns can only give check from their square.
+        let jumpers = [(attacks::knight_attacks(king_sq), SQ_N), (attacks::pawn_attacks(king_sq, color), SQ_P)];
+        for (squares, piece_type) in jumpers.iter() {
+            for sq in attacks::squares(*squares) {
+                if is_piece(get_square(board, &square_pos(sq)), enemy | piece_type) {
+                    legality.add_checker(1 << sq);
                 }
             }
         }
+
+        let maps = attacks::AttackMaps::from_occupancy(board, occupancy & !(1 << king_sq));
+        legality.king_danger = maps.by_color(enemy);
+        legality
     }
-    moves
-}
 
-/// Return true if `commit` is false, or the move is not illegal,
-///
-/// Committing a move means that it can be safely played afterwards.
-/// Sometimes it is not what is needed to accept a move in a collection
-/// of moves, e.g. when simply checking if some moves would make a
-/// previous move illegal.
-#[inline]
-fn can_register(commit: bool, board: &Board, game_state: &GameState, m: &Move) -> bool {
-    !commit || !is_illegal(board, game_state, m)
+    fn add_checker(&mut self, mask: Bitboard) {
+        self.num_checkers += 1;
+        self.check_mask &= mask;
+    }
+
+    /// Return true if `m`, a move that is not castling, is legal.
+    fn allows(&self, board: &Board, game_state: &GameState, m: &Move) -> bool {
+        let king = match self.king {
+            Some(king) => king,
+            None => return true,
+        };
+        let to: Bitboard = 1 << pos_square(&m.1);
+        if m.0 == king {
+            return self.king_danger & to == 0
+        }
+        if self.num_checkers > 1 {
+            return false
+        }
+        // En passant captures a pawn out of the destination square, it
+        // may stop a check or uncover one along the rank: play it.
+        if is_type(get_square(board, &m.0), SQ_P) && m.0.0 != m.1.0 && is_empty(board, &m.1) {
+            return !is_illegal(board, game_state, m)
+        }
+        self.check_mask & self.pin_masks[pos_square(&m.0)] & to != 0
+    }
 }
 
 /// Return a move from pos1 to pos2 if piece1 & piece2 are enemies.
 fn move_on_enemy(piece1: u8, pos1: &Pos, piece2: u8, pos2: &Pos) -> Option<Move> {
     let color1 = get_color(piece1);
     if is_color(piece2, opposite(color1)) {
-        // Automatic queen promotion for pawns moving to the opposite rank.
-        let prom = if
-            is_piece(piece1, SQ_P) &&
-            ((is_white(piece1) && pos2.1 == POS_MAX) || (is_black(piece1) && pos2.1 == POS_MIN))
-        {
-            Some(SQ_Q)
-        } else {
-            None
-        };
-        Some((*pos1, *pos2, prom))
+        Some((*pos1, *pos2, None))
+    } else {
+        None
+    }
+}
+
+/// Return an en passant capture from pos1 to pos2 if pos2 is the en
+/// passant square and the pawn `piece` is on the right rank to take.
+fn move_en_passant(piece: u8, pos1: &Pos, game_state: &GameState, pos2: &Pos) -> Option<Move> {
+    let capture_rank = if is_white(piece) { 5 } else { 2 };
+    if game_state.en_passant == Some(*pos2) && pos2.1 == capture_rank {
+        Some((*pos1, *pos2, None))
     } else {
         None
     }
@@ -400,42 +550,130 @@ fn is_illegal(board: &Board, game_state: &GameState, m: &Move) -> bool {
         // Rule 1: a move is illegal if the king ends up in check.
         // If king moves, use its new position.
         let king_p = if m.0 == king_p { m.1 } else { king_p };
-        let mut hypothetic_board = board.clone();
+        let mut hypothetic_board = *board;
         movement::apply_move_to_board(&mut hypothetic_board, m);
         // Check if the move makes the player king in check.
-        if is_attacked(&hypothetic_board, &game_state, &king_p) {
+        if is_attacked(&hypothetic_board, game_state, &king_p) {
             return true
         }
     }
     false
 }
 
+/// Return true if the king of the playing color is in check.
+pub fn is_in_check(board: &Board, game_state: &GameState) -> bool {
+    match find_king(board, game_state.color) {
+        Some(king_p) => is_attacked(board, game_state, &king_p),
+        None => false,
+    }
+}
+
+/// Rule ending a game in a draw, other than stalemate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawRule {
+    /// No capture nor pawn move in the last fifty moves.
+    FiftyMove,
+    /// No side has enough material left to mate.
+    InsufficientMaterial,
+}
+
+/// Status of a game for the player to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ongoing,
+    /// The player to move is mated; holds the color of the winner.
+    Checkmate(u8),
+    Stalemate,
+    DrawByRule(DrawRule),
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Status::Ongoing => write!(f, "ongoing"),
+            Status::Checkmate(_) => write!(f, "checkmate"),
+            Status::Stalemate => write!(f, "stalemate"),
+            Status::DrawByRule(DrawRule::FiftyMove) => write!(f, "draw fifty-move"),
+            Status::DrawByRule(DrawRule::InsufficientMaterial) => write!(f, "draw insufficient-material"),
+        }
+    }
+}
+
+/// Return the status of the game for the player to move.
+///
+/// A mate on the last move before the fifty-move rule still counts.
+/// Repetitions depend on the game history and are left to callers.
+pub fn game_status(board: &Board, game_state: &GameState) -> Status {
+    if generate_legal(board, game_state).is_empty() {
+        no_move_status(board, game_state)
+    } else if game_state.halfmove >= 100 {
+        Status::DrawByRule(DrawRule::FiftyMove)
+    } else if is_insufficient_material(board) {
+        Status::DrawByRule(DrawRule::InsufficientMaterial)
+    } else {
+        Status::Ongoing
+    }
+}
+
+/// Return the status of a position where the player to move has no
+/// legal move, for callers that already generated them.
+pub fn no_move_status(board: &Board, game_state: &GameState) -> Status {
+    if is_in_check(board, game_state) {
+        Status::Checkmate(opposite(game_state.color))
+    } else {
+        Status::Stalemate
+    }
+}
+
+/// Return true if no side has enough material left to mate.
+///
+/// Only the simple cases of a lone king against a king with at most
+/// one minor piece are considered.
+fn is_insufficient_material(board: &Board) -> bool {
+    let mut num_minors = 0;
+    for (piece, _) in get_piece_iterator(board) {
+        match get_type(piece) {
+            SQ_K => {}
+            SQ_B | SQ_N => num_minors += 1,
+            _ => return false,
+        }
+    }
+    num_minors <= 1
+}
+
 /// Return true if the piece at position `at` is attacked.
 ///
-/// Check all possible enemy moves and return true when one of them
-/// ends up attacking the position.
+/// Look from `at` as each piece type: if it reaches an enemy piece of
+/// the same type, this piece attacks the position.
 ///
 /// Beware that the game state must be coherent with the analysed
 /// square, i.e. if the piece at `at` is white, the game state should
 /// tell that it is white turn. If the square at `at` is empty, simply
 /// check if it is getting attacked by the opposite player.
 fn is_attacked(board: &Board, game_state: &GameState, at: &Pos) -> bool {
-    let mut enemy_game_state = game_state.clone();
-    enemy_game_state.color = opposite(game_state.color);
-    // Do not attempt to commit moves, just check for attacked squares.
-    let enemy_moves = get_player_moves(board, &enemy_game_state, false);
-    for m in enemy_moves.iter() {
-        if *at == m.1 {
-            return true
-        }
-    }
-    false
+    let enemy = opposite(game_state.color);
+    let is_attacked_by = |squares: Bitboard, piece_types: &[u8]| {
+        attacks::squares(squares).any(|s| {
+            let square = get_square(board, &square_pos(s));
+            is_color(square, enemy) && piece_types.contains(&get_type(square))
+        })
+    };
+    let occupancy = attacks::occupancy(board);
+    let sq = pos_square(at);
+    is_attacked_by(attacks::bishop_attacks(sq, occupancy), &[SQ_B, SQ_Q])
+    || is_attacked_by(attacks::rook_attacks(sq, occupancy), &[SQ_R, SQ_Q])
+    || is_attacked_by(attacks::knight_attacks(sq), &[SQ_N])
+    || is_attacked_by(attacks::king_attacks(sq), &[SQ_K])
+    // Enemy pawns attack `at` from where a pawn on `at` would attack.
+    || is_attacked_by(attacks::pawn_attacks(sq, game_state.color), &[SQ_P])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::move_ordering;
     use crate::notation::parse_move;
+    use crate::random;
 
     #[test]
     fn test_get_player_moves() {
@@ -455,20 +693,20 @@ mod tests {
         // Check that a pawn (here white queen's pawn) can move forward if the road is free.
         set_square(&mut b, &pos("d3"), SQ_WH_P);
         let moves = get_piece_moves(&b, &pos("d3"), &gs, true);
-        assert!(moves.len() == 1 && moves.contains( &parse_move("d3d4") ));
+        assert!(moves.len() == 1 && moves.contains( &parse_move("d3d4").unwrap() ));
 
         // Check that a pawn (here white king's pawn) can move 2 square forward on first move.
         set_square(&mut b, &pos("e2"), SQ_WH_P);
         let moves = get_piece_moves(&b, &pos("e2"), &gs, true);
         assert_eq!(moves.len(), 2);
-        assert!(moves.contains( &parse_move("e2e3") ));
-        assert!(moves.contains( &parse_move("e2e4") ));
+        assert!(moves.contains( &parse_move("e2e3").unwrap() ));
+        assert!(moves.contains( &parse_move("e2e4").unwrap() ));
 
         // Check that a pawn cannot move forward if a piece is blocking its path.
         // 1. black pawn 2 square forward; only 1 square forward available from start pos.
         set_square(&mut b, &pos("e4"), SQ_BL_P);
         let moves = get_piece_moves(&b, &pos("e2"), &gs, true);
-        assert!(moves.len() == 1 && moves.contains( &parse_move("e2e3") ));
+        assert!(moves.len() == 1 && moves.contains( &parse_move("e2e3").unwrap() ));
         // 2. black pawn 1 square forward; no square available.
         set_square(&mut b, &pos("e3"), SQ_BL_P);
         let moves = get_piece_moves(&b, &pos("e2"), &gs, true);
@@ -481,18 +719,66 @@ mod tests {
         // Check that a pawn can take a piece diagonally.
         set_square(&mut b, &pos("f3"), SQ_BL_P);
         let moves = get_piece_moves(&b, &pos("e2"), &gs, true);
-        assert!(moves.len() == 1 && moves.contains( &parse_move("e2f3") ));
+        assert!(moves.len() == 1 && moves.contains( &parse_move("e2f3").unwrap() ));
         set_square(&mut b, &pos("d3"), SQ_BL_P);
         let moves = get_piece_moves(&b, &pos("e2"), &gs, true);
         assert_eq!(moves.len(), 2);
-        assert!(moves.contains( &parse_move("e2f3") ));
-        assert!(moves.contains( &parse_move("e2d3") ));
+        assert!(moves.contains( &parse_move("e2f3").unwrap() ));
+        assert!(moves.contains( &parse_move("e2d3").unwrap() ));
 
-        // Check that a pawn moving to the last rank leads to queen promotion.
+        // Check that a pawn moving to the last rank leads to promotions to any piece.
         // 1. by simply moving forward.
         set_square(&mut b, &pos("a7"), SQ_WH_P);
         let moves = get_piece_moves(&b, &pos("a7"), &gs, true);
-        assert!(moves.len() == 1 && moves.contains( &parse_move("a7a8q") ));
+        assert_eq!(moves.len(), 4);
+        assert!(moves.contains( &parse_move("a7a8q").unwrap() ));
+        assert!(moves.contains( &parse_move("a7a8n").unwrap() ));
+        assert!(moves.contains( &parse_move("a7a8r").unwrap() ));
+        assert!(moves.contains( &parse_move("a7a8b").unwrap() ));
+        // 2. by taking a piece.
+        set_square(&mut b, &pos("b8"), SQ_BL_N);
+        let moves = get_piece_moves(&b, &pos("a7"), &gs, true);
+        assert_eq!(moves.len(), 8);
+        assert!(moves.contains( &parse_move("a7b8n").unwrap() ));
+        // Only queen and knight promotions in fast mode.
+        let moves = get_player_moves_with_promotions(&b, &gs, true, PromotionPolicy::QueenKnight);
+        assert_eq!(moves.iter().filter(|m| m.2.is_some()).count(), 4);
+        assert!(moves.contains( &parse_move("a7b8q").unwrap() ));
+        assert!(!moves.contains( &parse_move("a7b8r").unwrap() ));
+        // 3. by taking a piece in a corner, for both colors.
+        let mut b = new_empty();
+        set_square(&mut b, &pos("b7"), SQ_WH_P);
+        set_square(&mut b, &pos("a8"), SQ_BL_R);
+        set_square(&mut b, &pos("b8"), SQ_BL_N);
+        let moves = get_piece_moves(&b, &pos("b7"), &gs, true);
+        assert_eq!(moves.len(), 4);
+        assert!(moves.contains( &parse_move("b7a8n").unwrap() ));
+        set_square(&mut b, &pos("g2"), SQ_BL_P);
+        set_square(&mut b, &pos("h1"), SQ_WH_B);
+        let mut black_gs = GameState::new();
+        black_gs.color = SQ_BL;
+        let moves = get_piece_moves(&b, &pos("g2"), &black_gs, true);
+        assert_eq!(moves.len(), 8);
+        assert!(moves.contains( &parse_move("g2h1q").unwrap() ));
+        assert!(moves.contains( &parse_move("g2g1n").unwrap() ));
+
+        // En passant, only on the en passant square.
+        let mut b = new_empty();
+        set_square(&mut b, &pos("e5"), SQ_WH_P);
+        set_square(&mut b, &pos("d5"), SQ_BL_P);
+        set_square(&mut b, &pos("f5"), SQ_BL_P);
+        let mut gs = GameState::new();
+        gs.en_passant = Some(pos("d6"));
+        let moves = get_piece_moves(&b, &pos("e5"), &gs, true);
+        assert_eq!(moves.len(), 2);
+        assert!(moves.contains( &parse_move("e5d6").unwrap() ));
+        assert!(!moves.contains( &parse_move("e5f6").unwrap() ));
+        // Not if it leaves the king in check, with both pawns leaving the rank.
+        clear_square(&mut b, &pos("f5"));
+        set_square(&mut b, &pos("a5"), SQ_WH_K);
+        set_square(&mut b, &pos("h5"), SQ_BL_R);
+        let moves = get_piece_moves(&b, &pos("e5"), &gs, true);
+        assert!(!moves.contains( &parse_move("e5d6").unwrap() ));
     }
 
     #[test]
@@ -505,22 +791,22 @@ mod tests {
         let moves = get_piece_moves(&b, &pos("d4"), &gs, true);
         assert_eq!(moves.len(), 13);
         // Going top-right.
-        assert!(moves.contains( &parse_move("d4e5") ));
-        assert!(moves.contains( &parse_move("d4f6") ));
-        assert!(moves.contains( &parse_move("d4g7") ));
-        assert!(moves.contains( &parse_move("d4h8") ));
+        assert!(moves.contains( &parse_move("d4e5").unwrap() ));
+        assert!(moves.contains( &parse_move("d4f6").unwrap() ));
+        assert!(moves.contains( &parse_move("d4g7").unwrap() ));
+        assert!(moves.contains( &parse_move("d4h8").unwrap() ));
         // Going bottom-right.
-        assert!(moves.contains( &parse_move("d4e3") ));
-        assert!(moves.contains( &parse_move("d4f2") ));
-        assert!(moves.contains( &parse_move("d4g1") ));
+        assert!(moves.contains( &parse_move("d4e3").unwrap() ));
+        assert!(moves.contains( &parse_move("d4f2").unwrap() ));
+        assert!(moves.contains( &parse_move("d4g1").unwrap() ));
         // Going bottom-left.
-        assert!(moves.contains( &parse_move("d4c3") ));
-        assert!(moves.contains( &parse_move("d4b2") ));
-        assert!(moves.contains( &parse_move("d4a1") ));
+        assert!(moves.contains( &parse_move("d4c3").unwrap() ));
+        assert!(moves.contains( &parse_move("d4b2").unwrap() ));
+        assert!(moves.contains( &parse_move("d4a1").unwrap() ));
         // Going top-left.
-        assert!(moves.contains( &parse_move("d4c5") ));
-        assert!(moves.contains( &parse_move("d4b6") ));
-        assert!(moves.contains( &parse_move("d4a7") ));
+        assert!(moves.contains( &parse_move("d4c5").unwrap() ));
+        assert!(moves.contains( &parse_move("d4b6").unwrap() ));
+        assert!(moves.contains( &parse_move("d4a7").unwrap() ));
 
         // When blocking commit to one square with friendly piece, lose 2 moves.
         set_square(&mut b, &pos("b2"), SQ_WH_P);
@@ -619,6 +905,76 @@ mod tests {
         assert_eq!(all_wh_moves.len(), 2);
     }
 
+    #[test]
+    fn test_generate_legal() {
+        // The e2 knight is pinned, and the king is in check by the h4
+        // bishop: only king moves and the g3 block are legal. Pseudo-legal
+        // moves include knight moves and castling through f1.
+        let (b, gs) = notation::position("4k3/8/8/8/4r2b/8/4N3/4K2R w K - 0 1");
+        let legal = generate_legal(&b, &gs);
+        for m in ["e1d1", "e1d2", "e1f1", "h1h4"].iter() {
+            assert!(legal.contains(&parse_move(m).unwrap()), "{} should be legal", m);
+        }
+        assert!(!legal.iter().any(|m| m.0 == pos("e2")));
+        let pseudo_legal = generate_pseudo_legal(&b, &gs);
+        assert!(pseudo_legal.contains(&parse_move("e2g3").unwrap()));
+        assert!(pseudo_legal.contains(&parse_move("e1g1").unwrap()));
+        assert!(legal.iter().all(|m| pseudo_legal.contains(m)));
+
+        // Two checkers: only the king can move.
+        let (b, gs) = notation::position("4k3/8/8/8/4r3/3n4/8/R3K3 w Q - 0 1");
+        assert!(generate_legal(&b, &gs).iter().all(|m| m.0 == pos("e1")));
+
+        // An en passant capture that would leave the king in check on
+        // its rank is illegal.
+        let (b, gs) = notation::position("8/8/8/KPp4r/8/8/8/4k3 w - c6 0 2");
+        assert!(!generate_legal(&b, &gs).contains(&parse_move("b5c6").unwrap()));
+        assert!(generate_pseudo_legal(&b, &gs).contains(&parse_move("b5c6").unwrap()));
+    }
+
+    #[test]
+    fn test_get_captures() {
+        // Captures are the capturing legal moves, already in MVV-LVA
+        // order, along random games with en passant and promotions.
+        let mut rng = random::Rng::new(7);
+        let mut captures = vec!();
+        for _ in 0..20 {
+            let mut node = random::random_node_from_moves(&mut rng, 0);
+            for _ in 0..150 {
+                let moves = get_player_moves_with_promotions(&node.board, &node.game_state, true, PromotionPolicy::All);
+                if moves.is_empty() {
+                    break
+                }
+                get_captures_into(&node.board, &node.game_state, PromotionPolicy::All, &mut captures);
+                let keys: Vec<u32> = captures.iter().map(|m| move_ordering::mvv_lva(&node.board, m)).collect();
+                assert!(keys.windows(2).all(|w| w[0] >= w[1]));
+                let mut expected: Vec<Move> = moves.iter().copied()
+                    .filter(|m| move_ordering::is_capture(&node.board, m)).collect();
+                let mut sorted = captures.clone();
+                expected.sort();
+                sorted.sort();
+                assert_eq!(sorted, expected);
+                let m = moves[rng.below(moves.len())];
+                node.apply_move(&m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_moves_from() {
+        // The pinned knight can't move, the king can castle, the pawn
+        // destinations are listed once despite promotions.
+        let (b, gs) = notation::position("1k4n1/5P2/8/8/4r3/8/4N3/4K2R w K - 0 1");
+        assert!(moves_from(&b, &gs, &pos("e2")).is_empty());
+        let king_destinations = moves_from(&b, &gs, &pos("e1"));
+        assert_eq!(king_destinations.len(), 5);
+        assert!(king_destinations.contains(&pos("g1")));
+        assert_eq!(moves_from(&b, &gs, &pos("f7")), vec!(pos("f8"), pos("g8")));
+        // Empty squares and enemy pieces have no moves.
+        assert!(moves_from(&b, &gs, &pos("a1")).is_empty());
+        assert!(moves_from(&b, &gs, &pos("b8")).is_empty());
+    }
+
     #[test]
     fn test_is_attacked() {
         let mut b = new_empty();
@@ -629,7 +985,35 @@ mod tests {
         set_square(&mut b, &pos("d6"), SQ_BL_R);
         assert!(is_attacked(&b, &gs, &pos("d4")));
         // Move the rook on another file, no more attack.
-        movement::apply_move_to_board(&mut b, &parse_move("d6e6"));
+        movement::apply_move_to_board(&mut b, &parse_move("d6e6").unwrap());
         assert!(!is_attacked(&b, &gs, &pos("d4")));
     }
+
+    #[test]
+    fn test_is_in_check() {
+        let mut b = new_empty();
+        let gs = GameState::new();
+
+        set_square(&mut b, &pos("e1"), SQ_WH_K);
+        assert!(!is_in_check(&b, &gs));
+        set_square(&mut b, &pos("e8"), SQ_BL_R);
+        assert!(is_in_check(&b, &gs));
+        set_square(&mut b, &pos("e2"), SQ_WH_P);
+        assert!(!is_in_check(&b, &gs));
+    }
+
+    #[test]
+    fn test_game_status() {
+        let status = |fen: &str| {
+            let (board, game_state) = notation::position(fen);
+            game_status(&board, &game_state)
+        };
+        assert_eq!(status(notation::FEN_START), Status::Ongoing);
+        assert_eq!(status("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"), Status::Checkmate(SQ_BL));
+        assert_eq!(status("R5k1/5ppp/8/8/8/8/8/6K1 b - - 100 80"), Status::Checkmate(SQ_WH));
+        assert_eq!(status("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Status::Stalemate);
+        assert_eq!(status("8/8/4k3/8/8/4K3/8/4R3 w - - 100 80"), Status::DrawByRule(DrawRule::FiftyMove));
+        assert_eq!(status("8/8/4k3/8/8/4KB2/8/8 w - - 0 1"), Status::DrawByRule(DrawRule::InsufficientMaterial));
+        assert_eq!(status("8/8/4k3/8/8/4KB2/8/8 w - - 0 1").to_string(), "draw insufficient-material");
+    }
 }
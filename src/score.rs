@@ -0,0 +1,127 @@
+//! Score normalization.
+//!
+//! The evaluation produces raw scores whose scale depends on its terms
+//! and weights. Scores reported to users are normalized to centipawns
+//! such that a 100cp advantage always means the same expected score
+//! (about 59%), keeping analyses comparable across evaluation changes.
+//!
+//! Evaluation and search scores are relative to the player to move, as
+//! negamax needs, and so are UCI scores, given from the engine's point
+//! of view when it is to move. Only some evaluation terms, like the
+//! piece-square score, are computed from white's point of view. Outside
+//! of the search, centipawn scores are typed `RelativeScore` or
+//! `WhiteScore` so that converting between them takes the color.
+
+use std::fmt;
+use std::ops::Neg;
+
+use crate::board;
+
+/// Raw score reported as a 100cp advantage.
+///
+/// Recalibrate it when evaluation terms or weights change, so that
+/// 100cp keeps its meaning.
+pub const RAW_SCORE_100CP: f32 = 1.0;
+
+/// Expected score for an advantage of 100cp.
+pub const EXPECTED_SCORE_100CP: f32 = 0.59;
+
+/// Normalize a raw evaluation score to centipawns.
+pub fn raw_to_cp(raw: f32) -> i32 {
+    (raw * 100.0 / RAW_SCORE_100CP).round() as i32
+}
+
+/// Convert centipawns to a raw evaluation score.
+pub fn cp_to_raw(cp: i32) -> f32 {
+    cp as f32 * RAW_SCORE_100CP / 100.0
+}
+
+/// Return the expected score (between 0 and 1) for a centipawn advantage.
+///
+/// It uses a logistic curve scaled so that 100cp gives
+/// `EXPECTED_SCORE_100CP`.
+pub fn expected_score(cp: i32) -> f32 {
+    let scale = -100.0 / (1.0 / EXPECTED_SCORE_100CP - 1.0).log10();
+    1.0 / (1.0 + 10f32.powf(-cp as f32 / scale))
+}
+
+/// Score in centipawns for the player to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct RelativeScore(pub i32);
+
+/// Score in centipawns for white, whoever is to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct WhiteScore(pub i32);
+
+impl RelativeScore {
+    /// Normalize a raw search or evaluation score.
+    pub fn from_raw(raw: f32) -> RelativeScore {
+        RelativeScore(raw_to_cp(raw))
+    }
+
+    /// Return this score for white, `color` being the player to move.
+    pub fn to_white(self, color: u8) -> WhiteScore {
+        WhiteScore(if board::is_white(color) { self.0 } else { -self.0 })
+    }
+}
+
+impl WhiteScore {
+    /// Return this score for `color`, the player to move.
+    pub fn relative_to(self, color: u8) -> RelativeScore {
+        RelativeScore(if board::is_white(color) { self.0 } else { -self.0 })
+    }
+}
+
+/// Score for the opponent of the player to move.
+impl Neg for RelativeScore {
+    type Output = RelativeScore;
+
+    fn neg(self) -> RelativeScore {
+        RelativeScore(-self.0)
+    }
+}
+
+impl fmt::Display for RelativeScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for WhiteScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_to_cp() {
+        assert_eq!(raw_to_cp(0.0), 0);
+        assert_eq!(raw_to_cp(RAW_SCORE_100CP), 100);
+        assert_eq!(raw_to_cp(-RAW_SCORE_100CP * 2.5), -250);
+        assert_eq!(raw_to_cp(f32::INFINITY), i32::MAX);
+        assert_eq!(raw_to_cp(cp_to_raw(-37)), -37);
+    }
+
+    #[test]
+    fn test_expected_score() {
+        assert!((expected_score(0) - 0.5).abs() < 0.001);
+        assert!((expected_score(100) - EXPECTED_SCORE_100CP).abs() < 0.001);
+        assert!((expected_score(-100) - (1.0 - EXPECTED_SCORE_100CP)).abs() < 0.001);
+        assert!(expected_score(1000) > 0.95);
+    }
+
+    #[test]
+    fn test_perspectives() {
+        let score = RelativeScore(35);
+        assert_eq!(score.to_white(board::SQ_WH), WhiteScore(35));
+        assert_eq!(score.to_white(board::SQ_BL), WhiteScore(-35));
+        assert_eq!(score.to_white(board::SQ_BL).relative_to(board::SQ_BL), score);
+        assert_eq!(WhiteScore(-20).relative_to(board::SQ_BL), RelativeScore(20));
+        assert_eq!(-score, RelativeScore(-35));
+        assert_eq!(RelativeScore::from_raw(RAW_SCORE_100CP).to_string(), "100");
+    }
+}
@@ -0,0 +1,217 @@
+//! Self-test suites runnable from the command line.
+
+use std::fs;
+
+use crate::analysis::{self, EvalWeights};
+use crate::board::*;
+use crate::movement::Move;
+use crate::node::Node;
+use crate::notation;
+use crate::oracle;
+use crate::random;
+use crate::rules;
+use crate::score::RelativeScore;
+
+/// Rules suite bundled with the binary.
+const RULES_SUITE: &str = include_str!("../res/tests/rules.txt");
+
+/// Parse an expected status of the rules suite, for `color` to move.
+fn parse_status(s: &str, color: u8) -> Option<rules::Status> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    match fields.as_slice() {
+        ["ongoing"] => Some(rules::Status::Ongoing),
+        ["checkmate"] => Some(rules::Status::Checkmate(opposite(color))),
+        ["stalemate"] => Some(rules::Status::Stalemate),
+        ["draw", "fifty-move"] => Some(rules::Status::DrawByRule(rules::DrawRule::FiftyMove)),
+        ["draw", "insufficient-material"] => Some(rules::Status::DrawByRule(rules::DrawRule::InsufficientMaterial)),
+        _ => None,
+    }
+}
+
+/// Results of a test suite run.
+#[derive(Debug, PartialEq)]
+pub struct SuiteResults {
+    pub num_tests: usize,
+    pub num_failures: usize,
+}
+
+/// Run the rules suite in file `path`, or the bundled one if None.
+///
+/// Failures are printed to stdout unless `quiet` is set. Lines that
+/// could not be parsed count as failures. Return an error if the suite
+/// can't be read.
+pub fn run_rules_suite(path: Option<&str>, quiet: bool) -> Result<SuiteResults, String> {
+    let suite = match path {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => return Err(format!("Could not read suite {}: {}", path, e)),
+        },
+        None => RULES_SUITE.to_string(),
+    };
+    let mut num_tests = 0;
+    let mut num_failures = 0;
+    for (i, line) in suite.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        num_tests += 1;
+        match check_rules_line(line) {
+            Ok(()) => {}
+            Err(e) => {
+                num_failures += 1;
+                if !quiet {
+                    println!("FAIL line {}: {}", i + 1, e);
+                }
+            }
+        }
+    }
+    Ok(SuiteResults { num_tests, num_failures })
+}
+
+/// Compare legal moves of the move generator with the reference one
+/// on `num_positions` random positions drawn from `seed`.
+///
+/// Positions come both from random games and random placements, as
+/// they stress different rules. Mismatches are printed to stdout unless
+/// `quiet` is set.
+pub fn run_movegen_suite(num_positions: usize, seed: u64, quiet: bool) -> SuiteResults {
+    let mut rng = random::Rng::new(seed);
+    let mut num_failures = 0;
+    for i in 0..num_positions {
+        let node = if i % 2 == 0 {
+            let num_moves = rng.below(80) as u32;
+            random::random_node_from_moves(&mut rng, num_moves)
+        } else {
+            let num_pieces = rng.below(24) as u8;
+            random::random_node_from_placement(&mut rng, num_pieces)
+        };
+        let mut moves = rules::get_player_moves(&node.board, &node.game_state, true);
+        let mut expected = oracle::get_legal_moves(&node.board, &node.game_state);
+        moves.sort_unstable();
+        expected.sort_unstable();
+        if moves != expected {
+            num_failures += 1;
+            if !quiet {
+                let missing: Vec<Move> = expected.iter().filter(|m| !moves.contains(m)).copied().collect();
+                let extra: Vec<Move> = moves.iter().filter(|m| !expected.contains(m)).copied().collect();
+                println!(
+                    "FAIL {}: missing [{}], extra [{}]",
+                    notation::to_fen(&node.board, &node.game_state),
+                    notation::move_list_to_string(&missing),
+                    notation::move_list_to_string(&extra)
+                );
+            }
+        }
+    }
+    SuiteResults { num_tests: num_positions, num_failures }
+}
+
+/// Compare static evaluations with `weights_a` and `weights_b` on the
+/// positions of file `path`.
+///
+/// The file has a FEN per line, optionally followed by ";" and anything
+/// else, so the rules suite can be used. Positions where evaluations
+/// differ by more than `threshold` centipawns count as failures and are
+/// printed to stdout, with scores for white, unless `quiet` is set. Return an error if the file
+/// can't be read or has an invalid FEN.
+pub fn run_eval_comparison(
+    path: &str,
+    weights_a: &EvalWeights,
+    weights_b: &EvalWeights,
+    threshold: i32,
+    quiet: bool,
+) -> Result<SuiteResults, String> {
+    let positions = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read positions {}: {}", path, e))?;
+    let mut num_tests = 0;
+    let mut num_failures = 0;
+    for (i, line) in positions.lines().enumerate() {
+        let fen_str = line.split(';').next().unwrap_or("").trim();
+        if fen_str.is_empty() || fen_str.starts_with('#') {
+            continue
+        }
+        let (board, game_state) = notation::parse_fen(fen_str)
+            .and_then(|fen| notation::fen_to_position(&fen))
+            .ok_or_else(|| format!("Invalid FEN line {}: \"{}\"", i + 1, fen_str))?;
+        let node = Node { board, game_state };
+        num_tests += 1;
+        // Print scores for white, to compare them across positions.
+        let color = node.game_state.color;
+        let cp_a = RelativeScore::from_raw(analysis::evaluate_node(&node, weights_a)).to_white(color);
+        let cp_b = RelativeScore::from_raw(analysis::evaluate_node(&node, weights_b)).to_white(color);
+        if (cp_a.0 - cp_b.0).abs() > threshold {
+            num_failures += 1;
+            if !quiet {
+                println!("DIFF {}: {} cp vs {} cp", fen_str, cp_a, cp_b);
+            }
+        }
+    }
+    Ok(SuiteResults { num_tests, num_failures })
+}
+
+/// Check a line of the rules suite, returning an error description on failure.
+fn check_rules_line(line: &str) -> Result<(), String> {
+    let mut parts = line.splitn(2, ';');
+    let fen_str = parts.next().unwrap_or("").trim();
+    let fen = notation::parse_fen(fen_str).ok_or_else(|| format!("bad FEN \"{}\"", fen_str))?;
+    let (board, game_state) = notation::fen_to_position(&fen)
+        .ok_or_else(|| format!("invalid FEN \"{}\"", fen_str))?;
+    let expected_str = parts.next().unwrap_or("").trim();
+    let expected = parse_status(expected_str, game_state.color)
+        .ok_or_else(|| format!("bad status \"{}\"", expected_str))?;
+    let status = rules::game_status(&board, &game_state);
+    if status == expected {
+        Ok(())
+    } else {
+        Err(format!("{}: expected {}, got {}", fen_str, expected, status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_suite() {
+        assert_eq!(
+            run_rules_suite(None, true),
+            Ok(SuiteResults { num_tests: 15, num_failures: 0 })
+        );
+        assert!(run_rules_suite(Some("/nonexistent/rules.txt"), true).is_err());
+    }
+
+    #[test]
+    fn test_movegen_suite() {
+        assert_eq!(run_movegen_suite(40, 1, true), SuiteResults { num_tests: 40, num_failures: 0 });
+    }
+
+    #[test]
+    fn test_eval_comparison() {
+        let path = std::env::temp_dir().join(format!("vatu-eval-comparison-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, format!(
+            "# Start and a position with a knight up.\n{}\n4k3/8/8/8/8/8/8/1N2K3 w - - 0 1 ; extra\n",
+            notation::FEN_START,
+        )).unwrap();
+        let same = run_eval_comparison(path, &analysis::SHANNON_WEIGHTS, &analysis::SHANNON_WEIGHTS, 0, true);
+        assert_eq!(same, Ok(SuiteResults { num_tests: 2, num_failures: 0 }));
+        let weights = EvalWeights { knight: 4.0, ..analysis::SHANNON_WEIGHTS };
+        let results = run_eval_comparison(path, &analysis::SHANNON_WEIGHTS, &weights, 50, true);
+        assert_eq!(results, Ok(SuiteResults { num_tests: 2, num_failures: 1 }));
+        let results = run_eval_comparison(path, &analysis::SHANNON_WEIGHTS, &weights, 100, true);
+        assert_eq!(results, Ok(SuiteResults { num_tests: 2, num_failures: 0 }));
+        fs::write(path, "8/8/8 w\n").unwrap();
+        assert!(run_eval_comparison(path, &weights, &weights, 0, true).is_err());
+        fs::remove_file(path).unwrap();
+        assert!(run_eval_comparison(path, &weights, &weights, 0, true).is_err());
+    }
+
+    #[test]
+    fn test_check_rules_line() {
+        assert!(check_rules_line(&format!("{} ; ongoing", notation::FEN_START)).is_ok());
+        assert!(check_rules_line(&format!("{} ; checkmate", notation::FEN_START)).is_err());
+        assert!(check_rules_line(&format!("{} ; lost", notation::FEN_START)).is_err());
+        assert!(check_rules_line("8/8/8 w ; ongoing").is_err());
+    }
+}
@@ -41,6 +41,12 @@ impl BoardStats {
     }
 }
 
+impl Default for BoardStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for BoardStats {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -135,28 +141,20 @@ pub fn compute_color_stats_into(
                         isolated = false;
                     }
                     // Check for backward pawns.
-                    if backward {
-                        if color == SQ_WH && r <= pos_r {
-                            if (
-                                pos_f > POS_MIN &&
-                                is_type(get_square(board, &(pos_f - 1, r)), SQ_P)
-                            ) || (
-                                pos_f < POS_MAX &&
-                                is_type(get_square(board, &(pos_f + 1, r)), SQ_P)
-                            ) {
-                                backward = false;
-                            }
-                        } else if color == SQ_BL && r >= pos_r {
-                            if (
+                    if
+                        backward &&
+                        ((color == SQ_WH && r <= pos_r) || (color == SQ_BL && r >= pos_r)) &&
+                        (
+                            (
                                 pos_f > POS_MIN &&
                                 is_type(get_square(board, &(pos_f - 1, r)), SQ_P)
                             ) || (
                                 pos_f < POS_MAX &&
                                 is_type(get_square(board, &(pos_f + 1, r)), SQ_P)
-                            ) {
-                                backward = false;
-                            }
-                        }
+                            )
+                        )
+                    {
+                        backward = false;
                     }
                 }
                 if doubled {
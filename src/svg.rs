@@ -0,0 +1,133 @@
+//! SVG rendering of boards.
+//!
+//! Only available with the `svg` feature.
+
+use std::fmt::Write;
+
+use crate::board::*;
+use crate::movement::Move;
+
+/// Size of a square in pixels.
+const SQUARE_SIZE: i32 = 45;
+/// Size of the margin used for coordinates, in pixels.
+const MARGIN: i32 = 20;
+
+const LIGHT_SQUARE_COLOR: &str = "#f0d9b5";
+const DARK_SQUARE_COLOR: &str = "#b58863";
+const LAST_MOVE_COLOR: &str = "#cdd26a";
+const CHECK_COLOR: &str = "#e35050";
+
+/// Render `board` as a SVG document, white side at the bottom.
+///
+/// If `last_move` is set, its origin and destination squares are
+/// highlighted. If `check` is set, this square (usually the position
+/// of a king in check) is highlighted in red.
+pub fn board_to_svg(board: &Board, last_move: Option<&Move>, check: Option<&Pos>) -> String {
+    let size = SQUARE_SIZE * 8 + MARGIN * 2;
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" \
+         viewBox=\"0 0 {0} {0}\">",
+        size
+    ).unwrap();
+    for f in 0..8 {
+        for r in 0..8 {
+            let p = (f, r);
+            let (x, y) = square_origin(&p);
+            let color = if check == Some(&p) {
+                CHECK_COLOR
+            } else if last_move.is_some_and(|m| m.0 == p || m.1 == p) {
+                LAST_MOVE_COLOR
+            } else if (f + r) % 2 == 1 {
+                LIGHT_SQUARE_COLOR
+            } else {
+                DARK_SQUARE_COLOR
+            };
+            writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"{2}\" height=\"{2}\" fill=\"{3}\"/>",
+                x, y, SQUARE_SIZE, color
+            ).unwrap();
+            if let Some(glyph) = piece_glyph(get_square(board, &p)) {
+                writeln!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" \
+                     dominant-baseline=\"central\">{}</text>",
+                    x + SQUARE_SIZE / 2, y + SQUARE_SIZE / 2, SQUARE_SIZE * 4 / 5, glyph
+                ).unwrap();
+            }
+        }
+    }
+    // Coordinates, files below the board and ranks on its left.
+    for i in 0..8 {
+        let name = pos_string(&(i, i));
+        let (x, y) = square_origin(&(i, i));
+        writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"middle\">{}</text>",
+            x + SQUARE_SIZE / 2, size - MARGIN / 3, &name[0..1]
+        ).unwrap();
+        writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"middle\" \
+             dominant-baseline=\"central\">{}</text>",
+            MARGIN / 2, y + SQUARE_SIZE / 2, &name[1..2]
+        ).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Return the top-left corner coordinates of this square in the image.
+fn square_origin(p: &Pos) -> (i32, i32) {
+    (MARGIN + p.0 as i32 * SQUARE_SIZE, MARGIN + (7 - p.1 as i32) * SQUARE_SIZE)
+}
+
+/// Return the Unicode glyph for this square content, if any.
+fn piece_glyph(square: u8) -> Option<char> {
+    let glyphs = if is_white(square) {
+        ['♙', '♗', '♘', '♖', '♕', '♔']
+    } else {
+        ['♟', '♝', '♞', '♜', '♛', '♚']
+    };
+    match get_type(square) {
+        SQ_P => Some(glyphs[0]),
+        SQ_B => Some(glyphs[1]),
+        SQ_N => Some(glyphs[2]),
+        SQ_R => Some(glyphs[3]),
+        SQ_Q => Some(glyphs[4]),
+        SQ_K => Some(glyphs[5]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_move;
+
+    #[test]
+    fn test_board_to_svg() {
+        let b = new();
+        let svg = board_to_svg(&b, None, None);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<rect").count(), 64);
+        assert_eq!(svg.matches('♙').count(), 8);
+        assert_eq!(svg.matches('♚').count(), 1);
+        assert!(!svg.contains(LAST_MOVE_COLOR));
+
+        // Highlights for last move and check.
+        let m = parse_move("e2e4").unwrap();
+        let svg = board_to_svg(&b, Some(&m), Some(&pos("e8")));
+        assert_eq!(svg.matches(LAST_MOVE_COLOR).count(), 2);
+        assert_eq!(svg.matches(CHECK_COLOR).count(), 1);
+    }
+
+    #[test]
+    fn test_square_origin() {
+        assert_eq!(square_origin(&pos("a8")), (MARGIN, MARGIN));
+        assert_eq!(square_origin(&pos("h1")), (MARGIN + 7 * SQUARE_SIZE, MARGIN + 7 * SQUARE_SIZE));
+    }
+}
@@ -0,0 +1,203 @@
+//! Simple tactical motifs detection, for training front-ends.
+//!
+//! These are cheap heuristics on the current position, not a search:
+//! a "free" capture may still lose to a deeper combination.
+
+use crate::board::*;
+use crate::movement::Move;
+use crate::rules;
+
+/// Tactical hints for the player to move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hints {
+    /// Player pieces attacked and either undefended or attacked by a cheaper piece.
+    pub hanging: Vec<Pos>,
+    /// Legal captures of undefended or more valuable opponent pieces.
+    pub free_captures: Vec<Move>,
+    /// Forks by any piece on the board.
+    pub forks: Vec<Fork>,
+    /// Pieces of both colors pinned to their king.
+    pub pins: Vec<Pin>,
+}
+
+/// A piece attacking at least two enemy pieces worth attacking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fork {
+    pub attacker: Pos,
+    pub targets: Vec<Pos>,
+}
+
+/// A piece that can not move away without exposing its king.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pin {
+    pub pinner: Pos,
+    pub pinned: Pos,
+    pub king: Pos,
+}
+
+/// Compute tactical hints for the player to move.
+pub fn get_hints(board: &Board, game_state: &rules::GameState) -> Hints {
+    let color = game_state.color;
+    let hanging = get_piece_iterator(board)
+        .filter(|(piece, p)| {
+            is_color(*piece, color) && !is_type(*piece, SQ_K) && is_en_prise(board, p)
+        })
+        .map(|(_, p)| p)
+        .collect();
+    let free_captures = rules::get_player_moves(board, game_state, true).into_iter()
+        .filter(|m| !is_empty(board, &m.1) && is_free_capture(board, m))
+        .collect();
+    let forks = get_piece_iterator(board)
+        .filter_map(|(_, p)| get_fork(board, &p))
+        .collect();
+    let mut pins = get_pins(board, SQ_WH);
+    pins.append(&mut get_pins(board, SQ_BL));
+    Hints { hanging, free_captures, forks, pins }
+}
+
+/// Return a rough material value for this piece, kings being priceless.
+fn piece_value(piece: u8) -> i32 {
+    match get_type(piece) {
+        SQ_P => 1,
+        SQ_B | SQ_N => 3,
+        SQ_R => 5,
+        SQ_Q => 9,
+        SQ_K => 1000,
+        _ => 0,
+    }
+}
+
+/// Return the positions of the pieces of `color` attacking the square `at`.
+///
+/// An enemy piece is placed on `at` in a copy of the board so that
+/// pawn attacks and defenses of friendly pieces are found as well.
+fn get_attackers(board: &Board, color: u8, at: &Pos) -> Vec<Pos> {
+    let mut board = *board;
+    set_square(&mut board, at, opposite(color)|SQ_P);
+    let mut game_state = rules::GameState::new();
+    game_state.color = color;
+    game_state.castling = 0;
+    rules::get_player_moves(&board, &game_state, false).into_iter()
+        .filter(|m| m.1 == *at)
+        .map(|m| m.0)
+        .collect()
+}
+
+/// Return true if the piece at `at` is attacked and undefended or attacked by a cheaper piece.
+fn is_en_prise(board: &Board, at: &Pos) -> bool {
+    let piece = get_square(board, at);
+    let color = get_color(piece);
+    let attackers = get_attackers(board, opposite(color), at);
+    if attackers.is_empty() {
+        return false
+    }
+    get_attackers(board, color, at).is_empty()
+    || attackers.iter().any(|a| piece_value(get_square(board, a)) < piece_value(piece))
+}
+
+/// Return true if the capture `m` takes an undefended or more valuable piece.
+fn is_free_capture(board: &Board, m: &Move) -> bool {
+    let attacker = get_square(board, &m.0);
+    let victim = get_square(board, &m.1);
+    piece_value(victim) > piece_value(attacker)
+    || get_attackers(board, get_color(victim), &m.1).is_empty()
+}
+
+/// Return the fork made by the piece at `at`, if any.
+///
+/// Targets are enemy pieces that are either kings, undefended or more
+/// valuable than the attacker.
+fn get_fork(board: &Board, at: &Pos) -> Option<Fork> {
+    let piece = get_square(board, at);
+    let mut game_state = rules::GameState::new();
+    game_state.color = get_color(piece);
+    game_state.castling = 0;
+    let mut targets: Vec<Pos> = rules::get_piece_moves(board, at, &game_state, false).into_iter()
+        .filter(|m| !is_empty(board, &m.1))
+        .map(|m| m.1)
+        .filter(|p| {
+            let target = get_square(board, p);
+            piece_value(target) > piece_value(piece)
+            || get_attackers(board, get_color(target), p).is_empty()
+        })
+        .collect();
+    // Promotions produce several moves to the same square.
+    targets.sort_unstable();
+    targets.dedup();
+    if targets.len() >= 2 { Some(Fork { attacker: *at, targets }) } else { None }
+}
+
+/// Return the pieces of `color` pinned to their king.
+fn get_pins(board: &Board, color: u8) -> Vec<Pin> {
+    let mut pins = vec!();
+    let king = match find_king(board, color) {
+        Some(king) => king,
+        None => return pins,
+    };
+    let directions = [(0, 1), (1, 0), (0, -1), (-1, 0), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+    for (i, dir) in directions.iter().enumerate() {
+        let slider_type = if i < 4 { SQ_R } else { SQ_B };
+        let mut pinned = None;
+        for p in ray(&king, *dir) {
+            let s = get_square(board, &p);
+            if s != SQ_E {
+                if is_color(s, color) {
+                    if pinned.is_some() {
+                        break
+                    }
+                    pinned = Some(p);
+                } else {
+                    if is_type(s, slider_type) || is_type(s, SQ_Q) {
+                        if let Some(pinned) = pinned {
+                            pins.push(Pin { pinner: p, pinned, king });
+                        }
+                    }
+                    break
+                }
+            }
+        }
+    }
+    pins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn hints_for(fen: &str) -> Hints {
+        let (b, gs) = notation::position(fen);
+        get_hints(&b, &gs)
+    }
+
+    #[test]
+    fn test_hanging_and_free_captures() {
+        // White knight on e5 is attacked by the d6 pawn, black rook on a5 is undefended.
+        let hints = hints_for("4k3/8/3p4/r3N3/8/2B5/8/4K3 w - - 0 1");
+        assert_eq!(hints.hanging, vec![pos("e5")]);
+        assert_eq!(hints.free_captures, vec![notation::parse_move("c3a5").unwrap()]);
+
+        // Defended pieces attacked by equal pieces are not hanging.
+        let hints = hints_for("4k3/1p6/2n5/4N3/5P2/8/8/4K3 w - - 0 1");
+        assert!(hints.hanging.is_empty());
+        assert!(hints.free_captures.is_empty());
+    }
+
+    #[test]
+    fn test_forks() {
+        // Knight on c7 forks the king and the rook.
+        let hints = hints_for("r3k3/2N5/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(hints.forks, vec![Fork { attacker: pos("c7"), targets: vec![pos("a8"), pos("e8")] }]);
+    }
+
+    #[test]
+    fn test_pins() {
+        // Black knight on e6 is pinned by the white rook, white bishop on d2 by the black queen.
+        let hints = hints_for("4k3/8/4n3/8/8/8/3B4/4K3 w - - 0 1");
+        assert!(hints.pins.is_empty());
+        let hints = hints_for("4k3/8/4n3/8/1q6/8/3B4/4R1K1 w - - 0 1");
+        assert_eq!(hints.pins, vec![Pin { pinner: pos("e1"), pinned: pos("e6"), king: pos("e8") }]);
+        let hints = hints_for("4k3/8/4n3/8/1q6/8/3B4/4K3 w - - 0 1");
+        assert_eq!(hints.pins, vec![Pin { pinner: pos("b4"), pinned: pos("d2"), king: pos("e1") }]);
+    }
+}
@@ -0,0 +1,307 @@
+//! Time controls, clocks and time allocation for searches.
+
+use crate::analysis::AnalysisParams;
+use crate::board;
+
+/// Smallest time limit for a search, in milliseconds.
+pub const MIN_TIME_LIMIT: i32 = 10;
+
+/// Maximum percentage of the remaining time to use on a move.
+pub const MAX_TIME_PERCENT: i32 = 50;
+
+/// Time control for a player.
+///
+/// Times are in milliseconds, like in UCI commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    /// Number of moves per session, or None if the base time is for the whole game.
+    pub moves: Option<u32>,
+    /// Base time of a session.
+    pub base: i32,
+    /// Time added after each move.
+    pub increment: i32,
+}
+
+impl TimeControl {
+    /// Parse a human time control string.
+    ///
+    /// The format is `[moves/]base[+increment]`, where the base time is
+    /// in minutes (decimals or "m:ss" are allowed) and the increment in
+    /// seconds, e.g. "3+2" for 3 minutes plus 2 seconds per move, or
+    /// "40/90+30" for 40 moves in 90 minutes plus 30 seconds per move.
+    pub fn parse(s: &str) -> Result<TimeControl, String> {
+        let s = s.trim();
+        let (moves, rest) = match s.find('/') {
+            Some(i) => {
+                let moves = s[..i].parse::<u32>()
+                    .map_err(|_| format!("Bad number of moves in time control: {}", s))?;
+                if moves == 0 {
+                    return Err(format!("Number of moves can't be 0 in time control: {}", s))
+                }
+                (Some(moves), &s[i + 1..])
+            }
+            None => (None, s),
+        };
+        let (base, increment) = match rest.find('+') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let base = parse_minutes(base)
+            .ok_or_else(|| format!("Bad base time in time control: {}", s))?;
+        let increment = match increment {
+            Some(inc) => parse_seconds(inc)
+                .ok_or_else(|| format!("Bad increment in time control: {}", s))?,
+            None => 0,
+        };
+        if base == 0 && increment == 0 {
+            return Err(format!("Time control gives no time at all: {}", s))
+        }
+        Ok(TimeControl { moves, base, increment })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(moves) = self.moves {
+            write!(f, "{}/", moves)?;
+        }
+        write!(f, "{}", self.base as f32 / 60000.0)?;
+        if self.increment > 0 {
+            write!(f, "+{}", self.increment as f32 / 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a duration in minutes, either decimal or "m:ss", to milliseconds.
+fn parse_minutes(s: &str) -> Option<i32> {
+    let ms = match s.find(':') {
+        Some(i) => {
+            let minutes = s[..i].parse::<u32>().ok()?;
+            let seconds = s[i + 1..].parse::<u32>().ok()?;
+            if seconds >= 60 {
+                return None
+            }
+            (minutes as f64 * 60.0 + seconds as f64) * 1000.0
+        }
+        None => s.parse::<f64>().ok()? * 60000.0,
+    };
+    to_ms(ms)
+}
+
+/// Parse a decimal duration in seconds to milliseconds.
+fn parse_seconds(s: &str) -> Option<i32> {
+    to_ms(s.parse::<f64>().ok()? * 1000.0)
+}
+
+/// Convert a parsed duration to milliseconds, rejecting invalid values.
+fn to_ms(ms: f64) -> Option<i32> {
+    if ms.is_finite() && ms >= 0.0 && ms <= i32::MAX as f64 { Some(ms.round() as i32) } else { None }
+}
+
+/// Clock of a player, following a time control.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    /// Remaining time in milliseconds; it can go negative when flagging.
+    pub remaining: i32,
+    /// Moves played by this player since the start of the game.
+    pub moves_played: u32,
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl) -> Clock {
+        Clock { time_control, remaining: time_control.base, moves_played: 0 }
+    }
+
+    /// Number of moves before the next session starts, if any.
+    pub fn moves_to_go(&self) -> Option<u32> {
+        self.time_control.moves.map(|moves| moves - self.moves_played % moves)
+    }
+
+    /// Update the clock after the player spent `elapsed` ms on a move.
+    ///
+    /// Return false if the player ran out of time.
+    pub fn on_move_played(&mut self, elapsed: i32) -> bool {
+        self.remaining -= elapsed;
+        if self.remaining < 0 {
+            return false
+        }
+        self.moves_played += 1;
+        self.remaining += self.time_control.increment;
+        if let Some(moves) = self.time_control.moves {
+            if self.moves_played.is_multiple_of(moves) {
+                self.remaining += self.time_control.base;
+            }
+        }
+        true
+    }
+
+    pub fn has_flagged(&self) -> bool {
+        self.remaining < 0
+    }
+}
+
+/// Return the soft and hard time limits in ms for a search with these
+/// parameters, along with warnings about inconsistent parameters.
+///
+/// Time limits are never zero or negative: bad clock values are
+/// replaced with sane ones, and without any clock, time is unlimited.
+/// The hard limit is at most `MAX_TIME_PERCENT` of the remaining time.
+pub fn get_time_limits(args: &AnalysisParams, color: u8) -> (i32, i32, Vec<String>) {
+    let mut warnings = vec!();
+    if args.move_time != -1 {
+        if args.move_time <= 0 {
+            warnings.push(format!("invalid movetime {}, using {} ms", args.move_time, MIN_TIME_LIMIT));
+            return (MIN_TIME_LIMIT, MIN_TIME_LIMIT, warnings)
+        }
+        return (args.move_time, args.move_time, warnings)
+    }
+
+    let (time, inc) = if board::is_white(color) {
+        (args.white_time, args.white_inc)
+    } else {
+        (args.black_time, args.black_inc)
+    };
+    // Without clock for the player, do not use a time limit.
+    if time == -1 {
+        return (i32::MAX, i32::MAX, warnings)
+    }
+    let inc = if inc < 0 {
+        if inc != -1 {
+            warnings.push(format!("invalid increment {}, ignoring it", inc));
+        }
+        0
+    } else {
+        inc
+    };
+    if time <= 0 {
+        warnings.push(format!("invalid remaining time {}, using {} ms", time, MIN_TIME_LIMIT));
+        let time_limit = MIN_TIME_LIMIT.max(inc / 2);
+        return (time_limit, time_limit, warnings)
+    }
+    let moves_to_go = if args.moves_to_go == 0 || args.moves_to_go < -1 {
+        warnings.push(format!("invalid movestogo {}, ignoring it", args.moves_to_go));
+        -1
+    } else {
+        args.moves_to_go
+    };
+
+    let time_limit = if moves_to_go > 0 {
+        // Share the remaining time between the moves to play.
+        (time / moves_to_go).saturating_add(inc)
+    }
+    // If more than 2 minutes is left, use a 1m time limit.
+    else if time > 2*60*1000 {
+        60*1000
+    }
+    // Else use 1/4 of the remaining time (plus the increment).
+    else {
+        (time / 4).saturating_add(inc)
+    };
+    let hard_time_limit = (time / 100 * MAX_TIME_PERCENT).max(MIN_TIME_LIMIT);
+    (time_limit.clamp(MIN_TIME_LIMIT, hard_time_limit), hard_time_limit, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{SQ_BL, SQ_WH};
+
+    #[test]
+    fn test_parse_time_control() {
+        assert_eq!(
+            TimeControl::parse("3+2"),
+            Ok(TimeControl { moves: None, base: 180_000, increment: 2000 })
+        );
+        assert_eq!(
+            TimeControl::parse("40/5"),
+            Ok(TimeControl { moves: Some(40), base: 300_000, increment: 0 })
+        );
+        assert_eq!(
+            TimeControl::parse("40/90+30"),
+            Ok(TimeControl { moves: Some(40), base: 5_400_000, increment: 30_000 })
+        );
+        assert_eq!(
+            TimeControl::parse("0.5+0.1"),
+            Ok(TimeControl { moves: None, base: 30_000, increment: 100 })
+        );
+        assert_eq!(
+            TimeControl::parse("1:30"),
+            Ok(TimeControl { moves: None, base: 90_000, increment: 0 })
+        );
+        assert_eq!(
+            TimeControl::parse("0+1"),
+            Ok(TimeControl { moves: None, base: 0, increment: 1000 })
+        );
+        for bad in ["", "abc", "3+", "+2", "0/5", "x/5", "3+-1", "-3", "1:75", "0", "1e12"].iter() {
+            assert!(TimeControl::parse(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn test_time_control_to_string() {
+        for s in ["3+2", "40/5", "40/90+30", "0.5+0.1"].iter() {
+            assert_eq!(&TimeControl::parse(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut clock = Clock::new(TimeControl::parse("2/1+1").unwrap());
+        assert_eq!(clock.moves_to_go(), Some(2));
+        assert!(clock.on_move_played(10_000));
+        assert_eq!(clock.remaining, 51_000);
+        assert_eq!(clock.moves_to_go(), Some(1));
+        // A new session starts after the second move, adding the base time.
+        assert!(clock.on_move_played(1_000));
+        assert_eq!(clock.remaining, 111_000);
+        assert_eq!(clock.moves_to_go(), Some(2));
+        // Flagging.
+        assert!(!clock.on_move_played(200_000));
+        assert!(clock.has_flagged());
+
+        let clock = Clock::new(TimeControl::parse("3+2").unwrap());
+        assert_eq!(clock.moves_to_go(), None);
+    }
+
+    #[test]
+    fn test_get_time_limits() {
+        let params = |wtime, winc, moves_to_go| AnalysisParams {
+            white_time: wtime,
+            white_inc: winc,
+            black_time: 1000,
+            moves_to_go,
+            ..Default::default()
+        };
+        let limits = |args: &AnalysisParams, color| {
+            let (soft, hard, warnings) = get_time_limits(args, color);
+            (soft, hard, warnings.len())
+        };
+        assert_eq!(limits(&AnalysisParams::default(), SQ_WH), (i32::MAX, i32::MAX, 0));
+        assert_eq!(limits(&params(-1, -1, -1), SQ_WH), (i32::MAX, i32::MAX, 0));
+        assert_eq!(limits(&params(-1, -1, -1), SQ_BL), (250, 500, 0));
+        assert_eq!(limits(&params(60_000, -1, -1), SQ_WH), (15_000, 30_000, 0));
+        assert_eq!(limits(&params(60_000, 1000, -1), SQ_WH), (16_000, 30_000, 0));
+        assert_eq!(limits(&params(60_000, 1000, 10), SQ_WH), (7000, 30_000, 0));
+        assert_eq!(limits(&params(600_000, 0, -1), SQ_WH), (60_000, 300_000, 0));
+        // A big increment does not make us use most of the remaining time.
+        assert_eq!(limits(&params(1000, 5000, -1), SQ_WH), (500, 500, 0));
+        assert_eq!(limits(&params(1000, 0, 1), SQ_WH), (500, 500, 0));
+        // Bad values are ignored with a warning, and limits are never zero.
+        assert_eq!(limits(&params(0, -1, -1), SQ_WH), (MIN_TIME_LIMIT, MIN_TIME_LIMIT, 1));
+        assert_eq!(limits(&params(5, -1, -1), SQ_WH), (MIN_TIME_LIMIT, MIN_TIME_LIMIT, 0));
+        assert_eq!(limits(&params(-500, 2000, 0), SQ_WH), (1000, 1000, 1));
+        assert_eq!(limits(&params(60_000, -20, 0), SQ_WH), (15_000, 30_000, 2));
+        // Extreme values do not overflow.
+        let hard = i32::MAX / 100 * MAX_TIME_PERCENT;
+        assert_eq!(limits(&params(i32::MAX, i32::MAX, 1), SQ_WH), (hard, hard, 0));
+        assert_eq!(limits(&params(100_000, i32::MAX, -1), SQ_WH), (50_000, 50_000, 0));
+        assert_eq!(limits(&params(i32::MIN, i32::MAX, -1), SQ_WH), (i32::MAX / 2, i32::MAX / 2, 1));
+        assert_eq!(limits(&params(i32::MIN, -1, -1), SQ_WH), (MIN_TIME_LIMIT, MIN_TIME_LIMIT, 1));
+        let args = AnalysisParams { move_time: 0, ..Default::default() };
+        assert_eq!(limits(&args, SQ_WH), (MIN_TIME_LIMIT, MIN_TIME_LIMIT, 1));
+        let args = AnalysisParams { move_time: 500, white_time: 0, ..Default::default() };
+        assert_eq!(limits(&args, SQ_WH), (500, 500, 0));
+    }
+}
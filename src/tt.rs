@@ -0,0 +1,222 @@
+//! Transposition table.
+//!
+//! Search results are stored by Zobrist hash of the position, so a
+//! position reached again, through a transposition or at the next
+//! iteration, is not searched again or at least tries the best move
+//! found previously first.
+//!
+//! The table is lock-free and can be shared between threads: each
+//! entry is stored as 2 atomic words, the data and the key XORed with
+//! the data. An entry partially overwritten by another thread does not
+//! match its key anymore and is ignored. Entries are always replaced,
+//! except by quiescence search results, see `store_shallow`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::movement::{Move, MoveCode};
+
+/// Default size of a table in MB.
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+/// Minimum size of a table in MB.
+pub const MIN_SIZE_MB: usize = 1;
+
+/// Maximum size of a table in MB.
+pub const MAX_SIZE_MB: usize = 1024;
+
+/// Number of entries sampled to estimate how full a table is.
+const HASHFULL_SAMPLE: usize = 1000;
+
+/// Bound type of a stored score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    /// The score is exact.
+    Exact,
+    /// The score is at least this value (it failed high).
+    Lower,
+    /// The score is at most this value (it failed low).
+    Upper,
+}
+
+/// Search result for a position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtEntry {
+    /// Depth searched from this position, 0 for a quiescence search.
+    pub depth: u32,
+    /// Score for the player to move.
+    pub score: f32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+/// Transposition table, see module documentation.
+pub struct TranspositionTable {
+    entries: Vec<[AtomicU64; 2]>,
+    /// Memory used by entries in MB.
+    size_mb: usize,
+}
+
+impl TranspositionTable {
+    /// Create a table using about `size_mb` MB of memory.
+    ///
+    /// The size is clamped to [MIN_SIZE_MB, MAX_SIZE_MB]. If this much
+    /// memory can't be allocated, the size is halved until it can, so
+    /// check `size_mb` for the actual size.
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let mut size_mb = size_mb.clamp(MIN_SIZE_MB, MAX_SIZE_MB);
+        let mut entries = Vec::new();
+        while entries.try_reserve_exact(num_entries(size_mb)).is_err() && size_mb > MIN_SIZE_MB {
+            size_mb /= 2;
+        }
+        entries.resize_with(num_entries(size_mb), || [AtomicU64::new(0), AtomicU64::new(0)]);
+        TranspositionTable { entries, size_mb }
+    }
+
+    /// Return the memory used by entries in MB.
+    pub fn size_mb(&self) -> usize {
+        self.size_mb
+    }
+
+    /// Return the entry of the position with hash `key`, if any.
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        let [slot_key, slot_data] = &self.entries[self.index(key)];
+        let data = slot_data.load(Ordering::Relaxed);
+        if slot_key.load(Ordering::Relaxed) ^ data != key {
+            return None
+        }
+        unpack(data)
+    }
+
+    /// Start loading the entry of the position with hash `key` into the
+    /// CPU cache, to probe it soon, e.g. after playing a move.
+    pub fn prefetch(&self, key: u64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let slot = &self.entries[self.index(key)] as *const [AtomicU64; 2];
+            // Prefetching only hints the CPU, any address is safe.
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(slot as *const i8) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = key;
+    }
+
+    /// Store the entry of the position with hash `key`.
+    pub fn store(&self, key: u64, entry: &TtEntry) {
+        let [slot_key, slot_data] = &self.entries[self.index(key)];
+        let data = pack(entry);
+        slot_key.store(key ^ data, Ordering::Relaxed);
+        slot_data.store(data, Ordering::Relaxed);
+    }
+
+    /// Store the entry of the position with hash `key`, unless its slot
+    /// holds a deeper entry, of any position.
+    pub fn store_shallow(&self, key: u64, entry: &TtEntry) {
+        let slot_data = &self.entries[self.index(key)][1];
+        if unpack(slot_data.load(Ordering::Relaxed)).is_some_and(|e| e.depth > entry.depth) {
+            return
+        }
+        self.store(key, entry);
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        for [slot_key, slot_data] in &self.entries {
+            slot_key.store(0, Ordering::Relaxed);
+            slot_data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Return an estimate of the used entries, in permill, from the
+    /// first entries of the table.
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.entries[..HASHFULL_SAMPLE.min(self.entries.len())];
+        let used = sample.iter().filter(|[_, slot_data]| unpack(slot_data.load(Ordering::Relaxed)).is_some()).count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+}
+
+/// Return the number of entries fitting in `size_mb` MB.
+fn num_entries(size_mb: usize) -> usize {
+    size_mb * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()
+}
+
+// Entry data layout: score bits (32), depth (8), bound (2), then the
+// move code (16). A zero bound means an empty entry.
+
+fn pack(entry: &TtEntry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3,
+    };
+    let m = MoveCode::from_option(entry.best_move).0 as u64;
+    entry.score.to_bits() as u64 | (entry.depth.min(255) as u64) << 32 | bound << 40 | m << 42
+}
+
+fn unpack(data: u64) -> Option<TtEntry> {
+    let bound = match (data >> 40) & 0x3 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        3 => Bound::Upper,
+        _ => return None,
+    };
+    let best_move = MoveCode((data >> 42) as u16).to_move();
+    Some(TtEntry {
+        depth: ((data >> 32) & 0xFF) as u32,
+        score: f32::from_bits(data as u32),
+        bound,
+        best_move,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{pos, SQ_N};
+
+    #[test]
+    fn test_entries() {
+        let table = TranspositionTable::new(0);
+        assert_eq!(table.size_mb(), MIN_SIZE_MB);
+        assert_eq!(table.probe(42), None);
+        let entry = TtEntry {
+            depth: 3,
+            score: -1.5,
+            bound: Bound::Lower,
+            best_move: Some((pos("b7"), pos("a8"), Some(SQ_N))),
+        };
+        table.store(42, &entry);
+        assert_eq!(table.probe(42), Some(entry));
+        // Another key on the same slot replaces the entry.
+        let other_key = 42 + table.entries.len() as u64;
+        let other = TtEntry { depth: 1, score: 998.0, bound: Bound::Exact, best_move: None };
+        table.store(other_key, &other);
+        assert_eq!(table.probe(42), None);
+        assert_eq!(table.probe(other_key), Some(other));
+        // Shallow entries do not replace deeper ones.
+        table.store_shallow(42, &TtEntry { depth: 0, ..entry });
+        assert_eq!(table.probe(other_key), Some(other));
+        table.store_shallow(42, &TtEntry { depth: 1, ..entry });
+        assert_eq!(table.probe(42).map(|e| e.depth), Some(1));
+        table.clear();
+        assert_eq!(table.probe(42), None);
+    }
+
+    #[test]
+    fn test_hashfull() {
+        let table = TranspositionTable::new(MIN_SIZE_MB);
+        assert_eq!(table.hashfull(), 0);
+        let entry = TtEntry { depth: 1, score: 0.0, bound: Bound::Exact, best_move: None };
+        for key in 0..(HASHFULL_SAMPLE as u64 / 4) {
+            table.store(key, &entry);
+        }
+        assert_eq!(table.hashfull(), 250);
+        table.clear();
+        assert_eq!(table.hashfull(), 0);
+    }
+}
@@ -0,0 +1,145 @@
+//! Search parameters exposed for tuning.
+//!
+//! Tunables are constants of the search that can be changed at run
+//! time, so that they can be tuned with SPSA, e.g. by OpenBench. Each
+//! one is declared once in `tunables!` and gets a hidden UCI spin
+//! option of the same name, accepted by "setoption" but not listed by
+//! "uci" so GUIs do not show it, and a line in the output of the "tune"
+//! command, the SPSA input format of OpenBench.
+//!
+//! Tunables only describe the parameters: each engine has its own
+//! values, in `SearchParams`, and hands them to the analyzers of each
+//! search, so engines with different values can run in one process.
+
+use crate::reductions;
+
+/// SPSA learning rate given for all tunables.
+const SPSA_LEARNING_RATE: f32 = 0.002;
+
+/// A search parameter that can be changed at run time, with its
+/// default value and bounds.
+pub struct Tunable {
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl Tunable {
+    /// Return the SPSA input line of this tunable with `value`: name,
+    /// type, value, bounds, step and learning rate.
+    pub fn spsa_line(&self, value: i32) -> String {
+        let step = ((self.max - self.min) / 20).max(1);
+        format!("{}, int, {}, {}, {}, {}, {}", self.name, value, self.min, self.max, step, SPSA_LEARNING_RATE)
+    }
+}
+
+/// Declare tunables with the field holding their value in
+/// `SearchParams`, their default value and bounds, and the list of all
+/// of them, `TUNABLES`.
+macro_rules! tunables {
+    ($($(#[$doc:meta])* $name:ident, $field:ident: $default:expr, $min:expr, $max:expr;)*) => {
+        $(
+            $(#[$doc])*
+            pub static $name: Tunable = Tunable { name: stringify!($name), default: $default, min: $min, max: $max };
+        )*
+
+        /// All tunables, in declaration order.
+        pub static TUNABLES: &[&Tunable] = &[$(&$name),*];
+
+        /// Values of the tunables, defaults unless set.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub struct SearchParams {
+            $(
+                $(#[$doc])*
+                pub $field: i32,
+            )*
+        }
+
+        impl Default for SearchParams {
+            fn default() -> Self {
+                SearchParams { $($field: $default),* }
+            }
+        }
+
+        impl SearchParams {
+            /// Return the values, in the order of `TUNABLES`.
+            fn values(&self) -> Vec<i32> {
+                vec![$(self.$field),*]
+            }
+
+            /// Return the values to set, in the order of `TUNABLES`.
+            fn values_mut(&mut self) -> Vec<&mut i32> {
+                vec![$(&mut self.$field),*]
+            }
+        }
+    };
+}
+
+tunables! {
+    /// Margin in centipawns for delta pruning: in quiescence, a capture
+    /// is skipped if the captured piece value plus this margin can't
+    /// raise the static evaluation up to alpha.
+    DELTA_MARGIN_CP, delta_margin_cp: 200, 0, 1000;
+    /// Maximum remaining depth to prune quiet moves by history.
+    HISTORY_PRUNING_MAX_DEPTH, history_pruning_max_depth: 3, 0, 8;
+    /// Index of the first move that can be pruned by history.
+    HISTORY_PRUNING_MIN_MOVE_INDEX, history_pruning_min_move_index: 4, 1, 32;
+    /// History counter per squared remaining depth below which late
+    /// quiet moves are pruned, halved if the static evaluation improved
+    /// over the last move of the same player.
+    HISTORY_PRUNING_THRESHOLD, history_pruning_threshold: 2, 0, 64;
+    /// Late move reduction added to every reducible move, in hundredths
+    /// of a ply.
+    LMR_BASE, lmr_base: (reductions::DEFAULT_BASE * 100.0) as i32, 0, 300;
+    /// Divisor of late move reductions, in hundredths.
+    LMR_DIVISOR, lmr_divisor: (reductions::DEFAULT_DIVISOR * 100.0) as i32, 50, 1000;
+}
+
+impl SearchParams {
+    /// Set the tunable named `name` to `value`, clamped to its bounds;
+    /// return false if there is no such tunable.
+    pub fn set(&mut self, name: &str, value: i32) -> bool {
+        match TUNABLES.iter().zip(self.values_mut()).find(|(t, _)| t.name.eq_ignore_ascii_case(name)) {
+            Some((tunable, field)) => {
+                *field = value.clamp(tunable.min, tunable.max);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return the SPSA input of all tunables with these values, one
+    /// line each.
+    pub fn spsa_input(&self) -> String {
+        TUNABLES.iter().zip(self.values()).map(|(t, v)| t.spsa_line(v)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Return the tunable named `name`, ignoring case like UCI options.
+pub fn find(name: &str) -> Option<&'static Tunable> {
+    TUNABLES.iter().copied().find(|t| t.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tunables() {
+        assert!(TUNABLES.iter().all(|t| t.min <= t.default && t.default <= t.max));
+        assert_eq!(find("delta_margin_cp").unwrap().name, "DELTA_MARGIN_CP");
+        assert!(find("Hash").is_none());
+        let mut params = SearchParams::default();
+        assert_eq!(params.values(), TUNABLES.iter().map(|t| t.default).collect::<Vec<_>>());
+        assert_eq!(params.lmr_base, 75);
+        assert!(params.set("lmr_base", 120));
+        assert!(params.set("DELTA_MARGIN_CP", 5000));
+        assert!(!params.set("Hash", 16));
+        assert_eq!((params.lmr_base, params.delta_margin_cp), (120, 1000));
+        assert_ne!(params, SearchParams::default());
+        let input = params.spsa_input();
+        assert_eq!(input.lines().count(), TUNABLES.len());
+        assert!(input.contains("\nLMR_BASE, int, 120, 0, 300, 15, 0.002\n"));
+    }
+}
@@ -4,13 +4,21 @@ use std::fs;
 use std::io::{self, Write};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::analysis::AnalysisInfo;
+use crate::analysis::{self, AnalysisInfo};
+use crate::attacks;
+use crate::cache;
+use crate::capabilities;
+use crate::cpu;
 use crate::engine;
 use crate::movement::Move;
 use crate::notation;
+use crate::tt;
+use crate::tune;
+use crate::xboard::Xboard;
 
-const VATU_NAME: &str = env!("CARGO_PKG_NAME");
+pub(crate) const VATU_NAME: &str = env!("CARGO_PKG_NAME");
 const VATU_AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 
 // ************************************
@@ -29,6 +37,66 @@ pub struct Uci {
     debug: bool,
     /// If some, write logs to it.
     logfile: Option<fs::File>,
+    /// Path of the analysis cache file to pass to the engine.
+    analysis_cache: Option<String>,
+    /// Profile to apply to the engine on setup, if any.
+    profile: Option<&'static engine::Profile>,
+    /// Commands received before the engine channel is open, sent once it is.
+    pending_engine_cmds: Vec<engine::Cmd>,
+    /// If true, answer "isready" once the engine channel is open.
+    pending_ready: bool,
+    /// If true, answer "uci" once the engine channel is open.
+    pending_identities: bool,
+    /// Size of the transposition table in MB reported by the engine.
+    hash_mb: Option<usize>,
+    /// Searches started whose best move has not been received yet.
+    pending_searches: u32,
+    /// Session statistics to log on quit, if enabled.
+    session: Option<SessionStats>,
+    /// File recording received commands and the session start, if enabled.
+    recording: Option<(fs::File, Instant)>,
+}
+
+/// Commands of a recorded session, with their time in ms since the
+/// start of the session.
+pub type Recording = Vec<(u64, String)>;
+
+/// Statistics of a UCI session, for long-running bots.
+#[derive(Debug, Default)]
+struct SessionStats {
+    /// Number of "ucinewgame" commands.
+    games: u32,
+    /// Number of "go" commands.
+    searches: u32,
+    /// Number of actual searches, i.e. not answered from the analysis cache.
+    searches_done: u32,
+    nodes: u64,
+    /// Sum of the depths of searches.
+    depth_sum: u64,
+    tt_probes: u64,
+    tt_hits: u64,
+    /// Searches that took longer than the remaining clock time.
+    time_losses: u32,
+}
+
+impl SessionStats {
+    fn record_search(&mut self, result: &analysis::SearchResult, stats: &analysis::SearchStats) {
+        self.searches_done += 1;
+        self.nodes += stats.nodes;
+        self.depth_sum += result.depth as u64;
+        self.tt_probes += stats.tt_probes;
+        self.tt_hits += stats.tt_hits;
+        self.time_losses += stats.out_of_time as u32;
+    }
+
+    fn summary(&self) -> String {
+        let average_depth = self.depth_sum as f64 / self.searches_done.max(1) as f64;
+        let tt_hit_rate = self.tt_hits as f64 * 100.0 / self.tt_probes.max(1) as f64;
+        format!(
+            "Session: {} games, {} searches, {} nodes, average depth {:.1}, TT hit rate {:.1}%, {} time losses",
+            self.games, self.searches, self.nodes, average_depth, tt_hit_rate, self.time_losses
+        )
+    }
 }
 
 /// Internal UCI state.
@@ -52,11 +120,19 @@ pub enum UciCmd {
     Uci,
     Debug(bool),
     IsReady,
+    SetOption(String, Option<String>),
     UciNewGame,
     Stop,
     Position(Vec<PositionArgs>),
     Go(Vec<GoArgs>),
+    PonderHit,
     Quit,
+    /// Debug command playing a move on the current position.
+    MakeMove(String),
+    /// Debug command taking back the last "makemove".
+    UndoMove,
+    /// Print the tunables in the SPSA input format, see `tune`.
+    Tune,
     Unknown(String),
 }
 
@@ -85,14 +161,146 @@ pub enum GoArgs {
     Infinite,
 }
 
+/// Type of an UCI option, with its default value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionKind {
+    Check(bool),
+    Spin { default: i32, min: i32, max: i32 },
+    Combo { default: &'static str, vars: Vec<&'static str> },
+    String(&'static str),
+    Button,
+}
+
+/// UCI option declared to the interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+}
+
+/// Value of an option set with "setoption", valid for its declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Check(bool),
+    Spin(i32),
+    /// Combo variant, with the case of its declaration.
+    Combo(&'static str),
+    String(String),
+    Button,
+}
+
+impl UciOption {
+    /// Return the "option" command declaring it.
+    pub fn declaration(&self) -> String {
+        let kind = match &self.kind {
+            OptionKind::Check(default) => format!("check default {}", default),
+            OptionKind::Spin { default, min, max } => format!("spin default {} min {} max {}", default, min, max),
+            OptionKind::Combo { default, vars } => {
+                let vars: Vec<String> = vars.iter().map(|v| format!("var {}", v)).collect();
+                format!("combo default {} {}", default, vars.join(" "))
+            }
+            OptionKind::String(default) => {
+                format!("string default {}", if default.is_empty() { "<empty>" } else { default })
+            }
+            OptionKind::Button => "button".to_string(),
+        };
+        format!("option name {} type {}", self.name, kind)
+    }
+
+    /// Parse a "setoption" value for this option.
+    ///
+    /// Values are case-insensitive except for strings, and "<empty>"
+    /// is the empty string.
+    pub fn parse_value(&self, value: Option<&str>) -> Result<OptionValue, String> {
+        let invalid = || format!("Invalid {} value: {:?}", self.name, value);
+        match &self.kind {
+            OptionKind::Check(_) => match value.map(|v| v.to_lowercase()).as_deref() {
+                Some("true") => Ok(OptionValue::Check(true)),
+                Some("false") => Ok(OptionValue::Check(false)),
+                _ => Err(invalid()),
+            },
+            OptionKind::Spin { min, max, .. } => value.and_then(|v| v.parse::<i32>().ok())
+                .filter(|v| (min..=max).contains(&v))
+                .map(OptionValue::Spin)
+                .ok_or_else(invalid),
+            OptionKind::Combo { vars, .. } => value
+                .and_then(|v| vars.iter().find(|var| var.eq_ignore_ascii_case(v)))
+                .map(|var| OptionValue::Combo(var))
+                .ok_or_else(invalid),
+            OptionKind::String(_) => match value {
+                None | Some("<empty>") => Ok(OptionValue::String(String::new())),
+                Some(v) => Ok(OptionValue::String(v.to_string())),
+            },
+            OptionKind::Button => Ok(OptionValue::Button),
+        }
+    }
+}
+
+/// Return the hidden options setting tunables, see `tune`.
+pub fn tuning_options() -> Vec<UciOption> {
+    tune::TUNABLES.iter().map(|t| {
+        UciOption { name: t.name, kind: OptionKind::Spin { default: t.default, min: t.min, max: t.max } }
+    }).collect()
+}
+
+/// Return the options of the engine, with the defaults of `profile`
+/// and the actual size of its transposition table, `hash_mb`.
+pub fn engine_options(profile: &engine::Profile, hash_mb: usize) -> Vec<UciOption> {
+    let spin = |name, default, min, max| UciOption { name, kind: OptionKind::Spin { default, min, max } };
+    vec![
+        spin("Hash", hash_mb as i32, tt::MIN_SIZE_MB as i32, tt::MAX_SIZE_MB as i32),
+        UciOption { name: "Clear Hash", kind: OptionKind::Button },
+        UciOption {
+            name: "Profile",
+            kind: OptionKind::Combo {
+                default: profile.name,
+                vars: engine::PROFILES.iter().map(|p| p.name).collect(),
+            },
+        },
+        UciOption { name: "Ponder", kind: OptionKind::Check(false) },
+        spin("Contempt", 0, -analysis::MAX_CONTEMPT, analysis::MAX_CONTEMPT),
+        spin("UCI_RatingAdv", 0, -engine::MAX_RATING_ADVANTAGE, engine::MAX_RATING_ADVANTAGE),
+        spin("MaxNps", 0, 0, engine::MAX_NPS),
+        spin("MaxDepth", 0, 0, analysis::MAX_SEARCH_DEPTH as i32),
+        UciOption { name: "LateMoveReductions", kind: OptionKind::Check(true) },
+        UciOption { name: "HistoryPruning", kind: OptionKind::Check(true) },
+        spin("Threads", 1, 1, engine::MAX_THREADS),
+        spin("MultiPV", 1, 1, engine::MAX_MULTI_PV),
+        spin("Move Overhead", 0, 0, engine::MAX_MOVE_OVERHEAD),
+        UciOption { name: "EvalWeights", kind: OptionKind::String("") },
+        UciOption { name: "OwnBook", kind: OptionKind::Check(false) },
+        UciOption { name: "BookFile", kind: OptionKind::String("") },
+        spin("BookDepth", 0, 0, engine::MAX_BOOK_DEPTH),
+        UciOption { name: "ThreadAffinity", kind: OptionKind::Check(false) },
+        spin("Niceness", 0, 0, cpu::MAX_NICENESS),
+    ]
+}
+
 impl Uci {
     /// Start a new UCI listening for standard input.
-    pub fn start(debug: bool, output: Option<&str>) {
+    ///
+    /// If `analysis_cache` is provided, the engine uses the cache file
+    /// at this path to skip analysis of known positions. If `profile` is
+    /// provided, its settings are applied to the engine. If
+    /// `session_summary` is true, session statistics are logged on quit.
+    /// If `record` is provided, received commands are recorded in this
+    /// file, see `load_recording`. If `replay` is provided, its commands
+    /// are received at their recorded time instead of standard input.
+    pub fn start(
+        debug: bool,
+        output: Option<&str>,
+        analysis_cache: Option<&str>,
+        profile: Option<&'static engine::Profile>,
+        session_summary: bool,
+        record: Option<&str>,
+        replay: Option<Recording>,
+    ) {
         // Create the UCI queue, both for standard IO and for engine communication.
         let (uci_s, uci_r): (mpsc::Sender<Cmd>, mpsc::Receiver<Cmd>) = mpsc::channel();
         let stdin_tx = uci_s.clone();
-        thread::spawn(move || {
-            Uci::read_stdin(stdin_tx);
+        thread::spawn(move || match replay {
+            Some(recording) => Uci::replay(recording, stdin_tx),
+            None => Uci::read_stdin(stdin_tx),
         });
 
         let mut uci = Uci {
@@ -101,6 +309,15 @@ impl Uci {
             engine_in: None,
             debug,
             logfile: None,
+            analysis_cache: analysis_cache.map(|path| path.to_string()),
+            profile,
+            pending_engine_cmds: vec!(),
+            pending_ready: false,
+            pending_identities: false,
+            hash_mb: None,
+            pending_searches: 0,
+            session: if session_summary { Some(SessionStats::default()) } else { None },
+            recording: None,
         };
         // Configure log output, either a file or stderr.
         if let Some(output) = output {
@@ -109,6 +326,12 @@ impl Uci {
                 Err(e) => { eprintln!("Could not open log file: {}", e) }
             }
         }
+        if let Some(record) = record {
+            match fs::File::create(record).and_then(|f| write_recording_header(f, &capabilities::detect())) {
+                Ok(f) => { uci.recording = Some((f, Instant::now())) }
+                Err(e) => { eprintln!("Could not open recording file: {}", e) }
+            }
+        }
 
         // Start listening for Cmds.
         uci.listen();
@@ -119,6 +342,14 @@ impl Uci {
             match self.cmd_channel.1.recv() {
                 Ok(Cmd::Stdin(cmd)) => {
                     self.log(format!("UCI >>> {}", cmd));
+                    self.record(&cmd);
+                    // Interfaces using CECP start with "xboard" instead.
+                    if self.state == State::Init && cmd == "xboard" {
+                        let cmd_channel = std::mem::replace(&mut self.cmd_channel, mpsc::channel());
+                        let (logfile, analysis_cache) = (self.logfile.take(), self.analysis_cache.take());
+                        Xboard::new(cmd_channel, self.debug, logfile, analysis_cache, self.profile).listen();
+                        break
+                    }
                     if !self.handle_command(&parse_command(&cmd)) {
                         break
                     }
@@ -142,6 +373,34 @@ impl Uci {
         }
     }
 
+    /// Write a received command to the recording file, if any.
+    fn record(&mut self, cmd: &str) {
+        if let Some((f, start)) = &mut self.recording {
+            if let Err(e) = writeln!(f, "{} {}", start.elapsed().as_millis(), cmd) {
+                eprintln!("Could not record command: {}", e);
+            }
+        }
+    }
+
+    /// Send commands of `recording` at their time over an MPSC channel,
+    /// like `read_stdin` does for standard input.
+    ///
+    /// A "quit" command is sent at the end if it was not recorded.
+    pub fn replay(recording: Recording, tx: mpsc::Sender<Cmd>) {
+        let start = Instant::now();
+        for (ms, cmd) in recording {
+            let elapsed = start.elapsed().as_millis() as u64;
+            if ms > elapsed {
+                thread::sleep(Duration::from_millis(ms - elapsed));
+            }
+            tx.send(Cmd::Stdin(cmd.to_string())).unwrap();
+            if cmd == "quit" {
+                return
+            }
+        }
+        tx.send(Cmd::Stdin("quit".to_string())).unwrap();
+    }
+
     /// Read lines over stdin, notifying over an MPSC channel.
     ///
     /// As it is not trivial to add a timeout, or overly complicated
@@ -178,26 +437,71 @@ impl Uci {
     /// Handle an UCI command, return false if it should stop listening.
     fn handle_command(&mut self, cmd: &UciCmd) -> bool {
         match cmd {
+            // Options are declared once the engine is set up, with the
+            // size of the transposition table it could allocate.
             UciCmd::Uci => if self.state == State::Init {
-                self.send_identities();
                 self.setup_engine();
+                self.pending_identities = true;
             },
             UciCmd::Debug(on) => {
                 self.send_engine_command(engine::Cmd::UciDebug(*on));
             }
-            UciCmd::IsReady => if self.state == State::Ready { self.send_ready() },
-            UciCmd::UciNewGame => if self.state == State::Ready { /* Nothing to do. */ },
-            UciCmd::Position(args) => if self.state == State::Ready {
+            // Answer even while working, as the search runs in its own
+            // thread; only wait for the engine to be set up and for
+            // attack tables to be built.
+            UciCmd::IsReady => {
+                attacks::init();
+                if self.state != State::Init && self.engine_in.is_none() {
+                    self.pending_ready = true;
+                } else {
+                    self.send_ready();
+                }
+            }
+            UciCmd::SetOption(name, value) => if self.state == State::Ready {
+                self.set_option(name, value.as_deref());
+            },
+            UciCmd::UciNewGame => if self.state == State::Ready {
+                if let Some(session) = self.session.as_mut() {
+                    session.games += 1;
+                }
+            },
+            // While working, the engine aborts the search, sending its
+            // best move, before handling the new position or search.
+            UciCmd::Position(args) => if self.state != State::Init {
                 self.send_engine_command(engine::Cmd::UciPosition(args.to_vec()));
             },
-            UciCmd::Go(args) => if self.state == State::Ready {
+            UciCmd::Go(args) => if self.state != State::Init {
+                if let Some(session) = self.session.as_mut() {
+                    session.searches += 1;
+                }
                 self.send_engine_command(engine::Cmd::UciGo(args.to_vec()));
+                self.pending_searches += 1;
                 self.state = State::Working;
             }
+            UciCmd::PonderHit => if self.state == State::Working {
+                self.send_engine_command(engine::Cmd::UciPonderHit);
+            },
             UciCmd::Stop => if self.state == State::Working {
                 self.send_engine_command(engine::Cmd::Stop);
             },
-            UciCmd::Quit => return false,
+            UciCmd::MakeMove(m) => if self.state == State::Ready {
+                self.send_engine_command(engine::Cmd::MakeMove(m.to_string()));
+            },
+            UciCmd::UndoMove => if self.state == State::Ready {
+                self.send_engine_command(engine::Cmd::UndoMove);
+            },
+            // Values belong to the engine, defaults until it is set up.
+            UciCmd::Tune => if self.state == State::Init {
+                self.send_spsa_input(&tune::SearchParams::default().spsa_input());
+            } else {
+                self.send_engine_command(engine::Cmd::Tune);
+            },
+            UciCmd::Quit => {
+                if let Some(summary) = self.session.as_ref().map(|s| s.summary()) {
+                    self.log(summary);
+                }
+                return false
+            }
             UciCmd::Unknown(c) => { self.log(format!("Unknown command: {}", c)); }
         }
         true
@@ -209,61 +513,118 @@ impl Uci {
             engine::Cmd::UciChannel(s) => {
                 self.log("ENGINE: Channel opened.".to_string());
                 self.engine_in = Some(s.to_owned());
+                for cmd in std::mem::take(&mut self.pending_engine_cmds) {
+                    self.send_engine_command(cmd);
+                }
+                if self.pending_identities {
+                    self.pending_identities = false;
+                    self.send_identities();
+                }
+                if self.pending_ready {
+                    self.pending_ready = false;
+                    self.send_ready();
+                }
             }
             engine::Cmd::Log(s) => {
-                self.log(format!("ENGINE: {}", s.to_string()));
+                self.log(format!("ENGINE: {}", s));
             }
             engine::Cmd::Info(infos) => {
                 self.send_infos(infos);
             }
-            engine::Cmd::BestMove(m) => {
-                self.state = State::Ready;
-                self.send_bestmove(m);
+            engine::Cmd::SearchResult(result) => {
+                if let (Some(session), Some(stats)) = (self.session.as_mut(), &result.stats) {
+                    session.record_search(result, stats);
+                }
+                self.pending_searches = self.pending_searches.saturating_sub(1);
+                if self.pending_searches == 0 {
+                    self.state = State::Ready;
+                }
+                self.send_result(result);
+            }
+            engine::Cmd::SpsaInput(input) => {
+                self.send_spsa_input(input);
+            }
+            engine::Cmd::HashSize(mb) => {
+                self.hash_mb = Some(*mb);
             }
             _ => {}
         }
     }
 
+    /// Return the options declared to the interface.
+    fn options(&self) -> Vec<UciOption> {
+        let profile = self.profile.unwrap_or(&engine::PROFILES[0]);
+        engine_options(profile, self.hash_mb.unwrap_or(profile.hash_mb))
+    }
+
+    /// Check a "setoption" value against the declared options and the
+    /// hidden tuning options and pass it to the engine; unknown options
+    /// and invalid values are logged.
+    fn set_option(&mut self, name: &str, value: Option<&str>) {
+        let mut options = self.options().into_iter().chain(tuning_options());
+        let option = match options.find(|o| o.name.eq_ignore_ascii_case(name)) {
+            Some(option) => option,
+            None => { self.log(format!("Unknown option: {}", name)); return }
+        };
+        match option.parse_value(value) {
+            Ok(value) => self.send_engine_command(engine::Cmd::UciSetOption(option.name, value)),
+            Err(e) => self.log(e),
+        }
+    }
+
     /// Send IDs to interface.
     fn send_identities(&mut self) {
         self.send(&format!("id name {}", VATU_NAME));
         self.send(&format!("id author {}", VATU_AUTHORS));
+        for option in self.options() {
+            self.send(&option.declaration());
+        }
         self.send("uciok");
     }
 
     /// Setup engine for UCI.
     fn setup_engine(&mut self) {
-        let debug = self.debug;
-        let uci_s = self.cmd_channel.0.clone();
-        thread::spawn(move || {
-            let mut engine = engine::Engine::new();
-            if debug {
-                engine.enable_debug();
-            }
-            engine.setup_uci(uci_s);
-        });
+        spawn_engine(self.debug, self.analysis_cache.clone(), self.profile, self.cmd_channel.0.clone());
         self.state = State::Ready;
     }
 
-    /// Send a command to the engine if it is has been setup, else log an error.
+    /// Send a command to the engine if it is has been setup.
+    ///
+    /// If the engine is being setup, the command is sent when it is
+    /// ready, else an error is logged.
     fn send_engine_command(&mut self, cmd: engine::Cmd) {
         if let Some(tx) = self.engine_in.as_ref() {
             tx.send(cmd).unwrap();
+        } else if self.state != State::Init {
+            self.pending_engine_cmds.push(cmd);
         } else {
             self.log("Attempt to send command to offline engine.".to_string());
         }
     }
 
+    /// Print the SPSA input of the tunables, see `tune`.
+    fn send_spsa_input(&mut self, input: &str) {
+        for line in input.lines() {
+            self.send(line);
+        }
+    }
+
     /// Notify interface that it is ready.
     fn send_ready(&mut self) {
         self.send("readyok");
     }
 
     /// Send engine analysis information.
-    fn send_infos(&mut self, infos: &Vec<AnalysisInfo>) {
+    fn send_infos(&mut self, infos: &[AnalysisInfo]) {
         let mut s = "info".to_string();
         for i in infos {
             match i {
+                AnalysisInfo::Depth(n) => {
+                    s.push_str(&format!(" depth {}", n));
+                }
+                AnalysisInfo::SelDepth(n) => {
+                    s.push_str(&format!(" seldepth {}", n));
+                }
                 AnalysisInfo::Nodes(n) => {
                     s.push_str(&format!(" nodes {}", n));
                 }
@@ -273,39 +634,168 @@ impl Uci {
                 AnalysisInfo::CurrentMove(m) => {
                     s.push_str(&format!(" currmove {}", notation::move_to_string(m)));
                 }
+                AnalysisInfo::Time(ms) => {
+                    s.push_str(&format!(" time {}", ms));
+                }
+                AnalysisInfo::MultiPv(n) => {
+                    s.push_str(&format!(" multipv {}", n));
+                }
+                AnalysisInfo::Score(cp) => {
+                    s.push_str(&format!(" score cp {}", cp));
+                }
+                AnalysisInfo::Mate(moves) => {
+                    s.push_str(&format!(" score mate {}", moves));
+                }
+                AnalysisInfo::HashFull(permill) => {
+                    s.push_str(&format!(" hashfull {}", permill));
+                }
+                AnalysisInfo::Pv(moves) => {
+                    s.push_str(&format!(" pv {}", notation::move_list_to_string(moves)));
+                }
+                // Everything after "string" is part of the message, so
+                // it has to be the last info of the line.
+                AnalysisInfo::String(message) => {
+                    s.push_str(&format!(" string {}", message));
+                }
             }
         }
         self.send(&s);
     }
 
-    /// Send best move.
-    fn send_bestmove(&mut self, m: &Option<Move>) {
-        let move_str = match m {
+    /// Send the result of a search: a last info line for searches
+    /// actually done, then the best move.
+    fn send_result(&mut self, result: &analysis::SearchResult) {
+        if let Some(stats) = &result.stats {
+            self.send(&format_result_info(result, stats));
+        }
+        let move_str = match &result.best_move {
             Some(m) => notation::move_to_string(m),
             None => notation::NULL_MOVE.to_string(),
         };
-        self.send(&format!("bestmove {}", move_str));
+        match &result.ponder_move {
+            Some(ponder) => self.send(&format!("bestmove {} ponder {}", move_str, notation::move_to_string(ponder))),
+            None => self.send(&format!("bestmove {}", move_str)),
+        }
     }
 }
 
+/// Start an engine in its own thread, replying on `uci_s`, see
+/// `Uci::start` for the other parameters.
+///
+/// Attack tables are built there first, to not slow down the first
+/// search.
+pub(crate) fn spawn_engine(
+    debug: bool,
+    analysis_cache: Option<String>,
+    profile: Option<&'static engine::Profile>,
+    uci_s: mpsc::Sender<Cmd>,
+) {
+    thread::spawn(move || {
+        attacks::init();
+        let mut engine = engine::Engine::new();
+        if debug {
+            engine.enable_debug();
+        }
+        if let Some(profile) = profile {
+            engine.apply_profile(profile);
+        }
+        if let Some(path) = analysis_cache {
+            match cache::AnalysisCache::open(&path) {
+                Ok(cache) => engine.set_analysis_cache(cache),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        engine.setup_uci(uci_s);
+    });
+}
+
+/// Format the final info line of a search `result` with its `stats`.
+fn format_result_info(result: &analysis::SearchResult, stats: &analysis::SearchStats) -> String {
+    let mut s = format!("info depth {} seldepth {}", result.depth, result.sel_depth);
+    let score = match (result.mate, result.score) {
+        (Some(moves), _) => Some(format!("mate {}", moves)),
+        (None, Some(cp)) => Some(format!("cp {}", cp)),
+        (None, None) => None,
+    };
+    if let Some(score) = score {
+        s.push_str(&format!(" score {}", score));
+        match result.bound {
+            tt::Bound::Exact => {}
+            tt::Bound::Lower => s.push_str(" lowerbound"),
+            tt::Bound::Upper => s.push_str(" upperbound"),
+        }
+    }
+    s.push_str(&format!(" nodes {}", stats.nodes));
+    if !result.pv.is_empty() {
+        s.push_str(&format!(" pv {}", notation::move_list_to_string(&result.pv)));
+    }
+    s
+}
+
 // ************************************
 // UCI command parsers
 
+/// Load a session recorded with the `record` parameter of `Uci::start`.
+///
+/// Each line is a command preceded by its time in milliseconds since
+/// the start of the session, e.g. "1500 go movetime 1000". Lines
+/// starting with "#" are comments, e.g. the capabilities of the engine
+/// that recorded the session.
+pub fn load_recording(path: &str) -> Result<Recording, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read recording {}: {}", path, e))?;
+    parse_recording(&content).map_err(|e| format!("Bad recording {}: {}", path, e))
+}
+
+/// Write `capabilities` at the top of recording file `f`, as comments.
+fn write_recording_header(mut f: fs::File, capabilities: &capabilities::Capabilities) -> io::Result<fs::File> {
+    for line in capabilities.to_string().lines() {
+        writeln!(f, "# {}", line)?;
+    }
+    Ok(f)
+}
+
+fn parse_recording(content: &str) -> Result<Recording, String> {
+    let lines = content.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#'));
+    lines.map(|line| {
+        let (ms, cmd) = line.split_once(' ').unwrap_or((line, ""));
+        match ms.parse::<u64>() {
+            Ok(ms) => Ok((ms, cmd.trim().to_string())),
+            Err(_) => Err(format!("bad time in line \"{}\"", line)),
+        }
+    }).collect()
+}
+
 /// Parse an UCI command.
-fn parse_command(s: &str) -> UciCmd {
-    if s.len() == 0 {
+///
+/// Malformed commands are returned as `UciCmd::Unknown` with a short
+/// description of the issue.
+pub fn parse_command(s: &str) -> UciCmd {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.is_empty() {
         return UciCmd::Unknown("Empty command.".to_string());
     }
-    let fields: Vec<&str> = s.split_whitespace().collect();
     match fields[0] {
         "uci" => UciCmd::Uci,
-        "debug" => UciCmd::Debug(fields[1] == "on"),
+        "debug" => match fields.get(1) {
+            Some(&"on") => UciCmd::Debug(true),
+            Some(&"off") => UciCmd::Debug(false),
+            _ => UciCmd::Unknown("Bad format for debug".to_string()),
+        },
         "isready" => UciCmd::IsReady,
+        "setoption" => parse_setoption_command(&fields[1..]),
         "ucinewgame" => UciCmd::UciNewGame,
         "stop" => UciCmd::Stop,
         "position" => parse_position_command(&fields[1..]),
         "go" => parse_go_command(&fields[1..]),
+        "ponderhit" => UciCmd::PonderHit,
         "quit" => UciCmd::Quit,
+        "makemove" => match fields.get(1) {
+            Some(m) => UciCmd::MakeMove(m.to_string()),
+            None => UciCmd::Unknown("Missing move for makemove".to_string()),
+        },
+        "undomove" => UciCmd::UndoMove,
+        "tune" => UciCmd::Tune,
         c => UciCmd::Unknown(c.to_string()),
     }
 }
@@ -319,10 +809,12 @@ fn parse_position_command(fields: &[&str]) -> UciCmd {
         match fields[i] {
             // Subcommand "fen" is followed by a FEN string.
             "fen" => {
-                if let Some(fen) = notation::parse_fen_fields(&fields[i + 1 .. i + 7]) {
-                    subcommands.push(PositionArgs::Fen(fen))
-                } else {
-                    return UciCmd::Unknown(format!("Bad format for position fen"))
+                let fen = fields.get(i + 1 .. i + 7)
+                    .and_then(notation::parse_fen_fields)
+                    .filter(|fen| notation::fen_to_position(fen).is_some());
+                match fen {
+                    Some(fen) => subcommands.push(PositionArgs::Fen(fen)),
+                    None => return UciCmd::Unknown("Bad format for position fen".to_string()),
                 }
                 i += 6;
             }
@@ -332,7 +824,10 @@ fn parse_position_command(fields: &[&str]) -> UciCmd {
             "moves" => {
                 let mut moves = vec!();
                 while i + 1 < num_fields {
-                    moves.push(notation::parse_move(fields[i + 1]));
+                    match notation::parse_move(fields[i + 1]) {
+                        Some(m) => moves.push(m),
+                        None => return UciCmd::Unknown(format!("Bad move: {}", fields[i + 1])),
+                    }
                     i += 1;
                 }
                 subcommands.push(PositionArgs::Moves(moves));
@@ -344,53 +839,335 @@ fn parse_position_command(fields: &[&str]) -> UciCmd {
     UciCmd::Position(subcommands)
 }
 
+/// Parse an UCI "setoption" command.
+///
+/// Option names and values may contain spaces.
+fn parse_setoption_command(fields: &[&str]) -> UciCmd {
+    if fields.first() != Some(&"name") {
+        return UciCmd::Unknown("Bad format for setoption".to_string())
+    }
+    let fields = &fields[1..];
+    let (name, value) = match fields.iter().position(|f| *f == "value") {
+        Some(i) => (&fields[..i], Some(fields[i + 1..].join(" "))),
+        None => (fields, None),
+    };
+    if name.is_empty() {
+        return UciCmd::Unknown("Missing option name for setoption".to_string())
+    }
+    UciCmd::SetOption(name.join(" "), value)
+}
+
 /// Parse an UCI "go" command.
 fn parse_go_command(fields: &[&str]) -> UciCmd {
+    match parse_go_args(fields) {
+        Ok(subcommands) => UciCmd::Go(subcommands),
+        Err(e) => UciCmd::Unknown(e),
+    }
+}
+
+/// Parse the arguments of an UCI "go" command.
+fn parse_go_args(fields: &[&str]) -> Result<Vec<GoArgs>, String> {
     let num_fields = fields.len();
     let mut i = 0;
     let mut subcommands = vec!();
     while i < num_fields {
         match fields[i] {
             "infinite" => subcommands.push(GoArgs::Infinite),
+            "searchmoves" => {
+                // Moves go on until the next subcommand.
+                let mut moves = vec!();
+                while let Some(m) = fields.get(i + 1).and_then(|f| notation::parse_move(f)) {
+                    moves.push(m);
+                    i += 1;
+                }
+                subcommands.push(GoArgs::SearchMoves(moves));
+            }
+            "ponder" => subcommands.push(GoArgs::Ponder),
             "movetime" => {
                 i += 1;
-                subcommands.push(GoArgs::MoveTime(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::MoveTime(parse_go_value(fields, i)?));
             }
             "wtime" => {
                 i += 1;
-                subcommands.push(GoArgs::WTime(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::WTime(parse_go_value(fields, i)?));
             },
             "btime" => {
                 i += 1;
-                subcommands.push(GoArgs::BTime(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::BTime(parse_go_value(fields, i)?));
             }
             "winc" => {
                 i += 1;
-                subcommands.push(GoArgs::WInc(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::WInc(parse_go_value(fields, i)?));
             }
             "binc" => {
                 i += 1;
-                subcommands.push(GoArgs::BInc(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::BInc(parse_go_value(fields, i)?));
             }
             "movestogo" => {
                 i += 1;
-                subcommands.push(GoArgs::MovesToGo(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::MovesToGo(parse_go_value(fields, i)?));
             }
             "depth" => {
                 i += 1;
-                subcommands.push(GoArgs::Depth(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::Depth(parse_go_value(fields, i)?));
             }
             "nodes" => {
                 i += 1;
-                subcommands.push(GoArgs::Nodes(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::Nodes(parse_go_value(fields, i)?));
             }
             "mate" => {
                 i += 1;
-                subcommands.push(GoArgs::Mate(fields[i].parse::<i32>().unwrap()));
+                subcommands.push(GoArgs::Mate(parse_go_value(fields, i)?));
             }
             f => eprintln!("Unknown go subcommand: {}", f),
         }
         i += 1;
     }
-    UciCmd::Go(subcommands)
+    Ok(subcommands)
+}
+
+/// Parse the integer value at index `i` of a "go" command.
+fn parse_go_value(fields: &[&str], i: usize) -> Result<i32, String> {
+    match fields.get(i) {
+        Some(v) => v.parse::<i32>().map_err(|_| format!("Bad value for go subcommand: {}", v)),
+        None => Err("Missing value for go subcommand".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::RelativeScore;
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(parse_command("uci"), UciCmd::Uci));
+        assert!(matches!(parse_command("debug on"), UciCmd::Debug(true)));
+        assert!(matches!(parse_command("debug"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command(""), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("   "), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("what"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("makemove e2e4"), UciCmd::MakeMove(m) if m == "e2e4"));
+        assert!(matches!(parse_command("makemove"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("undomove"), UciCmd::UndoMove));
+        assert!(matches!(parse_command("tune"), UciCmd::Tune));
+    }
+
+    #[test]
+    fn test_parse_position_command() {
+        match parse_command("position startpos moves e2e4 e7e5") {
+            UciCmd::Position(args) => {
+                assert_eq!(args.len(), 2);
+                assert!(matches!(&args[1], PositionArgs::Moves(moves) if moves.len() == 2));
+            }
+            _ => panic!(),
+        }
+        match parse_command(&format!("position fen {}", notation::FEN_START)) {
+            UciCmd::Position(args) => assert!(matches!(&args[0], PositionArgs::Fen(_))),
+            _ => panic!(),
+        }
+        assert!(matches!(parse_command("position fen 8/8/8 w"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("position fen 8/8/8 w - - 0 1"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("position startpos moves e2e9"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("position wat"), UciCmd::Unknown(_)));
+    }
+
+    #[test]
+    fn test_parse_setoption_command() {
+        match parse_command("setoption name Hash value 64") {
+            UciCmd::SetOption(name, value) => {
+                assert_eq!(name, "Hash");
+                assert_eq!(value.as_deref(), Some("64"));
+            }
+            _ => panic!(),
+        }
+        match parse_command("setoption name Clear Hash") {
+            UciCmd::SetOption(name, value) => {
+                assert_eq!(name, "Clear Hash");
+                assert_eq!(value, None);
+            }
+            _ => panic!(),
+        }
+        assert!(matches!(parse_command("setoption Hash 64"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("setoption name value 64"), UciCmd::Unknown(_)));
+    }
+
+    #[test]
+    fn test_engine_options() {
+        let options = engine_options(&engine::PROFILES[0], 8);
+        let option = |name| options.iter().find(|o| o.name == name).unwrap();
+        assert_eq!(
+            option("Hash").declaration(),
+            format!("option name Hash type spin default 8 min {} max {}", tt::MIN_SIZE_MB, tt::MAX_SIZE_MB)
+        );
+        assert_eq!(option("Clear Hash").declaration(), "option name Clear Hash type button");
+        assert_eq!(option("Ponder").declaration(), "option name Ponder type check default false");
+        assert_eq!(option("EvalWeights").declaration(), "option name EvalWeights type string default <empty>");
+        assert!(option("Profile").declaration().contains(&format!(" var {}", engine::PROFILES[0].name)));
+
+        assert_eq!(option("Threads").parse_value(Some("4")), Ok(OptionValue::Spin(4)));
+        assert!(option("Threads").parse_value(Some("0")).is_err());
+        assert!(option("Threads").parse_value(Some("four")).is_err());
+        assert!(option("Threads").parse_value(None).is_err());
+        assert_eq!(option("Ponder").parse_value(Some("TRUE")), Ok(OptionValue::Check(true)));
+        assert!(option("Ponder").parse_value(Some("yes")).is_err());
+        let profile = engine::PROFILES[0].name;
+        assert_eq!(
+            option("Profile").parse_value(Some(&profile.to_uppercase())),
+            Ok(OptionValue::Combo(profile))
+        );
+        assert!(option("Profile").parse_value(Some("nope")).is_err());
+        assert_eq!(option("EvalWeights").parse_value(Some("<empty>")), Ok(OptionValue::String(String::new())));
+        assert_eq!(
+            option("EvalWeights").parse_value(Some("Weights.txt")),
+            Ok(OptionValue::String("Weights.txt".to_string()))
+        );
+        assert_eq!(option("Clear Hash").parse_value(None), Ok(OptionValue::Button));
+    }
+
+    #[test]
+    fn test_parse_go_command() {
+        match parse_command("go wtime 1000 btime 2000 movestogo 10") {
+            UciCmd::Go(args) => assert_eq!(args.len(), 3),
+            _ => panic!(),
+        }
+        match parse_command("go ponder wtime 1000") {
+            UciCmd::Go(args) => assert!(matches!(args[..], [GoArgs::Ponder, GoArgs::WTime(1000)])),
+            _ => panic!(),
+        }
+        match parse_command("go searchmoves e2e4 d2d4 depth 3") {
+            UciCmd::Go(args) => assert!(matches!(&args[..], [GoArgs::SearchMoves(m), GoArgs::Depth(3)] if m.len() == 2)),
+            _ => panic!(),
+        }
+        assert!(matches!(parse_command("ponderhit"), UciCmd::PonderHit));
+        assert!(matches!(parse_command("go wtime"), UciCmd::Unknown(_)));
+        assert!(matches!(parse_command("go wtime abc"), UciCmd::Unknown(_)));
+    }
+
+    #[test]
+    fn test_commands_before_engine_setup() {
+        let mut uci = Uci {
+            state: State::Ready,
+            cmd_channel: mpsc::channel(),
+            engine_in: None,
+            debug: false,
+            logfile: None,
+            analysis_cache: None,
+            profile: None,
+            pending_engine_cmds: vec!(),
+            pending_ready: false,
+            pending_identities: false,
+            hash_mb: None,
+            pending_searches: 0,
+            session: None,
+            recording: None,
+        };
+        // Commands sent before the engine channel is open are delayed.
+        uci.handle_command(&parse_command("position startpos"));
+        uci.handle_command(&parse_command("isready"));
+        assert!(uci.pending_ready);
+        // Options are declared with the Hash size of the engine.
+        uci.pending_identities = true;
+        uci.handle_engine_command(&engine::Cmd::HashSize(3));
+        let (engine_s, engine_r) = mpsc::channel();
+        uci.handle_engine_command(&engine::Cmd::UciChannel(engine_s));
+        assert!(!uci.pending_ready && !uci.pending_identities);
+        let hash = uci.options().into_iter().find(|o| o.name == "Hash").unwrap();
+        assert!(hash.declaration().contains(" default 3 "));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciPosition(_))));
+        // While working, isready is answered right away.
+        uci.handle_command(&parse_command("go infinite"));
+        assert!(uci.state == State::Working);
+        uci.handle_command(&parse_command("isready"));
+        assert!(!uci.pending_ready);
+        // A go during a search restarts it: only the result of the new
+        // search makes the engine ready again.
+        uci.handle_command(&parse_command("go infinite"));
+        uci.handle_engine_command(&engine::Cmd::SearchResult(search_result(1)));
+        assert!(uci.state == State::Working);
+        uci.handle_engine_command(&engine::Cmd::SearchResult(search_result(2)));
+        assert!(uci.state == State::Ready);
+        // Options are checked before being sent to the engine.
+        engine_r.try_iter().for_each(drop);
+        uci.handle_command(&parse_command("setoption name threads value 2"));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciSetOption("Threads", OptionValue::Spin(2)))));
+        uci.handle_command(&parse_command("setoption name Threads value 0"));
+        uci.handle_command(&parse_command("setoption name Nope value 1"));
+        assert!(engine_r.try_recv().is_err());
+        // Tuning options are hidden but can be set.
+        assert!(uci.options().iter().all(|o| tune::find(o.name).is_none()));
+        uci.handle_command(&parse_command("setoption name delta_margin_cp value 150"));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciSetOption("DELTA_MARGIN_CP", OptionValue::Spin(150)))));
+        uci.handle_command(&parse_command("setoption name DELTA_MARGIN_CP value 5000"));
+        assert!(engine_r.try_recv().is_err());
+        uci.handle_command(&parse_command("tune"));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::Tune)));
+    }
+
+    #[test]
+    fn test_parse_recording() {
+        let recording = parse_recording("# version: 0.1.0\n0 uci\n12 position startpos moves e2e4\n\n1500 quit\n").unwrap();
+        assert_eq!(recording, vec![
+            (0, "uci".to_string()),
+            (12, "position startpos moves e2e4".to_string()),
+            (1500, "quit".to_string()),
+        ]);
+        assert!(parse_recording("12\n").is_ok());
+        assert!(parse_recording("uci\n").is_err());
+        assert!(parse_recording("-5 uci\n").is_err());
+    }
+
+    fn search_result(depth: u32) -> analysis::SearchResult {
+        analysis::SearchResult {
+            best_move: None,
+            ponder_move: None,
+            score: None,
+            mate: None,
+            bound: tt::Bound::Exact,
+            pv: vec!(),
+            depth,
+            sel_depth: depth,
+            stats: None,
+        }
+    }
+
+    #[test]
+    fn test_format_result_info() {
+        let stats = analysis::SearchStats { nodes: 1000, ..Default::default() };
+        assert_eq!(format_result_info(&search_result(0), &stats), "info depth 0 seldepth 0 nodes 1000");
+        let pv = vec![notation::parse_move("e2e4").unwrap(), notation::parse_move("e7e5").unwrap()];
+        let result = analysis::SearchResult {
+            best_move: Some(pv[0]),
+            ponder_move: Some(pv[1]),
+            score: Some(RelativeScore(35)),
+            pv,
+            sel_depth: 7,
+            ..search_result(4)
+        };
+        assert_eq!(
+            format_result_info(&result, &stats),
+            "info depth 4 seldepth 7 score cp 35 nodes 1000 pv e2e4 e7e5"
+        );
+        let result = analysis::SearchResult { bound: tt::Bound::Lower, ..result };
+        assert!(format_result_info(&result, &stats).contains(" score cp 35 lowerbound "));
+        let result = analysis::SearchResult { mate: Some(-2), bound: tt::Bound::Exact, ..result };
+        assert!(format_result_info(&result, &stats).contains(" score mate -2 nodes "));
+    }
+
+    #[test]
+    fn test_session_summary() {
+        let mut session = SessionStats { games: 2, searches: 3, ..Default::default() };
+        assert_eq!(
+            session.summary(),
+            "Session: 2 games, 3 searches, 0 nodes, average depth 0.0, TT hit rate 0.0%, 0 time losses"
+        );
+        let result = search_result(4);
+        let stats = analysis::SearchStats { nodes: 1000, tt_probes: 400, tt_hits: 100, out_of_time: false };
+        session.record_search(&result, &stats);
+        session.record_search(&search_result(3), &analysis::SearchStats { out_of_time: true, ..stats });
+        assert_eq!(
+            session.summary(),
+            "Session: 2 games, 3 searches, 2000 nodes, average depth 3.5, TT hit rate 25.0%, 1 time losses"
+        );
+    }
 }
@@ -0,0 +1,705 @@
+//! XBoard (CECP) management, for interfaces not speaking UCI.
+//!
+//! The engine is the same as for UCI: the game is kept here and sent
+//! as an UCI position before each search, with the clocks and limits
+//! set by the interface as UCI "go" arguments. Only protocol version 2
+//! is supported, with moves in coordinate notation.
+
+use std::fs;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::analysis::{self, AnalysisInfo};
+use crate::board;
+use crate::engine;
+use crate::game::Game;
+use crate::node::Node;
+use crate::notation;
+use crate::rules::{self, DrawRule, Status};
+use crate::tt;
+use crate::uci::{self, Cmd, GoArgs, OptionValue, PositionArgs};
+
+/// Score sent for a mate in 0 moves; mates in N moves are sent as this
+/// value plus N, or minus N when getting mated.
+const MATE_SCORE: i32 = 100000;
+
+/// Score in centipawns at or below which draw offers of the opponent
+/// are accepted.
+const ACCEPT_DRAW_CP: i32 = 0;
+/// Largest score in centipawns, either way, at which the engine offers
+/// a draw itself.
+const OFFER_DRAW_CP: i32 = 10;
+/// First move at which the engine offers draws.
+const MIN_DRAW_OFFER_MOVE: i32 = 40;
+/// Plies between two draw offers of the engine.
+const DRAW_OFFER_INTERVAL: usize = 20;
+/// Score in centipawns at or below which the engine resigns, ten pawns
+/// down.
+const RESIGN_CP: i32 = -1000;
+
+/// XBoard manager, see module documentation.
+pub struct Xboard {
+    /// Channel of Cmd, from standard input and the engine.
+    cmd_channel: (mpsc::Sender<Cmd>, mpsc::Receiver<Cmd>),
+    /// Sender for engine comms.
+    engine_in: Option<mpsc::Sender<engine::Cmd>>,
+    /// Commands received before the engine channel is open, sent once it is.
+    pending_engine_cmds: Vec<engine::Cmd>,
+    /// If some, write logs to it.
+    logfile: Option<fs::File>,
+    game: Game,
+    /// Color played by the engine, None in force mode.
+    engine_color: Option<u8>,
+    /// True if the result of the pending search is to be played.
+    thinking: bool,
+    /// Searches started whose result has not been received yet.
+    pending_searches: u32,
+    limits: Limits,
+    /// True to send thinking output.
+    post: bool,
+    /// True if the opponent offered a draw since the last engine move.
+    draw_offered: bool,
+    /// Ply of the last draw offer of the engine in this game, if any.
+    last_draw_offer: Option<usize>,
+}
+
+/// Reaction of the engine to the result of its search, sent before its
+/// move, see `react`.
+#[derive(Debug, PartialEq)]
+enum Reaction {
+    /// Only play the move.
+    None,
+    /// Offer a draw, or accept the one of the opponent.
+    OfferDraw,
+    /// Resign instead of playing the move.
+    Resign,
+}
+
+/// Clocks and search limits set by the interface.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Limits {
+    /// Moves per time control, 0 if the whole game is played in it.
+    moves_per_control: i32,
+    /// Increment per move in ms.
+    increment: i32,
+    /// Time per move in ms, from "st".
+    move_time: Option<i32>,
+    /// Depth limit, from "sd".
+    depth: Option<i32>,
+    /// Clocks of the engine and its opponent in ms.
+    engine_time: Option<i32>,
+    opponent_time: Option<i32>,
+}
+
+/// XBoard commands.
+#[derive(Debug, PartialEq)]
+pub enum XboardCmd {
+    Xboard,
+    Protover(u32),
+    New,
+    Force,
+    Go,
+    UserMove(String),
+    /// Clock of the engine, in ms.
+    Time(i32),
+    /// Clock of the opponent, in ms.
+    Otim(i32),
+    /// Moves per time control, base time and increment in ms.
+    Level(i32, i32, i32),
+    /// Time per move in ms.
+    St(i32),
+    Sd(i32),
+    SetBoard(String),
+    Ping(String),
+    Result(String),
+    Undo,
+    Remove,
+    /// "?": play the best move found so far.
+    MoveNow,
+    Post(bool),
+    /// The opponent offers a draw.
+    Draw,
+    /// Hash size in MB.
+    Memory(i32),
+    Cores(i32),
+    Quit,
+    /// Commands without effect on this engine, e.g. "accepted".
+    Ignored,
+    Unknown(String),
+}
+
+impl Xboard {
+    /// Start a new XBoard manager listening for standard input, see
+    /// `uci::Uci::start` for the parameters.
+    pub fn start(
+        debug: bool,
+        output: Option<&str>,
+        analysis_cache: Option<&str>,
+        profile: Option<&'static engine::Profile>,
+    ) {
+        let cmd_channel = mpsc::channel();
+        let stdin_tx = cmd_channel.0.clone();
+        thread::spawn(move || uci::Uci::read_stdin(stdin_tx));
+        let logfile = output.and_then(|output| match fs::File::create(output) {
+            Ok(f) => Some(f),
+            Err(e) => { eprintln!("Could not open log file: {}", e); None }
+        });
+        let analysis_cache = analysis_cache.map(|path| path.to_string());
+        Xboard::new(cmd_channel, debug, logfile, analysis_cache, profile).listen();
+    }
+
+    /// Create a manager receiving commands on `cmd_channel`, e.g. from
+    /// an UCI manager that received "xboard", and start its engine.
+    pub fn new(
+        cmd_channel: (mpsc::Sender<Cmd>, mpsc::Receiver<Cmd>),
+        debug: bool,
+        logfile: Option<fs::File>,
+        analysis_cache: Option<String>,
+        profile: Option<&'static engine::Profile>,
+    ) -> Xboard {
+        uci::spawn_engine(debug, analysis_cache, profile, cmd_channel.0.clone());
+        Xboard {
+            cmd_channel,
+            engine_in: None,
+            pending_engine_cmds: vec!(),
+            logfile,
+            game: start_game(),
+            engine_color: Some(board::SQ_BL),
+            thinking: false,
+            pending_searches: 0,
+            limits: Limits::default(),
+            post: false,
+            draw_offered: false,
+            last_draw_offer: None,
+        }
+    }
+
+    pub fn listen(&mut self) {
+        loop {
+            match self.cmd_channel.1.recv() {
+                Ok(Cmd::Stdin(cmd)) => {
+                    self.log(format!("XBOARD >>> {}", cmd));
+                    if !self.handle_command(&parse_command(&cmd)) {
+                        break
+                    }
+                }
+                Ok(Cmd::Engine(cmd)) => {
+                    self.handle_engine_command(&cmd);
+                }
+                Err(e) => self.log(format!("Can't read commands: {}", e))
+            }
+        }
+    }
+
+    fn log(&mut self, s: String) {
+        match &mut self.logfile {
+            Some(f) => {
+                writeln!(f, "{}", s).unwrap();
+            }
+            None => {
+                eprintln!("{}", s);
+            }
+        }
+    }
+
+    /// Send an XBoard reply.
+    fn send(&mut self, s: &str) {
+        self.log(format!("XBOARD <<< {}", s));
+        println!("{}", s);
+        io::stdout().flush().unwrap();
+    }
+
+    /// Handle an XBoard command, return false if it should stop listening.
+    fn handle_command(&mut self, cmd: &XboardCmd) -> bool {
+        match cmd {
+            XboardCmd::Xboard | XboardCmd::Ignored => {}
+            XboardCmd::Protover(_) => {
+                self.send(&format!(
+                    "feature myname=\"{}\" ping=1 setboard=1 usermove=1 san=0 time=1 draw=1 \
+                     sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 memory=1 smp=1 done=1",
+                    uci::VATU_NAME
+                ));
+            }
+            XboardCmd::New => {
+                self.stop_thinking();
+                self.game = start_game();
+                self.engine_color = Some(board::SQ_BL);
+                self.limits.depth = None;
+                self.draw_offered = false;
+                self.last_draw_offer = None;
+            }
+            XboardCmd::Force => {
+                self.stop_thinking();
+                self.engine_color = None;
+            }
+            XboardCmd::Go => {
+                self.engine_color = Some(self.game.node().game_state.color);
+                self.think();
+            }
+            XboardCmd::UserMove(m_str) => {
+                self.stop_thinking();
+                match notation::parse_move(m_str).map(|m| self.game.play_move(&m)) {
+                    Some(Ok(_)) => self.think(),
+                    _ => self.send(&format!("Illegal move: {}", m_str)),
+                }
+            }
+            XboardCmd::Time(ms) => self.limits.engine_time = Some(*ms),
+            XboardCmd::Otim(ms) => self.limits.opponent_time = Some(*ms),
+            XboardCmd::Level(moves, _, inc) => {
+                self.limits.moves_per_control = *moves;
+                self.limits.increment = *inc;
+                self.limits.move_time = None;
+            }
+            XboardCmd::St(ms) => self.limits.move_time = Some(*ms),
+            XboardCmd::Sd(depth) => self.limits.depth = Some(*depth),
+            XboardCmd::SetBoard(fen) => {
+                self.stop_thinking();
+                match notation::parse_fen(fen).and_then(|fen| notation::fen_to_position(&fen)) {
+                    Some((board, game_state)) => {
+                        self.game = Game::new(&board, &game_state);
+                        self.draw_offered = false;
+                        self.last_draw_offer = None;
+                    }
+                    None => self.send(&format!("Error (bad FEN): {}", fen)),
+                }
+            }
+            XboardCmd::Ping(n) => self.send(&format!("pong {}", n)),
+            XboardCmd::Result(_) => {
+                self.stop_thinking();
+                self.engine_color = None;
+            }
+            XboardCmd::Undo | XboardCmd::Remove => {
+                self.stop_thinking();
+                let plies = if *cmd == XboardCmd::Undo { 1 } else { 2 };
+                let ply = self.game.ply().saturating_sub(plies);
+                self.game.goto_ply(ply);
+            }
+            XboardCmd::MoveNow => if self.thinking {
+                self.send_engine_command(engine::Cmd::Stop);
+            },
+            XboardCmd::Post(on) => self.post = *on,
+            XboardCmd::Draw => self.draw_offered = true,
+            XboardCmd::Memory(mb) => {
+                let mb = (*mb).clamp(tt::MIN_SIZE_MB as i32, tt::MAX_SIZE_MB as i32);
+                self.send_engine_command(engine::Cmd::UciSetOption("Hash", OptionValue::Spin(mb)));
+            }
+            XboardCmd::Cores(n) => {
+                let n = (*n).clamp(1, engine::MAX_THREADS);
+                self.send_engine_command(engine::Cmd::UciSetOption("Threads", OptionValue::Spin(n)));
+            }
+            XboardCmd::Quit => return false,
+            XboardCmd::Unknown(c) => self.send(&format!("Error (unknown command): {}", c)),
+        }
+        true
+    }
+
+    /// Handle an engine command.
+    fn handle_engine_command(&mut self, cmd: &engine::Cmd) {
+        match cmd {
+            engine::Cmd::UciChannel(s) => {
+                self.log("ENGINE: Channel opened.".to_string());
+                self.engine_in = Some(s.to_owned());
+                for cmd in std::mem::take(&mut self.pending_engine_cmds) {
+                    self.send_engine_command(cmd);
+                }
+            }
+            engine::Cmd::Log(s) => {
+                self.log(format!("ENGINE: {}", s));
+            }
+            engine::Cmd::Info(infos) if self.post && self.thinking => {
+                if let Some(line) = format_thinking(infos) {
+                    self.send(&line);
+                }
+            }
+            engine::Cmd::SearchResult(result) => {
+                self.pending_searches = self.pending_searches.saturating_sub(1);
+                // Results of searches stopped by other commands are dropped.
+                if self.pending_searches == 0 && self.thinking {
+                    self.thinking = false;
+                    self.play_result(result);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Search the current position if the engine plays its color.
+    fn think(&mut self) {
+        if self.engine_color != Some(self.game.node().game_state.color) || self.send_game_result() {
+            return
+        }
+        let start = self.game.node_at(0).unwrap();
+        let fen = notation::parse_fen(&notation::to_fen(&start.board, &start.game_state)).unwrap();
+        let moves = self.game.moves()[..self.game.ply()].to_vec();
+        let go_args = go_args(&self.limits, self.game.node(), start.game_state.fullmove);
+        self.send_engine_command(engine::Cmd::UciPosition(vec!(PositionArgs::Fen(fen), PositionArgs::Moves(moves))));
+        self.send_engine_command(engine::Cmd::UciGo(go_args));
+        self.pending_searches += 1;
+        self.thinking = true;
+    }
+
+    /// Stop the current search, if any, without playing its result.
+    fn stop_thinking(&mut self) {
+        if self.thinking {
+            self.thinking = false;
+            self.send_engine_command(engine::Cmd::Stop);
+        }
+    }
+
+    /// Play and send the best move of a search `result`, offering or
+    /// accepting a draw or resigning instead if its score says so.
+    fn play_result(&mut self, result: &analysis::SearchResult) {
+        let ply = self.game.ply();
+        let may_offer_draw = self.game.node().game_state.fullmove >= MIN_DRAW_OFFER_MOVE
+            && self.last_draw_offer.is_none_or(|last| ply >= last + DRAW_OFFER_INTERVAL);
+        let reaction = react(result, self.draw_offered, may_offer_draw);
+        self.draw_offered = false;
+        match reaction {
+            Reaction::Resign => {
+                self.send("resign");
+                self.engine_color = None;
+                return
+            }
+            Reaction::OfferDraw => {
+                self.send("offer draw");
+                self.last_draw_offer = Some(ply);
+            }
+            Reaction::None => {}
+        }
+        let m = match result.best_move.map(|m| self.game.play_move(&m)) {
+            Some(Ok(m)) => m,
+            _ => { self.log(format!("No move to play in {:?}", result)); return }
+        };
+        self.send(&format!("move {}", notation::move_to_string(&m)));
+        self.send_game_result();
+    }
+
+    /// Send the result of the game if it is over; return true if so.
+    ///
+    /// Draws by repetition are left to the interface.
+    fn send_game_result(&mut self) -> bool {
+        let node = self.game.node();
+        let result = match rules::game_status(&node.board, &node.game_state) {
+            Status::Ongoing => return false,
+            Status::Checkmate(board::SQ_WH) => "1-0 {White mates}",
+            Status::Checkmate(_) => "0-1 {Black mates}",
+            Status::Stalemate => "1/2-1/2 {Stalemate}",
+            Status::DrawByRule(DrawRule::FiftyMove) => "1/2-1/2 {Fifty move rule}",
+            Status::DrawByRule(DrawRule::InsufficientMaterial) => "1/2-1/2 {Insufficient material}",
+        };
+        self.send(result);
+        true
+    }
+
+    /// Send a command to the engine, or once it is set up.
+    fn send_engine_command(&mut self, cmd: engine::Cmd) {
+        match self.engine_in.as_ref() {
+            Some(tx) => tx.send(cmd).unwrap(),
+            None => self.pending_engine_cmds.push(cmd),
+        }
+    }
+}
+
+/// Return a game from the starting position.
+fn start_game() -> Game {
+    Game::new(&board::new(), &rules::GameState::new())
+}
+
+/// Return the UCI "go" arguments to search `node` with `limits`, the
+/// game having started at move `start_fullmove`.
+fn go_args(limits: &Limits, node: &Node, start_fullmove: i32) -> Vec<GoArgs> {
+    let mut args = vec!();
+    if let Some(ms) = limits.move_time {
+        args.push(GoArgs::MoveTime(ms));
+    } else if let Some(engine_time) = limits.engine_time {
+        let opponent_time = limits.opponent_time.unwrap_or(engine_time);
+        let white = node.game_state.color == board::SQ_WH;
+        let (white_time, black_time) = if white { (engine_time, opponent_time) } else { (opponent_time, engine_time) };
+        args.extend([
+            GoArgs::WTime(white_time),
+            GoArgs::BTime(black_time),
+            GoArgs::WInc(limits.increment),
+            GoArgs::BInc(limits.increment),
+        ]);
+        if limits.moves_per_control > 0 {
+            let played = (node.game_state.fullmove - start_fullmove).max(0);
+            args.push(GoArgs::MovesToGo(limits.moves_per_control - played % limits.moves_per_control));
+        }
+    }
+    if let Some(depth) = limits.depth {
+        args.push(GoArgs::Depth(depth));
+    }
+    args
+}
+
+/// Return how to react to a search `result` of the engine, from its
+/// score: accept the draw offer of the opponent if `draw_offered` and
+/// the engine is not better, resign if it is lost, or offer a draw if
+/// `may_offer_draw` and the position is level.
+///
+/// Results without a score, e.g. book moves, are only played.
+fn react(result: &analysis::SearchResult, draw_offered: bool, may_offer_draw: bool) -> Reaction {
+    let cp = match (result.mate, result.score) {
+        (Some(moves), _) if moves > 0 => i32::MAX,
+        (Some(_), _) => i32::MIN,
+        (None, Some(score)) => score.0,
+        (None, None) => return Reaction::None,
+    };
+    if draw_offered && cp <= ACCEPT_DRAW_CP {
+        Reaction::OfferDraw
+    } else if cp <= RESIGN_CP {
+        Reaction::Resign
+    } else if may_offer_draw && cp.abs() <= OFFER_DRAW_CP {
+        Reaction::OfferDraw
+    } else {
+        Reaction::None
+    }
+}
+
+/// Format search `infos` as a thinking output line: depth, score in
+/// centipawns, time in centiseconds, nodes and principal variation.
+///
+/// Return None if the infos have no depth or principal variation.
+fn format_thinking(infos: &[AnalysisInfo]) -> Option<String> {
+    let (mut depth, mut score, mut time, mut nodes, mut pv) = (None, 0, 0, 0, None);
+    for info in infos {
+        match info {
+            AnalysisInfo::Depth(n) => depth = Some(*n),
+            AnalysisInfo::Score(cp) => score = cp.0,
+            AnalysisInfo::Mate(moves) if *moves > 0 => score = MATE_SCORE + moves,
+            AnalysisInfo::Mate(moves) => score = -MATE_SCORE + moves,
+            AnalysisInfo::Time(ms) => time = ms / 10,
+            AnalysisInfo::Nodes(n) => nodes = *n,
+            AnalysisInfo::Pv(moves) => pv = Some(moves),
+            _ => {}
+        }
+    }
+    Some(format!("{} {} {} {} {}", depth?, score, time, nodes, notation::move_list_to_string(pv?)))
+}
+
+// ************************************
+// XBoard command parsers
+
+/// Parse an XBoard command.
+///
+/// Malformed commands are returned as `XboardCmd::Unknown` with the
+/// command, as are times too large to be counted in ms; moves can be
+/// sent without "usermove".
+pub fn parse_command(s: &str) -> XboardCmd {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    let parse_arg = |i: usize| fields.get(i).and_then(|v| v.parse::<i32>().ok());
+    // Times are sent in centiseconds or seconds.
+    let parse_ms = |i: usize, ms_per_unit: i32| parse_arg(i).and_then(|v| v.checked_mul(ms_per_unit));
+    let cmd = match fields.first() {
+        Some(cmd) => *cmd,
+        None => return XboardCmd::Unknown(String::new()),
+    };
+    let parsed = match cmd {
+        "xboard" => Some(XboardCmd::Xboard),
+        "protover" => parse_arg(1).map(|v| XboardCmd::Protover(v as u32)),
+        "new" => Some(XboardCmd::New),
+        "force" => Some(XboardCmd::Force),
+        "go" => Some(XboardCmd::Go),
+        "usermove" => fields.get(1).map(|m| XboardCmd::UserMove(m.to_string())),
+        "time" => parse_ms(1, 10).map(XboardCmd::Time),
+        "otim" => parse_ms(1, 10).map(XboardCmd::Otim),
+        "level" => parse_level(&fields[1..]),
+        "st" => parse_ms(1, 1000).map(XboardCmd::St),
+        "sd" => parse_arg(1).map(XboardCmd::Sd),
+        "setboard" => Some(XboardCmd::SetBoard(fields[1..].join(" "))),
+        "ping" => fields.get(1).map(|n| XboardCmd::Ping(n.to_string())),
+        "result" => Some(XboardCmd::Result(fields[1..].join(" "))),
+        "undo" => Some(XboardCmd::Undo),
+        "remove" => Some(XboardCmd::Remove),
+        "?" => Some(XboardCmd::MoveNow),
+        "post" => Some(XboardCmd::Post(true)),
+        "nopost" => Some(XboardCmd::Post(false)),
+        "draw" => Some(XboardCmd::Draw),
+        "memory" => parse_arg(1).map(XboardCmd::Memory),
+        "cores" => parse_arg(1).map(XboardCmd::Cores),
+        "quit" => Some(XboardCmd::Quit),
+        "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating" | "ics"
+        | "white" | "black" | "hint" | "bk" | "." => Some(XboardCmd::Ignored),
+        m if notation::parse_move(m).is_some() => Some(XboardCmd::UserMove(m.to_string())),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| XboardCmd::Unknown(s.to_string()))
+}
+
+/// Parse the arguments of a "level" command: moves per time control,
+/// base time in minutes or "minutes:seconds", and increment in seconds.
+fn parse_level(fields: &[&str]) -> Option<XboardCmd> {
+    if fields.len() != 3 {
+        return None
+    }
+    let moves = fields[0].parse::<i32>().ok()?;
+    let mut base = fields[1].split(':');
+    let minutes = base.next()?.parse::<i32>().ok()?;
+    let seconds = base.next().map_or(Some(0), |s| s.parse::<i32>().ok())?;
+    let increment = fields[2].parse::<f64>().ok()?;
+    let base = minutes.checked_mul(60)?.checked_add(seconds)?.checked_mul(1000)?;
+    Some(XboardCmd::Level(moves, base, (increment * 1000.0) as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_move;
+    use crate::score;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("protover 2"), XboardCmd::Protover(2));
+        assert_eq!(parse_command("usermove e7e8q"), XboardCmd::UserMove("e7e8q".to_string()));
+        assert_eq!(parse_command("e2e4"), XboardCmd::UserMove("e2e4".to_string()));
+        assert_eq!(parse_command("level 40 0:30 0"), XboardCmd::Level(40, 30000, 0));
+        assert_eq!(parse_command("level 0 5 2.5"), XboardCmd::Level(0, 300000, 2500));
+        assert_eq!(parse_command("time 1234"), XboardCmd::Time(12340));
+        assert_eq!(parse_command("st 5"), XboardCmd::St(5000));
+        assert_eq!(parse_command("result 1-0 {White mates}"), XboardCmd::Result("1-0 {White mates}".to_string()));
+        assert_eq!(parse_command("setboard 8/8/8/8/8/8/8/K1k5 w - - 0 1"), XboardCmd::SetBoard("8/8/8/8/8/8/8/K1k5 w - - 0 1".to_string()));
+        assert_eq!(parse_command("accepted usermove"), XboardCmd::Ignored);
+        assert_eq!(parse_command("draw"), XboardCmd::Draw);
+        assert_eq!(parse_command("time soon"), XboardCmd::Unknown("time soon".to_string()));
+        assert_eq!(parse_command("level 40 5"), XboardCmd::Unknown("level 40 5".to_string()));
+        // Times overflowing in ms are refused.
+        for cmd in ["time 2147483647", "otim 300000000", "st 2147484", "level 40 35792 0", "level 0 1:2147483647 0"] {
+            assert_eq!(parse_command(cmd), XboardCmd::Unknown(cmd.to_string()));
+        }
+        assert_eq!(parse_command("frobnicate"), XboardCmd::Unknown("frobnicate".to_string()));
+    }
+
+    #[test]
+    fn test_go_args() {
+        let mut node = Node::new();
+        node.game_state.color = board::SQ_BL;
+        node.game_state.fullmove = 12;
+        let limits = Limits {
+            moves_per_control: 40,
+            increment: 1000,
+            engine_time: Some(60000),
+            opponent_time: Some(30000),
+            ..Default::default()
+        };
+        let args = format!("{:?}", go_args(&limits, &node, 1));
+        assert_eq!(args, "[WTime(30000), BTime(60000), WInc(1000), BInc(1000), MovesToGo(29)]");
+        let limits = Limits { move_time: Some(5000), depth: Some(3), ..limits };
+        assert_eq!(format!("{:?}", go_args(&limits, &node, 1)), "[MoveTime(5000), Depth(3)]");
+    }
+
+    #[test]
+    fn test_format_thinking() {
+        let pv = vec!(parse_move("e2e4").unwrap(), parse_move("e7e5").unwrap());
+        let infos = [
+            AnalysisInfo::Depth(3),
+            AnalysisInfo::Mate(-2),
+            AnalysisInfo::Time(1234),
+            AnalysisInfo::Nodes(500),
+            AnalysisInfo::Pv(pv),
+        ];
+        assert_eq!(format_thinking(&infos), Some("3 -100002 123 500 e2e4 e7e5".to_string()));
+        assert_eq!(format_thinking(&infos[..4]), None);
+    }
+
+    #[test]
+    fn test_game_flow() {
+        let mut xboard = Xboard {
+            cmd_channel: mpsc::channel(),
+            engine_in: None,
+            pending_engine_cmds: vec!(),
+            logfile: None,
+            game: start_game(),
+            engine_color: Some(board::SQ_BL),
+            thinking: false,
+            pending_searches: 0,
+            limits: Limits::default(),
+            post: false,
+            draw_offered: false,
+            last_draw_offer: None,
+        };
+        // The engine plays black after "new": a white move starts a
+        // search, sent once the engine channel is open.
+        xboard.handle_command(&parse_command("new"));
+        xboard.handle_command(&parse_command("usermove e2e4"));
+        assert!(xboard.thinking);
+        let (engine_s, engine_r) = mpsc::channel();
+        xboard.handle_engine_command(&engine::Cmd::UciChannel(engine_s));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciPosition(_))));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciGo(_))));
+        // Its result is played.
+        xboard.handle_engine_command(&engine::Cmd::SearchResult(search_result("e7e5")));
+        assert!(!xboard.thinking);
+        assert_eq!(xboard.game.last_move(), parse_move("e7e5"));
+        // Illegal moves are refused.
+        xboard.handle_command(&parse_command("usermove e1e3"));
+        assert_eq!(xboard.game.num_plies(), 2);
+        // In force mode, moves are only recorded; a search stopped by
+        // another command is not played.
+        xboard.handle_command(&parse_command("usermove g1f3"));
+        xboard.handle_command(&parse_command("force"));
+        assert!(!xboard.thinking);
+        xboard.handle_engine_command(&engine::Cmd::SearchResult(search_result("b8c6")));
+        assert_eq!(xboard.game.num_plies(), 3);
+        xboard.handle_command(&parse_command("usermove b8c6"));
+        xboard.handle_command(&parse_command("remove"));
+        assert_eq!((xboard.game.ply(), xboard.game.node().game_state.color), (2, board::SQ_WH));
+        // "go" makes the engine play the side to move.
+        engine_r.try_iter().for_each(drop);
+        xboard.handle_command(&parse_command("go"));
+        assert_eq!(xboard.engine_color, Some(board::SQ_WH));
+        assert!(matches!(engine_r.try_recv(), Ok(engine::Cmd::UciPosition(_))));
+        xboard.handle_engine_command(&engine::Cmd::SearchResult(search_result("g1f3")));
+        assert_eq!(xboard.game.moves().len(), 3);
+        // No search starts when the game is over.
+        xboard.handle_command(&parse_command("setboard K7/8/1q6/8/8/8/8/7k w - - 0 1"));
+        xboard.handle_command(&parse_command("go"));
+        assert!(!xboard.thinking);
+        // A lost engine accepts a draw offer with its move, and else
+        // resigns instead of playing it.
+        xboard.handle_command(&parse_command("new"));
+        xboard.handle_command(&parse_command("draw"));
+        assert!(xboard.draw_offered);
+        xboard.handle_command(&parse_command("usermove e2e4"));
+        let result = |m| analysis::SearchResult { mate: Some(-3), ..search_result(m) };
+        xboard.handle_engine_command(&engine::Cmd::SearchResult(result("e7e5")));
+        assert!(!xboard.draw_offered);
+        assert_eq!(xboard.game.num_plies(), 2);
+        xboard.handle_command(&parse_command("usermove g1f3"));
+        xboard.handle_engine_command(&engine::Cmd::SearchResult(result("b8c6")));
+        assert_eq!((xboard.game.num_plies(), xboard.engine_color), (3, None));
+    }
+
+    #[test]
+    fn test_react() {
+        let scored = |cp| analysis::SearchResult { score: Some(score::RelativeScore(cp)), ..search_result("e2e4") };
+        assert_eq!(react(&search_result("e2e4"), true, true), Reaction::None);
+        // Draw offers are accepted unless the engine is better.
+        assert_eq!(react(&scored(-30), true, false), Reaction::OfferDraw);
+        assert_eq!(react(&scored(30), true, false), Reaction::None);
+        let mated = analysis::SearchResult { mate: Some(-2), ..scored(-30000) };
+        assert_eq!(react(&mated, true, false), Reaction::OfferDraw);
+        assert_eq!(react(&mated, false, true), Reaction::Resign);
+        assert_eq!(react(&scored(RESIGN_CP), false, false), Reaction::Resign);
+        // Level positions are offered as draws when allowed.
+        assert_eq!(react(&scored(5), false, true), Reaction::OfferDraw);
+        assert_eq!(react(&scored(5), false, false), Reaction::None);
+        assert_eq!(react(&scored(-50), false, true), Reaction::None);
+        let mating = analysis::SearchResult { mate: Some(1), ..scored(30000) };
+        assert_eq!(react(&mating, true, true), Reaction::None);
+    }
+
+    fn search_result(m: &str) -> analysis::SearchResult {
+        analysis::SearchResult {
+            best_move: parse_move(m),
+            ponder_move: None,
+            score: None,
+            mate: None,
+            bound: tt::Bound::Exact,
+            pv: vec!(),
+            depth: 1,
+            sel_depth: 1,
+            stats: None,
+        }
+    }
+}
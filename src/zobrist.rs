@@ -0,0 +1,217 @@
+//! Zobrist hashing of positions.
+//!
+//! A position hash is the XOR of random keys for each piece on its
+//! square, the castling options, the en passant file and the player to
+//! move. Keys are generated at compile time from a fixed seed, so
+//! hashes are stable across runs and can be stored.
+//!
+//! As a move only changes a few squares, the hash of the resulting
+//! position can be updated incrementally with `update_hash` instead of
+//! being computed again, or even before the move is played with
+//! `hash_after`.
+
+use crate::board::*;
+use crate::movement::{self, Move};
+use crate::rules::GameState;
+
+/// Random keys used for hashing.
+struct Keys {
+    /// Keys by piece index (see `piece_index`) and board index.
+    pieces: [[u64; 64]; 12],
+    /// Keys by castling options value.
+    castling: [u64; 16],
+    /// Keys by en passant file.
+    en_passant: [u64; 8],
+    /// Key for black to play.
+    black_to_play: u64,
+}
+
+const KEYS: Keys = generate_keys(0x5641_5455_2d5a_4f42);
+
+/// Generate keys with the SplitMix64 generator, usable at compile time.
+const fn generate_keys(seed: u64) -> Keys {
+    let mut state = seed;
+    let mut keys = Keys { pieces: [[0; 64]; 12], castling: [0; 16], en_passant: [0; 8], black_to_play: 0 };
+    let mut i = 0;
+    while i < 12 * 64 {
+        keys.pieces[i / 64][i % 64] = splitmix64(&mut state);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 16 {
+        keys.castling[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 8 {
+        keys.en_passant[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    keys.black_to_play = splitmix64(&mut state);
+    keys
+}
+
+pub(crate) const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Return the key index of a piece: white pieces first, in P, B, N,
+/// R, Q, K order.
+fn piece_index(square: u8) -> usize {
+    let color_offset = if is_white(square) { 0 } else { 6 };
+    color_offset + get_type(square).trailing_zeros() as usize
+}
+
+/// Return the key of the square at `pos`, or 0 if empty.
+fn square_key(board: &Board, pos: &Pos) -> u64 {
+    match get_square(board, pos) {
+        SQ_E => 0,
+        square => KEYS.pieces[piece_index(square)][pos_index(pos)],
+    }
+}
+
+/// Return the key of game state options: castling, en passant and color.
+fn game_state_key(game_state: &GameState) -> u64 {
+    let mut key = KEYS.castling[(game_state.castling & 0xF) as usize];
+    if let Some((f, _)) = game_state.en_passant {
+        key ^= KEYS.en_passant[f as usize];
+    }
+    if game_state.color == SQ_BL {
+        key ^= KEYS.black_to_play;
+    }
+    key
+}
+
+/// Compute the hash of a position.
+///
+/// Move counters are not part of the hash.
+pub fn hash(board: &Board, game_state: &GameState) -> u64 {
+    let mut hash = game_state_key(game_state);
+    for (piece, pos) in get_piece_iterator(board) {
+        hash ^= KEYS.pieces[piece_index(piece)][pos_index(&pos)];
+    }
+    hash
+}
+
+/// Return the hash of the position after move `m`.
+///
+/// `hash` is the hash of the position before the move, described by
+/// `board` and `game_state`; `new_board` and `new_game_state` are the
+/// position after `m` is applied. Only the squares changed by the move
+/// are hashed again.
+pub fn update_hash(
+    hash: u64,
+    board: &Board,
+    game_state: &GameState,
+    new_board: &Board,
+    new_game_state: &GameState,
+    m: &Move,
+) -> u64 {
+    let mut hash = hash ^ game_state_key(game_state) ^ game_state_key(new_game_state);
+    movement::for_each_changed_square(board, m, |pos| {
+        hash ^= square_key(board, pos) ^ square_key(new_board, pos)
+    });
+    hash
+}
+
+/// Return the hash of the position after move `m`, without playing it.
+///
+/// `hash` is the hash of the position before the move, described by
+/// `board` and `game_state`.
+pub fn hash_after(hash: u64, board: &Board, game_state: &GameState, m: &Move) -> u64 {
+    let mut new_game_state = game_state.clone();
+    movement::update_game_state(board, &mut new_game_state, m);
+    let mut hash = hash ^ game_state_key(game_state) ^ game_state_key(&new_game_state);
+    for piece in movement::get_dirty_pieces(board, m).as_slice() {
+        for pos in piece.from.iter().chain(piece.to.iter()) {
+            hash ^= KEYS.pieces[piece_index(piece.piece)][pos_index(pos)];
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+    use crate::random;
+    use crate::rules;
+
+    #[test]
+    fn test_hash() {
+        let (board, game_state) = notation::position(notation::FEN_START);
+        let start_hash = hash(&board, &game_state);
+        // Counters are not hashed, but everything else is.
+        let (board2, game_state2) = notation::position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3 12");
+        assert_eq!(hash(&board2, &game_state2), start_hash);
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+        ].iter() {
+            let (board, game_state) = notation::position(fen);
+            assert_ne!(hash(&board, &game_state), start_hash, "{}", fen);
+        }
+        // Transpositions have the same hash.
+        let mut b1 = board;
+        let mut gs1 = game_state.clone();
+        let mut b2 = board;
+        let mut gs2 = game_state;
+        for m in ["g1f3", "g8f6", "b1c3"].iter() {
+            movement::apply_move_to(&mut b1, &mut gs1, &notation::parse_move(m).unwrap());
+        }
+        for m in ["b1c3", "g8f6", "g1f3"].iter() {
+            movement::apply_move_to(&mut b2, &mut gs2, &notation::parse_move(m).unwrap());
+        }
+        assert_eq!(hash(&b1, &gs1), hash(&b2, &gs2));
+    }
+
+    #[test]
+    fn test_update_hash() {
+        // Incremental updates and hashes before playing moves match full
+        // hashing, including castles, en passant and promotions.
+        let mut rng = random::Rng::new(42);
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/1P6/8/8/8/8/6p1/4K3 w - - 0 1",
+        ].iter() {
+            let (board, game_state) = notation::position(fen);
+            let h = hash(&board, &game_state);
+            for m in rules::get_player_moves(&board, &game_state, true) {
+                let (new_board, new_game_state) = movement::apply_move(&board, &game_state, &m);
+                let new_hash = hash(&new_board, &new_game_state);
+                assert_eq!(
+                    update_hash(h, &board, &game_state, &new_board, &new_game_state, &m),
+                    new_hash,
+                    "{} {}", fen, notation::move_to_string(&m)
+                );
+                assert_eq!(hash_after(h, &board, &game_state, &m), new_hash, "{} {}", fen, notation::move_to_string(&m));
+            }
+        }
+        for _ in 0..20 {
+            let mut node = random::random_node_from_moves(&mut rng, 0);
+            let mut h = hash(&node.board, &node.game_state);
+            for _ in 0..60 {
+                let moves = node.get_player_moves(true);
+                if moves.is_empty() {
+                    break
+                }
+                let m = moves[rng.below(moves.len())];
+                let h_after = hash_after(h, &node.board, &node.game_state, &m);
+                let (new_board, new_game_state) = movement::apply_move(&node.board, &node.game_state, &m);
+                h = update_hash(h, &node.board, &node.game_state, &new_board, &new_game_state, &m);
+                node.board = new_board;
+                node.game_state = new_game_state;
+                assert_eq!(h, hash(&node.board, &node.game_state));
+                assert_eq!(h_after, h);
+            }
+        }
+    }
+}